@@ -0,0 +1,63 @@
+#version 450
+
+layout(location = 0) in vec2 in_uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 0) uniform sampler2D sourceSampler;
+
+layout(push_constant) uniform PushConstants {
+    float threshold;
+    uint first;
+} push_constants;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Weights the samples by inverse luminance to keep single very bright texels from flickering
+vec3 karis_average(vec3 a, vec3 b, vec3 c, vec3 d) {
+    vec4 weights = 1.0 / (1.0 + vec4(luminance(a), luminance(b), luminance(c), luminance(d)));
+
+    return (a * weights.x + b * weights.y + c * weights.z + d * weights.w)
+        / (weights.x + weights.y + weights.z + weights.w);
+}
+
+// 13 tap filter from the Call of Duty: Advanced Warfare presentation
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sourceSampler, 0));
+
+    vec3 a = texture(sourceSampler, in_uv + texel * vec2(-2.0, -2.0)).rgb;
+    vec3 b = texture(sourceSampler, in_uv + texel * vec2(0.0, -2.0)).rgb;
+    vec3 c = texture(sourceSampler, in_uv + texel * vec2(2.0, -2.0)).rgb;
+    vec3 d = texture(sourceSampler, in_uv + texel * vec2(-2.0, 0.0)).rgb;
+    vec3 e = texture(sourceSampler, in_uv).rgb;
+    vec3 f = texture(sourceSampler, in_uv + texel * vec2(2.0, 0.0)).rgb;
+    vec3 g = texture(sourceSampler, in_uv + texel * vec2(-2.0, 2.0)).rgb;
+    vec3 h = texture(sourceSampler, in_uv + texel * vec2(0.0, 2.0)).rgb;
+    vec3 i = texture(sourceSampler, in_uv + texel * vec2(2.0, 2.0)).rgb;
+    vec3 j = texture(sourceSampler, in_uv + texel * vec2(-1.0, -1.0)).rgb;
+    vec3 k = texture(sourceSampler, in_uv + texel * vec2(1.0, -1.0)).rgb;
+    vec3 l = texture(sourceSampler, in_uv + texel * vec2(-1.0, 1.0)).rgb;
+    vec3 m = texture(sourceSampler, in_uv + texel * vec2(1.0, 1.0)).rgb;
+
+    vec3 color;
+
+    if (push_constants.first == 1) {
+        color = karis_average(j, k, l, m) * 0.5
+            + karis_average(a, b, d, e) * 0.125
+            + karis_average(b, c, e, f) * 0.125
+            + karis_average(d, e, g, h) * 0.125
+            + karis_average(e, f, h, i) * 0.125;
+
+        float brightness = luminance(color);
+        color *= max(brightness - push_constants.threshold, 0.0) / max(brightness, 0.0001);
+    } else {
+        color = e * 0.125
+            + (a + c + g + i) * 0.03125
+            + (b + d + f + h) * 0.0625
+            + (j + k + l + m) * 0.125;
+    }
+
+    out_color = vec4(color, 1.0);
+}
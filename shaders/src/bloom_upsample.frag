@@ -0,0 +1,29 @@
+#version 450
+
+layout(location = 0) in vec2 in_uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 0) uniform sampler2D currentSampler;
+layout(binding = 1) uniform sampler2D coarserSampler;
+
+layout(push_constant) uniform PushConstants {
+    float radius;
+} push_constants;
+
+// 3x3 tent filter over the coarser level, added to the level of the same size
+void main() {
+    vec2 offset = push_constants.radius / vec2(textureSize(coarserSampler, 0));
+
+    vec3 coarser = texture(coarserSampler, in_uv).rgb * 4.0;
+    coarser += (texture(coarserSampler, in_uv + vec2(-offset.x, 0.0)).rgb
+        + texture(coarserSampler, in_uv + vec2(offset.x, 0.0)).rgb
+        + texture(coarserSampler, in_uv + vec2(0.0, -offset.y)).rgb
+        + texture(coarserSampler, in_uv + vec2(0.0, offset.y)).rgb) * 2.0;
+    coarser += texture(coarserSampler, in_uv + vec2(-offset.x, -offset.y)).rgb
+        + texture(coarserSampler, in_uv + vec2(offset.x, -offset.y)).rgb
+        + texture(coarserSampler, in_uv + vec2(-offset.x, offset.y)).rgb
+        + texture(coarserSampler, in_uv + vec2(offset.x, offset.y)).rgb;
+
+    out_color = vec4(texture(currentSampler, in_uv).rgb + coarser / 16.0, 1.0);
+}
@@ -1,2 +0,0 @@
-glslangValidator ./main.vert -V100 -g -o ../build/main.vert.spv
-glslangValidator ./main.frag -V100 -g -o ../build/main.frag.spv
@@ -0,0 +1,142 @@
+#version 450
+
+layout(location = 0) in vec2 in_uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 0) uniform sampler2D ldrSampler;
+
+layout(push_constant) uniform PushConstants {
+    uint enabled;
+} push_constants;
+
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float EDGE_THRESHOLD_MAX = 0.125;
+const float SUBPIXEL_QUALITY = 0.75;
+const int SEARCH_STEPS = 10;
+
+// Perceptual luma, the LDR image stores linear values decoded from sRGB
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+float luma_at(vec2 uv) {
+    return luma(texture(ldrSampler, uv).rgb);
+}
+
+void main() {
+    vec3 color = texture(ldrSampler, in_uv).rgb;
+
+    if (push_constants.enabled == 0) {
+        out_color = vec4(color, 1.0);
+        return;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(ldrSampler, 0));
+
+    float luma_center = luma(color);
+    float luma_down = luma_at(in_uv + vec2(0.0, texel.y));
+    float luma_up = luma_at(in_uv - vec2(0.0, texel.y));
+    float luma_left = luma_at(in_uv - vec2(texel.x, 0.0));
+    float luma_right = luma_at(in_uv + vec2(texel.x, 0.0));
+
+    float luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    float luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    float luma_range = luma_max - luma_min;
+
+    if (luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        out_color = vec4(color, 1.0);
+        return;
+    }
+
+    float luma_down_left = luma_at(in_uv + vec2(-texel.x, texel.y));
+    float luma_up_right = luma_at(in_uv + vec2(texel.x, -texel.y));
+    float luma_up_left = luma_at(in_uv - texel);
+    float luma_down_right = luma_at(in_uv + texel);
+
+    float luma_down_up = luma_down + luma_up;
+    float luma_left_right = luma_left + luma_right;
+    float luma_left_corners = luma_down_left + luma_up_left;
+    float luma_down_corners = luma_down_left + luma_down_right;
+    float luma_right_corners = luma_down_right + luma_up_right;
+    float luma_up_corners = luma_up_right + luma_up_left;
+
+    float edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    float edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    bool horizontal = edge_horizontal >= edge_vertical;
+
+    float luma_negative = horizontal ? luma_up : luma_left;
+    float luma_positive = horizontal ? luma_down : luma_right;
+    float gradient_negative = abs(luma_negative - luma_center);
+    float gradient_positive = abs(luma_positive - luma_center);
+    bool negative_steepest = gradient_negative >= gradient_positive;
+    float gradient_scaled = 0.25 * max(gradient_negative, gradient_positive);
+
+    float step_length = horizontal ? texel.y : texel.x;
+    float luma_local_average;
+
+    if (negative_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_negative + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_positive + luma_center);
+    }
+
+    vec2 edge_uv = in_uv;
+    if (horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+
+    vec2 offset = horizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+    vec2 uv_negative = edge_uv - offset;
+    vec2 uv_positive = edge_uv + offset;
+    float luma_end_negative = luma_at(uv_negative) - luma_local_average;
+    float luma_end_positive = luma_at(uv_positive) - luma_local_average;
+    bool reached_negative = abs(luma_end_negative) >= gradient_scaled;
+    bool reached_positive = abs(luma_end_positive) >= gradient_scaled;
+
+    for (int i = 1; i < SEARCH_STEPS && !(reached_negative && reached_positive); i++) {
+        if (!reached_negative) {
+            uv_negative -= offset * 1.5;
+            luma_end_negative = luma_at(uv_negative) - luma_local_average;
+            reached_negative = abs(luma_end_negative) >= gradient_scaled;
+        }
+        if (!reached_positive) {
+            uv_positive += offset * 1.5;
+            luma_end_positive = luma_at(uv_positive) - luma_local_average;
+            reached_positive = abs(luma_end_positive) >= gradient_scaled;
+        }
+    }
+
+    float distance_negative = horizontal ? in_uv.x - uv_negative.x : in_uv.y - uv_negative.y;
+    float distance_positive = horizontal ? uv_positive.x - in_uv.x : uv_positive.y - in_uv.y;
+    bool negative_closer = distance_negative < distance_positive;
+    float edge_length = distance_negative + distance_positive;
+    float pixel_offset = -min(distance_negative, distance_positive) / edge_length + 0.5;
+
+    bool center_smaller = luma_center < luma_local_average;
+    bool correct_variation = ((negative_closer ? luma_end_negative : luma_end_positive) < 0.0)
+        != center_smaller;
+    float final_offset = correct_variation ? pixel_offset : 0.0;
+
+    float luma_average = (2.0 * (luma_down_up + luma_left_right) + luma_left_corners
+        + luma_right_corners) / 12.0;
+    float subpixel_offset = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    subpixel_offset = (-2.0 * subpixel_offset + 3.0) * subpixel_offset * subpixel_offset;
+    final_offset = max(final_offset, subpixel_offset * subpixel_offset * SUBPIXEL_QUALITY);
+
+    vec2 final_uv = in_uv;
+    if (horizontal) {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+
+    out_color = vec4(texture(ldrSampler, final_uv).rgb, 1.0);
+}
@@ -0,0 +1,31 @@
+#version 450
+
+layout(location = 0) in vec3 in_color;
+layout(location = 1) in vec2 in_texture_coords;
+layout(location = 2) in vec3 in_world_pos;
+layout(location = 3) in vec3 in_normal;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 0) uniform UBO {
+	mat4 view;
+	mat4 projection;
+} ubo;
+
+const vec3 TINT = vec3(0.2, 0.8, 1.0);
+const float SCANLINE_DENSITY = 80.0;
+
+// Meant for additive blending without depth writes: bright fresnel edges and horizontal
+// scanlines, the faces turned to the camera are almost transparent
+void main() {
+    vec3 camera_pos = inverse(ubo.view)[3].xyz;
+    vec3 n = normalize(in_normal);
+    vec3 v = normalize(camera_pos - in_world_pos);
+
+    float fresnel = pow(1.0 - abs(dot(n, v)), 2.0);
+    float scanline = 0.5 + 0.5 * sin(in_world_pos.z * SCANLINE_DENSITY);
+
+    float alpha = clamp(fresnel + scanline * 0.15, 0.0, 1.0);
+
+    out_color = vec4(TINT * (1.0 + fresnel * 2.0), alpha);
+}
@@ -2,11 +2,42 @@
 
 layout(location = 0) in vec3 in_color;
 layout(location = 1) in vec2 in_texture_coords;
+layout(location = 2) in vec3 in_world_pos;
+layout(location = 3) in vec3 in_normal;
 
 layout(location = 0) out vec4 out_color;
 
+layout(binding = 0) uniform UBO {
+	mat4 view;
+	mat4 projection;
+} ubo;
+
 layout(binding = 1) uniform sampler2D textureSampler;
+layout(binding = 2) uniform samplerCube irradianceSampler;
+layout(binding = 3) uniform samplerCube prefilteredSampler;
+layout(binding = 4) uniform sampler2D brdfLutSampler;
+
+const float ROUGHNESS = 0.5;
+const float METALLIC = 0.0;
+const float MAX_PREFILTERED_LOD = 4.0;
 
 void main() {
-    out_color = texture(textureSampler, in_texture_coords);
+    vec4 albedo = texture(textureSampler, in_texture_coords);
+
+    vec3 camera_pos = inverse(ubo.view)[3].xyz;
+    vec3 n = normalize(in_normal);
+    vec3 v = normalize(camera_pos - in_world_pos);
+    vec3 r = reflect(-v, n);
+    float n_dot_v = max(dot(n, v), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), albedo.rgb, METALLIC);
+    vec3 f = f0 + (max(vec3(1.0 - ROUGHNESS), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+    vec3 kd = (1.0 - f) * (1.0 - METALLIC);
+
+    vec3 diffuse = texture(irradianceSampler, n).rgb * albedo.rgb;
+    vec3 prefiltered = textureLod(prefilteredSampler, r, ROUGHNESS * MAX_PREFILTERED_LOD).rgb;
+    vec2 brdf = texture(brdfLutSampler, vec2(n_dot_v, ROUGHNESS)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    out_color = vec4(kd * diffuse + specular, albedo.a);
 }
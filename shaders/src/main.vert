@@ -3,21 +3,27 @@
 layout(location = 0) in vec3 in_pos;
 layout(location = 1) in vec3 in_color;
 layout(location = 2) in vec2 in_texture_coords;
+layout(location = 3) in vec3 in_normal;
+
+layout(location = 5) in mat4 in_model;
+layout(location = 9) in mat3 in_normal_matrix;
 
 layout(location = 0) out vec3 out_color;
 layout(location = 1) out vec2 out_texture_coords;
+layout(location = 2) out vec3 out_world_pos;
+layout(location = 3) out vec3 out_normal;
 
 layout(binding = 0) uniform UBO {
 	mat4 view;
 	mat4 projection;
 } ubo;
 
-layout(push_constant) uniform PushConstants {
-	mat4 model;
-} pc;
-
 void main() {
-    gl_Position = ubo.projection * ubo.view * pc.model * vec4(in_pos, 1.0);
+    vec4 world_pos = in_model * vec4(in_pos, 1.0);
+
+    gl_Position = ubo.projection * ubo.view * world_pos;
     out_color = in_color;
 	out_texture_coords = in_texture_coords;
+	out_world_pos = world_pos.xyz;
+	out_normal = in_normal_matrix * in_normal;
 }
@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) in vec3 in_direction;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 1) uniform samplerCube environmentSampler;
+
+void main() {
+    out_color = vec4(textureLod(environmentSampler, in_direction, 0.0).rgb, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) in vec3 in_pos;
+
+layout(location = 0) out vec3 out_direction;
+
+layout(binding = 0) uniform UBO {
+	mat4 view;
+	mat4 projection;
+} ubo;
+
+void main() {
+    vec4 pos = ubo.projection * mat4(mat3(ubo.view)) * vec4(in_pos, 1.0);
+
+    gl_Position = pos.xyww;
+    out_direction = in_pos;
+}
@@ -0,0 +1,153 @@
+#version 450
+
+layout(location = 0) in vec2 in_uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 0) uniform sampler2D hdrSampler;
+layout(binding = 1) uniform sampler2D bloomSampler;
+layout(binding = 2) uniform sampler3D colorLutSampler;
+
+layout(push_constant) uniform PushConstants {
+    float exposure;
+    uint tonemapper;
+    float bloom_intensity;
+    float vignette_intensity;
+    float vignette_smoothness;
+    float chromatic_aberration;
+    float color_grading;
+    vec4 color_lut_domain_min;
+    vec4 color_lut_domain_max;
+} push_constants;
+
+const uint REINHARD = 0;
+const uint ACES = 1;
+const uint AGX = 2;
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + color);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+vec3 aces(vec3 color) {
+    const mat3 input_matrix = mat3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777
+    );
+    const mat3 output_matrix = mat3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602
+    );
+
+    vec3 v = input_matrix * color;
+    vec3 a = v * (v + 0.0245786) - 0.000090537;
+    vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+
+    return clamp(output_matrix * (a / b), 0.0, 1.0);
+}
+
+// Polynomial approximation of the AgX base contrast curve
+vec3 agx_contrast(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 color) {
+    const mat3 inset_matrix = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    const mat3 outset_matrix = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    vec3 v = inset_matrix * color;
+    v = clamp(log2(max(v, 1e-10)), min_ev, max_ev);
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+    v = outset_matrix * v;
+
+    // The curve outputs display encoded values, the swapchain encodes again on write
+    return pow(max(v, 0.0), vec3(2.2));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(
+        color * 12.92,
+        1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055,
+        step(vec3(0.0031308), color)
+    );
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(
+        color / 12.92,
+        pow((color + 0.055) / 1.055, vec3(2.4)),
+        step(vec3(0.04045), color)
+    );
+}
+
+// Offsets red and blue away from the center along the radius
+vec3 sample_hdr(vec2 uv) {
+    vec2 offset = (uv - 0.5) * push_constants.chromatic_aberration;
+
+    return vec3(
+        texture(hdrSampler, uv + offset).r,
+        texture(hdrSampler, uv).g,
+        texture(hdrSampler, uv - offset).b
+    );
+}
+
+// .cube tables map display encoded colors
+vec3 grade(vec3 color) {
+    vec3 lut_size = vec3(textureSize(colorLutSampler, 0));
+    vec3 domain_min = push_constants.color_lut_domain_min.xyz;
+    vec3 domain_max = push_constants.color_lut_domain_max.xyz;
+
+    vec3 encoded = linear_to_srgb(clamp(color, 0.0, 1.0));
+    vec3 uvw = clamp((encoded - domain_min) / (domain_max - domain_min), 0.0, 1.0);
+    uvw = uvw * (lut_size - 1.0) / lut_size + 0.5 / lut_size;
+    vec3 graded = srgb_to_linear(texture(colorLutSampler, uvw).rgb);
+
+    return mix(color, graded, push_constants.color_grading);
+}
+
+float vignette(vec2 uv) {
+    float distance = length(uv - 0.5) * sqrt(2.0);
+    float smoothness = max(push_constants.vignette_smoothness, 0.001);
+    float falloff = smoothstep(1.0 - smoothness, 1.0, distance);
+
+    return 1.0 - falloff * push_constants.vignette_intensity;
+}
+
+void main() {
+    vec3 color = sample_hdr(in_uv);
+    // The bloom image is not rendered while bloom is disabled
+    if (push_constants.bloom_intensity > 0.0) {
+        color += texture(bloomSampler, in_uv).rgb * push_constants.bloom_intensity;
+    }
+    color *= push_constants.exposure;
+
+    if (push_constants.tonemapper == REINHARD) {
+        color = reinhard(color);
+    } else if (push_constants.tonemapper == ACES) {
+        color = aces(color);
+    } else {
+        color = agx(color);
+    }
+
+    if (push_constants.color_grading > 0.0) {
+        color = grade(color);
+    }
+
+    out_color = vec4(color * vignette(in_uv), 1.0);
+}
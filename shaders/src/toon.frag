@@ -0,0 +1,40 @@
+#version 450
+
+layout(location = 0) in vec3 in_color;
+layout(location = 1) in vec2 in_texture_coords;
+layout(location = 2) in vec3 in_world_pos;
+layout(location = 3) in vec3 in_normal;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 0) uniform UBO {
+	mat4 view;
+	mat4 projection;
+} ubo;
+
+layout(binding = 1) uniform sampler2D textureSampler;
+layout(binding = 2) uniform samplerCube irradianceSampler;
+
+const vec3 LIGHT_DIRECTION = vec3(0.5, 0.3, 1.0);
+const float BANDS = 3.0;
+const float RIM_THRESHOLD = 0.7;
+
+// Cel shading: the key light is quantized into bands and the environment only tints the
+// shadowed side, with a hard rim on silhouettes
+void main() {
+    vec4 albedo = texture(textureSampler, in_texture_coords);
+
+    vec3 camera_pos = inverse(ubo.view)[3].xyz;
+    vec3 n = normalize(in_normal);
+    vec3 v = normalize(camera_pos - in_world_pos);
+
+    float diffuse = max(dot(n, normalize(LIGHT_DIRECTION)), 0.0);
+    diffuse = floor(diffuse * BANDS + 0.5) / BANDS;
+
+    vec3 ambient = texture(irradianceSampler, n).rgb;
+    float rim = step(RIM_THRESHOLD, 1.0 - max(dot(n, v), 0.0)) * diffuse;
+
+    vec3 color = albedo.rgb * in_color * (ambient * 0.5 + diffuse) + rim * 0.3;
+
+    out_color = vec4(color, albedo.a);
+}
@@ -0,0 +1,16 @@
+#version 450
+
+layout(location = 0) in vec3 in_color;
+layout(location = 1) in vec2 in_texture_coords;
+layout(location = 2) in vec3 in_world_pos;
+layout(location = 3) in vec3 in_normal;
+
+layout(location = 0) out vec4 out_color;
+
+layout(binding = 1) uniform sampler2D textureSampler;
+
+void main() {
+    vec4 albedo = texture(textureSampler, in_texture_coords);
+
+    out_color = vec4(albedo.rgb * in_color, albedo.a);
+}
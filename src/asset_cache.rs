@@ -0,0 +1,780 @@
+use std::{
+    borrow::Cow,
+    convert::TryInto,
+    env,
+    fs::{self, File},
+    mem::{size_of, size_of_val},
+    path::{Path, PathBuf},
+    process,
+};
+
+use image::{imageops, imageops::FilterType, RgbaImage};
+use log::warn;
+use memmap2::Mmap;
+use nalgebra::Vector3;
+use vulkanalia::vk::{Format, IndexType};
+
+use crate::{
+    bounding_volume::{Aabb, BoundingSphere},
+    mesh::{Lod, Mesh, Submesh},
+    mesh_simplifier::LodSettings,
+    vertex::Vertex,
+};
+
+// Relative to the working directory, like the assets, unless set by this variable
+const CACHE_DIRECTORY_VARIABLE: &str = "CPYTE_ASSET_CACHE";
+const DEFAULT_CACHE_DIRECTORY: &str = "target/asset_cache";
+const MAGIC: &[u8; 4] = b"CPYA";
+const VERSION: u32 = 5;
+// Payloads start at a multiple of this, so that mapped vertices are aligned
+const PAYLOAD_ALIGNMENT: usize = 16;
+const COMPRESSED: u32 = 1;
+const TEXTURE_FORMAT: Format = Format::R8G8B8A8_SRGB;
+const TEXEL_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AssetKind {
+    Mesh,
+    Texture,
+}
+
+#[derive(Debug)]
+enum Storage {
+    Mapped(Mmap),
+    /// Decompressed or just imported.
+    Owned(Vec<u8>),
+}
+
+/// Data of an asset file that is uploaded as is.
+#[derive(Debug)]
+struct Payload {
+    storage: Storage,
+    offset: usize,
+    size: usize,
+}
+
+impl Payload {
+    fn bytes(&self) -> &[u8] {
+        let bytes = match &self.storage {
+            Storage::Mapped(mmap) => &mmap[..],
+            Storage::Owned(bytes) => bytes.as_slice(),
+        };
+
+        &bytes[self.offset..self.offset + self.size]
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CachedMesh {
+    pub(crate) vertex_count: u32,
+    /// Size of an index in bytes, 2 or 4.
+    pub(crate) index_width: u32,
+    pub(crate) lods: Vec<Lod>,
+    pub(crate) aabb: Aabb,
+    pub(crate) bounding_sphere: BoundingSphere,
+    /// Vertices followed by indices.
+    payload: Payload,
+}
+
+impl CachedMesh {
+    pub(crate) fn vertex_bytes(&self) -> &[u8] {
+        &self.payload.bytes()[..self.vertex_count as usize * size_of::<Vertex>()]
+    }
+
+    pub(crate) fn index_bytes(&self) -> &[u8] {
+        &self.payload.bytes()[self.vertex_count as usize * size_of::<Vertex>()..]
+    }
+
+    pub(crate) fn index_type(&self) -> IndexType {
+        if self.index_width == 2 {
+            IndexType::UINT16
+        } else {
+            IndexType::UINT32
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CachedTexture {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Mip levels one after another, starting with the base level.
+    payload: Payload,
+}
+
+impl CachedTexture {
+    pub(crate) fn texels(&self) -> &[u8] {
+        self.payload.bytes()
+    }
+}
+
+/// Engine format of meshes and textures, kept in `target/asset_cache` by the hash of the
+/// source file. Cached assets are memory mapped and uploaded without parsing OBJ text,
+/// decoding images or creating mip levels.
+///
+/// A file starts with a header of little endian fields: the magic `CPYA`, the version, the
+/// asset kind and flags. Meshes follow with their vertex layout, index width, vertex, index
+/// and submesh counts, submeshes, LOD count, LODs, bounding box and bounding sphere,
+/// textures with their format, extent and mip level count.
+/// The header ends with the size of the payload before and after compression, and the
+/// payload starts at the next multiple of 16 bytes.
+pub(crate) struct AssetCache;
+
+impl AssetCache {
+    /// Stable FNV-1a hash that names cached files.
+    pub(crate) fn content_hash(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    /// Mesh of a model file with the given hash, it is imported into the cache on the first
+    /// load. Engine files are read as they are, with the LODs they were converted with.
+    pub(crate) fn load_mesh(source_path: &Path, content_hash: u64) -> Result<CachedMesh, String> {
+        if Self::is_converted(source_path, AssetKind::Mesh) {
+            return Self::read_mesh(source_path);
+        }
+
+        let cache_path = Self::path(content_hash, AssetKind::Mesh);
+        if cache_path.exists() {
+            match Self::read_mesh(&cache_path) {
+                Ok(mesh) => return Ok(mesh),
+                Err(error) => warn!("Re-importing {}: {}", source_path.display(), error),
+            }
+        }
+
+        let bytes = Self::import(source_path, AssetKind::Mesh, &LodSettings::default(), false)?;
+        if let Err(error) = Self::save(&cache_path, &bytes) {
+            warn!("Failed to save {}: {}", cache_path.display(), error);
+        }
+
+        Self::parse_mesh(Storage::Owned(bytes))
+    }
+
+    /// Texture of an image file with the given hash, it is imported into the cache on the
+    /// first load.
+    pub(crate) fn load_texture(
+        source_path: &Path,
+        content_hash: u64,
+    ) -> Result<CachedTexture, String> {
+        if Self::is_converted(source_path, AssetKind::Texture) {
+            return Self::read_texture(source_path);
+        }
+
+        let cache_path = Self::path(content_hash, AssetKind::Texture);
+        if cache_path.exists() {
+            match Self::read_texture(&cache_path) {
+                Ok(texture) => return Ok(texture),
+                Err(error) => warn!("Re-importing {}: {}", source_path.display(), error),
+            }
+        }
+
+        let bytes = Self::import(
+            source_path,
+            AssetKind::Texture,
+            &LodSettings::default(),
+            false,
+        )?;
+        if let Err(error) = Self::save(&cache_path, &bytes) {
+            warn!("Failed to save {}: {}", cache_path.display(), error);
+        }
+
+        Self::parse_texture(Storage::Owned(bytes))
+    }
+
+    /// Reads an engine file, mapping it unless its payload is compressed.
+    pub(crate) fn read_mesh(path: &Path) -> Result<CachedMesh, String> {
+        Self::map(path).and_then(Self::parse_mesh)
+    }
+
+    pub(crate) fn read_texture(path: &Path) -> Result<CachedTexture, String> {
+        Self::map(path).and_then(Self::parse_texture)
+    }
+
+    /// Converts a model or image file to the engine format, compressing the payload if
+    /// asked to. Meshes are processed on the way, into the LODs of the settings.
+    pub(crate) fn import(
+        source_path: &Path,
+        kind: AssetKind,
+        lod_settings: &LodSettings,
+        compress: bool,
+    ) -> Result<Vec<u8>, String> {
+        match kind {
+            AssetKind::Mesh => {
+                let mut mesh = Mesh::load(source_path)?;
+                mesh.process(lod_settings);
+
+                Ok(Self::encode_mesh(
+                    &mesh.vertices,
+                    &mesh.indices,
+                    &mesh.submeshes,
+                    &mesh.lods,
+                    compress,
+                ))
+            }
+            AssetKind::Texture => {
+                let image = image::open(source_path).map_err(|error| error.to_string())?;
+
+                Ok(Self::encode_texture(&image.into_rgba8(), compress))
+            }
+        }
+    }
+
+    /// Number of mip levels down to 1 by 1 texel.
+    pub(crate) fn mip_level_count(width: u32, height: u32) -> u32 {
+        u32::BITS - width.max(height).max(1).leading_zeros()
+    }
+
+    pub(crate) fn encode_mesh(
+        vertices: &[Vertex],
+        indices: &[u32],
+        submeshes: &[Submesh],
+        lods: &[Lod],
+        compress: bool,
+    ) -> Vec<u8> {
+        let attribute_descriptions = Vertex::attribute_descriptions();
+        let binding_description = Vertex::binding_description();
+        // The largest 16-bit index is left out as it restarts primitives when that is enabled
+        let index_width = if vertices.len() <= u16::MAX as usize {
+            size_of::<u16>()
+        } else {
+            size_of::<u32>()
+        };
+
+        let mut metadata = vec![
+            binding_description.stride,
+            attribute_descriptions.len() as u32,
+        ];
+        attribute_descriptions.iter().for_each(|attribute| {
+            metadata.extend([
+                attribute.location,
+                attribute.format.as_raw() as u32,
+                attribute.offset,
+            ])
+        });
+        metadata.extend([
+            index_width as u32,
+            vertices.len() as u32,
+            indices.len() as u32,
+            submeshes.len() as u32,
+        ]);
+        submeshes
+            .iter()
+            .for_each(|submesh| metadata.extend([submesh.index_offset, submesh.index_count]));
+        metadata.push(lods.len() as u32);
+        lods.iter().for_each(|lod| {
+            metadata.extend([lod.index_offset, lod.index_count, lod.error.to_bits()])
+        });
+        let aabb = Aabb::new(vertices);
+        let bounding_sphere = BoundingSphere::new(vertices);
+        metadata.extend(
+            aabb.min
+                .iter()
+                .chain(aabb.max.iter())
+                .chain(bounding_sphere.center.iter())
+                .chain([bounding_sphere.radius].iter())
+                .map(|value| value.to_bits()),
+        );
+
+        let mut payload = Vec::with_capacity(size_of_val(vertices) + indices.len() * index_width);
+        payload.extend_from_slice(unsafe { vertices.align_to::<u8>().1 });
+        if index_width == size_of::<u16>() {
+            indices
+                .iter()
+                .for_each(|index| payload.extend((*index as u16).to_le_bytes()));
+        } else {
+            indices
+                .iter()
+                .for_each(|index| payload.extend(index.to_le_bytes()));
+        }
+
+        Self::encode(AssetKind::Mesh, &metadata, &payload, compress)
+    }
+
+    /// Stores the image with every mip level, which are made with a triangle filter.
+    pub(crate) fn encode_texture(image: &RgbaImage, compress: bool) -> Vec<u8> {
+        let mip_level_count = Self::mip_level_count(image.width(), image.height());
+
+        let mut payload = image.as_raw().clone();
+        let mut mip_level = image.clone();
+        for level in 1..mip_level_count {
+            mip_level = imageops::resize(
+                &mip_level,
+                (image.width() >> level).max(1),
+                (image.height() >> level).max(1),
+                FilterType::Triangle,
+            );
+            payload.extend_from_slice(mip_level.as_raw());
+        }
+
+        let metadata = [
+            TEXTURE_FORMAT.as_raw() as u32,
+            image.width(),
+            image.height(),
+            mip_level_count,
+        ];
+
+        Self::encode(AssetKind::Texture, &metadata, &payload, compress)
+    }
+
+    fn encode(kind: AssetKind, metadata: &[u32], payload: &[u8], compress: bool) -> Vec<u8> {
+        let stored_payload = if compress {
+            Cow::Owned(lz4_flex::compress(payload))
+        } else {
+            Cow::Borrowed(payload)
+        };
+
+        let mut bytes = MAGIC.to_vec();
+        [
+            VERSION,
+            Self::kind_code(kind),
+            if compress { COMPRESSED } else { 0 },
+        ]
+        .iter()
+        .chain(metadata)
+        .for_each(|field| bytes.extend(field.to_le_bytes()));
+        bytes.extend((payload.len() as u64).to_le_bytes());
+        bytes.extend((stored_payload.len() as u64).to_le_bytes());
+
+        bytes.resize(bytes.len().next_multiple_of(PAYLOAD_ALIGNMENT), 0);
+        bytes.extend_from_slice(&stored_payload);
+
+        bytes
+    }
+
+    fn parse_mesh(storage: Storage) -> Result<CachedMesh, String> {
+        let (mut reader, kind) = Reader::new(&storage)?;
+        if kind != Self::kind_code(AssetKind::Mesh) {
+            return Err("the file is not a mesh".to_string());
+        }
+
+        let attribute_descriptions = Vertex::attribute_descriptions();
+        let stride = reader.u32()?;
+        let attribute_count = reader.u32()? as usize;
+        let attributes = (0..attribute_count)
+            .map(|_| Ok((reader.u32()?, reader.u32()?, reader.u32()?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let layout_matches = stride == Vertex::binding_description().stride
+            && attributes.len() == attribute_descriptions.len()
+            && attributes.iter().zip(attribute_descriptions.iter()).all(
+                |((location, format, offset), attribute)| {
+                    *location == attribute.location
+                        && *format == attribute.format.as_raw() as u32
+                        && *offset == attribute.offset
+                },
+            );
+        if !layout_matches {
+            return Err("the vertex layout has changed".to_string());
+        }
+
+        let index_width = reader.u32()?;
+        if index_width != 2 && index_width != 4 {
+            return Err(format!("indices can't be {} bytes wide", index_width));
+        }
+        let vertex_count = reader.u32()?;
+        let index_count = reader.u32()?;
+        let submesh_count = reader.u32()?;
+        for _ in 0..submesh_count {
+            let (submesh_index_offset, submesh_index_count) = (reader.u32()?, reader.u32()?);
+            if submesh_index_offset as u64 + submesh_index_count as u64 > index_count as u64 {
+                return Err("a submesh is out of bounds".to_string());
+            }
+        }
+        let lod_count = reader.u32()?;
+        let lods = (0..lod_count)
+            .map(|_| {
+                let lod = Lod {
+                    index_offset: reader.u32()?,
+                    index_count: reader.u32()?,
+                    error: reader.f32()?,
+                };
+                if lod.index_offset as u64 + lod.index_count as u64 > index_count as u64 {
+                    return Err("a LOD is out of bounds".to_string());
+                }
+
+                Ok(lod)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if lods.is_empty() {
+            return Err("the mesh has no LODs".to_string());
+        }
+        let aabb = Aabb {
+            min: reader.vector3()?,
+            max: reader.vector3()?,
+        };
+        let bounding_sphere = BoundingSphere {
+            center: reader.vector3()?,
+            radius: reader.f32()?,
+        };
+
+        let payload = reader.payload()?;
+        // In 64 bits so that corrupted counts can't overflow
+        let payload_size = vertex_count as u64 * size_of::<Vertex>() as u64
+            + index_count as u64 * index_width as u64;
+        if payload.size as u64 != payload_size {
+            return Err("the payload doesn't fit the counts".to_string());
+        }
+
+        Ok(CachedMesh {
+            vertex_count,
+            index_width,
+            lods,
+            aabb,
+            bounding_sphere,
+            payload: Self::payload(storage, payload)?,
+        })
+    }
+
+    fn parse_texture(storage: Storage) -> Result<CachedTexture, String> {
+        let (mut reader, kind) = Reader::new(&storage)?;
+        if kind != Self::kind_code(AssetKind::Texture) {
+            return Err("the file is not a texture".to_string());
+        }
+
+        if reader.u32()? != TEXTURE_FORMAT.as_raw() as u32 {
+            return Err("the texture format has changed".to_string());
+        }
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let mip_level_count = reader.u32()?;
+        if mip_level_count != Self::mip_level_count(width, height) {
+            return Err("the texture has missing mip levels".to_string());
+        }
+
+        let payload = reader.payload()?;
+        let payload_size = (0..mip_level_count)
+            .map(|level| {
+                (width >> level).max(1) as u64 * (height >> level).max(1) as u64 * TEXEL_SIZE as u64
+            })
+            .sum::<u64>();
+        if payload.size as u64 != payload_size {
+            return Err("the payload doesn't fit the extent".to_string());
+        }
+
+        Ok(CachedTexture {
+            width,
+            height,
+            payload: Self::payload(storage, payload)?,
+        })
+    }
+
+    /// Decompresses the payload if it is compressed.
+    fn payload(storage: Storage, layout: PayloadLayout) -> Result<Payload, String> {
+        if !layout.compressed {
+            return Ok(Payload {
+                storage,
+                offset: layout.offset,
+                size: layout.size,
+            });
+        }
+
+        let stored = match &storage {
+            Storage::Mapped(mmap) => &mmap[..],
+            Storage::Owned(bytes) => bytes.as_slice(),
+        };
+        let bytes = lz4_flex::decompress(
+            &stored[layout.offset..layout.offset + layout.stored_size],
+            layout.size,
+        )
+        .map_err(|error| error.to_string())?;
+
+        Ok(Payload {
+            storage: Storage::Owned(bytes),
+            offset: 0,
+            size: layout.size,
+        })
+    }
+
+    fn kind_code(kind: AssetKind) -> u32 {
+        match kind {
+            AssetKind::Mesh => 0,
+            AssetKind::Texture => 1,
+        }
+    }
+
+    /// Extension of engine files.
+    pub(crate) fn extension(kind: AssetKind) -> &'static str {
+        match kind {
+            AssetKind::Mesh => "mesh",
+            AssetKind::Texture => "texture",
+        }
+    }
+
+    fn is_converted(path: &Path, kind: AssetKind) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(Self::extension(kind)))
+    }
+
+    fn path(content_hash: u64, kind: AssetKind) -> PathBuf {
+        let directory = env::var_os(CACHE_DIRECTORY_VARIABLE)
+            .map_or_else(|| PathBuf::from(DEFAULT_CACHE_DIRECTORY), PathBuf::from);
+
+        directory.join(format!("{:016x}.{}", content_hash, Self::extension(kind)))
+    }
+
+    fn map(path: &Path) -> Result<Storage, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+
+        // Cached files are only replaced through a rename, never written in place
+        unsafe { Mmap::map(&file) }
+            .map(Storage::Mapped)
+            .map_err(|error| error.to_string())
+    }
+
+    /// Writes to a temporary file first so that an interrupted save, or another process
+    /// reading the file, never sees a truncated one.
+    pub(crate) fn save(path: &Path, bytes: &[u8]) -> Result<(), String> {
+        let temporary_path = path.with_extension(format!("{}.tmp", process::id()));
+
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temporary_path, bytes))
+            .and_then(|_| fs::rename(&temporary_path, path))
+            .map_err(|error| error.to_string())
+    }
+}
+
+#[derive(Debug)]
+struct PayloadLayout {
+    offset: usize,
+    size: usize,
+    stored_size: usize,
+    compressed: bool,
+}
+
+/// Reads the little endian fields of a header.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    flags: u32,
+}
+
+impl<'a> Reader<'a> {
+    /// Reader positioned after the common header, and the asset kind.
+    fn new(storage: &'a Storage) -> Result<(Self, u32), String> {
+        let bytes = match storage {
+            Storage::Mapped(mmap) => &mmap[..],
+            Storage::Owned(bytes) => bytes.as_slice(),
+        };
+        if !bytes.starts_with(MAGIC) {
+            return Err("the file is not an engine asset".to_string());
+        }
+
+        let mut reader = Self {
+            bytes,
+            offset: MAGIC.len(),
+            flags: 0,
+        };
+
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("version {} is not {}", version, VERSION));
+        }
+        let kind = reader.u32()?;
+        reader.flags = reader.u32()?;
+
+        Ok((reader, kind))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let field = self
+            .bytes
+            .get(self.offset..self.offset + size_of::<u32>())
+            .ok_or_else(|| "the file is truncated".to_string())?;
+        self.offset += size_of::<u32>();
+
+        Ok(u32::from_le_bytes(field.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn vector3(&mut self) -> Result<Vector3<f32>, String> {
+        Ok(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    /// Reads the payload sizes that end the header.
+    fn payload(mut self) -> Result<PayloadLayout, String> {
+        let size = self.u64()? as usize;
+        let stored_size = self.u64()? as usize;
+
+        let compressed = self.flags & COMPRESSED != 0;
+        if !compressed && stored_size != size {
+            return Err("the payload sizes differ".to_string());
+        }
+        let offset = self.offset.next_multiple_of(PAYLOAD_ALIGNMENT);
+        if offset
+            .checked_add(stored_size)
+            .is_none_or(|end| end > self.bytes.len())
+        {
+            return Err("the file is truncated".to_string());
+        }
+
+        Ok(PayloadLayout {
+            offset,
+            size,
+            stored_size,
+            compressed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use image::{Rgba, RgbaImage};
+    use itertools::Itertools;
+    use nalgebra::{Vector2, Vector3};
+
+    use super::{AssetCache, Storage, VERSION};
+    use crate::{
+        mesh::{Lod, Submesh},
+        vertex::Vertex,
+    };
+
+    fn triangle() -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+            .iter()
+            .map(|(x, y)| {
+                Vertex::new(
+                    Vector3::new(*x, *y, 0.0),
+                    Vector3::repeat(1.0),
+                    Vector2::new(*x, *y),
+                    Vector3::z(),
+                )
+            })
+            .collect_vec();
+
+        (vertices, vec![0, 1, 2])
+    }
+
+    fn encode_triangle(compress: bool) -> Vec<u8> {
+        let (vertices, indices) = triangle();
+        let submeshes = [Submesh {
+            index_offset: 0,
+            index_count: 3,
+        }];
+        let lods = [Lod {
+            index_offset: 0,
+            index_count: 3,
+            error: 0.0,
+        }];
+
+        AssetCache::encode_mesh(&vertices, &indices, &submeshes, &lods, compress)
+    }
+
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(4, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    #[test]
+    fn mesh_round_trip() {
+        let (vertices, indices) = triangle();
+
+        for compress in [false, true] {
+            let mesh = AssetCache::parse_mesh(Storage::Owned(encode_triangle(compress))).unwrap();
+
+            assert_eq!(mesh.vertex_count, 3);
+            assert_eq!(mesh.index_width, 2);
+            assert_eq!(mesh.vertex_bytes(), unsafe { vertices.align_to::<u8>().1 });
+            let parsed_indices = mesh
+                .index_bytes()
+                .chunks_exact(2)
+                .map(|index| u16::from_le_bytes([index[0], index[1]]) as u32)
+                .collect_vec();
+            assert_eq!(parsed_indices, indices);
+            assert_eq!(mesh.lods.len(), 1);
+            assert_eq!(mesh.aabb.max, Vector3::new(1.0, 1.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn empty_mesh_round_trip() {
+        let lods = [Lod {
+            index_offset: 0,
+            index_count: 0,
+            error: 0.0,
+        }];
+        let bytes = AssetCache::encode_mesh(&[], &[], &[], &lods, false);
+
+        let mesh = AssetCache::parse_mesh(Storage::Owned(bytes)).unwrap();
+
+        assert_eq!(mesh.vertex_count, 0);
+        assert!(mesh.vertex_bytes().is_empty());
+        assert!(mesh.index_bytes().is_empty());
+    }
+
+    #[test]
+    fn texture_round_trip() {
+        let image = image();
+
+        for compress in [false, true] {
+            let bytes = AssetCache::encode_texture(&image, compress);
+            let texture = AssetCache::parse_texture(Storage::Owned(bytes)).unwrap();
+
+            assert_eq!((texture.width, texture.height), (4, 2));
+            // 4 by 2, 2 by 1 and 1 by 1 texels
+            assert_eq!(texture.texels().len(), (8 + 2 + 1) * 4);
+            assert!(texture.texels().starts_with(image.as_raw()));
+        }
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        for compress in [false, true] {
+            let mesh_bytes = encode_triangle(compress);
+            for length in 0..mesh_bytes.len() {
+                let bytes = mesh_bytes[..length].to_vec();
+                assert!(AssetCache::parse_mesh(Storage::Owned(bytes)).is_err());
+            }
+
+            let texture_bytes = AssetCache::encode_texture(&image(), compress);
+            for length in 0..texture_bytes.len() {
+                let bytes = texture_bytes[..length].to_vec();
+                assert!(AssetCache::parse_texture(Storage::Owned(bytes)).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn other_versions_and_kinds_are_refused() {
+        let mut bytes = encode_triangle(false);
+        assert!(AssetCache::parse_texture(Storage::Owned(bytes.clone())).is_err());
+
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(AssetCache::parse_mesh(Storage::Owned(bytes)).is_err());
+    }
+
+    #[test]
+    fn counts_that_disagree_with_the_payload_are_refused() {
+        // After the magic, version, kind, flags, vertex layout and index width
+        let vertex_count_offset =
+            4 + 3 * 4 + 2 * 4 + Vertex::attribute_descriptions().len() * 3 * 4 + 4;
+
+        for vertex_count in [2, 4, u32::MAX] {
+            let mut bytes = encode_triangle(false);
+            bytes[vertex_count_offset..vertex_count_offset + size_of::<u32>()]
+                .copy_from_slice(&vertex_count.to_le_bytes());
+
+            assert_eq!(
+                AssetCache::parse_mesh(Storage::Owned(bytes)).unwrap_err(),
+                "the payload doesn't fit the counts"
+            );
+        }
+    }
+
+    #[test]
+    fn mip_level_count() {
+        assert_eq!(AssetCache::mip_level_count(0, 0), 1);
+        assert_eq!(AssetCache::mip_level_count(1, 1), 1);
+        assert_eq!(AssetCache::mip_level_count(4, 2), 3);
+        assert_eq!(AssetCache::mip_level_count(1, 256), 9);
+    }
+}
@@ -0,0 +1,681 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs,
+    hash::{Hash, Hasher},
+    iter,
+    marker::PhantomData,
+    path::PathBuf,
+    rc::Rc,
+};
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use log::{info, warn};
+use vulkanalia::vk::{
+    BufferUsageFlags, Extent3D, HasBuilder, IndexType, MemoryPropertyFlags, SampleCountFlags,
+};
+
+use crate::{
+    asset_cache::AssetKind,
+    asset_streamer::{AssetStreamer, StreamedAsset},
+    asset_watcher::AssetWatcher,
+    bounding_volume::{Aabb, BoundingSphere},
+    buffer::Buffer,
+    descriptor_pool::DescriptorPool,
+    descriptor_set::DescriptorSet,
+    device::Device,
+    image::Image,
+    image_based_lighting::ImageBasedLighting,
+    instance::Instance,
+    mesh::Lod,
+    mesh_pool::{MeshPool, PooledMesh},
+    model::Model,
+    physical_device::PhysicalDevice,
+    pipeline::Pipeline,
+    renderable::Renderable,
+    renderer::MAX_FLIGHT_FRAMES_COUNT,
+    sampler::Sampler,
+    texture::Texture,
+    ubo::Ubo,
+    upload_batcher::UploadBatcher,
+    vertex::Vertex,
+};
+
+// Textures that can be loaded at once, each one needs a descriptor set and another one
+// while it is re-imported
+const MAX_TEXTURE_COUNT: usize = 1024;
+
+/// Typed reference to an asset of the [`AssetServer`], the asset is freed once every handle
+/// to it has been dropped.
+pub(crate) struct Handle<T> {
+    id: Rc<usize>,
+    asset_type: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(id: usize) -> Self {
+        Self {
+            id: Rc::new(id),
+            asset_type: PhantomData,
+        }
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        *self.id
+    }
+
+    // Only the asset server holds it
+    fn is_unused(&self) -> bool {
+        Rc::strong_count(&self.id) == 1
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: Rc::clone(&self.id),
+            asset_type: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for Handle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.id()).finish()
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+/// Vertex and index buffers of a mesh.
+#[derive(Clone, Debug)]
+pub(crate) struct GpuMesh {
+    pub(crate) vertex_buffer: Buffer<Vertex>,
+    /// 16 or 32-bit indices, as told by `index_type`.
+    pub(crate) index_buffer: Buffer<u8>,
+    pub(crate) index_type: IndexType,
+    pub(crate) vertex_count: u32,
+    /// Indices of every LOD.
+    pub(crate) index_count: u32,
+    /// From the full mesh to the simplest one.
+    pub(crate) lods: Vec<Lod>,
+    pub(crate) aabb: Aabb,
+    pub(crate) bounding_sphere: BoundingSphere,
+    /// Copy in the mesh pool, when there is one.
+    pub(crate) pooled: Option<PooledMesh>,
+}
+
+impl GpuMesh {
+    pub(crate) fn destroy(&self) {
+        self.index_buffer.destroy();
+        self.vertex_buffer.destroy();
+    }
+}
+
+/// Image of a texture and the sets the default pipeline samples it through.
+#[derive(Clone, Debug)]
+pub(crate) struct GpuTexture {
+    pub(crate) image: Image,
+    pub(crate) sampler: Sampler,
+    /// One by swapchain image, with the uniform buffer of the image.
+    pub(crate) descriptor_sets: Vec<DescriptorSet>,
+}
+
+impl GpuTexture {
+    fn destroy(&self, descriptor_pool: &DescriptorPool) {
+        self.descriptor_sets
+            .iter()
+            .for_each(|descriptor_set| descriptor_set.free(descriptor_pool.into()));
+        self.sampler.destroy();
+        self.image.destroy();
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    handle: Handle<T>,
+    path: PathBuf,
+    content_hash: Option<u64>,
+    /// Shared with the slots of files with the same contents, `None` while loading.
+    asset: Option<Rc<T>>,
+    /// Whether the file couldn't be loaded, it is requested again when it changes.
+    failed: bool,
+}
+
+/// Assets of one type by the id of their handle.
+#[derive(Debug)]
+struct Assets<T> {
+    slots: HashMap<usize, Slot<T>>,
+    ids_by_path: HashMap<PathBuf, usize>,
+    /// Loaded assets by the hash of their file.
+    ids_by_content_hash: HashMap<u64, usize>,
+    /// Assets nothing uses anymore and the frame they were dropped in.
+    dropped: Vec<(u64, T)>,
+    next_id: usize,
+}
+
+impl<T> Assets<T> {
+    fn new() -> Self {
+        Self {
+            slots: HashMap::new(),
+            ids_by_path: HashMap::new(),
+            ids_by_content_hash: HashMap::new(),
+            dropped: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Handle of the asset of a file and whether the file has to be loaded.
+    fn get_or_insert(&mut self, path: PathBuf) -> (Handle<T>, bool) {
+        if let Some(id) = self.ids_by_path.get(&path) {
+            return (self.slots[id].handle.clone(), false);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let handle = Handle::new(id);
+        self.ids_by_path.insert(path.clone(), id);
+        self.slots.insert(
+            id,
+            Slot {
+                handle: handle.clone(),
+                path,
+                content_hash: None,
+                asset: None,
+                failed: false,
+            },
+        );
+
+        (handle, true)
+    }
+
+    fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        self.slots.get(&handle.id())?.asset.as_deref()
+    }
+
+    fn is_failed(&self, handle: &Handle<T>) -> bool {
+        self.slots.get(&handle.id()).is_some_and(|slot| slot.failed)
+    }
+
+    /// Marks an asset whose file couldn't be loaded. A failed re-import keeps the previous
+    /// asset.
+    fn set_failed(&mut self, id: usize) {
+        if let Some(slot) = self.slots.get_mut(&id) {
+            slot.failed = slot.asset.is_none();
+        }
+    }
+
+    /// Gives a decoded asset the loaded asset with the same contents, returns whether it
+    /// still has to be uploaded.
+    fn share_loaded(&mut self, id: usize, content_hash: u64, frame_count: u64) -> bool {
+        let slot = match self.slots.get(&id) {
+            Some(slot) => slot,
+            // Dropped while it was being re-imported
+            None => return false,
+        };
+        // Re-imported without changes
+        if slot.asset.is_some() && slot.content_hash == Some(content_hash) {
+            return false;
+        }
+
+        let loaded = self
+            .ids_by_content_hash
+            .get(&content_hash)
+            .and_then(|id| self.slots[id].asset.clone());
+
+        match loaded {
+            Some(asset) => {
+                self.replace(id, content_hash, asset, frame_count);
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn insert_loaded(&mut self, id: usize, content_hash: u64, asset: T, frame_count: u64) {
+        if self.slots.contains_key(&id) {
+            self.replace(id, content_hash, Rc::new(asset), frame_count);
+        } else {
+            self.dropped.push((frame_count, asset));
+        }
+    }
+
+    /// Swaps the asset of a slot, handles to it stay valid. The previous asset is dropped
+    /// unless another slot shares it.
+    fn replace(&mut self, id: usize, content_hash: u64, asset: Rc<T>, frame_count: u64) {
+        let slot = self.slots.get_mut(&id).unwrap();
+        let previous_content_hash = slot.content_hash.replace(content_hash);
+        let previous_asset = slot.asset.replace(asset);
+        slot.failed = false;
+
+        if let Some(previous_content_hash) = previous_content_hash {
+            self.forget_content_hash(id, previous_content_hash);
+        }
+        self.ids_by_content_hash.entry(content_hash).or_insert(id);
+
+        if let Some(Ok(previous_asset)) = previous_asset.map(Rc::try_unwrap) {
+            self.dropped.push((frame_count, previous_asset));
+        }
+    }
+
+    fn forget_content_hash(&mut self, id: usize, content_hash: u64) {
+        if self.ids_by_content_hash.get(&content_hash) != Some(&id) {
+            return;
+        }
+
+        self.ids_by_content_hash.remove(&content_hash);
+
+        // Another file with the same contents may still share the asset
+        if let Some((other_id, _)) = self.slots.iter().find(|(other_id, other)| {
+            **other_id != id && other.content_hash == Some(content_hash) && other.asset.is_some()
+        }) {
+            self.ids_by_content_hash.insert(content_hash, *other_id);
+        }
+    }
+
+    /// Forgets the loaded and failed assets that only the server has a handle to. Assets
+    /// still loading are kept, they are collected once they have arrived. Re-imports of
+    /// forgotten assets are dropped when they arrive, and failed files are requested again
+    /// when they are next used.
+    fn collect_unused(&mut self, frame_count: u64) {
+        let unused_ids = self
+            .slots
+            .iter()
+            .filter(|(_, slot)| (slot.asset.is_some() || slot.failed) && slot.handle.is_unused())
+            .map(|(id, _)| *id)
+            .collect_vec();
+
+        for id in unused_ids {
+            if let Some(content_hash) = self.slots[&id].content_hash {
+                self.forget_content_hash(id, content_hash);
+            }
+
+            let slot = self.slots.remove(&id).unwrap();
+            self.ids_by_path.remove(&slot.path);
+
+            if let Some(Ok(asset)) = slot.asset.map(Rc::try_unwrap) {
+                self.dropped.push((frame_count, asset));
+            }
+        }
+    }
+
+    /// Dropped assets that no frame in flight can use anymore.
+    fn take_destroyable(&mut self, frame_count: u64) -> Vec<T> {
+        let (destroyable, dropped) =
+            self.dropped
+                .drain(..)
+                .partition::<Vec<_>, _>(|(dropped_frame, _)| {
+                    dropped_frame + MAX_FLIGHT_FRAMES_COUNT as u64 <= frame_count
+                });
+        self.dropped = dropped;
+
+        destroyable
+            .into_iter()
+            .map(|(_, asset)| asset)
+            .collect_vec()
+    }
+
+    /// Every asset, each one once even if it is shared.
+    fn take_all(&mut self) -> Vec<T> {
+        let mut assets = self.dropped.drain(..).map(|(_, asset)| asset).collect_vec();
+
+        self.ids_by_path.clear();
+        self.ids_by_content_hash.clear();
+        assets.extend(
+            self.slots
+                .drain()
+                .filter_map(|(_, slot)| Rc::try_unwrap(slot.asset?).ok()),
+        );
+
+        assets
+    }
+}
+
+/// Loads meshes and textures once per file, entities using the same file or a copy of it
+/// share the GPU resources. Files that change on disk are re-imported and swapped in.
+#[derive(Debug)]
+pub(crate) struct AssetServer {
+    meshes: Assets<GpuMesh>,
+    textures: Assets<GpuTexture>,
+    /// Drawn in place of meshes that are still loading.
+    pub(crate) placeholder_mesh: GpuMesh,
+    /// Drawn in place of textures that are still loading and for entities without one.
+    pub(crate) placeholder_texture: GpuTexture,
+    /// Every mesh, for indirect draws.
+    pub(crate) mesh_pool: Option<MeshPool>,
+    asset_streamer: AssetStreamer,
+    asset_watcher: AssetWatcher,
+    descriptor_pool: DescriptorPool,
+    frame_count: u64,
+    device: Device,
+}
+
+impl AssetServer {
+    pub(crate) fn new(
+        pipeline: &Pipeline,
+        uniform_buffers: &[Buffer<Ubo>],
+        image_based_lighting: &ImageBasedLighting,
+        device: Device,
+        instance: Instance,
+        physical_device: PhysicalDevice,
+        upload_batcher: &mut UploadBatcher,
+    ) -> Self {
+        let descriptor_pool = DescriptorPool::new_freeable(
+            device.clone(),
+            &[(
+                &pipeline.reflection,
+                (2 * MAX_TEXTURE_COUNT + 1) * uniform_buffers.len(),
+            )],
+        );
+
+        let placeholder = Model::placeholder();
+        let placeholder_index_bytes = placeholder
+            .indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect_vec();
+        let placeholder_index_buffer = Buffer::new(
+            placeholder_index_bytes.len() as u64,
+            BufferUsageFlags::INDEX_BUFFER
+                | BufferUsageFlags::TRANSFER_SRC
+                | BufferUsageFlags::TRANSFER_DST,
+            device.clone(),
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        placeholder_index_buffer.fill_bytes(&placeholder_index_bytes, upload_batcher);
+        let mut placeholder_mesh = GpuMesh {
+            vertex_buffer: Buffer::<Vertex>::from_vertices(
+                placeholder.vertices.as_slice(),
+                device.clone(),
+                upload_batcher,
+            ),
+            index_buffer: placeholder_index_buffer,
+            index_type: IndexType::UINT32,
+            vertex_count: placeholder.vertices.len() as u32,
+            index_count: placeholder.indices.len() as u32,
+            lods: vec![Lod {
+                index_offset: 0,
+                index_count: placeholder.indices.len() as u32,
+                error: 0.0,
+            }],
+            aabb: Aabb::new(&placeholder.vertices),
+            bounding_sphere: BoundingSphere::new(&placeholder.vertices),
+            pooled: None,
+        };
+
+        let mut mesh_pool = device
+            .indirect_drawing
+            .then(|| MeshPool::new(device.clone()));
+        if let Some(mesh_pool) = &mut mesh_pool {
+            placeholder_mesh.pooled = Some(mesh_pool.insert(&placeholder_mesh, 0, upload_batcher));
+        }
+
+        let placeholder_extent = Extent3D::builder()
+            .width(placeholder.texture.image.width())
+            .height(placeholder.texture.image.height())
+            .depth(1)
+            .build();
+        let placeholder_image = Texture::create_image(
+            placeholder_extent,
+            SampleCountFlags::_1,
+            device.clone(),
+            instance.clone(),
+            physical_device.clone(),
+        );
+        placeholder_image.fill(Rc::clone(&placeholder.texture.image), upload_batcher);
+        let placeholder_texture = Self::create_texture(
+            placeholder_image,
+            pipeline,
+            uniform_buffers,
+            image_based_lighting,
+            &descriptor_pool,
+            device.clone(),
+        );
+
+        let asset_streamer = AssetStreamer::new(device.clone(), instance, physical_device);
+
+        Self {
+            meshes: Assets::new(),
+            textures: Assets::new(),
+            placeholder_mesh,
+            placeholder_texture,
+            mesh_pool,
+            asset_streamer,
+            asset_watcher: AssetWatcher::new(),
+            descriptor_pool,
+            frame_count: 0,
+            device,
+        }
+    }
+
+    pub(crate) fn load_mesh(&mut self, path: &str) -> Handle<GpuMesh> {
+        let path = Self::canonical_path(path);
+        let (handle, is_new) = self.meshes.get_or_insert(path.clone());
+        if is_new {
+            self.asset_streamer
+                .request(handle.id(), AssetKind::Mesh, &path);
+            self.asset_watcher.watch(&path);
+        }
+
+        handle
+    }
+
+    pub(crate) fn load_texture(&mut self, path: &str) -> Handle<GpuTexture> {
+        let path = Self::canonical_path(path);
+        let (handle, is_new) = self.textures.get_or_insert(path.clone());
+        if is_new {
+            self.asset_streamer
+                .request(handle.id(), AssetKind::Texture, &path);
+            self.asset_watcher.watch(&path);
+        }
+
+        handle
+    }
+
+    /// `None` while the mesh is loading.
+    pub(crate) fn mesh(&self, handle: &Handle<GpuMesh>) -> Option<&GpuMesh> {
+        self.meshes.get(handle)
+    }
+
+    /// Mesh `renderable` is drawn with, the placeholder until its mesh and texture are
+    /// loaded.
+    pub(crate) fn drawn_mesh(&self, renderable: &Renderable) -> &GpuMesh {
+        renderable
+            .mesh
+            .as_ref()
+            .filter(|_| renderable.loaded)
+            .and_then(|mesh| self.mesh(mesh))
+            .unwrap_or(&self.placeholder_mesh)
+    }
+
+    /// Whether the mesh couldn't be loaded.
+    pub(crate) fn mesh_failed(&self, handle: &Handle<GpuMesh>) -> bool {
+        self.meshes.is_failed(handle)
+    }
+
+    /// `None` while the texture is loading.
+    pub(crate) fn texture(&self, handle: &Handle<GpuTexture>) -> Option<&GpuTexture> {
+        self.textures.get(handle)
+    }
+
+    /// Whether the texture couldn't be loaded.
+    pub(crate) fn texture_failed(&self, handle: &Handle<GpuTexture>) -> bool {
+        self.textures.is_failed(handle)
+    }
+
+    /// Moves the loading assets along, re-imports changed files and frees the assets that
+    /// are no longer used, it is called once per frame. The graphics queue takes arrived
+    /// assets over in `graphics_upload_batcher`, which has to be submitted before they are
+    /// drawn.
+    pub(crate) fn update(
+        &mut self,
+        pipeline: &Pipeline,
+        uniform_buffers: &[Buffer<Ubo>],
+        image_based_lighting: &ImageBasedLighting,
+        graphics_upload_batcher: &mut UploadBatcher,
+    ) {
+        self.frame_count += 1;
+        self.destroy_unused();
+        self.reimport_changed();
+
+        for (id, kind) in self.asset_streamer.failed() {
+            match kind {
+                AssetKind::Mesh => self.meshes.set_failed(id),
+                AssetKind::Texture => self.textures.set_failed(id),
+            }
+        }
+
+        let frame_count = self.frame_count;
+        for decoded_asset in self.asset_streamer.decoded() {
+            let (id, content_hash) = (decoded_asset.id, decoded_asset.content_hash);
+            let needs_upload = match decoded_asset.kind() {
+                AssetKind::Mesh => self.meshes.share_loaded(id, content_hash, frame_count),
+                AssetKind::Texture => self.textures.share_loaded(id, content_hash, frame_count),
+            };
+
+            if needs_upload {
+                self.asset_streamer.upload(decoded_asset);
+            }
+        }
+
+        for streamed_asset in self.asset_streamer.poll(graphics_upload_batcher) {
+            match streamed_asset {
+                StreamedAsset::Mesh {
+                    id,
+                    content_hash,
+                    mesh,
+                } => {
+                    let mut mesh = *mesh;
+                    if let Some(mesh_pool) = &mut self.mesh_pool {
+                        mesh.pooled =
+                            Some(mesh_pool.insert(&mesh, frame_count, graphics_upload_batcher));
+                    }
+
+                    self.meshes
+                        .insert_loaded(id, content_hash, mesh, frame_count)
+                }
+                StreamedAsset::Texture {
+                    id,
+                    content_hash,
+                    image,
+                } => {
+                    let texture = Self::create_texture(
+                        *image,
+                        pipeline,
+                        uniform_buffers,
+                        image_based_lighting,
+                        &self.descriptor_pool,
+                        self.device.clone(),
+                    );
+
+                    self.textures
+                        .insert_loaded(id, content_hash, texture, frame_count);
+                }
+            }
+        }
+    }
+
+    /// Loads changed files again, the assets are swapped once the new ones have arrived.
+    fn reimport_changed(&mut self) {
+        for path in self.asset_watcher.changed_paths() {
+            if let Some(id) = self.meshes.ids_by_path.get(&path) {
+                info!("Re-importing {}", path.display());
+                self.asset_streamer.request(*id, AssetKind::Mesh, &path);
+            }
+            if let Some(id) = self.textures.ids_by_path.get(&path) {
+                info!("Re-importing {}", path.display());
+                self.asset_streamer.request(*id, AssetKind::Texture, &path);
+            }
+        }
+    }
+
+    fn destroy_unused(&mut self) {
+        self.meshes.collect_unused(self.frame_count);
+        self.textures.collect_unused(self.frame_count);
+
+        for mesh in self.meshes.take_destroyable(self.frame_count) {
+            if let (Some(mesh_pool), Some(pooled)) = (&mut self.mesh_pool, &mesh.pooled) {
+                mesh_pool.free(pooled);
+            }
+            mesh.destroy();
+        }
+        if let Some(mesh_pool) = &mut self.mesh_pool {
+            mesh_pool.destroy_retired(self.frame_count);
+        }
+        self.textures
+            .take_destroyable(self.frame_count)
+            .iter()
+            .for_each(|texture| texture.destroy(&self.descriptor_pool));
+    }
+
+    fn canonical_path(path: &str) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|error| {
+            warn!("Failed to resolve {}: {}", path, error);
+            PathBuf::from(path)
+        })
+    }
+
+    fn create_texture(
+        image: Image,
+        pipeline: &Pipeline,
+        uniform_buffers: &[Buffer<Ubo>],
+        image_based_lighting: &ImageBasedLighting,
+        descriptor_pool: &DescriptorPool,
+        device: Device,
+    ) -> GpuTexture {
+        let sampler = Texture::create_sampler(device.clone(), image.mip_levels);
+        let descriptor_sets = uniform_buffers
+            .iter()
+            .map(|uniform_buffer| {
+                DescriptorSet::new(
+                    device.clone(),
+                    descriptor_pool.into(),
+                    pipeline,
+                    uniform_buffer.into(),
+                    image.view,
+                    sampler.clone(),
+                    image_based_lighting,
+                )
+            })
+            .collect_vec();
+
+        GpuTexture {
+            image,
+            sampler,
+            descriptor_sets,
+        }
+    }
+
+    pub(crate) fn destroy(&mut self) {
+        self.asset_streamer.destroy();
+
+        self.meshes
+            .take_all()
+            .iter()
+            .chain(iter::once(&self.placeholder_mesh))
+            .for_each(GpuMesh::destroy);
+        self.textures
+            .take_all()
+            .iter()
+            .chain(iter::once(&self.placeholder_texture))
+            .for_each(|texture| texture.destroy(&self.descriptor_pool));
+
+        if let Some(mesh_pool) = &mut self.mesh_pool {
+            mesh_pool.destroy();
+        }
+        self.descriptor_pool.destroy();
+    }
+}
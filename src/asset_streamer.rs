@@ -0,0 +1,403 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use itertools::Itertools;
+use log::error;
+use vulkanalia::vk::{
+    AccessFlags, BufferUsageFlags, Extent3D, HasBuilder, MemoryPropertyFlags, PipelineStageFlags,
+    SampleCountFlags,
+};
+
+use crate::{
+    asset_cache::{AssetCache, AssetKind, CachedMesh, CachedTexture},
+    asset_server::GpuMesh,
+    buffer::Buffer,
+    command_buffer::CommandBuffer,
+    command_pool::CommandPool,
+    device::Device,
+    image::Image,
+    instance::Instance,
+    physical_device::PhysicalDevice,
+    queue::Queue,
+    queue_family_index::QueueFamilyIndex,
+    texture::Texture,
+    upload_batcher::UploadBatcher,
+};
+
+const WORKER_COUNT: usize = 4;
+
+#[derive(Debug)]
+struct AssetRequest {
+    id: usize,
+    kind: AssetKind,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+enum DecodedData {
+    Mesh(CachedMesh),
+    Texture(CachedTexture),
+}
+
+/// Asset read from the cache by a worker, along with the hash of its file so that copies of
+/// loaded assets don't have to be uploaded.
+#[derive(Debug)]
+pub(crate) struct DecodedAsset {
+    pub(crate) id: usize,
+    pub(crate) content_hash: u64,
+    data: DecodedData,
+}
+
+impl DecodedAsset {
+    pub(crate) fn kind(&self) -> AssetKind {
+        match self.data {
+            DecodedData::Mesh(_) => AssetKind::Mesh,
+            DecodedData::Texture(_) => AssetKind::Texture,
+        }
+    }
+}
+
+/// Asset that is on the GPU.
+#[derive(Clone, Debug)]
+pub(crate) enum StreamedAsset {
+    Mesh {
+        id: usize,
+        content_hash: u64,
+        mesh: Box<GpuMesh>,
+    },
+    Texture {
+        id: usize,
+        content_hash: u64,
+        image: Box<Image>,
+    },
+}
+
+/// Loads assets without blocking rendering. Files are read from the asset cache on worker
+/// threads and uploaded through the transfer queue, then the graphics queue takes the
+/// resources over.
+#[derive(Debug)]
+pub(crate) struct AssetStreamer {
+    request_sender: Option<Sender<AssetRequest>>,
+    decoded_receiver: Receiver<DecodedAsset>,
+    /// Id and kind of the requests that couldn't be decoded.
+    failed_receiver: Receiver<(usize, AssetKind)>,
+    workers: Vec<JoinHandle<()>>,
+    upload_batcher: UploadBatcher,
+    /// Assets recorded since the last submit.
+    recorded: Vec<StreamedAsset>,
+    /// Assets whose upload was submitted and the number of the batch it is in.
+    uploading: Vec<(u64, StreamedAsset)>,
+    transfer_queue_family_index: u32,
+    graphics_queue_family_index: u32,
+    command_pool: CommandPool,
+    device: Device,
+    instance: Instance,
+    physical_device: PhysicalDevice,
+}
+
+impl AssetStreamer {
+    pub(crate) fn new(device: Device, instance: Instance, physical_device: PhysicalDevice) -> Self {
+        let (request_sender, request_receiver) = mpsc::channel::<AssetRequest>();
+        let (decoded_sender, decoded_receiver) = mpsc::channel();
+        let (failed_sender, failed_receiver) = mpsc::channel();
+
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+        let workers = (0..WORKER_COUNT)
+            .map(|_| {
+                let request_receiver = Arc::clone(&request_receiver);
+                let decoded_sender = decoded_sender.clone();
+                let failed_sender = failed_sender.clone();
+
+                thread::spawn(move || loop {
+                    let request = request_receiver.lock().unwrap().recv();
+                    let request = match request {
+                        Ok(request) => request,
+                        // The streamer is gone
+                        Err(_) => return,
+                    };
+
+                    match panic::catch_unwind(AssertUnwindSafe(|| Self::decode(&request))) {
+                        Ok(Ok(decoded_asset)) => {
+                            if decoded_sender.send(decoded_asset).is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                        Ok(Err(error)) => {
+                            error!("Failed to load {}: {}", request.path.display(), error)
+                        }
+                        Err(_) => error!("Failed to load {}", request.path.display()),
+                    }
+                    if failed_sender.send((request.id, request.kind)).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect_vec();
+
+        let transfer_queue_family_index =
+            QueueFamilyIndex::transfer(instance.clone(), physical_device.clone());
+        let graphics_queue_family_index =
+            QueueFamilyIndex::graphics(instance.clone(), physical_device.clone());
+
+        let command_pool = CommandPool::new(device.clone(), transfer_queue_family_index);
+        let transfer_queue = Queue::new(device.clone(), transfer_queue_family_index);
+        let upload_batcher =
+            UploadBatcher::new(device.clone(), command_pool.clone(), transfer_queue);
+
+        Self {
+            request_sender: Some(request_sender),
+            decoded_receiver,
+            failed_receiver,
+            workers,
+            upload_batcher,
+            recorded: Vec::new(),
+            uploading: Vec::new(),
+            transfer_queue_family_index,
+            graphics_queue_family_index,
+            command_pool,
+            device,
+            instance,
+            physical_device,
+        }
+    }
+
+    pub(crate) fn request(&self, id: usize, kind: AssetKind, path: &Path) {
+        if let Some(request_sender) = &self.request_sender {
+            request_sender
+                .send(AssetRequest {
+                    id,
+                    kind,
+                    path: path.to_path_buf(),
+                })
+                .unwrap();
+        }
+    }
+
+    fn decode(request: &AssetRequest) -> Result<DecodedAsset, String> {
+        let bytes = fs::read(&request.path).map_err(|error| error.to_string())?;
+        let content_hash = AssetCache::content_hash(&bytes);
+
+        let data = match request.kind {
+            AssetKind::Mesh => {
+                DecodedData::Mesh(AssetCache::load_mesh(&request.path, content_hash)?)
+            }
+            AssetKind::Texture => {
+                DecodedData::Texture(AssetCache::load_texture(&request.path, content_hash)?)
+            }
+        };
+
+        Ok(DecodedAsset {
+            id: request.id,
+            content_hash,
+            data,
+        })
+    }
+
+    /// Assets decoded since the last call, they are uploaded with [`Self::upload`].
+    pub(crate) fn decoded(&self) -> Vec<DecodedAsset> {
+        self.decoded_receiver.try_iter().collect_vec()
+    }
+
+    /// Id and kind of the requests that failed to load since the last call.
+    pub(crate) fn failed(&self) -> Vec<(usize, AssetKind)> {
+        self.failed_receiver.try_iter().collect_vec()
+    }
+
+    /// Starts uploading the assets given to [`Self::upload`] since the last call and returns
+    /// the ones that have arrived. The graphics queue takes those over in
+    /// `graphics_upload_batcher`, which has to be submitted before they are used.
+    pub(crate) fn poll(
+        &mut self,
+        graphics_upload_batcher: &mut UploadBatcher,
+    ) -> Vec<StreamedAsset> {
+        if !self.recorded.is_empty() {
+            let batch_number = self.upload_batcher.submit();
+
+            self.uploading
+                .extend(self.recorded.drain(..).map(|asset| (batch_number, asset)));
+        }
+
+        self.upload_batcher.retire_finished();
+
+        let upload_batcher = &self.upload_batcher;
+        let (uploaded, uploading) = self
+            .uploading
+            .drain(..)
+            .partition::<Vec<_>, _>(|(batch_number, _)| upload_batcher.is_finished(*batch_number));
+        self.uploading = uploading;
+
+        uploaded
+            .into_iter()
+            .map(|(_, asset)| {
+                graphics_upload_batcher
+                    .record(|command_buffer| self.acquire(&asset, command_buffer));
+
+                asset
+            })
+            .collect_vec()
+    }
+
+    fn acquire(&self, asset: &StreamedAsset, command_buffer: CommandBuffer) {
+        let (src_queue_family_index, dst_queue_family_index) = (
+            self.transfer_queue_family_index,
+            self.graphics_queue_family_index,
+        );
+
+        match asset {
+            // Also copied into the mesh pool when there is one
+            StreamedAsset::Mesh { mesh, .. } => {
+                mesh.vertex_buffer.acquire(
+                    command_buffer.clone(),
+                    src_queue_family_index,
+                    dst_queue_family_index,
+                    (
+                        PipelineStageFlags::VERTEX_INPUT | PipelineStageFlags::TRANSFER,
+                        AccessFlags::VERTEX_ATTRIBUTE_READ | AccessFlags::TRANSFER_READ,
+                    ),
+                );
+                mesh.index_buffer.acquire(
+                    command_buffer,
+                    src_queue_family_index,
+                    dst_queue_family_index,
+                    (
+                        PipelineStageFlags::VERTEX_INPUT | PipelineStageFlags::TRANSFER,
+                        AccessFlags::INDEX_READ | AccessFlags::TRANSFER_READ,
+                    ),
+                );
+            }
+            StreamedAsset::Texture { image, .. } => {
+                image.acquire(
+                    command_buffer.clone(),
+                    src_queue_family_index,
+                    dst_queue_family_index,
+                );
+                image.record_shader_read_layout(command_buffer);
+            }
+        }
+    }
+
+    /// Records the copies of an asset on the transfer queue and the release of its resources.
+    pub(crate) fn upload(&mut self, decoded_asset: DecodedAsset) {
+        let (src_queue_family_index, dst_queue_family_index) = (
+            self.transfer_queue_family_index,
+            self.graphics_queue_family_index,
+        );
+
+        let asset = match decoded_asset.data {
+            DecodedData::Mesh(cached_mesh) => {
+                let vertex_bytes = cached_mesh.vertex_bytes();
+                let index_bytes = cached_mesh.index_bytes();
+
+                let vertex_buffer = Buffer::new(
+                    vertex_bytes.len() as u64,
+                    BufferUsageFlags::VERTEX_BUFFER
+                        | BufferUsageFlags::TRANSFER_SRC
+                        | BufferUsageFlags::TRANSFER_DST,
+                    self.device.clone(),
+                    MemoryPropertyFlags::DEVICE_LOCAL,
+                );
+                let index_buffer = Buffer::new(
+                    index_bytes.len() as u64,
+                    BufferUsageFlags::INDEX_BUFFER
+                        | BufferUsageFlags::TRANSFER_SRC
+                        | BufferUsageFlags::TRANSFER_DST,
+                    self.device.clone(),
+                    MemoryPropertyFlags::DEVICE_LOCAL,
+                );
+
+                vertex_buffer.fill_bytes(vertex_bytes, &mut self.upload_batcher);
+                index_buffer.fill_bytes(index_bytes, &mut self.upload_batcher);
+
+                self.upload_batcher.record(|command_buffer| {
+                    vertex_buffer.release(
+                        command_buffer.clone(),
+                        src_queue_family_index,
+                        dst_queue_family_index,
+                    );
+                    index_buffer.release(
+                        command_buffer,
+                        src_queue_family_index,
+                        dst_queue_family_index,
+                    );
+                });
+
+                StreamedAsset::Mesh {
+                    id: decoded_asset.id,
+                    content_hash: decoded_asset.content_hash,
+                    mesh: Box::new(GpuMesh {
+                        vertex_buffer,
+                        index_buffer,
+                        index_type: cached_mesh.index_type(),
+                        vertex_count: cached_mesh.vertex_count,
+                        index_count: index_bytes.len() as u32 / cached_mesh.index_width,
+                        lods: cached_mesh.lods.clone(),
+                        aabb: cached_mesh.aabb,
+                        bounding_sphere: cached_mesh.bounding_sphere,
+                        pooled: None,
+                    }),
+                }
+            }
+            DecodedData::Texture(cached_texture) => {
+                let extent = Extent3D::builder()
+                    .width(cached_texture.width)
+                    .height(cached_texture.height)
+                    .depth(1)
+                    .build();
+                let image = Texture::create_image(
+                    extent,
+                    SampleCountFlags::_1,
+                    self.device.clone(),
+                    self.instance.clone(),
+                    self.physical_device.clone(),
+                );
+
+                image.fill_levels(cached_texture.texels(), &mut self.upload_batcher);
+
+                self.upload_batcher.record(|command_buffer| {
+                    image.release(
+                        command_buffer,
+                        src_queue_family_index,
+                        dst_queue_family_index,
+                    );
+                });
+
+                StreamedAsset::Texture {
+                    id: decoded_asset.id,
+                    content_hash: decoded_asset.content_hash,
+                    image: Box::new(image),
+                }
+            }
+        };
+
+        self.recorded.push(asset);
+    }
+
+    /// Stops the workers once they have finished what they are decoding, and destroys the
+    /// assets that are still uploading.
+    pub(crate) fn destroy(&mut self) {
+        self.request_sender = None;
+        self.workers
+            .drain(..)
+            .for_each(|worker| worker.join().unwrap());
+
+        self.upload_batcher.destroy();
+        self.recorded
+            .drain(..)
+            .chain(self.uploading.drain(..).map(|(_, asset)| asset))
+            .for_each(|asset| match asset {
+                StreamedAsset::Mesh { mesh, .. } => mesh.destroy(),
+                StreamedAsset::Texture { image, .. } => image.destroy(),
+            });
+
+        self.command_pool.destroy();
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+};
+
+use hashbrown::HashSet;
+use log::error;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Watches the directories of loaded asset files so that they can be re-imported when they
+/// change. Directories are watched instead of files because editors often replace a file
+/// rather than write to it.
+#[derive(Debug)]
+pub(crate) struct AssetWatcher {
+    /// `None` when the watcher failed to start, assets are then not re-imported.
+    watcher: Option<RecommendedWatcher>,
+    receiver: Receiver<notify::Result<Event>>,
+    directories: HashSet<PathBuf>,
+}
+
+impl AssetWatcher {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = channel();
+
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|error| error!("Failed to start the asset watcher: {}", error))
+            .ok();
+
+        Self {
+            watcher,
+            receiver,
+            directories: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn watch(&mut self, path: &Path) {
+        let directory = match path.parent() {
+            Some(directory) => directory,
+            None => return,
+        };
+
+        let watcher = match &mut self.watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+
+        if self.directories.insert(directory.to_path_buf()) {
+            if let Err(error) = watcher.watch(directory, RecursiveMode::NonRecursive) {
+                error!("Failed to watch {}: {}", directory.display(), error);
+            }
+        }
+    }
+
+    /// Canonical paths of the files created or modified since the last call.
+    pub(crate) fn changed_paths(&self) -> HashSet<PathBuf> {
+        self.receiver
+            .try_iter()
+            .filter_map(|event| {
+                event
+                    .map_err(|error| error!("Asset watcher failed: {}", error))
+                    .ok()
+            })
+            .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)))
+            .flat_map(|event| event.paths)
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect()
+    }
+}
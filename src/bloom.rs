@@ -0,0 +1,232 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use vulkanalia::vk::{Extent2D, Extent3D, HasBuilder, ImageView};
+
+use crate::{
+    command_buffer::CommandBuffer,
+    device::Device,
+    fullscreen_pass::FullscreenPass,
+    image::Image,
+    render_graph::{Access, ImageDescription, RenderGraph, ResourceHandle},
+    sampler::Sampler,
+};
+
+const MIP_COUNT: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BloomPass {
+    Downsample(usize),
+    Upsample(usize),
+}
+
+/// Images of the mip chain, declared in the render graph before it is compiled.
+#[derive(Clone, Debug)]
+pub(crate) struct BloomImages {
+    downsample: Vec<ResourceHandle>,
+    upsample: Vec<ResourceHandle>,
+}
+
+impl BloomImages {
+    /// Half resolution bloom.
+    pub(crate) fn output(&self) -> ResourceHandle {
+        self.upsample[0]
+    }
+}
+
+/// Bloom built from a chain of half resolution downsamples of the HDR target, which are
+/// then upsampled and accumulated back up to the first level.
+#[derive(Clone, Debug)]
+pub(crate) struct Bloom {
+    downsample: FullscreenPass,
+    upsample: FullscreenPass,
+}
+
+impl Bloom {
+    pub(crate) fn declare<P: Copy + From<BloomPass>>(
+        render_graph: &mut RenderGraph<P>,
+        hdr_image: ResourceHandle,
+    ) -> BloomImages {
+        let hdr_description = render_graph.description(hdr_image);
+
+        let descriptions = (1..=MIP_COUNT)
+            .map(|mip_level| ImageDescription {
+                extent: Extent3D::builder()
+                    .width((hdr_description.extent.width >> mip_level).max(1))
+                    .height((hdr_description.extent.height >> mip_level).max(1))
+                    .depth(1)
+                    .build(),
+                ..hdr_description
+            })
+            .collect_vec();
+
+        let downsample = descriptions
+            .iter()
+            .map(|description| render_graph.create_image(*description))
+            .collect_vec();
+        let upsample = descriptions[..MIP_COUNT - 1]
+            .iter()
+            .map(|description| render_graph.create_image(*description))
+            .collect_vec();
+
+        (0..MIP_COUNT).for_each(|mip_level| {
+            let input = if mip_level == 0 {
+                hdr_image
+            } else {
+                downsample[mip_level - 1]
+            };
+
+            render_graph.add_pass(
+                BloomPass::Downsample(mip_level).into(),
+                &[(input, Access::Sampled)],
+                &[(downsample[mip_level], Access::ColorAttachment)],
+            );
+        });
+
+        (0..MIP_COUNT - 1).rev().for_each(|mip_level| {
+            render_graph.add_pass(
+                BloomPass::Upsample(mip_level).into(),
+                &[
+                    (downsample[mip_level], Access::Sampled),
+                    (
+                        Self::coarser(&downsample, &upsample, mip_level),
+                        Access::Sampled,
+                    ),
+                ],
+                &[(upsample[mip_level], Access::ColorAttachment)],
+            );
+        });
+
+        BloomImages {
+            downsample,
+            upsample,
+        }
+    }
+
+    // The last upsample starts from the smallest downsample
+    fn coarser(
+        downsample: &[ResourceHandle],
+        upsample: &[ResourceHandle],
+        mip_level: usize,
+    ) -> ResourceHandle {
+        if mip_level == MIP_COUNT - 2 {
+            downsample[mip_level + 1]
+        } else {
+            upsample[mip_level + 1]
+        }
+    }
+
+    /// Creates the passes over `images`, the graph has to be compiled.
+    pub(crate) fn new<P: Copy>(
+        render_graph: &RenderGraph<P>,
+        hdr_image: ResourceHandle,
+        images: &BloomImages,
+        sampler: &Sampler,
+        device: Device,
+    ) -> Self {
+        let input = |handle: ResourceHandle| (render_graph.image(handle).view, sampler.clone());
+
+        let downsample_targets = images
+            .downsample
+            .iter()
+            .map(|handle| render_graph.image(*handle))
+            .collect_vec();
+        let downsample_inputs = std::iter::once(hdr_image)
+            .chain(images.downsample[..MIP_COUNT - 1].iter().copied())
+            .map(|handle| vec![input(handle)])
+            .collect_vec();
+        let downsample = FullscreenPass::new(
+            device.clone(),
+            render_graph.description(hdr_image).format,
+            "bloom_downsample.frag",
+            8,
+            Self::targets(downsample_targets.as_slice()).as_slice(),
+            downsample_inputs.as_slice(),
+        );
+
+        let upsample_targets = images
+            .upsample
+            .iter()
+            .map(|handle| render_graph.image(*handle))
+            .collect_vec();
+        let upsample_inputs = (0..MIP_COUNT - 1)
+            .map(|mip_level| {
+                vec![
+                    input(images.downsample[mip_level]),
+                    input(Self::coarser(
+                        &images.downsample,
+                        &images.upsample,
+                        mip_level,
+                    )),
+                ]
+            })
+            .collect_vec();
+        let upsample = FullscreenPass::new(
+            device,
+            render_graph.description(hdr_image).format,
+            "bloom_upsample.frag",
+            4,
+            Self::targets(upsample_targets.as_slice()).as_slice(),
+            upsample_inputs.as_slice(),
+        );
+
+        Self {
+            downsample,
+            upsample,
+        }
+    }
+
+    fn targets(images: &[&Image]) -> Vec<(ImageView, Extent2D)> {
+        images
+            .iter()
+            .map(|image| {
+                (
+                    image.view,
+                    Extent2D::builder()
+                        .width(image.extent.width)
+                        .height(image.extent.height)
+                        .build(),
+                )
+            })
+            .collect_vec()
+    }
+
+    pub(crate) fn record(
+        &self,
+        pass: BloomPass,
+        command_buffer: &CommandBuffer,
+        threshold: f32,
+        radius: f32,
+    ) {
+        match pass {
+            BloomPass::Downsample(mip_level) => {
+                let push_constants = [threshold.to_bits(), (mip_level == 0) as u32]
+                    .iter()
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect_vec();
+
+                self.downsample.record(
+                    command_buffer,
+                    mip_level,
+                    mip_level,
+                    push_constants.as_slice(),
+                );
+            }
+            BloomPass::Upsample(mip_level) => {
+                self.upsample
+                    .record(command_buffer, mip_level, mip_level, &radius.to_ne_bytes());
+            }
+        }
+    }
+
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        self.downsample.reload_shaders(changed_paths);
+        self.upsample.reload_shaders(changed_paths);
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.upsample.destroy();
+        self.downsample.destroy();
+    }
+}
@@ -0,0 +1,56 @@
+use nalgebra::Vector3;
+
+use crate::vertex::Vertex;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Aabb {
+    pub(crate) min: Vector3<f32>,
+    pub(crate) max: Vector3<f32>,
+}
+
+impl Aabb {
+    /// Box around the vertices, empty boxes are a point at the origin.
+    pub(crate) fn new(vertices: &[Vertex]) -> Self {
+        if vertices.is_empty() {
+            return Self {
+                min: Vector3::zeros(),
+                max: Vector3::zeros(),
+            };
+        }
+
+        vertices.iter().fold(
+            Self {
+                min: Vector3::repeat(f32::INFINITY),
+                max: Vector3::repeat(f32::NEG_INFINITY),
+            },
+            |aabb, vertex| Self {
+                min: aabb.min.inf(&vertex.pos),
+                max: aabb.max.sup(&vertex.pos),
+            },
+        )
+    }
+
+    pub(crate) fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct BoundingSphere {
+    pub(crate) center: Vector3<f32>,
+    pub(crate) radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the center of the bounding box of the vertices.
+    pub(crate) fn new(vertices: &[Vertex]) -> Self {
+        let center = Aabb::new(vertices).center();
+        let radius = vertices
+            .iter()
+            .map(|vertex| (vertex.pos - center).norm())
+            .fold(0.0, f32::max);
+
+        Self { center, radius }
+    }
+}
@@ -1,31 +1,33 @@
-use std::{marker::PhantomData, mem::size_of, ptr::copy_nonoverlapping as copy_memory};
+use std::{
+    marker::PhantomData,
+    mem::{size_of, size_of_val},
+    ptr::copy_nonoverlapping as copy_memory,
+};
 
-use nalgebra::{Matrix4, Vector3};
 use vulkanalia::{
     prelude::v1_0::Device as vkDevice,
     vk::{
-        self, BufferCopy, BufferCreateInfo, BufferUsageFlags, DeviceMemory, DeviceSize, DeviceV1_0,
-        Extent2D, HasBuilder, MemoryAllocateInfo, MemoryMapFlags, MemoryPropertyFlags, SharingMode,
+        self, AccessFlags, BufferCopy, BufferCreateInfo, BufferMemoryBarrier, BufferUsageFlags,
+        DependencyFlags, DeviceSize, DeviceV1_0, HasBuilder, ImageMemoryBarrier, MemoryBarrier,
+        MemoryPropertyFlags, PipelineStageFlags, SharingMode, WHOLE_SIZE,
     },
 };
 
 use crate::{
-    command_executor::CommandExecutor, command_pool::CommandPool, device::Device,
-    instance::Instance, memory::Memory, physical_device::PhysicalDevice, queue::Queue, ubo::Ubo,
-    vertex::Vertex,
+    command_buffer::CommandBuffer, device::Device, instance_data::InstanceData, memory::Allocation,
+    ubo::Ubo, upload_batcher::UploadBatcher, vertex::Vertex,
 };
 
 pub(crate) type IndexBuffer = Buffer<u32>;
 pub(crate) type VertexBuffer = Buffer<Vertex>;
 pub(crate) type UniformBuffer = Buffer<Ubo>;
+pub(crate) type InstanceBuffer = Buffer<InstanceData>;
 
 #[derive(Clone, Debug)]
 pub(crate) struct Buffer<T: Clone> {
     buffer: vk::Buffer,
-    pub(crate) memory: DeviceMemory,
+    pub(crate) allocation: Allocation,
     device: Device,
-    instance: Instance,
-    physical_device: PhysicalDevice,
     phantom: PhantomData<T>,
 }
 
@@ -34,63 +36,41 @@ impl<T: Clone> Buffer<T> {
         size: DeviceSize,
         usage_flags: BufferUsageFlags,
         device: Device,
-        instance: Instance,
-        physical_device: PhysicalDevice,
         memory_property_flags: MemoryPropertyFlags,
     ) -> Self {
         let buffer = Self::create_self(size, usage_flags, device.clone());
-        let memory = Self::create_memory(
-            device.clone(),
-            buffer,
-            instance.clone(),
-            physical_device.clone(),
-            memory_property_flags,
-        );
+        let allocation = device.allocate_buffer_memory(buffer, memory_property_flags);
 
         Self {
             buffer,
-            memory,
+            allocation,
             device,
             phantom: PhantomData,
-            instance,
-            physical_device,
         }
     }
 
     pub(crate) fn from_indices(
         indices: &[u32],
         device: Device,
-        instance: Instance,
-        physical_device: PhysicalDevice,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
+        upload_batcher: &mut UploadBatcher,
     ) -> IndexBuffer {
         let buffer = Buffer::new(
             (size_of::<u32>() * indices.len()) as u64,
             BufferUsageFlags::TRANSFER_DST | BufferUsageFlags::INDEX_BUFFER,
             device,
-            instance,
-            physical_device,
             MemoryPropertyFlags::DEVICE_LOCAL,
         );
 
-        buffer.fill(indices, command_pool, graphics_queue);
+        buffer.fill(indices, upload_batcher);
 
         buffer
     }
 
-    pub(crate) fn from_staging_data(
-        values: &[T],
-        device: Device,
-        instance: Instance,
-        physical_device: PhysicalDevice,
-    ) -> Buffer<T> {
+    pub(crate) fn from_staging_data(values: &[T], device: Device) -> Buffer<T> {
         Buffer::new(
             (size_of::<T>() * values.len()) as u64,
             BufferUsageFlags::TRANSFER_SRC,
             device,
-            instance,
-            physical_device,
             MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
         )
     }
@@ -98,109 +78,171 @@ impl<T: Clone> Buffer<T> {
     pub(crate) fn from_vertices(
         vertices: &[Vertex],
         device: Device,
-        instance: Instance,
-        physical_device: PhysicalDevice,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
+        upload_batcher: &mut UploadBatcher,
     ) -> VertexBuffer {
         let buffer = Buffer::new(
             (size_of::<Vertex>() * vertices.len()) as u64,
-            BufferUsageFlags::VERTEX_BUFFER | BufferUsageFlags::TRANSFER_DST,
+            BufferUsageFlags::VERTEX_BUFFER
+                | BufferUsageFlags::TRANSFER_SRC
+                | BufferUsageFlags::TRANSFER_DST,
             device,
-            instance,
-            physical_device,
             MemoryPropertyFlags::DEVICE_LOCAL,
         );
 
-        buffer.fill(vertices, command_pool, graphics_queue);
+        buffer.fill(vertices, upload_batcher);
 
         buffer
     }
 
-    pub(crate) fn from_uniform_data(
-        device: Device,
-        instance: Instance,
-        physical_device: PhysicalDevice,
-    ) -> UniformBuffer {
+    /// Host visible vertex buffer for `len` instances, written every frame.
+    pub(crate) fn from_instances(len: usize, device: Device) -> InstanceBuffer {
+        Buffer::new(
+            (size_of::<InstanceData>() * len) as u64,
+            BufferUsageFlags::VERTEX_BUFFER,
+            device,
+            MemoryPropertyFlags::HOST_COHERENT | MemoryPropertyFlags::HOST_VISIBLE,
+        )
+    }
+
+    pub(crate) fn from_uniform_data(device: Device) -> UniformBuffer {
         Buffer::new(
             size_of::<Ubo>() as u64,
             BufferUsageFlags::UNIFORM_BUFFER,
             device,
-            instance,
-            physical_device,
             MemoryPropertyFlags::HOST_COHERENT | MemoryPropertyFlags::HOST_VISIBLE,
         )
     }
 
-    pub(crate) fn fill(&self, values: &[T], command_pool: CommandPool, graphics_queue: Queue) {
-        let size = Self::size(values.len());
-        let staging_buffer = Buffer::from_staging_data(
-            values,
-            self.device.clone(),
-            self.instance.clone(),
-            self.physical_device.clone(),
-        );
+    /// Host visible transfer destination for data copied back from the GPU.
+    pub(crate) fn new_readback(len: usize, device: Device) -> Buffer<T> {
+        Buffer::new(
+            Self::size(len),
+            BufferUsageFlags::TRANSFER_DST,
+            device,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )
+    }
 
-        self.copy_memory(staging_buffer.clone(), size, values);
-        self.copy_buffer(
-            command_pool,
-            graphics_queue,
-            size,
-            staging_buffer.clone(),
-            self.clone(),
-        );
+    pub(crate) fn read(&self, len: usize) -> Vec<T> {
+        unsafe { std::slice::from_raw_parts(self.allocation.mapped.cast::<T>(), len).to_vec() }
+    }
 
-        staging_buffer.destroy();
+    /// Records a copy of `values` to the start of the buffer, it is done once the batch is
+    /// submitted.
+    pub(crate) fn fill(&self, values: &[T], upload_batcher: &mut UploadBatcher) {
+        self.record_fill(values, upload_batcher);
     }
 
-    fn size(len: usize) -> u64 {
-        (size_of::<T>() * len) as u64
+    /// Like [`Self::fill`] for values that are only available as bytes, like ones read from
+    /// a file.
+    pub(crate) fn fill_bytes(&self, bytes: &[u8], upload_batcher: &mut UploadBatcher) {
+        self.record_fill(bytes, upload_batcher);
     }
 
-    pub(crate) fn copy_memory(&self, staging_buffer: Buffer<T>, buffer_size: u64, values: &[T]) {
-        unsafe {
-            let mapped_memory = vkDevice::from(self.device.clone())
-                .map_memory(
-                    staging_buffer.memory,
-                    0,
-                    buffer_size,
-                    MemoryMapFlags::empty(),
-                )
-                .unwrap();
-
-            copy_memory(values.as_ptr(), mapped_memory.cast(), values.len());
-
-            vkDevice::from(self.device.clone()).unmap_memory(staging_buffer.memory);
+    fn record_fill<V>(&self, values: &[V], upload_batcher: &mut UploadBatcher) {
+        let size = size_of_val(values) as DeviceSize;
+
+        upload_batcher.upload(values, |command_buffer, staging_buffer, offset| {
+            let buffer_copy = BufferCopy::builder().src_offset(offset).size(size);
+
+            unsafe {
+                vkDevice::from(self.device.clone()).cmd_copy_buffer(
+                    command_buffer.into(),
+                    staging_buffer,
+                    self.buffer,
+                    &[buffer_copy],
+                );
+            }
+        });
+    }
+
+    /// Hands the buffer, filled by a transfer, over to another queue family that has to
+    /// [`Self::acquire`] it. Nothing is recorded within one family.
+    pub(crate) fn release(
+        &self,
+        command_buffer: CommandBuffer,
+        src_queue_family_index: u32,
+        dst_queue_family_index: u32,
+    ) {
+        if src_queue_family_index == dst_queue_family_index {
+            return;
         }
+
+        self.queue_family_barrier(
+            command_buffer,
+            (src_queue_family_index, dst_queue_family_index),
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+            (PipelineStageFlags::BOTTOM_OF_PIPE, AccessFlags::empty()),
+        );
     }
 
-    pub(crate) fn copy_buffer(
+    /// Takes the buffer over from the family that released it for the given accesses.
+    pub(crate) fn acquire(
         &self,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
-        buffer_size: u64,
-        src_buffer: Buffer<T>,
-        dst_buffer: Buffer<T>,
+        command_buffer: CommandBuffer,
+        src_queue_family_index: u32,
+        dst_queue_family_index: u32,
+        dst_scope: (PipelineStageFlags, AccessFlags),
     ) {
-        CommandExecutor::execute(
-            command_pool,
-            self.device.clone(),
-            |command_buffer| {
-                let copy_buffer = BufferCopy::builder().size(buffer_size);
-
-                unsafe {
-                    vkDevice::from(self.device.clone()).cmd_copy_buffer(
-                        command_buffer.into(),
-                        src_buffer.buffer,
-                        dst_buffer.buffer,
-                        &[copy_buffer],
-                    );
-                }
-            },
-            graphics_queue,
+        // Within one family the barrier only has to wait for the copy
+        let src_scope = if src_queue_family_index == dst_queue_family_index {
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE)
+        } else {
+            (PipelineStageFlags::TOP_OF_PIPE, AccessFlags::empty())
+        };
+
+        self.queue_family_barrier(
+            command_buffer,
+            (src_queue_family_index, dst_queue_family_index),
+            src_scope,
+            dst_scope,
         );
     }
 
+    fn queue_family_barrier(
+        &self,
+        command_buffer: CommandBuffer,
+        (src_queue_family_index, dst_queue_family_index): (u32, u32),
+        (src_stage_mask, src_access_mask): (PipelineStageFlags, AccessFlags),
+        (dst_stage_mask, dst_access_mask): (PipelineStageFlags, AccessFlags),
+    ) {
+        let buffer_memory_barrier = BufferMemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(src_queue_family_index)
+            .dst_queue_family_index(dst_queue_family_index)
+            .buffer(self.buffer)
+            .offset(0)
+            .size(WHOLE_SIZE as u64);
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_pipeline_barrier(
+                command_buffer.into(),
+                src_stage_mask,
+                dst_stage_mask,
+                DependencyFlags::empty(),
+                &[] as &[MemoryBarrier],
+                &[buffer_memory_barrier],
+                &[] as &[ImageMemoryBarrier],
+            );
+        }
+    }
+
+    fn size(len: usize) -> u64 {
+        (size_of::<T>() * len) as u64
+    }
+
+    /// Writes `values` to the start of `staging_buffer`, which must be host visible.
+    pub(crate) fn copy_memory(&self, staging_buffer: Buffer<T>, values: &[T]) {
+        unsafe {
+            copy_memory(
+                values.as_ptr(),
+                staging_buffer.allocation.mapped.cast(),
+                values.len(),
+            );
+        }
+    }
+
     fn create_self(size: DeviceSize, usage_flags: BufferUsageFlags, device: Device) -> vk::Buffer {
         let buffer_create_info = BufferCreateInfo::builder()
             .size(size)
@@ -214,68 +256,18 @@ impl<T: Clone> Buffer<T> {
         }
     }
 
-    fn create_memory(
-        device: Device,
-        buffer: vk::Buffer,
-        instance: Instance,
-        physical_device: PhysicalDevice,
-        memory_property_flags: MemoryPropertyFlags,
-    ) -> DeviceMemory {
-        let buffer_memory_requirements =
-            unsafe { vkDevice::from(device.clone()).get_buffer_memory_requirements(buffer) };
-
-        let memory_allocate_info = MemoryAllocateInfo::builder()
-            .allocation_size(buffer_memory_requirements.size)
-            .memory_type_index(Memory::type_index(
-                instance,
-                physical_device,
-                memory_property_flags,
-                buffer_memory_requirements,
-            ));
-
-        let buffer_memory = unsafe {
-            vkDevice::from(device.clone())
-                .allocate_memory(&memory_allocate_info, None)
-                .unwrap()
-        };
-
-        unsafe {
-            vkDevice::from(device)
-                .bind_buffer_memory(buffer, buffer_memory, 0)
-                .unwrap()
-        };
-
-        buffer_memory
-    }
-
     pub(crate) fn destroy(&self) {
         unsafe {
             vkDevice::from(self.device.clone()).destroy_buffer(self.buffer, None);
-            vkDevice::from(self.device.clone()).free_memory(self.memory, None);
         }
+
+        self.device.free_memory(&self.allocation);
     }
 }
 
 impl UniformBuffer {
-    pub(crate) fn update(&mut self, extent: Extent2D) {
-        let view_matrix = Matrix4::look_at_rh(
-            &Vector3::new(2.0, 2.0, 2.0).into(),
-            &Vector3::new(0.0, 0.0, 0.0).into(),
-            &Vector3::new(0.0, 0.0, 0.1),
-        );
-
-        let mut perspective_matrix = Matrix4::new_perspective(
-            extent.width as f32 / extent.height as f32,
-            45.0f32.to_radians(),
-            0.1,
-            10.0,
-        );
-
-        perspective_matrix[(1, 1)] *= -1.0;
-
-        let ubo = Ubo::new(view_matrix, perspective_matrix);
-
-        self.copy_memory(self.clone(), size_of::<Ubo>() as u64, &[ubo]);
+    pub(crate) fn update(&mut self, camera: &Ubo) {
+        self.copy_memory(self.clone(), &[*camera]);
     }
 
     // pub(crate) fn create_perspective_matrix(
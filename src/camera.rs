@@ -0,0 +1,31 @@
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Perspective camera looking along the negative z axis of its entity. The renderer draws
+/// the scene from the first active camera.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct Camera {
+    /// Vertical field of view in degrees.
+    #[pyo3(get, set)]
+    pub(crate) fov: f32,
+    #[pyo3(get, set)]
+    pub(crate) near: f32,
+    #[pyo3(get, set)]
+    pub(crate) far: f32,
+    #[pyo3(get, set)]
+    pub(crate) active: bool,
+}
+
+#[pymethods]
+impl Camera {
+    #[new]
+    pub(crate) fn new(fov: f32, near: f32, far: f32) -> Self {
+        Self {
+            fov,
+            near,
+            far,
+            active: true,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+
+use half::f16;
+use itertools::Itertools;
+use log::error;
+use nalgebra::Vector3;
+
+/// 3D color lookup table in the `.cube` format, red changes fastest in `texels`.
+#[derive(Clone, Debug)]
+pub(crate) struct ColorLut {
+    pub(crate) size: u32,
+    pub(crate) domain_min: Vector3<f32>,
+    pub(crate) domain_max: Vector3<f32>,
+    pub(crate) texels: Vec<Vector3<f32>>,
+}
+
+impl ColorLut {
+    /// The identity is returned when the file can't be read or parsed.
+    pub(crate) fn new(path: &str) -> Self {
+        let lut = fs::read_to_string(path)
+            .map_err(|error| error.to_string())
+            .and_then(|source| Self::parse(&source));
+
+        match lut {
+            Ok(lut) => lut,
+            Err(error) => {
+                error!("Failed to load {}: {}", path, error);
+                Self::identity()
+            }
+        }
+    }
+
+    /// Smallest table that maps every color to itself with linear filtering.
+    pub(crate) fn identity() -> Self {
+        let texels = (0..8)
+            .map(|i| Vector3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32))
+            .collect_vec();
+
+        Self {
+            size: 2,
+            domain_min: Vector3::zeros(),
+            domain_max: Vector3::new(1.0, 1.0, 1.0),
+            texels,
+        }
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let mut lut = Self {
+            size: 0,
+            domain_min: Vector3::zeros(),
+            domain_max: Vector3::new(1.0, 1.0, 1.0),
+            texels: Vec::new(),
+        };
+
+        for line in source
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let words = line.split_whitespace().collect_vec();
+
+            match words[0] {
+                "LUT_3D_SIZE" => {
+                    lut.size = words
+                        .get(1)
+                        .and_then(|size| size.parse().ok())
+                        .ok_or_else(|| format!("Invalid size in \"{}\"", line))?
+                }
+                "DOMAIN_MIN" => lut.domain_min = Self::parse_vector(line, &words[1..])?,
+                "DOMAIN_MAX" => lut.domain_max = Self::parse_vector(line, &words[1..])?,
+                "TITLE" | "LUT_1D_SIZE" | "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {}
+                _ => lut.texels.push(Self::parse_vector(line, &words)?),
+            }
+        }
+
+        if lut.size == 0 {
+            return Err("LUT_3D_SIZE is missing or 0".to_string());
+        }
+        let entry_count = (lut.size as usize).pow(3);
+        if lut.texels.len() != entry_count {
+            return Err(format!(
+                "{} entries instead of {}",
+                lut.texels.len(),
+                entry_count
+            ));
+        }
+
+        Ok(lut)
+    }
+
+    fn parse_vector(line: &str, words: &[&str]) -> Result<Vector3<f32>, String> {
+        let components = words
+            .iter()
+            .map(|word| word.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|components| components.len() == 3)
+            .ok_or_else(|| format!("Invalid color in \"{}\"", line))?;
+
+        Ok(Vector3::new(components[0], components[1], components[2]))
+    }
+
+    /// Texels as `R16G16B16A16_SFLOAT`, in the order expected by `Image::fill_layers`.
+    pub(crate) fn to_texels(&self) -> Vec<u8> {
+        self.texels
+            .iter()
+            .flat_map(|texel| [texel.x, texel.y, texel.z, 1.0])
+            .flat_map(|channel| f16::from_f32(channel).to_bits().to_ne_bytes())
+            .collect_vec()
+    }
+}
@@ -1,17 +1,28 @@
+use std::{mem::size_of, ops::Range};
+
 use vulkanalia::{
     vk::{
-        self, ClearColorValue, ClearDepthStencilValue, ClearValue, CommandBufferAllocateInfo,
-        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferResetFlags,
-        CommandBufferUsageFlags, DescriptorSet, DeviceV1_0, Extent2D, HasBuilder, IndexType,
-        Offset2D, PipelineBindPoint, PipelineLayout, Rect2D, RenderPassBeginInfo, ShaderStageFlags,
+        self, AccessFlags, BufferMemoryBarrier, ClearColorValue, ClearDepthStencilValue,
+        ClearValue, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferResetFlags, CommandBufferUsageFlags, DependencyFlags, DescriptorSet,
+        DeviceSize, DeviceV1_0, DrawIndexedIndirectCommand, Extent2D, HasBuilder,
+        ImageMemoryBarrier, IndexType, KhrDrawIndirectCountExtension, MemoryBarrier, Offset2D,
+        PipelineBindPoint, PipelineLayout, PipelineStageFlags, Rect2D, RenderPassBeginInfo,
         SubpassContents, Viewport,
     },
     Device as vkDevice,
 };
 
 use crate::{
-    buffer::Buffer, command_pool::CommandPool, device::Device, framebuffer::Framebuffer,
-    pipeline::Pipeline, render_pass::RenderPass, vertex::Vertex,
+    buffer::{Buffer, InstanceBuffer},
+    command_pool::CommandPool,
+    compute_pipeline::ComputePipeline,
+    device::Device,
+    framebuffer::Framebuffer,
+    pipeline::Pipeline,
+    render_pass::RenderPass,
+    shader::ShaderReflection,
+    vertex::Vertex,
 };
 
 #[derive(Debug, Clone)]
@@ -49,16 +60,13 @@ impl CommandBuffer {
         }
     }
 
-    pub(crate) fn start_recording(
+    pub(crate) fn begin_render_pass(
         &self,
-        swapchain_extent: Extent2D,
+        extent: Extent2D,
         render_pass: RenderPass,
         framebuffer: Framebuffer,
         pipeline: Pipeline,
     ) {
-        let command_buffer_begin_info =
-            CommandBufferBeginInfo::builder().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-
         let color_clear_value = ClearValue {
             color: ClearColorValue {
                 float32: [1.0, 1.0, 1.0, 1.0],
@@ -74,7 +82,7 @@ impl CommandBuffer {
 
         let clear_values = &[color_clear_value, depth_clear_value];
         let offset = Offset2D::builder().x(0).y(0);
-        let render_area = Rect2D::builder().offset(offset).extent(swapchain_extent);
+        let render_area = Rect2D::builder().offset(offset).extent(extent);
 
         let render_pass_begin_info = RenderPassBeginInfo::builder()
             .clear_values(clear_values)
@@ -85,18 +93,14 @@ impl CommandBuffer {
         let viewports = &[Viewport::builder()
             .x(0.0)
             .y(0.0)
-            .width(swapchain_extent.width as f32)
-            .height(swapchain_extent.height as f32)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
             .min_depth(0.0)
             .max_depth(1.0)];
 
-        let scissors = &[Rect2D::builder().extent(swapchain_extent).offset(offset)];
+        let scissors = &[Rect2D::builder().extent(extent).offset(offset)];
 
         unsafe {
-            vkDevice::from(self.device.clone())
-                .begin_command_buffer(self.command_buffer, &command_buffer_begin_info)
-                .unwrap();
-
             vkDevice::from(self.device.clone()).cmd_begin_render_pass(
                 self.command_buffer,
                 &render_pass_begin_info,
@@ -114,15 +118,33 @@ impl CommandBuffer {
         }
     }
 
+    pub(crate) fn end_render_pass(&self) {
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_end_render_pass(self.command_buffer);
+        }
+    }
+
+    pub(crate) fn bind_pipeline(&self, pipeline: Pipeline) {
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_bind_pipeline(
+                self.command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
-    pub(crate) fn record_drawing(
+    pub(crate) fn record_drawing<I: Clone>(
         &self,
         vertex_buffer: Buffer<Vertex>,
-        index_buffer: Buffer<u32>,
-        pipeline_layout: PipelineLayout,
+        index_buffer: Buffer<I>,
+        index_type: IndexType,
+        pipeline: &Pipeline,
         descriptor_set: DescriptorSet,
-        model_matrix: &[f32],
-        indices: &[u32],
+        first_index: u32,
+        index_count: u32,
+        instances: Range<u32>,
     ) {
         unsafe {
             vkDevice::from(self.device.clone()).cmd_bind_vertex_buffers(
@@ -135,42 +157,199 @@ impl CommandBuffer {
                 self.command_buffer,
                 index_buffer.into(),
                 0,
-                IndexType::UINT32,
+                index_type,
             );
 
             vkDevice::from(self.device.clone()).cmd_bind_descriptor_sets(
                 self.command_buffer,
                 PipelineBindPoint::GRAPHICS,
-                pipeline_layout,
+                pipeline.layout,
                 0,
                 &[descriptor_set],
                 &[],
             );
-            vkDevice::from(self.device.clone()).cmd_push_constants(
+
+            vkDevice::from(self.device.clone()).cmd_draw_indexed(
                 self.command_buffer,
-                pipeline_layout,
-                ShaderStageFlags::VERTEX,
+                index_count,
+                instances.len() as u32,
+                first_index,
                 0,
-                model_matrix.align_to::<u8>().1,
+                instances.start,
             );
+        }
+    }
 
-            vkDevice::from(self.device.clone()).cmd_draw_indexed(
+    /// Draws the commands of `draw_buffer` from `first_draw` on. With a count buffer the
+    /// number of draws is read from it at `count_offset` and capped to `max_draw_count`,
+    /// which needs `device.draw_indirect_count`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record_indirect_drawing<I: Clone>(
+        &self,
+        vertex_buffer: &Buffer<Vertex>,
+        index_buffer: &Buffer<I>,
+        index_type: IndexType,
+        pipeline: &Pipeline,
+        descriptor_set: DescriptorSet,
+        draw_buffer: vk::Buffer,
+        first_draw: u32,
+        max_draw_count: u32,
+        count_buffer: Option<(vk::Buffer, DeviceSize)>,
+    ) {
+        let stride = size_of::<DrawIndexedIndirectCommand>() as u32;
+        let offset = first_draw as DeviceSize * stride as DeviceSize;
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_bind_vertex_buffers(
                 self.command_buffer,
-                indices.len() as u32,
-                1,
                 0,
+                &[vertex_buffer.into()],
+                &[0],
+            );
+            vkDevice::from(self.device.clone()).cmd_bind_index_buffer(
+                self.command_buffer,
+                index_buffer.into(),
                 0,
+                index_type,
+            );
+
+            vkDevice::from(self.device.clone()).cmd_bind_descriptor_sets(
+                self.command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                pipeline.layout,
                 0,
+                &[descriptor_set],
+                &[],
             );
+
+            match count_buffer {
+                Some((count_buffer, count_offset)) => vkDevice::from(self.device.clone())
+                    .cmd_draw_indexed_indirect_count_khr(
+                        self.command_buffer,
+                        draw_buffer,
+                        offset,
+                        count_buffer,
+                        count_offset,
+                        max_draw_count,
+                        stride,
+                    ),
+                None => vkDevice::from(self.device.clone()).cmd_draw_indexed_indirect(
+                    self.command_buffer,
+                    draw_buffer,
+                    offset,
+                    max_draw_count,
+                    stride,
+                ),
+            }
         }
     }
 
-    pub(crate) fn finish_recording(&self) {
+    /// Binds the per instance data of the following draws, it stays bound across pipelines.
+    pub(crate) fn bind_instance_buffer(&self, instance_buffer: &InstanceBuffer) {
         unsafe {
-            vkDevice::from(self.device.clone()).cmd_end_render_pass(self.command_buffer);
-            vkDevice::from(self.device.clone())
-                .end_command_buffer(self.command_buffer)
-                .unwrap();
+            vkDevice::from(self.device.clone()).cmd_bind_vertex_buffers(
+                self.command_buffer,
+                1,
+                &[instance_buffer.into()],
+                &[0],
+            );
+        }
+    }
+
+    /// Draws a triangle covering the framebuffer with the bound fullscreen pipeline.
+    pub(crate) fn record_fullscreen_drawing(
+        &self,
+        pipeline: &Pipeline,
+        descriptor_set: DescriptorSet,
+        push_constants: &[u8],
+    ) {
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_bind_descriptor_sets(
+                self.command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                pipeline.layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            self.push_constants(pipeline.layout, &pipeline.reflection, push_constants);
+
+            vkDevice::from(self.device.clone()).cmd_draw(self.command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    /// Runs `group_counts` work groups of a compute pipeline.
+    pub(crate) fn record_dispatch(
+        &self,
+        pipeline: &ComputePipeline,
+        descriptor_set: DescriptorSet,
+        push_constants: &[u8],
+        group_counts: [u32; 3],
+    ) {
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_bind_pipeline(
+                self.command_buffer,
+                PipelineBindPoint::COMPUTE,
+                pipeline.pipeline,
+            );
+            vkDevice::from(self.device.clone()).cmd_bind_descriptor_sets(
+                self.command_buffer,
+                PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            self.push_constants(pipeline.layout, &pipeline.reflection, push_constants);
+
+            let [x, y, z] = group_counts;
+            vkDevice::from(self.device.clone()).cmd_dispatch(self.command_buffer, x, y, z);
+        }
+    }
+
+    /// Makes the memory written by the source scope visible to the destination scope, for
+    /// dependencies within a pass that the render graph doesn't see.
+    pub(crate) fn record_memory_barrier(
+        &self,
+        (src_stage_mask, src_access_mask): (PipelineStageFlags, AccessFlags),
+        (dst_stage_mask, dst_access_mask): (PipelineStageFlags, AccessFlags),
+    ) {
+        let memory_barrier = MemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask);
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_pipeline_barrier(
+                self.command_buffer,
+                src_stage_mask,
+                dst_stage_mask,
+                DependencyFlags::empty(),
+                &[memory_barrier],
+                &[] as &[BufferMemoryBarrier],
+                &[] as &[ImageMemoryBarrier],
+            );
+        }
+    }
+
+    /// Pushes the part of `push_constants` that the shaders of a pipeline declare.
+    fn push_constants(
+        &self,
+        layout: PipelineLayout,
+        reflection: &ShaderReflection,
+        push_constants: &[u8],
+    ) {
+        if reflection.push_constant_size == 0 {
+            return;
+        }
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_push_constants(
+                self.command_buffer,
+                layout,
+                reflection.push_constant_stage_flags,
+                0,
+                &push_constants[..reflection.push_constant_size],
+            );
         }
     }
 
@@ -0,0 +1,151 @@
+use crate::entity::Entity;
+
+/// Components of one type as a sparse set: they are packed for iteration, and found by
+/// entity through the position of each entity index in the packed arrays.
+#[derive(Clone, Debug)]
+pub(crate) struct ComponentStorage<T> {
+    /// Position in `entities` and `components` by entity index.
+    sparse: Vec<Option<u32>>,
+    entities: Vec<Entity>,
+    components: Vec<T>,
+}
+
+impl<T> ComponentStorage<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            sparse: Vec::new(),
+            entities: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+
+    // Positions of removed entities aren't cleared when their index is reused
+    fn position(&self, entity: Entity) -> Option<usize> {
+        let position = (*self.sparse.get(entity.index as usize)?)? as usize;
+
+        Some(position).filter(|position| self.entities[*position] == entity)
+    }
+
+    /// Returns the component `entity` had.
+    pub(crate) fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(position) = self.position(entity) {
+            return Some(std::mem::replace(&mut self.components[position], component));
+        }
+
+        let index = entity.index as usize;
+        if index >= self.sparse.len() {
+            self.sparse.resize(index + 1, None);
+        }
+        self.sparse[index] = Some(self.entities.len() as u32);
+        self.entities.push(entity);
+        self.components.push(component);
+
+        None
+    }
+
+    /// The last component takes the place of the removed one.
+    pub(crate) fn remove(&mut self, entity: Entity) -> Option<T> {
+        let position = self.position(entity)?;
+
+        self.sparse[entity.index as usize] = None;
+        self.entities.swap_remove(position);
+        if let Some(moved) = self.entities.get(position) {
+            self.sparse[moved.index as usize] = Some(position as u32);
+        }
+
+        Some(self.components.swap_remove(position))
+    }
+
+    pub(crate) fn contains(&self, entity: Entity) -> bool {
+        self.position(entity).is_some()
+    }
+
+    pub(crate) fn get(&self, entity: Entity) -> Option<&T> {
+        self.position(entity)
+            .map(|position| &self.components[position])
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(&self.components)
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.entities.iter().copied().zip(&mut self.components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::ComponentStorage;
+    use crate::entity::Entity;
+
+    fn entity(index: u32, generation: u32) -> Entity {
+        Entity { index, generation }
+    }
+
+    #[test]
+    fn insert_replaces_the_component() {
+        let mut storage = ComponentStorage::new();
+
+        assert_eq!(storage.insert(entity(3, 0), "a"), None);
+        assert_eq!(storage.insert(entity(3, 0), "b"), Some("a"));
+
+        assert_eq!(storage.get(entity(3, 0)), Some(&"b"));
+        assert_eq!(storage.iter().count(), 1);
+    }
+
+    #[test]
+    fn remove_moves_the_last_component() {
+        let mut storage = ComponentStorage::new();
+        (0..4).for_each(|index| {
+            storage.insert(entity(index, 0), index);
+        });
+
+        assert_eq!(storage.remove(entity(1, 0)), Some(1));
+        assert_eq!(storage.remove(entity(1, 0)), None);
+
+        assert!(!storage.contains(entity(1, 0)));
+        assert_eq!(
+            storage
+                .iter()
+                .map(|(_, component)| *component)
+                .collect_vec(),
+            [0, 3, 2]
+        );
+        for index in [0, 2, 3] {
+            assert_eq!(storage.get(entity(index, 0)), Some(&index));
+        }
+
+        assert_eq!(storage.remove(entity(2, 0)), Some(2));
+        assert_eq!(storage.get(entity(3, 0)), Some(&3));
+    }
+
+    #[test]
+    fn stale_generations_dont_resolve() {
+        let mut storage = ComponentStorage::new();
+        storage.insert(entity(0, 0), "old");
+        storage.remove(entity(0, 0));
+        storage.insert(entity(1, 0), "other");
+        storage.insert(entity(0, 1), "new");
+
+        assert!(!storage.contains(entity(0, 0)));
+        assert_eq!(storage.remove(entity(0, 0)), None);
+        assert_eq!(storage.get(entity(0, 1)), Some(&"new"));
+
+        // The stale id still points at a position, which now belongs to another entity
+        storage.remove(entity(0, 1));
+        assert_eq!(storage.get(entity(0, 0)), None);
+        assert_eq!(storage.get(entity(1, 0)), Some(&"other"));
+    }
+
+    #[test]
+    fn empty_storage() {
+        let mut storage = ComponentStorage::<u32>::new();
+
+        assert_eq!(storage.get(entity(7, 0)), None);
+        assert_eq!(storage.remove(entity(7, 0)), None);
+        assert_eq!(storage.iter().count(), 0);
+    }
+}
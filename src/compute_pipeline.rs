@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use log::{error, info};
+use vulkanalia::{
+    vk::{
+        self, ComputePipelineCreateInfo, DescriptorSetLayout, DeviceV1_0, Handle, HasBuilder,
+        PipelineLayout, PipelineShaderStageCreateInfo, ShaderStageFlags,
+    },
+    Device as vkDevice,
+};
+
+use crate::{
+    device::Device,
+    pipeline::Pipeline,
+    shader::{CompiledShader, Shader, ShaderReflection},
+};
+
+/// Pipeline running a single compute shader, its bindings are all in set 0.
+#[derive(Clone, Debug)]
+pub(crate) struct ComputePipeline {
+    pub(crate) pipeline: vk::Pipeline,
+    pub(crate) layout: PipelineLayout,
+    pub(crate) descriptor_set_layout: DescriptorSetLayout,
+    pub(crate) reflection: ShaderReflection,
+    shader: String,
+    dependencies: Vec<PathBuf>,
+    device: Device,
+}
+
+impl ComputePipeline {
+    pub(crate) fn new(device: Device, shader: &str) -> Self {
+        Self::with_shader(device, shader).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Gives compile errors back instead of panicking, for shaders that come from scripts.
+    pub(crate) fn with_shader(device: Device, shader: &str) -> Result<Self, String> {
+        let compiled_shader = Self::compile_shader(shader)?;
+        let reflection = compiled_shader.reflection.clone();
+
+        let descriptor_set_layout =
+            Pipeline::create_descriptor_set_layout(device.clone(), &reflection);
+        let layout = Pipeline::create_layout(device.clone(), descriptor_set_layout, &reflection);
+        let pipeline = Self::create_pipeline(device.clone(), layout, &compiled_shader.code);
+
+        Ok(Self {
+            pipeline,
+            layout,
+            descriptor_set_layout,
+            reflection,
+            shader: shader.to_string(),
+            dependencies: compiled_shader.dependencies,
+            device,
+        })
+    }
+
+    /// Rebuilds the pipeline when one of the files its shader was compiled from has changed,
+    /// like [`Pipeline::reload_shaders`]. The pipeline must not be in use.
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        if !self
+            .dependencies
+            .iter()
+            .any(|path| changed_paths.contains(path))
+        {
+            return;
+        }
+
+        let compiled_shader = Self::compile_shader(&self.shader).and_then(|compiled_shader| {
+            if compiled_shader.reflection.layout_matches(&self.reflection) {
+                Ok(compiled_shader)
+            } else {
+                Err(
+                    "Descriptor bindings or push constants changed, restart to apply them"
+                        .to_string(),
+                )
+            }
+        });
+
+        match compiled_shader {
+            Ok(compiled_shader) => {
+                let pipeline =
+                    Self::create_pipeline(self.device.clone(), self.layout, &compiled_shader.code);
+
+                unsafe {
+                    vkDevice::from(self.device.clone()).destroy_pipeline(self.pipeline, None);
+                }
+
+                self.pipeline = pipeline;
+                self.reflection = compiled_shader.reflection;
+                self.dependencies = compiled_shader.dependencies;
+
+                info!("Reloaded {}", self.shader);
+            }
+            Err(error) => error!("Keeping the previous pipeline: {}", error),
+        }
+    }
+
+    fn compile_shader(shader: &str) -> Result<CompiledShader, String> {
+        let compiled_shader = Shader::compile(shader, &[])?;
+
+        if let Some(binding) = compiled_shader
+            .reflection
+            .descriptor_bindings
+            .iter()
+            .find(|binding| binding.set != 0)
+        {
+            return Err(format!(
+                "{}: set {} binding {} is not in set 0",
+                shader, binding.set, binding.binding
+            ));
+        }
+
+        Ok(compiled_shader)
+    }
+
+    fn create_pipeline(
+        device: Device,
+        pipeline_layout: PipelineLayout,
+        shader_code: &[u32],
+    ) -> vk::Pipeline {
+        let shader = Shader::new(device.clone(), shader_code);
+
+        let shader_stage_create_info = PipelineShaderStageCreateInfo::builder()
+            .stage(ShaderStageFlags::COMPUTE)
+            .module(shader.module)
+            .name(b"main\0");
+
+        let compute_pipeline_create_info = ComputePipelineCreateInfo::builder()
+            .stage(shader_stage_create_info)
+            .layout(pipeline_layout)
+            .base_pipeline_handle(vk::Pipeline::null())
+            .base_pipeline_index(-1);
+
+        let compute_pipeline = unsafe {
+            vkDevice::from(device.clone())
+                .create_compute_pipelines(
+                    device.pipeline_cache,
+                    &[compute_pipeline_create_info],
+                    None,
+                )
+                .unwrap()
+                .0
+        };
+
+        shader.destroy();
+
+        compute_pipeline
+    }
+
+    pub(crate) fn destroy(&self) {
+        unsafe {
+            vkDevice::from(self.device.clone()).destroy_pipeline(self.pipeline, None);
+            vkDevice::from(self.device.clone()).destroy_pipeline_layout(self.layout, None);
+            vkDevice::from(self.device.clone())
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+impl From<&ComputePipeline> for vk::Pipeline {
+    fn from(value: &ComputePipeline) -> Self {
+        value.pipeline
+    }
+}
@@ -0,0 +1,470 @@
+mod asset_cache;
+mod bounding_volume;
+mod mesh;
+mod mesh_optimizer;
+mod mesh_simplifier;
+mod vertex;
+
+use std::{
+    env, fs,
+    mem::size_of,
+    path::{Path, PathBuf},
+    process, ptr,
+    str::FromStr,
+};
+
+use itertools::Itertools;
+use vulkanalia::vk::IndexType;
+
+use crate::{
+    asset_cache::{AssetCache, AssetKind, CachedMesh},
+    bounding_volume::{Aabb, BoundingSphere},
+    mesh::{Lod, Mesh},
+    mesh_simplifier::LodSettings,
+    vertex::Vertex,
+};
+
+const USAGE: &str = "\
+Usage: cpyte-asset <command> [options] <path>...
+
+Commands:
+    convert     Converts model and image files to the engine format
+    validate    Checks that source and engine files load and hold sane meshes
+    stats       Prints vertex and triangle counts, LODs, bounds and texture sizes
+
+Options:
+    --output <directory>    Writes converted files there instead of the asset cache
+    --compress              Compresses the payloads of the files written with --output
+    --lods <count>          Makes this many LODs after the full mesh, 3 by default
+    --lod-reduction <ratio> Keeps this fraction of the triangles at each LOD, 0.5 by default
+    --lod-error <ratio>     Stops at this error relative to the mesh radius, 0.1 by default
+
+Directories are processed recursively. Converting with other LOD settings needs --output,
+as the engine imports into the asset cache with the default ones. The asset cache is in
+target/asset_cache, or in the directory set by CPYTE_ASSET_CACHE.";
+
+const MODEL_EXTENSIONS: &[&str] = &["obj", "gltf", "glb", "fbx", "dae"];
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "tga", "bmp", "gif", "tif", "tiff", "webp",
+];
+// Normals and tangents further from unit length were not normalized
+const UNIT_LENGTH_TOLERANCE: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Convert,
+    Validate,
+    Stats,
+}
+
+#[derive(Debug)]
+struct Options {
+    command: Command,
+    output: Option<PathBuf>,
+    compress: bool,
+    lod_settings: LodSettings,
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+struct AssetFile {
+    path: PathBuf,
+    /// Path from the directory given on the command line.
+    relative_path: PathBuf,
+    kind: AssetKind,
+    /// Whether the file is already in the engine format.
+    converted: bool,
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let options = match parse_arguments(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let files = options
+        .paths
+        .iter()
+        .flat_map(|path| find_files(path))
+        .collect_vec();
+
+    let mut failure_count = 0;
+    for file in &files {
+        let result = match options.command {
+            Command::Convert => convert(file, &options),
+            Command::Validate => validate(file, &options.lod_settings),
+            Command::Stats => stats(file, &options.lod_settings),
+        };
+
+        if let Err(error) = result {
+            eprintln!("{}: {}", file.path.display(), error);
+            failure_count += 1;
+        }
+    }
+
+    println!("{} files, {} failed", files.len(), failure_count);
+    if failure_count > 0 {
+        process::exit(1);
+    }
+}
+
+fn parse_arguments(mut arguments: impl Iterator<Item = String>) -> Result<Options, String> {
+    let command = match arguments.next().as_deref() {
+        Some("convert") => Command::Convert,
+        Some("validate") => Command::Validate,
+        Some("stats") => Command::Stats,
+        Some(command) => return Err(format!("Unknown command {}", command)),
+        None => return Err("Missing command".to_string()),
+    };
+
+    let mut output = None;
+    let mut compress = false;
+    let mut lod_settings = LodSettings::default();
+    let mut paths = Vec::new();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--output" => {
+                let directory = arguments
+                    .next()
+                    .ok_or_else(|| "--output needs a directory".to_string())?;
+                output = Some(PathBuf::from(directory));
+            }
+            "--compress" => compress = true,
+            "--lods" => lod_settings.level_count = parse_value(&argument, arguments.next())?,
+            "--lod-reduction" => {
+                lod_settings.reduction = parse_value(&argument, arguments.next())?;
+                if !(0.0..1.0).contains(&lod_settings.reduction) {
+                    return Err("--lod-reduction needs a ratio under 1".to_string());
+                }
+            }
+            "--lod-error" => lod_settings.max_error = parse_value(&argument, arguments.next())?,
+            option if option.starts_with("--") => return Err(format!("Unknown option {}", option)),
+            _ => paths.push(PathBuf::from(argument)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("Missing paths".to_string());
+    }
+    if command != Command::Convert && (output.is_some() || compress) {
+        return Err("--output and --compress only apply to convert".to_string());
+    }
+    // The engine maps cached files, which it can't do with compressed payloads
+    if compress && output.is_none() {
+        return Err("--compress needs --output".to_string());
+    }
+    // The asset cache is only keyed by the contents of the source files
+    if command == Command::Convert && output.is_none() && lod_settings != LodSettings::default() {
+        return Err("Converting with other LOD settings needs --output".to_string());
+    }
+
+    Ok(Options {
+        command,
+        output,
+        compress,
+        lod_settings,
+        paths,
+    })
+}
+
+fn parse_value<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} needs a number", option))
+}
+
+fn find_files(path: &Path) -> Vec<AssetFile> {
+    if !path.is_dir() {
+        return match asset_file(path, path.parent().unwrap_or_else(|| Path::new(""))) {
+            Some(file) => vec![file],
+            None => {
+                eprintln!("{}: not a model, image or engine file", path.display());
+                Vec::new()
+            }
+        };
+    }
+
+    let mut files = Vec::new();
+    let mut directories = vec![path.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                eprintln!("{}: {}", directory.display(), error);
+                continue;
+            }
+        };
+
+        for entry_path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if entry_path.is_dir() {
+                directories.push(entry_path);
+            } else if let Some(file) = asset_file(&entry_path, path) {
+                files.push(file);
+            }
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    files
+}
+
+fn asset_file(path: &Path, root: &Path) -> Option<AssetFile> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let (kind, converted) = match extension.as_str() {
+        "mesh" => (AssetKind::Mesh, true),
+        "texture" => (AssetKind::Texture, true),
+        extension if MODEL_EXTENSIONS.contains(&extension) => (AssetKind::Mesh, false),
+        extension if IMAGE_EXTENSIONS.contains(&extension) => (AssetKind::Texture, false),
+        _ => return None,
+    };
+
+    Some(AssetFile {
+        path: path.to_path_buf(),
+        relative_path: path.strip_prefix(root).unwrap_or(path).to_path_buf(),
+        kind,
+        converted,
+    })
+}
+
+/// Writes the engine file to the output directory under the relative path of the source,
+/// or imports the source into the asset cache the way the engine does.
+fn convert(file: &AssetFile, options: &Options) -> Result<(), String> {
+    if file.converted {
+        println!("Skipping {}, it is converted", file.path.display());
+        return Ok(());
+    }
+
+    match &options.output {
+        Some(output) => {
+            let bytes = AssetCache::import(
+                &file.path,
+                file.kind,
+                &options.lod_settings,
+                options.compress,
+            )?;
+            let output_path = output
+                .join(&file.relative_path)
+                .with_extension(AssetCache::extension(file.kind));
+            AssetCache::save(&output_path, &bytes)
+                .map_err(|error| format!("failed to save {}: {}", output_path.display(), error))?;
+
+            println!("{} -> {}", file.path.display(), output_path.display());
+        }
+        None => {
+            let bytes = fs::read(&file.path).map_err(|error| error.to_string())?;
+            let content_hash = AssetCache::content_hash(&bytes);
+            match file.kind {
+                AssetKind::Mesh => AssetCache::load_mesh(&file.path, content_hash).map(|_| ())?,
+                AssetKind::Texture => {
+                    AssetCache::load_texture(&file.path, content_hash).map(|_| ())?
+                }
+            }
+
+            println!("{} -> asset cache", file.path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn validate(file: &AssetFile, lod_settings: &LodSettings) -> Result<(), String> {
+    let problems = match (file.kind, file.converted) {
+        (AssetKind::Mesh, false) => {
+            let mut mesh = Mesh::load(&file.path)?;
+            mesh.process(lod_settings);
+
+            mesh_problems(&mesh.vertices, &mesh.indices)
+        }
+        (AssetKind::Mesh, true) => {
+            let mesh = AssetCache::read_mesh(&file.path)?;
+
+            mesh_problems(&cached_vertices(&mesh), &cached_indices(&mesh))
+        }
+        (AssetKind::Texture, false) => {
+            image::open(&file.path).map_err(|error| error.to_string())?;
+            Vec::new()
+        }
+        (AssetKind::Texture, true) => {
+            AssetCache::read_texture(&file.path)?;
+            Vec::new()
+        }
+    };
+
+    if !problems.is_empty() {
+        return Err(problems.join(", "));
+    }
+
+    println!("{} is valid", file.path.display());
+
+    Ok(())
+}
+
+fn mesh_problems(vertices: &[Vertex], indices: &[u32]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if indices.is_empty() {
+        problems.push("the mesh has no triangles".to_string());
+    }
+    if !indices.len().is_multiple_of(3) {
+        problems.push(format!(
+            "{} indices don't make whole triangles",
+            indices.len()
+        ));
+    }
+    if let Some(index) = indices
+        .iter()
+        .find(|index| **index as usize >= vertices.len())
+    {
+        problems.push(format!(
+            "index {} is out of {} vertices",
+            index,
+            vertices.len()
+        ));
+    }
+
+    let non_finite_count = vertices
+        .iter()
+        .filter(|vertex| {
+            !vertex
+                .pos
+                .iter()
+                .chain(vertex.texture_uv.iter())
+                .chain(vertex.normal.iter())
+                .chain(vertex.tangent.iter())
+                .all(|attribute| attribute.is_finite())
+        })
+        .count();
+    if non_finite_count > 0 {
+        problems.push(format!("{} vertices are not finite", non_finite_count));
+    }
+
+    let non_unit_count = vertices
+        .iter()
+        .filter(|vertex| {
+            (vertex.normal.norm() - 1.0).abs() > UNIT_LENGTH_TOLERANCE
+                || (vertex.tangent.xyz().norm() - 1.0).abs() > UNIT_LENGTH_TOLERANCE
+                || (vertex.tangent.w.abs() - 1.0).abs() > UNIT_LENGTH_TOLERANCE
+        })
+        .count();
+    if non_unit_count > 0 {
+        problems.push(format!(
+            "{} vertices have a normal or tangent that is not unit length",
+            non_unit_count
+        ));
+    }
+
+    problems
+}
+
+fn stats(file: &AssetFile, lod_settings: &LodSettings) -> Result<(), String> {
+    println!("{}", file.path.display());
+
+    match (file.kind, file.converted) {
+        (AssetKind::Mesh, false) => {
+            let mut mesh = Mesh::load(&file.path)?;
+            mesh.process(lod_settings);
+
+            print_mesh_stats(
+                &mesh.vertices,
+                &mesh.lods,
+                &Aabb::new(&mesh.vertices),
+                &BoundingSphere::new(&mesh.vertices),
+            );
+            println!("    {} objects", mesh.submeshes.len());
+        }
+        (AssetKind::Mesh, true) => {
+            let mesh = AssetCache::read_mesh(&file.path)?;
+
+            print_mesh_stats(
+                &cached_vertices(&mesh),
+                &mesh.lods,
+                &mesh.aabb,
+                &mesh.bounding_sphere,
+            );
+            println!("    {}-bit indices", mesh.index_width * 8);
+        }
+        (AssetKind::Texture, false) => {
+            let image = image::open(&file.path).map_err(|error| error.to_string())?;
+
+            print_texture_stats(image.width(), image.height());
+        }
+        (AssetKind::Texture, true) => {
+            let texture = AssetCache::read_texture(&file.path)?;
+
+            print_texture_stats(texture.width, texture.height);
+            println!("    {} bytes of texels", texture.texels().len());
+        }
+    }
+
+    Ok(())
+}
+
+fn print_mesh_stats(
+    vertices: &[Vertex],
+    lods: &[Lod],
+    aabb: &Aabb,
+    bounding_sphere: &BoundingSphere,
+) {
+    println!(
+        "    {} vertices, {} triangles",
+        vertices.len(),
+        lods[0].index_count / 3
+    );
+    lods.iter().enumerate().skip(1).for_each(|(level, lod)| {
+        println!(
+            "    LOD {}: {} triangles, error {:.4}",
+            level,
+            lod.index_count / 3,
+            lod.error
+        )
+    });
+
+    if !vertices.is_empty() {
+        println!(
+            "    bounds {:.3?} to {:.3?}",
+            aabb.min.as_slice(),
+            aabb.max.as_slice()
+        );
+        println!(
+            "    bounding sphere at {:.3?}, radius {:.3}",
+            bounding_sphere.center.as_slice(),
+            bounding_sphere.radius
+        );
+    }
+}
+
+fn print_texture_stats(width: u32, height: u32) {
+    println!(
+        "    {}x{}, {} mip levels",
+        width,
+        height,
+        AssetCache::mip_level_count(width, height)
+    );
+}
+
+/// Vertices of an engine file, read one by one since a decompressed payload may not be
+/// aligned.
+fn cached_vertices(mesh: &CachedMesh) -> Vec<Vertex> {
+    mesh.vertex_bytes()
+        .chunks_exact(size_of::<Vertex>())
+        .map(|bytes| unsafe { ptr::read_unaligned(bytes.as_ptr().cast::<Vertex>()) })
+        .collect_vec()
+}
+
+fn cached_indices(mesh: &CachedMesh) -> Vec<u32> {
+    let index_bytes = mesh.index_bytes().chunks_exact(mesh.index_width as usize);
+    if mesh.index_type() == IndexType::UINT16 {
+        index_bytes
+            .map(|index| u16::from_le_bytes([index[0], index[1]]) as u32)
+            .collect_vec()
+    } else {
+        index_bytes
+            .map(|index| u32::from_le_bytes([index[0], index[1], index[2], index[3]]))
+            .collect_vec()
+    }
+}
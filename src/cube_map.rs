@@ -0,0 +1,149 @@
+use half::f16;
+use itertools::Itertools;
+use nalgebra::Vector3;
+
+pub(crate) const FACE_COUNT: u32 = 6;
+
+/// Cube map kept on the CPU. Faces follow the Vulkan layer order (+X, -X, +Y, -Y, +Z, -Z)
+/// and every level stores its six faces one after another.
+#[derive(Clone, Debug)]
+pub(crate) struct CubeMap {
+    pub(crate) size: u32,
+    pub(crate) levels: Vec<Vec<Vector3<f32>>>,
+}
+
+impl CubeMap {
+    pub(crate) fn from_fn<F: Fn(Vector3<f32>) -> Vector3<f32>>(size: u32, function: F) -> Self {
+        let level = (0..FACE_COUNT)
+            .cartesian_product(0..size * size)
+            .map(|(face, i)| function(Self::direction(face, i % size, i / size, size)))
+            .collect_vec();
+
+        Self {
+            size,
+            levels: vec![level],
+        }
+    }
+
+    pub(crate) fn with_mipmaps(mut self) -> Self {
+        let mut size = self.size;
+
+        while size > 1 {
+            let half_size = size / 2;
+            let level = self.levels.last().unwrap();
+
+            let next_level = (0..FACE_COUNT)
+                .cartesian_product(0..half_size * half_size)
+                .map(|(face, i)| {
+                    let (x, y) = (2 * (i % half_size), 2 * (i / half_size));
+                    let texel =
+                        |x: u32, y: u32| level[(face * size * size + y * size + x) as usize];
+
+                    (texel(x, y) + texel(x + 1, y) + texel(x, y + 1) + texel(x + 1, y + 1)) / 4.0
+                })
+                .collect_vec();
+
+            self.levels.push(next_level);
+            size = half_size;
+        }
+
+        self
+    }
+
+    pub(crate) fn mip_levels(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    pub(crate) fn level_size(&self, level: usize) -> u32 {
+        (self.size >> level).max(1)
+    }
+
+    pub(crate) fn direction(face: u32, x: u32, y: u32, size: u32) -> Vector3<f32> {
+        let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+        let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+
+        let direction = match face {
+            0 => Vector3::new(1.0, -v, -u),
+            1 => Vector3::new(-1.0, -v, u),
+            2 => Vector3::new(u, 1.0, v),
+            3 => Vector3::new(u, -1.0, -v),
+            4 => Vector3::new(u, -v, 1.0),
+            _ => Vector3::new(-u, -v, -1.0),
+        };
+
+        direction.normalize()
+    }
+
+    pub(crate) fn solid_angle(x: u32, y: u32, size: u32) -> f32 {
+        let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+        let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+        let texel_area = (2.0 / size as f32).powi(2);
+
+        texel_area / (1.0 + u * u + v * v).powf(1.5)
+    }
+
+    fn face_coordinates(direction: &Vector3<f32>) -> (u32, f32, f32) {
+        let (x, y, z) = (direction.x, direction.y, direction.z);
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+        let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+            if x > 0.0 {
+                (0, -z, -y, ax)
+            } else {
+                (1, z, -y, ax)
+            }
+        } else if ay >= az {
+            if y > 0.0 {
+                (2, x, z, ay)
+            } else {
+                (3, x, -z, ay)
+            }
+        } else if z > 0.0 {
+            (4, x, -y, az)
+        } else {
+            (5, -x, -y, az)
+        };
+
+        (face, sc / ma, tc / ma)
+    }
+
+    pub(crate) fn sample(&self, direction: &Vector3<f32>, level: usize) -> Vector3<f32> {
+        let level = level.min(self.levels.len() - 1);
+        let size = self.level_size(level);
+        let (face, u, v) = Self::face_coordinates(direction);
+
+        let max_coordinate = (size - 1) as f32;
+        let x = ((u + 1.0) * 0.5 * size as f32 - 0.5).clamp(0.0, max_coordinate);
+        let y = ((v + 1.0) * 0.5 * size as f32 - 0.5).clamp(0.0, max_coordinate);
+
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(size - 1), (y0 + 1).min(size - 1));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+        let texel =
+            |x: u32, y: u32| self.levels[level][(face * size * size + y * size + x) as usize];
+
+        texel(x0, y0) * (1.0 - tx) * (1.0 - ty)
+            + texel(x1, y0) * tx * (1.0 - ty)
+            + texel(x0, y1) * (1.0 - tx) * ty
+            + texel(x1, y1) * tx * ty
+    }
+
+    pub(crate) fn sample_lod(&self, direction: &Vector3<f32>, lod: f32) -> Vector3<f32> {
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+        let (lower, upper) = (lod.floor() as usize, lod.ceil() as usize);
+        let t = lod - lower as f32;
+
+        self.sample(direction, lower) * (1.0 - t) + self.sample(direction, upper) * t
+    }
+
+    /// Texels as `R16G16B16A16_SFLOAT`, in the order expected by `Image::fill_layers`.
+    pub(crate) fn to_texels(&self) -> Vec<u8> {
+        self.levels
+            .iter()
+            .flatten()
+            .flat_map(|texel| [texel.x, texel.y, texel.z, 1.0])
+            .flat_map(|channel| f16::from_f32(channel).to_bits().to_ne_bytes())
+            .collect_vec()
+    }
+}
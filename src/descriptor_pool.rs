@@ -1,11 +1,14 @@
+use hashbrown::HashMap;
+use itertools::Itertools;
 use vulkanalia::{
     vk::{
-        self, DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorType, DeviceV1_0, HasBuilder,
+        self, DescriptorPoolCreateFlags, DescriptorPoolCreateInfo, DescriptorPoolSize,
+        DescriptorType, DeviceV1_0, HasBuilder,
     },
     Device as vkDevice,
 };
 
-use crate::{device::Device, entity::Entity};
+use crate::{device::Device, shader::ShaderReflection};
 
 #[derive(Debug, Clone)]
 pub(crate) struct DescriptorPool {
@@ -14,18 +17,53 @@ pub(crate) struct DescriptorPool {
 }
 
 impl DescriptorPool {
-    pub(crate) fn new(device: Device, entities: &[&Entity]) -> Self {
-        let ubo_pool_size = DescriptorPoolSize::builder()
-            .type_(DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(entities.len() as u32);
-        let image_sampler_pool_size = DescriptorPoolSize::builder()
-            .type_(DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(entities.len() as u32);
-
-        let descriptor_pool_sizes = &[ubo_pool_size, image_sampler_pool_size];
+    /// Pool sized for a number of sets of each layout, given by the reflection of the
+    /// pipelines they are bound to.
+    pub(crate) fn new(device: Device, set_counts: &[(&ShaderReflection, usize)]) -> Self {
+        Self::with_flags(device, set_counts, DescriptorPoolCreateFlags::empty())
+    }
+
+    /// Pool whose sets can be given back one by one.
+    pub(crate) fn new_freeable(device: Device, set_counts: &[(&ShaderReflection, usize)]) -> Self {
+        Self::with_flags(
+            device,
+            set_counts,
+            DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+        )
+    }
+
+    fn with_flags(
+        device: Device,
+        set_counts: &[(&ShaderReflection, usize)],
+        flags: DescriptorPoolCreateFlags,
+    ) -> Self {
+        let mut descriptor_counts = HashMap::<DescriptorType, u32>::new();
+        set_counts.iter().for_each(|(reflection, set_count)| {
+            reflection.descriptor_bindings.iter().for_each(|binding| {
+                *descriptor_counts
+                    .entry(binding.descriptor_type)
+                    .or_default() += binding.count * *set_count as u32;
+            })
+        });
+        let set_count = set_counts
+            .iter()
+            .map(|(_, set_count)| set_count)
+            .sum::<usize>();
+
+        let descriptor_pool_sizes = descriptor_counts
+            .into_iter()
+            .filter(|(_, descriptor_count)| *descriptor_count > 0)
+            .map(|(descriptor_type, descriptor_count)| {
+                DescriptorPoolSize::builder()
+                    .type_(descriptor_type)
+                    .descriptor_count(descriptor_count)
+                    .build()
+            })
+            .collect_vec();
         let descriptor_pool_create_info = DescriptorPoolCreateInfo::builder()
-            .pool_sizes(descriptor_pool_sizes)
-            .max_sets(entities.len() as u32);
+            .pool_sizes(descriptor_pool_sizes.as_slice())
+            .max_sets(set_count as u32)
+            .flags(flags);
 
         let descriptor_pool = unsafe {
             vkDevice::from(device.clone())
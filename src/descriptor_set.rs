@@ -1,21 +1,48 @@
-use std::mem::size_of;
+use std::{iter, mem::size_of};
 
+use itertools::Itertools;
 use vulkanalia::{
     vk::{
         self, Buffer, CopyDescriptorSet, DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool,
-        DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding,
-        DescriptorSetLayoutCreateInfo, DescriptorType, DeviceV1_0, HasBuilder, ImageLayout,
-        ImageView, ShaderStageFlags, WriteDescriptorSet,
+        DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorType, DeviceV1_0, HasBuilder,
+        ImageLayout, ImageView, WriteDescriptorSet, WHOLE_SIZE,
     },
     Device as vkDevice,
 };
 
-use crate::{device::Device, sampler::Sampler, ubo::Ubo};
+use crate::{
+    compute_pipeline::ComputePipeline, device::Device, image_based_lighting::ImageBasedLighting,
+    pipeline::Pipeline, sampler::Sampler, shader::DescriptorBinding, ubo::Ubo,
+};
 
+/// Resource bound to a binding of a compute set.
+#[derive(Clone, Debug)]
+pub(crate) enum ComputeResource {
+    /// Holds a [`Ubo`].
+    UniformBuffer(Buffer),
+    /// Bound whole, the shader decides how much of it is read.
+    StorageBuffer(Buffer),
+    ImageSampler(ImageView, vk::Sampler),
+    /// Image in the general layout.
+    StorageImage(ImageView),
+}
+
+impl ComputeResource {
+    fn descriptor_type(&self) -> DescriptorType {
+        match self {
+            Self::UniformBuffer(_) => DescriptorType::UNIFORM_BUFFER,
+            Self::StorageBuffer(_) => DescriptorType::STORAGE_BUFFER,
+            Self::ImageSampler(..) => DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Self::StorageImage(_) => DescriptorType::STORAGE_IMAGE,
+        }
+    }
+}
+
+/// Set 0 of a pipeline, it is freed along with its pool unless the pool is freeable.
 #[derive(Clone, Debug)]
 pub(crate) struct DescriptorSet {
     descriptor_set: vk::DescriptorSet,
-    pub(crate) layout: DescriptorSetLayout,
+    bindings: Vec<DescriptorBinding>,
     device: Device,
 }
 
@@ -23,108 +50,412 @@ impl DescriptorSet {
     pub(crate) fn new(
         device: Device,
         descriptor_pool: DescriptorPool,
+        pipeline: &Pipeline,
         uniform_buffer: Buffer,
         texture_image_view: ImageView,
         texture_sampler: Sampler,
+        image_based_lighting: &ImageBasedLighting,
+    ) -> Self {
+        let image_samplers = [
+            (texture_image_view, texture_sampler),
+            (
+                image_based_lighting.irradiance.view,
+                image_based_lighting.irradiance_sampler.clone(),
+            ),
+            (
+                image_based_lighting.prefiltered.view,
+                image_based_lighting.prefiltered_sampler.clone(),
+            ),
+            (
+                image_based_lighting.brdf_lut.view,
+                image_based_lighting.brdf_lut_sampler.clone(),
+            ),
+        ];
+
+        Self::with_image_samplers(
+            device,
+            descriptor_pool,
+            pipeline,
+            Some(uniform_buffer),
+            &image_samplers,
+        )
+    }
+
+    pub(crate) fn new_skybox(
+        device: Device,
+        descriptor_pool: DescriptorPool,
+        pipeline: &Pipeline,
+        uniform_buffer: Buffer,
+        image_based_lighting: &ImageBasedLighting,
     ) -> Self {
-        let layout = Self::create_layout(device.clone());
+        let image_samplers = [(
+            image_based_lighting.environment.view,
+            image_based_lighting.environment_sampler.clone(),
+        )];
+
+        Self::with_image_samplers(
+            device,
+            descriptor_pool,
+            pipeline,
+            Some(uniform_buffer),
+            &image_samplers,
+        )
+    }
+
+    /// Input images of a fullscreen pass, in the order of their bindings.
+    pub(crate) fn new_fullscreen(
+        device: Device,
+        descriptor_pool: DescriptorPool,
+        pipeline: &Pipeline,
+        image_samplers: &[(ImageView, Sampler)],
+    ) -> Self {
+        Self::with_image_samplers(device, descriptor_pool, pipeline, None, image_samplers)
+    }
+
+    /// Resources of a compute pipeline, in the order of its bindings. It panics when they
+    /// don't match what the shader declares.
+    pub(crate) fn new_compute(
+        device: Device,
+        descriptor_pool: DescriptorPool,
+        pipeline: &ComputePipeline,
+        resources: &[ComputeResource],
+    ) -> Self {
+        Self::try_new_compute(device, descriptor_pool, pipeline, resources)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`Self::new_compute`], but tells when `resources` don't match the bindings
+    /// instead of panicking.
+    pub(crate) fn try_new_compute(
+        device: Device,
+        descriptor_pool: DescriptorPool,
+        pipeline: &ComputePipeline,
+        resources: &[ComputeResource],
+    ) -> Result<Self, String> {
+        let bindings = pipeline.reflection.descriptor_bindings.clone();
+        Self::check_compute_bindings(bindings.as_slice(), resources)?;
+
+        let descriptor_set = Self::allocate(
+            pipeline.descriptor_set_layout,
+            descriptor_pool,
+            device.clone(),
+        );
+        Self::write_compute(device.clone(), descriptor_set, &bindings, resources);
+
+        Ok(Self {
+            descriptor_set,
+            bindings,
+            device,
+        })
+    }
+
+    /// The uniform buffer goes to the first binding and the images to the following ones,
+    /// it panics when the shaders of `pipeline` declare other bindings.
+    fn with_image_samplers(
+        device: Device,
+        descriptor_pool: DescriptorPool,
+        pipeline: &Pipeline,
+        uniform_buffer: Option<Buffer>,
+        image_samplers: &[(ImageView, Sampler)],
+    ) -> Self {
+        let bindings = pipeline.reflection.descriptor_bindings.clone();
+        Self::check_bindings(
+            bindings.as_slice(),
+            uniform_buffer.is_some(),
+            image_samplers.len(),
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+
         let descriptor_set = Self::create_descriptor_set(
-            layout,
+            pipeline.descriptor_set_layout,
             descriptor_pool,
             device.clone(),
+            bindings.as_slice(),
             uniform_buffer,
-            texture_image_view,
-            texture_sampler,
+            image_samplers,
         );
 
         Self {
             descriptor_set,
-            layout,
+            bindings,
             device,
         }
     }
 
-    fn create_descriptor_set(
+    fn check_bindings(
+        bindings: &[DescriptorBinding],
+        uniform_buffer: bool,
+        image_sampler_count: usize,
+    ) -> Result<(), String> {
+        let resources = uniform_buffer
+            .then_some((DescriptorType::UNIFORM_BUFFER, size_of::<Ubo>()))
+            .into_iter()
+            .chain(iter::repeat_n(
+                (DescriptorType::COMBINED_IMAGE_SAMPLER, 0),
+                image_sampler_count,
+            ))
+            .collect_vec();
+
+        if bindings.len() != resources.len() {
+            return Err(format!(
+                "The shaders declare {} bindings but the renderer binds {} resources",
+                bindings.len(),
+                resources.len()
+            ));
+        }
+
+        bindings
+            .iter()
+            .zip(resources)
+            .try_for_each(|(binding, (descriptor_type, size))| {
+                if binding.descriptor_type != descriptor_type || binding.count != 1 {
+                    Err(format!(
+                        "Binding {} is {} {:?} but the renderer binds one {:?}",
+                        binding.binding, binding.count, binding.descriptor_type, descriptor_type
+                    ))
+                } else if binding.size != size {
+                    Err(format!(
+                        "Binding {} takes {} bytes but the renderer binds {}",
+                        binding.binding, binding.size, size
+                    ))
+                } else {
+                    Ok(())
+                }
+            })
+    }
+
+    fn check_compute_bindings(
+        bindings: &[DescriptorBinding],
+        resources: &[ComputeResource],
+    ) -> Result<(), String> {
+        if bindings.len() != resources.len() {
+            return Err(format!(
+                "The shader declares {} bindings but the renderer binds {} resources",
+                bindings.len(),
+                resources.len()
+            ));
+        }
+
+        bindings
+            .iter()
+            .zip(resources)
+            .try_for_each(|(binding, resource)| {
+                let descriptor_type = resource.descriptor_type();
+
+                if binding.descriptor_type != descriptor_type || binding.count != 1 {
+                    Err(format!(
+                        "Binding {} is {} {:?} but the renderer binds one {:?}",
+                        binding.binding, binding.count, binding.descriptor_type, descriptor_type
+                    ))
+                } else if descriptor_type == DescriptorType::UNIFORM_BUFFER
+                    && binding.size != size_of::<Ubo>()
+                {
+                    Err(format!(
+                        "Binding {} takes {} bytes but the renderer binds {}",
+                        binding.binding,
+                        binding.size,
+                        size_of::<Ubo>()
+                    ))
+                } else {
+                    Ok(())
+                }
+            })
+    }
+
+    fn allocate(
         descriptor_set_layout: DescriptorSetLayout,
         descriptor_pool: DescriptorPool,
         device: Device,
-        uniform_buffer: Buffer,
-        texture_image_view: ImageView,
-        texture_sampler: Sampler,
     ) -> vk::DescriptorSet {
         let descriptor_set_layouts = vec![descriptor_set_layout; 1];
         let descriptor_set_allocate_info = DescriptorSetAllocateInfo::builder()
             .descriptor_pool(descriptor_pool)
             .set_layouts(&descriptor_set_layouts);
 
-        let descriptor_set = unsafe {
-            vkDevice::from(device.clone())
+        let descriptor_sets = unsafe {
+            vkDevice::from(device)
                 .allocate_descriptor_sets(&descriptor_set_allocate_info)
                 .unwrap()
-        }[0];
-
-        let descriptor_buffer_info = DescriptorBufferInfo::builder()
-            .buffer(uniform_buffer)
-            .offset(0)
-            .range(size_of::<Ubo>() as u64);
-
-        let descriptor_image_info = DescriptorImageInfo::builder()
-            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(texture_image_view)
-            .sampler(texture_sampler.sampler);
-
-        let descriptor_buffer_infos = &[descriptor_buffer_info];
-        let descriptor_image_infos = &[descriptor_image_info];
-
-        let ubo_write_descriptor_set = WriteDescriptorSet::builder()
-            .dst_set(descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(DescriptorType::UNIFORM_BUFFER)
-            .buffer_info(descriptor_buffer_infos);
-        let image_write_descriptor_set = WriteDescriptorSet::builder()
-            .dst_set(descriptor_set)
-            .dst_binding(1)
-            .dst_array_element(0)
-            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(descriptor_image_infos);
+        };
 
-        unsafe {
-            vkDevice::from(device).update_descriptor_sets(
-                &[ubo_write_descriptor_set, image_write_descriptor_set],
-                &[] as &[CopyDescriptorSet],
-            );
-        }
+        descriptor_sets[0]
+    }
+
+    fn create_descriptor_set(
+        descriptor_set_layout: DescriptorSetLayout,
+        descriptor_pool: DescriptorPool,
+        device: Device,
+        bindings: &[DescriptorBinding],
+        uniform_buffer: Option<Buffer>,
+        image_samplers: &[(ImageView, Sampler)],
+    ) -> vk::DescriptorSet {
+        let descriptor_set = Self::allocate(descriptor_set_layout, descriptor_pool, device.clone());
+
+        Self::write(
+            device,
+            descriptor_set,
+            bindings,
+            uniform_buffer,
+            image_samplers,
+        );
 
         descriptor_set
     }
 
-    fn create_layout(device: Device) -> vk::DescriptorSetLayout {
-        let ubo_layout_binding = DescriptorSetLayoutBinding::builder()
-            .binding(0)
-            .descriptor_type(DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(ShaderStageFlags::VERTEX);
-        let image_sampler_binding = DescriptorSetLayoutBinding::builder()
-            .binding(1)
-            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(ShaderStageFlags::FRAGMENT);
+    /// Points the images of a fullscreen pass set to other images, the set must not be in use.
+    pub(crate) fn update_fullscreen(&self, image_samplers: &[(ImageView, Sampler)]) {
+        Self::write(
+            self.device.clone(),
+            self.descriptor_set,
+            self.bindings.as_slice(),
+            None,
+            image_samplers,
+        );
+    }
+
+    /// Gives the set back to a freeable pool, the set must not be in use.
+    pub(crate) fn free(&self, descriptor_pool: DescriptorPool) {
+        unsafe {
+            vkDevice::from(self.device.clone())
+                .free_descriptor_sets(descriptor_pool, &[self.descriptor_set])
+                .unwrap();
+        }
+    }
+
+    fn write(
+        device: Device,
+        descriptor_set: vk::DescriptorSet,
+        bindings: &[DescriptorBinding],
+        uniform_buffer: Option<Buffer>,
+        image_samplers: &[(ImageView, Sampler)],
+    ) {
+        let descriptor_buffer_infos = uniform_buffer
+            .iter()
+            .map(|uniform_buffer| {
+                DescriptorBufferInfo::builder()
+                    .buffer(*uniform_buffer)
+                    .offset(0)
+                    .range(size_of::<Ubo>() as u64)
+                    .build()
+            })
+            .collect_vec();
+        let (buffer_bindings, image_bindings) = bindings.split_at(descriptor_buffer_infos.len());
+        let descriptor_image_infos = image_samplers
+            .iter()
+            .map(|(image_view, sampler)| {
+                [DescriptorImageInfo::builder()
+                    .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(*image_view)
+                    .sampler(sampler.sampler)
+                    .build()]
+            })
+            .collect_vec();
+
+        let ubo_write_descriptor_sets = descriptor_buffer_infos.chunks(1).zip(buffer_bindings).map(
+            |(descriptor_buffer_info, binding)| {
+                WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(descriptor_buffer_info)
+                    .build()
+            },
+        );
+        let image_write_descriptor_sets = descriptor_image_infos.iter().zip(image_bindings).map(
+            |(descriptor_image_info, binding)| {
+                WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(descriptor_image_info)
+                    .build()
+            },
+        );
 
-        let descriptor_set_layout_bindings = &[ubo_layout_binding, image_sampler_binding];
-        let descriptor_set_layout_create_info =
-            DescriptorSetLayoutCreateInfo::builder().bindings(descriptor_set_layout_bindings);
+        let write_descriptor_sets = ubo_write_descriptor_sets
+            .chain(image_write_descriptor_sets)
+            .collect_vec();
 
         unsafe {
-            vkDevice::from(device)
-                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
-                .unwrap()
+            vkDevice::from(device).update_descriptor_sets(
+                write_descriptor_sets.as_slice(),
+                &[] as &[CopyDescriptorSet],
+            );
         }
     }
 
-    pub(crate) fn destroy(&self) {
+    fn write_compute(
+        device: Device,
+        descriptor_set: vk::DescriptorSet,
+        bindings: &[DescriptorBinding],
+        resources: &[ComputeResource],
+    ) {
+        let descriptor_infos = resources
+            .iter()
+            .map(|resource| match resource {
+                ComputeResource::UniformBuffer(buffer) => (
+                    [DescriptorBufferInfo::builder()
+                        .buffer(*buffer)
+                        .offset(0)
+                        .range(size_of::<Ubo>() as u64)
+                        .build()],
+                    [DescriptorImageInfo::default()],
+                ),
+                ComputeResource::StorageBuffer(buffer) => (
+                    [DescriptorBufferInfo::builder()
+                        .buffer(*buffer)
+                        .offset(0)
+                        .range(WHOLE_SIZE as u64)
+                        .build()],
+                    [DescriptorImageInfo::default()],
+                ),
+                ComputeResource::ImageSampler(image_view, sampler) => (
+                    [DescriptorBufferInfo::default()],
+                    [DescriptorImageInfo::builder()
+                        .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .image_view(*image_view)
+                        .sampler(*sampler)
+                        .build()],
+                ),
+                ComputeResource::StorageImage(image_view) => (
+                    [DescriptorBufferInfo::default()],
+                    [DescriptorImageInfo::builder()
+                        .image_layout(ImageLayout::GENERAL)
+                        .image_view(*image_view)
+                        .build()],
+                ),
+            })
+            .collect_vec();
+
+        let write_descriptor_sets = resources
+            .iter()
+            .zip(bindings)
+            .zip(descriptor_infos.iter())
+            .map(|((resource, binding), (buffer_info, image_info))| {
+                let write_descriptor_set = WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_type(resource.descriptor_type());
+
+                match resource {
+                    ComputeResource::UniformBuffer(_) | ComputeResource::StorageBuffer(_) => {
+                        write_descriptor_set.buffer_info(buffer_info).build()
+                    }
+                    ComputeResource::ImageSampler(..) | ComputeResource::StorageImage(_) => {
+                        write_descriptor_set.image_info(image_info).build()
+                    }
+                }
+            })
+            .collect_vec();
+
         unsafe {
-            vkDevice::from(self.device.clone()).destroy_descriptor_set_layout(self.layout, None);
+            vkDevice::from(device).update_descriptor_sets(
+                write_descriptor_sets.as_slice(),
+                &[] as &[CopyDescriptorSet],
+            );
         }
     }
 }
@@ -1,21 +1,51 @@
+use std::{
+    cell::RefCell,
+    convert::TryInto,
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
 use hashbrown::HashSet;
 use itertools::Itertools;
+use log::{error, warn};
 use vulkanalia::{
     prelude::v1_0::Device as vkDevice,
     vk::{
-        DeviceCreateInfo, DeviceQueueCreateInfo, DeviceV1_0, HasBuilder, PhysicalDeviceFeatures,
-        SampleCountFlags, StringArray,
+        self, DeviceCreateInfo, DeviceQueueCreateInfo, DeviceV1_0, HasBuilder, ImageTiling,
+        InstanceV1_0, MemoryPropertyFlags, PhysicalDeviceFeatures, PhysicalDeviceProperties,
+        PipelineCache, PipelineCacheCreateInfo, PipelineCacheHeaderVersion, SampleCountFlags,
+        StringArray, KHR_DRAW_INDIRECT_COUNT_EXTENSION, TRUE,
     },
 };
 
 use crate::{
-    instance::Instance, physical_device::PhysicalDevice, queue_family_index::QueueFamilyIndex,
+    instance::Instance,
+    memory::{Allocation, Allocator, AllocatorStatistics},
+    physical_device::PhysicalDevice,
+    queue_family_index::QueueFamilyIndex,
     surface::Surface,
 };
 
+// Relative to the working directory, like the assets, unless set by this variable
+const PIPELINE_CACHE_DIRECTORY_VARIABLE: &str = "CPYTE_PIPELINE_CACHE";
+const DEFAULT_PIPELINE_CACHE_DIRECTORY: &str = "target/pipeline_cache";
+// Size of VkPipelineCacheHeaderVersionOne
+const PIPELINE_CACHE_HEADER_SIZE: usize = 32;
+
 #[derive(Clone, Debug)]
 pub(crate) struct Device {
     device: vkDevice,
+    /// Used for every pipeline creation, it is saved to disk when the device is destroyed.
+    pub(crate) pipeline_cache: PipelineCache,
+    pipeline_cache_path: PathBuf,
+    allocator: Rc<RefCell<Allocator>>,
+    /// Indirect draws can draw many commands at once and start at any instance, which
+    /// GPU-driven rendering relies on.
+    pub(crate) indirect_drawing: bool,
+    /// The number of indirect draws can be read from a buffer.
+    pub(crate) draw_indirect_count: bool,
+    pub(crate) max_draw_indirect_count: u32,
 }
 
 impl Device {
@@ -32,9 +62,18 @@ impl Device {
         let present_queue_family_index =
             QueueFamilyIndex::present(instance.clone(), physical_device.clone(), surface);
 
+        let transfer_queue_family_index =
+            QueueFamilyIndex::transfer(instance.clone(), physical_device.clone());
+        let compute_queue_family_index =
+            QueueFamilyIndex::compute(instance.clone(), physical_device.clone());
+
         let queue_priorities = &[1.0];
-        let unique_queue_family_indices =
-            HashSet::<u32>::from([graphics_queue_family_index, present_queue_family_index]);
+        let unique_queue_family_indices = HashSet::<u32>::from([
+            graphics_queue_family_index,
+            present_queue_family_index,
+            transfer_queue_family_index,
+            compute_queue_family_index,
+        ]);
         let unique_queue_families_create_info = unique_queue_family_indices
             .iter()
             .map(|queue_family_index| {
@@ -43,12 +82,31 @@ impl Device {
                     .queue_priorities(queue_priorities)
             })
             .collect_vec();
+        let supported_features = unsafe {
+            instance
+                .instance
+                .get_physical_device_features(physical_device.physical_device)
+        };
+        let indirect_drawing = supported_features.multi_draw_indirect == TRUE
+            && supported_features.draw_indirect_first_instance == TRUE;
         let physical_device_features = PhysicalDeviceFeatures::builder()
             .sampler_anisotropy(true)
-            .sample_rate_shading(msaa_sample_count != SampleCountFlags::_1);
+            .fill_mode_non_solid(true)
+            .sample_rate_shading(msaa_sample_count != SampleCountFlags::_1)
+            .multi_draw_indirect(indirect_drawing)
+            .draw_indirect_first_instance(indirect_drawing);
 
+        let draw_indirect_count = unsafe {
+            instance
+                .instance
+                .enumerate_device_extension_properties(physical_device.physical_device, None)
+                .unwrap()
+                .iter()
+                .any(|extension| extension.extension_name == KHR_DRAW_INDIRECT_COUNT_EXTENSION.name)
+        };
         let extensions = extensions
             .iter()
+            .chain(draw_indirect_count.then_some(&KHR_DRAW_INDIRECT_COUNT_EXTENSION.name))
             .map(|extension| extension.as_ptr())
             .collect::<Vec<_>>();
 
@@ -65,11 +123,179 @@ impl Device {
                 .unwrap()
         };
 
-        Self { device }
+        let physical_device_properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_properties(physical_device.physical_device)
+        };
+        // Pipelines built by another driver are useless, so each one gets its own file
+        let pipeline_cache_directory = env::var_os(PIPELINE_CACHE_DIRECTORY_VARIABLE).map_or_else(
+            || PathBuf::from(DEFAULT_PIPELINE_CACHE_DIRECTORY),
+            PathBuf::from,
+        );
+        let pipeline_cache_path = pipeline_cache_directory.join(format!(
+            "{}-{:08x}.bin",
+            physical_device_properties
+                .pipeline_cache_uuid
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .join(""),
+            physical_device_properties.driver_version
+        ));
+        let pipeline_cache =
+            Self::create_pipeline_cache(&device, &physical_device_properties, &pipeline_cache_path);
+
+        let allocator = Allocator::new(&instance, &physical_device);
+
+        Self {
+            device,
+            pipeline_cache,
+            pipeline_cache_path,
+            allocator: Rc::new(RefCell::new(allocator)),
+            indirect_drawing,
+            draw_indirect_count,
+            max_draw_indirect_count: physical_device_properties.limits.max_draw_indirect_count,
+        }
+    }
+
+    /// Allocates and binds memory for `buffer`.
+    pub(crate) fn allocate_buffer_memory(
+        &self,
+        buffer: vk::Buffer,
+        memory_property_flags: MemoryPropertyFlags,
+    ) -> Allocation {
+        let memory_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+        let allocation = self.allocator.borrow_mut().allocate(
+            &self.device,
+            memory_requirements,
+            memory_property_flags,
+            true,
+        );
+
+        unsafe {
+            self.device
+                .bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+                .unwrap();
+        }
+
+        allocation
+    }
+
+    /// Allocates and binds memory for `image`.
+    pub(crate) fn allocate_image_memory(
+        &self,
+        image: vk::Image,
+        image_tiling: ImageTiling,
+        memory_property_flags: MemoryPropertyFlags,
+    ) -> Allocation {
+        let memory_requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let allocation = self.allocator.borrow_mut().allocate(
+            &self.device,
+            memory_requirements,
+            memory_property_flags,
+            image_tiling == ImageTiling::LINEAR,
+        );
+
+        unsafe {
+            self.device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+                .unwrap();
+        }
+
+        allocation
+    }
+
+    pub(crate) fn free_memory(&self, allocation: &Allocation) {
+        self.allocator.borrow_mut().free(&self.device, allocation);
+    }
+
+    pub(crate) fn memory_statistics(&self) -> AllocatorStatistics {
+        self.allocator.borrow().statistics()
+    }
+
+    /// Starts from the data saved by a previous run when it was written for this device and
+    /// driver, and from an empty cache otherwise.
+    fn create_pipeline_cache(
+        device: &vkDevice,
+        physical_device_properties: &PhysicalDeviceProperties,
+        path: &Path,
+    ) -> PipelineCache {
+        let initial_data = match fs::read(path) {
+            Ok(data) if Self::is_compatible_pipeline_cache(&data, physical_device_properties) => {
+                data
+            }
+            Ok(_) => {
+                warn!("Discarding the incompatible pipeline cache {:?}", path);
+                Vec::new()
+            }
+            Err(_) => Vec::new(),
+        };
+
+        let pipeline_cache_create_info =
+            PipelineCacheCreateInfo::builder().initial_data(initial_data.as_slice());
+
+        unsafe {
+            device
+                .create_pipeline_cache(&pipeline_cache_create_info, None)
+                .or_else(|error| {
+                    warn!("Discarding the pipeline cache {:?}: {}", path, error);
+                    device.create_pipeline_cache(&PipelineCacheCreateInfo::builder(), None)
+                })
+                .unwrap()
+        }
+    }
+
+    fn is_compatible_pipeline_cache(
+        data: &[u8],
+        physical_device_properties: &PhysicalDeviceProperties,
+    ) -> bool {
+        if data.len() < PIPELINE_CACHE_HEADER_SIZE {
+            return false;
+        }
+
+        let field =
+            |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+
+        field(0) as usize >= PIPELINE_CACHE_HEADER_SIZE
+            && field(4) == PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && field(8) == physical_device_properties.vendor_id
+            && field(12) == physical_device_properties.device_id
+            && data[16..32] == physical_device_properties.pipeline_cache_uuid[..]
+    }
+
+    /// Writes the pipeline cache to a temporary file first so that an interrupted save never
+    /// leaves a truncated cache behind.
+    fn save_pipeline_cache(&self) {
+        let data = match unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache) } {
+            Ok(data) => data,
+            Err(error) => {
+                error!("Failed to read the pipeline cache: {}", error);
+                return;
+            }
+        };
+
+        let temporary_path = self.pipeline_cache_path.with_extension("tmp");
+        if let Err(error) = self
+            .pipeline_cache_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temporary_path, data))
+            .and_then(|_| fs::rename(&temporary_path, &self.pipeline_cache_path))
+        {
+            error!(
+                "Failed to save the pipeline cache {:?}: {}",
+                self.pipeline_cache_path, error
+            );
+        }
     }
 
     pub(crate) fn destroy(&self) {
+        self.save_pipeline_cache();
+        self.allocator.borrow_mut().destroy(&self.device);
+
         unsafe {
+            self.device
+                .destroy_pipeline_cache(self.pipeline_cache, None);
             self.device.destroy_device(None);
         }
     }
@@ -1,57 +1,26 @@
-use hashbrown::HashMap;
-use rand::prelude::*;
-use pyo3::prelude::*;
-use numpy::nalgebra::{Vector3, UnitQuaternion, Matrix4};
+use pyo3::{basic::CompareOp, prelude::*};
 
-use crate::model::Model;
-
-#[derive(Clone, Debug)]
+/// Generational id of an entity of a [`SceneGraph`](crate::scene_graph::SceneGraph), its
+/// data are the components stored for it. An id no longer resolves once the entity is
+/// despawned, even after its index is reused.
 #[pyclass]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(crate) struct Entity {
-    pub(crate) id: usize,
-    #[pyo3(get, set)]
-    pub(crate) position: Vector3<f32>,
-    #[pyo3(get, set)]
-    pub(crate) rotation: UnitQuaternion<f32>,
-    #[pyo3(get, set)]
-    pub(crate) scale: Vector3<f32>,
-    pub(crate) model: Model,
-    #[pyo3(get, set)]
-    pub(crate) parent: Option<Box<Self>>,
-    #[pyo3(get, set)]
-    pub(crate) children: HashMap<String, Self>,
+    pub(crate) index: u32,
+    pub(crate) generation: u32,
 }
 
 #[pymethods]
 impl Entity {
-    #[new]
-    pub(crate) fn new(
-        position: Vector3<f32>,
-        rotation: UnitQuaternion<f32>,
-        scale: Vector3<f32>,
-        model_path: &str,
-        texture_path: Option<&str>,
-    ) -> Self {
-        Self {
-            id: thread_rng().gen::<usize>(),
-            position,
-            rotation,
-            scale,
-            model: Model::new(model_path, texture_path),
-            parent: None,
-            children: HashMap::new()
-        }
+    fn __repr__(&self) -> String {
+        format!("Entity({}v{})", self.index, self.generation)
     }
 
-    pub(crate) fn on_update(&mut self, exec_time: f32) {
-        self.rotation *= UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 1.0f32.to_radians());
+    fn __hash__(&self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
     }
 
-    pub(crate) fn transform_matrix(&self) -> Matrix4<f32> {
-        Matrix4::new_nonuniform_scaling(&self.scale)
-            * self.rotation.to_homogeneous()
-            * Matrix4::new_translation(&self.position)
+    fn __richcmp__(&self, other: &Self, op: CompareOp) -> bool {
+        op.matches(self.cmp(other))
     }
 }
-
-unsafe impl Send for Entity {}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::f32::consts::PI;
+
+use half::f16;
+use image::{io::Reader as ImageReader, Rgba, Rgba32FImage};
+use itertools::Itertools;
+use log::error;
+use nalgebra::{Vector2, Vector3};
+
+use crate::cube_map::{CubeMap, FACE_COUNT};
+
+const SKYBOX_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const IRRADIANCE_SOURCE_SIZE: u32 = 16;
+const PREFILTERED_SIZE: u32 = 128;
+pub(crate) const PREFILTERED_MIP_LEVELS: u32 = 5;
+const PREFILTERED_SAMPLE_COUNT: u32 = 64;
+pub(crate) const BRDF_LUT_SIZE: u32 = 128;
+const BRDF_LUT_SAMPLE_COUNT: u32 = 128;
+
+/// Skybox and image-based lighting data precomputed from an equirectangular HDR image.
+#[derive(Clone, Debug)]
+pub(crate) struct EnvironmentMap {
+    pub(crate) skybox: CubeMap,
+    pub(crate) irradiance: CubeMap,
+    pub(crate) prefiltered: CubeMap,
+    pub(crate) brdf_lut: Vec<Vector2<f32>>,
+}
+
+impl EnvironmentMap {
+    /// A uniform gray environment is used without `hdr_path`, or when it can't be loaded.
+    pub(crate) fn new(hdr_path: Option<&str>) -> Self {
+        let equirectangular = hdr_path
+            .and_then(|hdr_path| {
+                ImageReader::open(hdr_path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|reader| reader.decode().map_err(|error| error.to_string()))
+                    .map_err(|error| error!("Failed to load {}: {}", hdr_path, error))
+                    .ok()
+            })
+            .map_or_else(
+                || Rgba32FImage::from_pixel(2, 1, Rgba([0.5, 0.5, 0.5, 1.0])),
+                |image| image.into_rgba32f(),
+            );
+
+        let skybox = CubeMap::from_fn(SKYBOX_SIZE, |direction| {
+            Self::sample_equirectangular(&equirectangular, &direction)
+        })
+        .with_mipmaps();
+
+        let irradiance = Self::create_irradiance(&skybox);
+        let prefiltered = Self::create_prefiltered(&skybox);
+        let brdf_lut = Self::create_brdf_lut();
+
+        Self {
+            skybox,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+        }
+    }
+
+    /// Texels as `R16G16_SFLOAT`, scale in red and bias in green.
+    pub(crate) fn brdf_lut_texels(&self) -> Vec<u8> {
+        self.brdf_lut
+            .iter()
+            .flat_map(|texel| [texel.x, texel.y])
+            .flat_map(|channel| f16::from_f32(channel).to_bits().to_ne_bytes())
+            .collect_vec()
+    }
+
+    fn sample_equirectangular(image: &Rgba32FImage, direction: &Vector3<f32>) -> Vector3<f32> {
+        let u = 0.5 + direction.y.atan2(direction.x) / (2.0 * PI);
+        let v = direction.z.clamp(-1.0, 1.0).acos() / PI;
+
+        let (width, height) = image.dimensions();
+        let x = (u * width as f32 - 0.5).rem_euclid(width as f32);
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+
+        let (x0, y0) = (x.floor() as u32 % width, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1) % width, (y0 + 1).min(height - 1));
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+
+        let texel = |x: u32, y: u32| {
+            let pixel = image.get_pixel(x, y);
+            Vector3::new(pixel[0], pixel[1], pixel[2])
+        };
+
+        texel(x0, y0) * (1.0 - tx) * (1.0 - ty)
+            + texel(x1, y0) * tx * (1.0 - ty)
+            + texel(x0, y1) * (1.0 - tx) * ty
+            + texel(x1, y1) * tx * ty
+    }
+
+    fn create_irradiance(skybox: &CubeMap) -> CubeMap {
+        let source_level = (skybox.size / IRRADIANCE_SOURCE_SIZE).trailing_zeros() as usize;
+        let source_size = skybox.level_size(source_level);
+
+        let weighted_radiances = (0..FACE_COUNT)
+            .cartesian_product(0..source_size * source_size)
+            .map(|(face, i)| {
+                let (x, y) = (i % source_size, i / source_size);
+                let radiance =
+                    skybox.levels[source_level][(face * source_size * source_size + i) as usize];
+
+                (
+                    CubeMap::direction(face, x, y, source_size),
+                    radiance * CubeMap::solid_angle(x, y, source_size),
+                )
+            })
+            .collect_vec();
+
+        CubeMap::from_fn(IRRADIANCE_SIZE, |normal| {
+            weighted_radiances
+                .iter()
+                .fold(Vector3::zeros(), |irradiance, (direction, radiance)| {
+                    irradiance + radiance * normal.dot(direction).max(0.0)
+                })
+                / PI
+        })
+    }
+
+    fn create_prefiltered(skybox: &CubeMap) -> CubeMap {
+        let levels = (0..PREFILTERED_MIP_LEVELS)
+            .map(|mip_level| {
+                let roughness = mip_level as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32;
+                let size = (PREFILTERED_SIZE >> mip_level).max(1);
+
+                CubeMap::from_fn(size, |normal| Self::prefilter(skybox, &normal, roughness))
+                    .levels
+                    .remove(0)
+            })
+            .collect_vec();
+
+        CubeMap {
+            size: PREFILTERED_SIZE,
+            levels,
+        }
+    }
+
+    fn prefilter(skybox: &CubeMap, normal: &Vector3<f32>, roughness: f32) -> Vector3<f32> {
+        if roughness == 0.0 {
+            let lod = (skybox.size as f32 / PREFILTERED_SIZE as f32).log2();
+            return skybox.sample_lod(normal, lod);
+        }
+
+        let (tangent, bitangent) = Self::tangent_frame(normal);
+        let texel_solid_angle = 4.0 * PI / (6.0 * (skybox.size * skybox.size) as f32);
+
+        let (color, weight) =
+            (0..PREFILTERED_SAMPLE_COUNT).fold((Vector3::zeros(), 0.0), |(color, weight), i| {
+                let xi = Self::hammersley(i, PREFILTERED_SAMPLE_COUNT);
+                let half = Self::importance_sample_ggx(&xi, roughness);
+                let half = tangent * half.x + bitangent * half.y + normal * half.z;
+                let light = half * 2.0 * normal.dot(&half) - normal;
+
+                let n_dot_l = normal.dot(&light);
+                if n_dot_l <= 0.0 {
+                    return (color, weight);
+                }
+
+                let pdf = Self::distribution_ggx(normal.dot(&half).max(0.0), roughness) / 4.0;
+                let sample_solid_angle = 1.0 / (PREFILTERED_SAMPLE_COUNT as f32 * pdf + 0.0001);
+                let lod = 0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0;
+
+                (
+                    color + skybox.sample_lod(&light, lod) * n_dot_l,
+                    weight + n_dot_l,
+                )
+            });
+
+        color / weight
+    }
+
+    fn create_brdf_lut() -> Vec<Vector2<f32>> {
+        (0..BRDF_LUT_SIZE * BRDF_LUT_SIZE)
+            .map(|i| {
+                let n_dot_v = ((i % BRDF_LUT_SIZE) as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+                let roughness = ((i / BRDF_LUT_SIZE) as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+
+                Self::integrate_brdf(n_dot_v, roughness)
+            })
+            .collect_vec()
+    }
+
+    fn integrate_brdf(n_dot_v: f32, roughness: f32) -> Vector2<f32> {
+        let view = Vector3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+
+        (0..BRDF_LUT_SAMPLE_COUNT).fold(Vector2::zeros(), |scale_and_bias, i| {
+            let xi = Self::hammersley(i, BRDF_LUT_SAMPLE_COUNT);
+            let half = Self::importance_sample_ggx(&xi, roughness);
+            let light = half * 2.0 * view.dot(&half) - view;
+
+            let n_dot_l = light.z.max(0.0);
+            let n_dot_h = half.z.max(0.0);
+            let v_dot_h = view.dot(&half).max(0.0);
+
+            if n_dot_l <= 0.0 {
+                return scale_and_bias;
+            }
+
+            let geometry = Self::geometry_schlick_ggx(n_dot_v, roughness)
+                * Self::geometry_schlick_ggx(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = (1.0 - v_dot_h).powi(5);
+
+            scale_and_bias + Vector2::new((1.0 - fresnel) * visibility, fresnel * visibility)
+        }) / BRDF_LUT_SAMPLE_COUNT as f32
+    }
+
+    fn hammersley(i: u32, count: u32) -> Vector2<f32> {
+        Vector2::new(
+            i as f32 / count as f32,
+            i.reverse_bits() as f32 * 2.328_306_4e-10,
+        )
+    }
+
+    fn importance_sample_ggx(xi: &Vector2<f32>, roughness: f32) -> Vector3<f32> {
+        let alpha = roughness * roughness;
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = ((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+    }
+
+    fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+        let alpha = roughness * roughness;
+        let alpha_squared = alpha * alpha;
+        let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+
+        alpha_squared / (PI * denominator * denominator)
+    }
+
+    fn geometry_schlick_ggx(n_dot: f32, roughness: f32) -> f32 {
+        let k = roughness * roughness / 2.0;
+
+        n_dot / (n_dot * (1.0 - k) + k)
+    }
+
+    fn tangent_frame(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        let up = if normal.z.abs() < 0.999 {
+            Vector3::z()
+        } else {
+            Vector3::x()
+        };
+
+        let tangent = up.cross(normal).normalize();
+        let bitangent = normal.cross(&tangent);
+
+        (tangent, bitangent)
+    }
+}
@@ -0,0 +1,270 @@
+use half::f16;
+use itertools::Itertools;
+use vulkanalia::{
+    vk::{
+        self, BufferImageCopy, DeviceV1_0, Extent3D, Filter, HasBuilder, ImageAspectFlags,
+        ImageBlit, ImageLayout, ImageSubresourceLayers, Offset3D, SampleCountFlags,
+    },
+    Device as vkDevice,
+};
+
+use crate::{
+    buffer::Buffer,
+    command_buffer::CommandBuffer,
+    device::Device,
+    render_graph::{Access, ImageDescription, RenderGraph, ResourceHandle},
+};
+
+const LUMINANCE_SIZE: u32 = 64;
+const HISTOGRAM_BIN_COUNT: usize = 64;
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const MAX_LOG_LUMINANCE: f32 = 10.0;
+const LOW_PERCENTILE: f32 = 0.5;
+const HIGH_PERCENTILE: f32 = 0.95;
+const MIDDLE_GRAY: f32 = 0.18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ExposurePass {
+    Downsample,
+    Readback,
+}
+
+/// Exposure applied before tonemapping. In automatic mode the HDR target is downsampled
+/// and read back every frame, and the exposure adapts towards the one that maps the average
+/// luminance of a trimmed histogram to middle gray.
+#[derive(Clone, Debug)]
+pub(crate) struct Exposure {
+    pub(crate) automatic: bool,
+    /// Linear multiplier used when `automatic` is off.
+    pub(crate) manual: f32,
+    /// Offset in stops applied in both modes.
+    pub(crate) compensation: f32,
+    /// How fast automatic exposure follows the scene, in 1 / seconds.
+    pub(crate) adaptation_speed: f32,
+    adapted: f32,
+    hdr_image: ResourceHandle,
+    luminance_image: ResourceHandle,
+    readback_buffers: Vec<Buffer<f16>>,
+    metered: Vec<bool>,
+    device: Device,
+}
+
+impl Exposure {
+    /// Adds the metering passes of `hdr_image` to the graph.
+    pub(crate) fn new<P: Copy + From<ExposurePass>>(
+        render_graph: &mut RenderGraph<P>,
+        hdr_image: ResourceHandle,
+        frame_count: usize,
+        device: Device,
+    ) -> Self {
+        let readback_buffers = (0..frame_count)
+            .map(|_| Buffer::<f16>::new_readback(Self::texel_count() * 4, device.clone()))
+            .collect_vec();
+
+        let luminance_image = render_graph.create_image(ImageDescription {
+            extent: Extent3D::builder()
+                .width(LUMINANCE_SIZE)
+                .height(LUMINANCE_SIZE)
+                .depth(1)
+                .build(),
+            format: render_graph.description(hdr_image).format,
+            msaa_sample_count: SampleCountFlags::_1,
+        });
+        let readback = render_graph.import_buffers(
+            readback_buffers.iter().map(vk::Buffer::from).collect_vec(),
+            Some(Access::HostRead),
+        );
+
+        render_graph.add_pass(
+            ExposurePass::Downsample.into(),
+            &[(hdr_image, Access::TransferSrc)],
+            &[(luminance_image, Access::TransferDst)],
+        );
+        render_graph.add_pass(
+            ExposurePass::Readback.into(),
+            &[(luminance_image, Access::TransferSrc)],
+            &[(readback, Access::TransferDst)],
+        );
+
+        Self {
+            automatic: true,
+            manual: 1.0,
+            compensation: 0.0,
+            adaptation_speed: 1.5,
+            adapted: 1.0,
+            hdr_image,
+            luminance_image,
+            readback_buffers,
+            metered: vec![false; frame_count],
+            device,
+        }
+    }
+
+    pub(crate) fn value(&self) -> f32 {
+        let exposure = if self.automatic {
+            self.adapted
+        } else {
+            self.manual
+        };
+
+        exposure * self.compensation.exp2()
+    }
+
+    /// Adapts to the luminance metered the last time `frame` was rendered, so it has to be
+    /// called after waiting for the fence of that frame and before recording it again.
+    pub(crate) fn update(&mut self, frame: usize, delta_time: f32) {
+        let metered = std::mem::replace(&mut self.metered[frame], self.automatic);
+
+        if !self.automatic || !metered {
+            return;
+        }
+
+        let texels = self.readback_buffers[frame].read(Self::texel_count() * 4);
+        let luminances = texels
+            .chunks(4)
+            .map(|texel| {
+                0.2126 * texel[0].to_f32() + 0.7152 * texel[1].to_f32() + 0.0722 * texel[2].to_f32()
+            })
+            .collect_vec();
+
+        let target = MIDDLE_GRAY / Self::average_luminance(&Self::histogram(&luminances));
+        let blend = 1.0 - (-delta_time * self.adaptation_speed).exp();
+
+        self.adapted += (target - self.adapted) * blend;
+    }
+
+    /// Records `pass` of the metering, nothing is recorded in manual mode.
+    pub(crate) fn record<P: Copy>(
+        &self,
+        render_graph: &RenderGraph<P>,
+        pass: ExposurePass,
+        command_buffer: &CommandBuffer,
+        frame: usize,
+    ) {
+        if !self.automatic {
+            return;
+        }
+
+        let subresource = ImageSubresourceLayers::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let hdr_image = render_graph.image(self.hdr_image);
+        let luminance_image = render_graph.image(self.luminance_image);
+
+        match pass {
+            ExposurePass::Downsample => {
+                let image_blit = ImageBlit::builder()
+                    .src_offsets([
+                        Offset3D::default(),
+                        Offset3D {
+                            x: hdr_image.extent.width as i32,
+                            y: hdr_image.extent.height as i32,
+                            z: 1,
+                        },
+                    ])
+                    .src_subresource(subresource)
+                    .dst_offsets([
+                        Offset3D::default(),
+                        Offset3D {
+                            x: LUMINANCE_SIZE as i32,
+                            y: LUMINANCE_SIZE as i32,
+                            z: 1,
+                        },
+                    ])
+                    .dst_subresource(subresource);
+
+                unsafe {
+                    vkDevice::from(self.device.clone()).cmd_blit_image(
+                        command_buffer.into(),
+                        hdr_image.into(),
+                        ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        luminance_image.into(),
+                        ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &[image_blit],
+                        Filter::LINEAR,
+                    );
+                }
+            }
+            ExposurePass::Readback => {
+                let buffer_image_copy = BufferImageCopy::builder()
+                    .buffer_offset(0)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(subresource)
+                    .image_offset(Offset3D::default())
+                    .image_extent(luminance_image.extent);
+
+                unsafe {
+                    vkDevice::from(self.device.clone()).cmd_copy_image_to_buffer(
+                        command_buffer.into(),
+                        luminance_image.into(),
+                        ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        (&self.readback_buffers[frame]).into(),
+                        &[buffer_image_copy],
+                    );
+                }
+            }
+        }
+    }
+
+    fn texel_count() -> usize {
+        (LUMINANCE_SIZE * LUMINANCE_SIZE) as usize
+    }
+
+    fn histogram(luminances: &[f32]) -> [u32; HISTOGRAM_BIN_COUNT] {
+        let mut histogram = [0; HISTOGRAM_BIN_COUNT];
+
+        luminances.iter().for_each(|luminance| {
+            let log_luminance = luminance.max(f32::MIN_POSITIVE).log2();
+            let t = (log_luminance - MIN_LOG_LUMINANCE) / (MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE);
+            let bin = ((t.clamp(0.0, 1.0) * HISTOGRAM_BIN_COUNT as f32) as usize)
+                .min(HISTOGRAM_BIN_COUNT - 1);
+
+            histogram[bin] += 1;
+        });
+
+        histogram
+    }
+
+    // Averages the log luminance between the low and high percentiles, so that small very
+    // dark or very bright areas do not drive the exposure.
+    fn average_luminance(histogram: &[u32; HISTOGRAM_BIN_COUNT]) -> f32 {
+        let total = histogram.iter().sum::<u32>() as f32;
+        let (low, high) = (total * LOW_PERCENTILE, total * HIGH_PERCENTILE);
+
+        let (_, weighted_sum, weight) = histogram.iter().enumerate().fold(
+            (0.0, 0.0, 0.0),
+            |(passed, weighted_sum, weight), (bin, &count)| {
+                let count = count as f32;
+                let included = (passed + count).min(high) - passed.max(low);
+
+                if included <= 0.0 {
+                    return (passed + count, weighted_sum, weight);
+                }
+
+                let t = (bin as f32 + 0.5) / HISTOGRAM_BIN_COUNT as f32;
+                let log_luminance = MIN_LOG_LUMINANCE + t * (MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE);
+
+                (
+                    passed + count,
+                    weighted_sum + log_luminance * included,
+                    weight + included,
+                )
+            },
+        );
+
+        if weight > 0.0 {
+            (weighted_sum / weight).exp2()
+        } else {
+            MIDDLE_GRAY
+        }
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.readback_buffers.iter().for_each(Buffer::destroy);
+    }
+}
@@ -1,5 +1,5 @@
 use vulkanalia::{
-    vk::{self, DeviceV1_0, FenceCreateFlags, FenceCreateInfo, Handle, HasBuilder},
+    vk::{self, DeviceV1_0, FenceCreateFlags, FenceCreateInfo, Handle, HasBuilder, SuccessCode},
     Device as vkDevice,
 };
 
@@ -38,6 +38,16 @@ impl Fence {
         }
     }
 
+    /// Checks the fence without blocking.
+    pub(crate) fn is_signaled(&self) -> bool {
+        unsafe {
+            vkDevice::from(self.device.clone())
+                .get_fence_status(self.fence)
+                .unwrap()
+                == SuccessCode::SUCCESS
+        }
+    }
+
     pub(crate) fn reset(&self) {
         unsafe {
             vkDevice::from(self.device.clone())
@@ -30,11 +30,34 @@ impl Framebuffer {
             vec![*swapchain_image_view, depth_image_view]
         };
 
+        Self::with_attachments(
+            device,
+            attachments.as_slice(),
+            render_pass,
+            swapchain_extent,
+        )
+    }
+
+    pub(crate) fn new_fullscreen(
+        device: Device,
+        image_view: ImageView,
+        render_pass: RenderPass,
+        extent: Extent2D,
+    ) -> Self {
+        Self::with_attachments(device, &[image_view], render_pass, extent)
+    }
+
+    fn with_attachments(
+        device: Device,
+        attachments: &[ImageView],
+        render_pass: RenderPass,
+        extent: Extent2D,
+    ) -> Self {
         let framebuffer_create_info = FramebufferCreateInfo::builder()
             .render_pass(render_pass.render_pass)
-            .attachments(attachments.as_slice())
-            .width(swapchain_extent.width)
-            .height(swapchain_extent.height)
+            .attachments(attachments)
+            .width(extent.width)
+            .height(extent.height)
             .layers(1);
 
         let framebuffer = unsafe {
@@ -0,0 +1,74 @@
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::bounding_volume::{Aabb, BoundingSphere};
+
+/// Planes of the volume a camera sees, in world space with their normals pointing inside.
+/// The tests are conservative: volumes close to an edge of the frustum can pass while being
+/// just outside of it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Frustum of an OpenGL style projection, as in "Fast Extraction of Viewing Frustum
+    /// Planes from the World-View-Projection Matrix" by Gribb and Hartmann.
+    pub(crate) fn new(view_projection: &Matrix4<f32>) -> Self {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(3) + row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.xyz().norm().max(f32::MIN_POSITIVE));
+
+        Self { planes }
+    }
+
+    pub(crate) fn intersects_sphere(
+        &self,
+        sphere: &BoundingSphere,
+        model_matrix: &Matrix4<f32>,
+    ) -> bool {
+        let (center, radius) = Self::transform_sphere(sphere, model_matrix);
+
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&center) + plane.w >= -radius)
+    }
+
+    /// Center and radius of a sphere around the transformed sphere.
+    pub(crate) fn transform_sphere(
+        sphere: &BoundingSphere,
+        model_matrix: &Matrix4<f32>,
+    ) -> (Vector3<f32>, f32) {
+        let center = model_matrix.transform_point(&sphere.center.into()).coords;
+        // Scaled as much as the most scaled axis
+        let radius = sphere.radius * Self::max_scale(model_matrix);
+
+        (center, radius)
+    }
+
+    /// Scale of the most scaled axis of `model_matrix`.
+    pub(crate) fn max_scale(model_matrix: &Matrix4<f32>) -> f32 {
+        (0..3)
+            .map(|axis| model_matrix.fixed_view::<3, 1>(0, axis).norm())
+            .fold(0.0, f32::max)
+    }
+
+    /// Tests the box around the transformed box, as in "Transforming Axis-Aligned Bounding
+    /// Boxes" by Arvo.
+    pub(crate) fn intersects_aabb(&self, aabb: &Aabb, model_matrix: &Matrix4<f32>) -> bool {
+        let center = model_matrix.transform_point(&aabb.center().into()).coords;
+        let half_extent = model_matrix.fixed_view::<3, 3>(0, 0).abs() * (aabb.max - aabb.min) / 2.0;
+
+        self.planes.iter().all(|plane| {
+            let normal: Vector3<f32> = plane.xyz();
+
+            normal.dot(&center) + plane.w + normal.abs().dot(&half_extent) >= 0.0
+        })
+    }
+}
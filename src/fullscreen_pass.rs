@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use vulkanalia::vk::{Extent2D, Format, ImageLayout, ImageView};
+
+use crate::{
+    command_buffer::CommandBuffer, descriptor_pool::DescriptorPool, descriptor_set::DescriptorSet,
+    device::Device, framebuffer::Framebuffer, pipeline::Pipeline, render_pass::RenderPass,
+    sampler::Sampler,
+};
+
+/// Fragment shader drawn over whole images. Every target gets its own framebuffer and every
+/// group of inputs its own descriptor set, so one pass can run over a chain of images. Targets
+/// are left in `COLOR_ATTACHMENT_OPTIMAL` for the render graph to transition.
+#[derive(Clone, Debug)]
+pub(crate) struct FullscreenPass {
+    render_pass: RenderPass,
+    pipeline: Pipeline,
+    framebuffers: Vec<Framebuffer>,
+    extents: Vec<Extent2D>,
+    descriptor_pool: DescriptorPool,
+    descriptor_sets: Vec<DescriptorSet>,
+}
+
+impl FullscreenPass {
+    pub(crate) fn new(
+        device: Device,
+        format: Format,
+        fragment_shader: &str,
+        push_constant_size: usize,
+        targets: &[(ImageView, Extent2D)],
+        inputs: &[Vec<(ImageView, Sampler)>],
+    ) -> Self {
+        let render_pass = RenderPass::new_fullscreen(
+            device.clone(),
+            format,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+
+        let framebuffers = targets
+            .iter()
+            .map(|(image_view, extent)| {
+                Framebuffer::new_fullscreen(
+                    device.clone(),
+                    *image_view,
+                    render_pass.clone(),
+                    *extent,
+                )
+            })
+            .collect_vec();
+        let extents = targets.iter().map(|(_, extent)| *extent).collect_vec();
+
+        let pipeline = Pipeline::new_fullscreen(
+            device.clone(),
+            render_pass.clone(),
+            fragment_shader,
+            push_constant_size,
+        );
+
+        let descriptor_pool =
+            DescriptorPool::new(device.clone(), &[(&pipeline.reflection, inputs.len())]);
+        let descriptor_sets = inputs
+            .iter()
+            .map(|image_samplers| {
+                DescriptorSet::new_fullscreen(
+                    device.clone(),
+                    descriptor_pool.clone().into(),
+                    &pipeline,
+                    image_samplers.as_slice(),
+                )
+            })
+            .collect_vec();
+
+        Self {
+            render_pass,
+            pipeline,
+            framebuffers,
+            extents,
+            descriptor_pool,
+            descriptor_sets,
+        }
+    }
+
+    pub(crate) fn record(
+        &self,
+        command_buffer: &CommandBuffer,
+        target: usize,
+        input: usize,
+        push_constants: &[u8],
+    ) {
+        command_buffer.begin_render_pass(
+            self.extents[target],
+            self.render_pass.clone(),
+            self.framebuffers[target].clone(),
+            self.pipeline.clone(),
+        );
+        command_buffer.record_fullscreen_drawing(
+            &self.pipeline,
+            self.descriptor_sets[input].clone().into(),
+            push_constants,
+        );
+        command_buffer.end_render_pass();
+    }
+
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        self.pipeline.reload_shaders(changed_paths);
+    }
+
+    /// Replaces a group of inputs, the pass must not be in use.
+    pub(crate) fn update_input(&self, input: usize, image_samplers: &[(ImageView, Sampler)]) {
+        self.descriptor_sets[input].update_fullscreen(image_samplers);
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.pipeline.destroy();
+        self.descriptor_pool.destroy();
+        self.framebuffers.iter().for_each(Framebuffer::destroy);
+        self.render_pass.destroy();
+    }
+}
@@ -0,0 +1,198 @@
+use std::mem::size_of;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use numpy::{PyArray1, PyArrayDyn, PyReadonlyArrayDyn};
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+use vulkanalia::vk::{
+    self, AccessFlags, BufferUsageFlags, HasBuilder, MemoryPropertyFlags, PipelineStageFlags,
+    SubmitInfo,
+};
+
+use crate::{
+    buffer::Buffer,
+    command_buffer::CommandBuffer,
+    command_pool::CommandPool,
+    compute_pipeline::ComputePipeline,
+    descriptor_pool::DescriptorPool,
+    descriptor_set::{ComputeResource, DescriptorSet},
+    device::Device,
+    fence::Fence,
+    queue::Queue,
+};
+
+/// Runs compute shaders over NumPy arrays for scripts, on the compute queue. Every array
+/// goes to a storage buffer of 32-bit floats and is read back once the shader is done, so
+/// a run takes as long as the shader. Scripts reach it through the [`Scene`].
+///
+/// [`Scene`]: crate::scene::Scene
+#[derive(Debug)]
+pub(crate) struct GpuCompute {
+    /// By shader name, they are built on their first run.
+    pipelines: HashMap<String, ComputePipeline>,
+    command_pool: CommandPool,
+    command_buffer: CommandBuffer,
+    fence: Fence,
+    queue: Queue,
+    destroyed: bool,
+    device: Device,
+}
+
+impl GpuCompute {
+    pub(crate) fn new(device: Device, queue_family_index: u32, queue: Queue) -> Self {
+        let command_pool = CommandPool::new(device.clone(), queue_family_index);
+        let command_buffer = CommandBuffer::new(device.clone(), command_pool.clone());
+        let fence = Fence::new(device.clone(), true);
+
+        Self {
+            pipelines: HashMap::new(),
+            command_pool,
+            command_buffer,
+            fence,
+            queue,
+            destroyed: false,
+            device,
+        }
+    }
+
+    fn pipeline(&mut self, shader: &str) -> Result<&ComputePipeline, String> {
+        if !self.pipelines.contains_key(shader) {
+            let pipeline = ComputePipeline::with_shader(self.device.clone(), shader)?;
+            self.pipelines.insert(shader.to_string(), pipeline);
+        }
+
+        Ok(&self.pipelines[shader])
+    }
+
+    fn dispatch(
+        &mut self,
+        shader: &str,
+        arrays: &[Vec<f32>],
+        group_counts: [u32; 3],
+        push_constants: &[u8],
+    ) -> Result<Vec<Vec<f32>>, String> {
+        if arrays.iter().any(Vec::is_empty) {
+            return Err("Empty arrays can't be bound to storage buffers".to_string());
+        }
+
+        let device = self.device.clone();
+        let pipeline = self.pipeline(shader)?.clone();
+        if push_constants.len() < pipeline.reflection.push_constant_size {
+            return Err(format!(
+                "{} takes {} bytes of push constants but {} were given",
+                shader,
+                pipeline.reflection.push_constant_size,
+                push_constants.len()
+            ));
+        }
+
+        let buffers = arrays
+            .iter()
+            .map(|values| {
+                let buffer = Buffer::<f32>::new(
+                    (size_of::<f32>() * values.len()) as u64,
+                    BufferUsageFlags::STORAGE_BUFFER,
+                    device.clone(),
+                    MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+                );
+                buffer.copy_memory(buffer.clone(), values);
+
+                buffer
+            })
+            .collect_vec();
+        let resources = buffers
+            .iter()
+            .map(|buffer| ComputeResource::StorageBuffer(buffer.into()))
+            .collect_vec();
+
+        let descriptor_pool = DescriptorPool::new(device.clone(), &[(&pipeline.reflection, 1)]);
+        let result = DescriptorSet::try_new_compute(
+            device,
+            (&descriptor_pool).into(),
+            &pipeline,
+            &resources,
+        )
+        .map(|descriptor_set| {
+            self.command_buffer.reset();
+            self.command_buffer.begin();
+            self.command_buffer.record_dispatch(
+                &pipeline,
+                (&descriptor_set).into(),
+                push_constants,
+                group_counts,
+            );
+            self.command_buffer.record_memory_barrier(
+                (
+                    PipelineStageFlags::COMPUTE_SHADER,
+                    AccessFlags::SHADER_WRITE,
+                ),
+                (PipelineStageFlags::HOST, AccessFlags::HOST_READ),
+            );
+            self.command_buffer.end();
+
+            let command_buffers = &[vk::CommandBuffer::from(&self.command_buffer)];
+            let submit_info = SubmitInfo::builder()
+                .command_buffers(command_buffers)
+                .build();
+
+            self.fence.reset();
+            self.queue.submit(submit_info, self.fence.clone());
+            self.fence.wait();
+
+            buffers
+                .iter()
+                .zip(arrays)
+                .map(|(buffer, values)| buffer.read(values.len()))
+                .collect_vec()
+        });
+
+        descriptor_pool.destroy();
+        buffers.iter().for_each(Buffer::destroy);
+
+        result
+    }
+
+    /// Must be called before the device is destroyed, later runs fail.
+    pub(crate) fn destroy(&mut self) {
+        self.pipelines.values().for_each(ComputePipeline::destroy);
+        self.pipelines.clear();
+        self.fence.destroy();
+        self.command_pool.destroy();
+        self.destroyed = true;
+    }
+
+    /// Runs `group_counts` work groups of `shader`, a compute shader whose bindings are
+    /// storage buffers taking `arrays` in order, and returns the arrays as it left them.
+    pub(crate) fn run(
+        &mut self,
+        py: Python,
+        shader: &str,
+        arrays: Vec<PyReadonlyArrayDyn<f32>>,
+        group_counts: (u32, u32, u32),
+        push_constants: Option<&[u8]>,
+    ) -> PyResult<Vec<Py<PyArrayDyn<f32>>>> {
+        if self.destroyed {
+            return Err(PyRuntimeError::new_err("The renderer has been destroyed"));
+        }
+
+        let shapes = arrays
+            .iter()
+            .map(|array| array.shape().to_vec())
+            .collect_vec();
+        let values = arrays
+            .iter()
+            .map(|array| array.as_array().iter().copied().collect_vec())
+            .collect_vec();
+
+        let (x, y, z) = group_counts;
+        let results = self
+            .dispatch(shader, &values, [x, y, z], push_constants.unwrap_or(&[]))
+            .map_err(PyRuntimeError::new_err)?;
+
+        results
+            .into_iter()
+            .zip(shapes)
+            .map(|(values, shape)| Ok(PyArray1::from_vec(py, values).reshape(shape)?.to_owned()))
+            .collect()
+    }
+}
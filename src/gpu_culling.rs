@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use nalgebra::Vector4;
+use vulkanalia::vk::{
+    self, BufferUsageFlags, DeviceSize, DrawIndexedIndirectCommand, MemoryPropertyFlags,
+};
+
+use crate::{
+    buffer::Buffer,
+    command_buffer::CommandBuffer,
+    compute_pipeline::ComputePipeline,
+    descriptor_pool::DescriptorPool,
+    descriptor_set::{ComputeResource, DescriptorSet},
+    device::Device,
+    hi_z::HiZ,
+    render_graph::{Access, RenderGraph, ResourceHandle},
+    ubo::Ubo,
+};
+
+/// Objects that can be culled in a frame, the others are not drawn.
+pub(crate) const MAX_OBJECT_COUNT: usize = 1 << 17;
+/// Indirect draws of a frame, each one with its own draw count.
+pub(crate) const MAX_BATCH_COUNT: usize = 4096;
+const GROUP_SIZE: u32 = 64;
+
+/// Object tested by the culling shader, and the draw of it that is written when it is
+/// visible. Laid out as `Object` in `cull.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CulledObject {
+    /// Center and radius of the bounding sphere in world space.
+    pub(crate) sphere: Vector4<f32>,
+    pub(crate) first_index: u32,
+    pub(crate) index_count: u32,
+    pub(crate) vertex_offset: i32,
+    /// Index of the count of the indirect draw the object is drawn by.
+    pub(crate) batch: u32,
+    /// Command of the object, or first command of its batch when visible objects are packed.
+    pub(crate) first_command: u32,
+    padding: [u32; 3],
+}
+
+impl CulledObject {
+    pub(crate) fn new(
+        sphere: Vector4<f32>,
+        first_index: u32,
+        index_count: u32,
+        vertex_offset: i32,
+        batch: u32,
+        first_command: u32,
+    ) -> Self {
+        Self {
+            sphere,
+            first_index,
+            index_count,
+            vertex_offset,
+            batch,
+            first_command,
+            padding: [0; 3],
+        }
+    }
+}
+
+/// Frustum and occlusion culling on the GPU. A compute pass tests the bounding sphere of
+/// every object against the frustum and the depth pyramid of the previous frame, and writes
+/// the indirect draw commands of the visible ones. With `draw_indirect_count` the visible
+/// objects are packed and their number is read by the draws, otherwise hidden objects get a
+/// command drawing no instance.
+#[derive(Debug)]
+pub(crate) struct GpuCulling {
+    /// Objects are packed and counted for indirect draws with a count buffer.
+    pub(crate) compact: bool,
+    /// One per frame in flight.
+    pub(crate) draw_buffers: Vec<Buffer<DrawIndexedIndirectCommand>>,
+    /// Visible objects of every batch, one buffer per frame in flight.
+    pub(crate) count_buffers: Vec<Buffer<u32>>,
+    camera_buffers: Vec<Buffer<Ubo>>,
+    object_buffers: Vec<Buffer<CulledObject>>,
+    /// Objects, batches and whether occlusion was tested the last time a frame was culled.
+    culled: Vec<(usize, usize, bool)>,
+    pipeline: ComputePipeline,
+    descriptor_pool: DescriptorPool,
+    descriptor_sets: Vec<DescriptorSet>,
+}
+
+impl GpuCulling {
+    /// Adds the culling pass, labeled `pass`, for `frame_count` frames in flight. The
+    /// returned draw and count buffers have to be read by the passes drawing them.
+    pub(crate) fn new<P: Copy>(
+        render_graph: &mut RenderGraph<P>,
+        pass: P,
+        hi_z: &HiZ,
+        frame_count: usize,
+        device: Device,
+    ) -> (Self, [ResourceHandle; 2]) {
+        let host_visible = MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT;
+        let indirect_storage = BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::INDIRECT_BUFFER;
+
+        let camera_buffers = (0..frame_count)
+            .map(|_| Buffer::<Ubo>::from_uniform_data(device.clone()))
+            .collect_vec();
+        let object_buffers = (0..frame_count)
+            .map(|_| {
+                Buffer::new(
+                    Self::size::<CulledObject>(MAX_OBJECT_COUNT),
+                    BufferUsageFlags::STORAGE_BUFFER,
+                    device.clone(),
+                    host_visible,
+                )
+            })
+            .collect_vec();
+        let draw_buffers = (0..frame_count)
+            .map(|_| {
+                Buffer::new(
+                    Self::size::<DrawIndexedIndirectCommand>(MAX_OBJECT_COUNT),
+                    indirect_storage,
+                    device.clone(),
+                    MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect_vec();
+        let count_buffers = (0..frame_count)
+            .map(|_| {
+                Buffer::new(
+                    Self::size::<u32>(MAX_BATCH_COUNT),
+                    indirect_storage,
+                    device.clone(),
+                    host_visible,
+                )
+            })
+            .collect_vec();
+
+        let pipeline = ComputePipeline::new(device.clone(), "cull.comp");
+        let descriptor_pool =
+            DescriptorPool::new(device.clone(), &[(&pipeline.reflection, frame_count)]);
+        let descriptor_sets = (0..frame_count)
+            .map(|frame| {
+                let resources = [
+                    ComputeResource::UniformBuffer((&camera_buffers[frame]).into()),
+                    ComputeResource::StorageBuffer((&object_buffers[frame]).into()),
+                    ComputeResource::StorageBuffer((&draw_buffers[frame]).into()),
+                    ComputeResource::StorageBuffer((&count_buffers[frame]).into()),
+                    ComputeResource::ImageSampler(hi_z.image.view, (&hi_z.sampler).into()),
+                ];
+
+                DescriptorSet::new_compute(
+                    device.clone(),
+                    (&descriptor_pool).into(),
+                    &pipeline,
+                    &resources,
+                )
+            })
+            .collect_vec();
+
+        let draws = render_graph.import_buffers(
+            draw_buffers.iter().map(vk::Buffer::from).collect_vec(),
+            None,
+        );
+        // The counts are read back for the statistics
+        let counts = render_graph.import_buffers(
+            count_buffers.iter().map(vk::Buffer::from).collect_vec(),
+            Some(Access::HostRead),
+        );
+        // The depth pyramid is left readable by the frame before, so its read isn't declared
+        // or the pass would depend on the pass building it from this frame
+        render_graph.add_pass(
+            pass,
+            &[],
+            &[(draws, Access::Storage), (counts, Access::Storage)],
+        );
+
+        let gpu_culling = Self {
+            compact: device.draw_indirect_count,
+            draw_buffers,
+            count_buffers,
+            camera_buffers,
+            object_buffers,
+            culled: vec![(0, 0, false); frame_count],
+            pipeline,
+            descriptor_pool,
+            descriptor_sets,
+        };
+
+        (gpu_culling, [draws, counts])
+    }
+
+    fn size<T>(len: usize) -> DeviceSize {
+        (std::mem::size_of::<T>() * len) as DeviceSize
+    }
+
+    /// Objects found visible and culled the last time `frame` was culled, it has to be
+    /// called after waiting for the fence of that frame.
+    pub(crate) fn counts(&self, frame: usize) -> (usize, usize) {
+        let (object_count, batch_count, _) = self.culled[frame];
+
+        let visible_count = self.count_buffers[frame]
+            .read(batch_count)
+            .iter()
+            .map(|count| *count as usize)
+            .sum::<usize>();
+
+        (visible_count, object_count.saturating_sub(visible_count))
+    }
+
+    /// Writes what the culling of `frame` reads, at most [`MAX_OBJECT_COUNT`] objects in
+    /// at most [`MAX_BATCH_COUNT`] batches. The frame must not be in flight.
+    pub(crate) fn update(
+        &mut self,
+        frame: usize,
+        camera: &Ubo,
+        objects: &[CulledObject],
+        batch_count: usize,
+        occlusion: bool,
+    ) {
+        let camera_buffer = &self.camera_buffers[frame];
+        camera_buffer.copy_memory(camera_buffer.clone(), &[*camera]);
+        let object_buffer = &self.object_buffers[frame];
+        object_buffer.copy_memory(object_buffer.clone(), objects);
+        let count_buffer = &self.count_buffers[frame];
+        count_buffer.copy_memory(count_buffer.clone(), &vec![0; batch_count]);
+
+        self.culled[frame] = (objects.len(), batch_count, occlusion);
+    }
+
+    pub(crate) fn record(&self, command_buffer: &CommandBuffer, frame: usize) {
+        let (object_count, _, occlusion) = self.culled[frame];
+        if object_count == 0 {
+            return;
+        }
+
+        let push_constants = [object_count as u32, self.compact as u32, occlusion as u32]
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect_vec();
+
+        command_buffer.record_dispatch(
+            &self.pipeline,
+            (&self.descriptor_sets[frame]).into(),
+            push_constants.as_slice(),
+            [(object_count as u32).div_ceil(GROUP_SIZE), 1, 1],
+        );
+    }
+
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        self.pipeline.reload_shaders(changed_paths);
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.descriptor_pool.destroy();
+        self.pipeline.destroy();
+        self.camera_buffers.iter().for_each(Buffer::destroy);
+        self.object_buffers.iter().for_each(Buffer::destroy);
+        self.draw_buffers.iter().for_each(Buffer::destroy);
+        self.count_buffers.iter().for_each(Buffer::destroy);
+    }
+}
@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use vulkanalia::{
+    vk::{
+        AccessFlags, DeviceV1_0, Extent3D, Format, ImageAspectFlags, ImageTiling, ImageUsageFlags,
+        ImageView, MemoryPropertyFlags, PipelineStageFlags, SampleCountFlags,
+    },
+    Device as vkDevice,
+};
+
+use crate::{
+    command_buffer::CommandBuffer,
+    compute_pipeline::ComputePipeline,
+    descriptor_pool::DescriptorPool,
+    descriptor_set::{ComputeResource, DescriptorSet},
+    device::Device,
+    image::Image,
+    instance::Instance,
+    physical_device::PhysicalDevice,
+    render_graph::{Access, RenderGraph, ResourceHandle},
+    sampler::Sampler,
+    upload_batcher::UploadBatcher,
+};
+
+const GROUP_SIZE: u32 = 8;
+
+/// Depth pyramid for occlusion culling: level 0 is a copy of the depth buffer and every
+/// other level keeps the farthest depth of the texels it covers in the level below. It is
+/// built after the scene is drawn and read by the culling of the next frame, so objects
+/// that were hidden may show up a frame late.
+#[derive(Debug)]
+pub(crate) struct HiZ {
+    pub(crate) image: Image,
+    pub(crate) sampler: Sampler,
+    /// Whether a pyramid has been built yet, it is undefined before.
+    pub(crate) built: bool,
+    level_views: Vec<ImageView>,
+    pipeline: ComputePipeline,
+    descriptor_pool: DescriptorPool,
+    descriptor_sets: Vec<DescriptorSet>,
+    depth_image: Option<ResourceHandle>,
+    device: Device,
+}
+
+impl HiZ {
+    /// Pyramid for a depth buffer of `extent`, left readable by the culling.
+    pub(crate) fn new(
+        extent: Extent3D,
+        device: Device,
+        instance: Instance,
+        physical_device: PhysicalDevice,
+        upload_batcher: &mut UploadBatcher,
+    ) -> Self {
+        let image = Image::new(
+            extent,
+            SampleCountFlags::_1,
+            device.clone(),
+            instance,
+            physical_device,
+            Image::mip_levels(extent),
+            Format::R32_SFLOAT,
+            ImageTiling::OPTIMAL,
+            ImageUsageFlags::SAMPLED | ImageUsageFlags::STORAGE,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+            ImageAspectFlags::COLOR,
+        );
+        upload_batcher
+            .record(|command_buffer| image.record_empty_shader_read_layout(command_buffer));
+
+        let level_views = (0..image.mip_levels)
+            .map(|mip_level| image.create_level_view(mip_level))
+            .collect_vec();
+        let sampler = Sampler::new_clamped(device.clone(), image.mip_levels);
+
+        let pipeline = ComputePipeline::new(device.clone(), "hi_z.comp");
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            &[(&pipeline.reflection, image.mip_levels as usize)],
+        );
+
+        Self {
+            image,
+            sampler,
+            built: false,
+            level_views,
+            pipeline,
+            descriptor_pool,
+            descriptor_sets: Vec::new(),
+            depth_image: None,
+            device,
+        }
+    }
+
+    /// Adds the pass building the pyramid from `depth_image`, which must be single sampled.
+    /// The pass is labeled `pass`, and the pyramid it writes is returned.
+    pub(crate) fn declare<P: Copy>(
+        &mut self,
+        render_graph: &mut RenderGraph<P>,
+        pass: P,
+        depth_image: ResourceHandle,
+    ) -> ResourceHandle {
+        let pyramid =
+            render_graph.import_persistent_image(self.image.vk_image, Access::ComputeSampled);
+
+        render_graph.add_pass(
+            pass,
+            &[(depth_image, Access::ComputeSampled)],
+            &[(pyramid, Access::Storage)],
+        );
+        self.depth_image = Some(depth_image);
+
+        pyramid
+    }
+
+    /// Points the first level at the depth image, once the graph has been compiled.
+    pub(crate) fn create_descriptor_sets<P: Copy>(&mut self, render_graph: &RenderGraph<P>) {
+        let depth_image = match self.depth_image {
+            Some(depth_image) => render_graph.image(depth_image),
+            None => return,
+        };
+
+        self.descriptor_sets = (0..self.level_views.len())
+            .map(|level| {
+                // The first level doesn't read a previous one
+                let source = self.level_views[level.saturating_sub(1)];
+                let resources = [
+                    ComputeResource::ImageSampler(depth_image.view, (&self.sampler).into()),
+                    ComputeResource::StorageImage(source),
+                    ComputeResource::StorageImage(self.level_views[level]),
+                ];
+
+                DescriptorSet::new_compute(
+                    self.device.clone(),
+                    (&self.descriptor_pool).into(),
+                    &self.pipeline,
+                    &resources,
+                )
+            })
+            .collect_vec();
+    }
+
+    /// Whether the pass building the pyramid was added to the graph.
+    pub(crate) fn declared(&self) -> bool {
+        self.depth_image.is_some()
+    }
+
+    pub(crate) fn record(&self, command_buffer: &CommandBuffer) {
+        self.descriptor_sets
+            .iter()
+            .enumerate()
+            .for_each(|(level, descriptor_set)| {
+                if level > 0 {
+                    command_buffer.record_memory_barrier(
+                        (
+                            PipelineStageFlags::COMPUTE_SHADER,
+                            AccessFlags::SHADER_WRITE,
+                        ),
+                        (PipelineStageFlags::COMPUTE_SHADER, AccessFlags::SHADER_READ),
+                    );
+                }
+
+                let width = (self.image.extent.width >> level).max(1);
+                let height = (self.image.extent.height >> level).max(1);
+                let first = ((level == 0) as u32).to_ne_bytes();
+
+                command_buffer.record_dispatch(
+                    &self.pipeline,
+                    descriptor_set.into(),
+                    &first,
+                    [width.div_ceil(GROUP_SIZE), height.div_ceil(GROUP_SIZE), 1],
+                );
+            });
+    }
+
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        self.pipeline.reload_shaders(changed_paths);
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.descriptor_pool.destroy();
+        self.pipeline.destroy();
+        self.sampler.destroy();
+        self.level_views.iter().for_each(|level_view| unsafe {
+            vkDevice::from(self.device.clone()).destroy_image_view(*level_view, None);
+        });
+        self.image.destroy();
+    }
+}
@@ -1,32 +1,35 @@
-use std::{ptr::copy_nonoverlapping as copy_memory, rc::Rc};
+use std::rc::Rc;
 
 use image::RgbaImage;
+use itertools::Itertools;
 use log::error;
 use vulkanalia::{
     vk::{
-        self, AccessFlags, BufferImageCopy, BufferMemoryBarrier, DependencyFlags, DeviceMemory,
+        self, AccessFlags, BufferImageCopy, BufferMemoryBarrier, DependencyFlags, DeviceSize,
         DeviceV1_0, Extent3D, Filter, Format, FormatFeatureFlags, HasBuilder, ImageAspectFlags,
-        ImageBlit, ImageCreateInfo, ImageLayout, ImageMemoryBarrier, ImageSubresourceLayers,
-        ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags, ImageView,
-        ImageViewCreateInfo, ImageViewType, InstanceV1_0, MemoryAllocateInfo, MemoryBarrier,
-        MemoryMapFlags, MemoryPropertyFlags, Offset3D, PipelineStageFlags, SampleCountFlags,
-        SharingMode, QUEUE_FAMILY_IGNORED,
+        ImageBlit, ImageCreateFlags, ImageCreateInfo, ImageLayout, ImageMemoryBarrier,
+        ImageSubresourceLayers, ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags,
+        ImageView, ImageViewCreateInfo, ImageViewType, InstanceV1_0, MemoryBarrier,
+        MemoryPropertyFlags, Offset3D, PipelineStageFlags, SampleCountFlags, SharingMode,
+        QUEUE_FAMILY_IGNORED,
     },
     Device as vkDevice,
 };
 
 use crate::{
-    buffer::Buffer, command_executor::CommandExecutor, command_pool::CommandPool, device::Device,
-    instance::Instance, memory::Memory, physical_device::PhysicalDevice, queue::Queue,
+    command_buffer::CommandBuffer, device::Device, instance::Instance, memory::Allocation,
+    physical_device::PhysicalDevice, upload_batcher::UploadBatcher,
 };
 
 #[derive(Clone, Debug)]
 pub(crate) struct Image {
     pub(crate) vk_image: vk::Image,
-    pub(crate) memory: DeviceMemory,
+    pub(crate) allocation: Allocation,
     pub(crate) view: ImageView,
     pub(crate) mip_levels: u32,
+    pub(crate) layer_count: u32,
     pub(crate) extent: Extent3D,
+    pub(crate) format: Format,
     device: Device,
     instance: Instance,
     physical_device: PhysicalDevice,
@@ -50,6 +53,8 @@ impl Image {
         let vk_image = Self::create_image(
             extent,
             mip_levels,
+            1,
+            ImageCreateFlags::empty(),
             format,
             image_tiling,
             image_usage_flags,
@@ -57,15 +62,8 @@ impl Image {
             device.clone(),
         );
 
-        let memory = Self::create_memory(
-            device.clone(),
-            vk_image,
-            instance.clone(),
-            physical_device.clone(),
-            memory_property_flags,
-        );
-
-        Self::bind_memory(device.clone(), vk_image, memory);
+        let allocation =
+            device.allocate_image_memory(vk_image, image_tiling, memory_property_flags);
 
         let view = Self::create_view(
             device.clone(),
@@ -77,59 +75,120 @@ impl Image {
 
         Self {
             vk_image,
-            memory,
+            allocation,
             view,
             mip_levels,
+            layer_count: 1,
             device,
             extent,
+            format,
             instance,
             physical_device,
         }
     }
 
-    pub(crate) fn new_unresolved(
-        extent: Extent3D,
-        msaa_sample_count: SampleCountFlags,
+    pub(crate) fn new_cube(
+        size: u32,
+        mip_levels: u32,
+        format: Format,
         device: Device,
         instance: Instance,
         physical_device: PhysicalDevice,
-        swapchain_format: Format,
-    ) -> Image {
-        Image::new(
+    ) -> Self {
+        let extent = Extent3D::builder()
+            .width(size)
+            .height(size)
+            .depth(1)
+            .build();
+
+        let vk_image = Self::create_image(
             extent,
-            msaa_sample_count,
+            mip_levels,
+            6,
+            ImageCreateFlags::CUBE_COMPATIBLE,
+            format,
+            ImageTiling::OPTIMAL,
+            ImageUsageFlags::SAMPLED | ImageUsageFlags::TRANSFER_DST,
+            SampleCountFlags::_1,
+            device.clone(),
+        );
+
+        let allocation = device.allocate_image_memory(
+            vk_image,
+            ImageTiling::OPTIMAL,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        let view = Self::create_typed_view(
+            device.clone(),
+            vk_image,
+            format,
+            ImageViewType::CUBE,
+            mip_levels,
+            6,
+        );
+
+        Self {
+            vk_image,
+            allocation,
+            view,
+            mip_levels,
+            layer_count: 6,
             device,
+            extent,
+            format,
             instance,
             physical_device,
-            1,
-            swapchain_format,
-            ImageTiling::OPTIMAL,
-            ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSIENT_ATTACHMENT,
-            MemoryPropertyFlags::DEVICE_LOCAL,
-            ImageAspectFlags::COLOR,
-        )
+        }
     }
 
-    pub(crate) fn new_depth(
-        extent: Extent3D,
+    /// Sampled volume without mipmaps, used for lookup tables.
+    pub(crate) fn new_3d(
+        size: u32,
+        format: Format,
+        device: Device,
         instance: Instance,
         physical_device: PhysicalDevice,
-        msaa_sample_count: SampleCountFlags,
-        device: Device,
     ) -> Self {
-        Self::new(
+        let extent = Extent3D::builder()
+            .width(size)
+            .height(size)
+            .depth(size)
+            .build();
+
+        let vk_image = Self::create_image(
             extent,
-            msaa_sample_count,
-            device,
-            instance.clone(),
-            physical_device.clone(),
             1,
-            Self::depth_format(instance, physical_device),
+            1,
+            ImageCreateFlags::empty(),
+            format,
+            ImageTiling::OPTIMAL,
+            ImageUsageFlags::SAMPLED | ImageUsageFlags::TRANSFER_DST,
+            SampleCountFlags::_1,
+            device.clone(),
+        );
+
+        let allocation = device.allocate_image_memory(
+            vk_image,
             ImageTiling::OPTIMAL,
-            ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
             MemoryPropertyFlags::DEVICE_LOCAL,
-            ImageAspectFlags::DEPTH,
-        )
+        );
+
+        let view =
+            Self::create_typed_view(device.clone(), vk_image, format, ImageViewType::_3D, 1, 1);
+
+        Self {
+            vk_image,
+            allocation,
+            view,
+            mip_levels: 1,
+            layer_count: 1,
+            device,
+            extent,
+            format,
+            instance,
+            physical_device,
+        }
     }
 
     pub(crate) fn depth_format(instance: Instance, physical_device: PhysicalDevice) -> Format {
@@ -144,43 +203,171 @@ impl Image {
         )
     }
 
-    pub(crate) fn fill(
-        &self,
-        image: Rc<RgbaImage>,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
-    ) {
-        let pixels = image.as_raw();
+    /// Records the upload of the base level and the creation of the other mip levels.
+    pub(crate) fn fill(&self, image: Rc<RgbaImage>, upload_batcher: &mut UploadBatcher) {
+        self.fill_base_level(image.as_raw().as_slice(), upload_batcher);
+
+        upload_batcher.record(|command_buffer| self.record_mipmaps(command_buffer));
+    }
 
-        let staging_buffer = Buffer::from_staging_data(
-            pixels.as_slice(),
+    /// Records the upload of the base level, every level is left as a transfer destination.
+    pub(crate) fn fill_base_level(&self, pixels: &[u8], upload_batcher: &mut UploadBatcher) {
+        upload_batcher.upload(pixels, |command_buffer, staging_buffer, offset| {
+            self.transition_image_layout(
+                command_buffer.clone(),
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+
+            self.copy_buffer_to_image(command_buffer, staging_buffer, offset);
+        });
+    }
+
+    /// Creates the other mip levels from the base level and makes the image readable by
+    /// shaders.
+    pub(crate) fn record_mipmaps(&self, command_buffer: CommandBuffer) {
+        Self::create_mipmaps(
             self.device.clone(),
             self.instance.clone(),
             self.physical_device.clone(),
+            command_buffer,
+            self.vk_image,
+            self.mip_levels,
+            self.extent,
+            self.format,
+        );
+    }
+
+    /// Hands the image, filled by [`Self::fill_base_level`] or [`Self::fill_levels`], over
+    /// to another queue family that has to [`Self::acquire`] it. Nothing is recorded within
+    /// one family.
+    pub(crate) fn release(
+        &self,
+        command_buffer: CommandBuffer,
+        src_queue_family_index: u32,
+        dst_queue_family_index: u32,
+    ) {
+        if src_queue_family_index == dst_queue_family_index {
+            return;
+        }
+
+        self.queue_family_barrier(
+            command_buffer,
+            (src_queue_family_index, dst_queue_family_index),
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+            (PipelineStageFlags::BOTTOM_OF_PIPE, AccessFlags::empty()),
         );
+    }
+
+    /// Takes the image over from the family that released it, ready for
+    /// [`Self::record_mipmaps`] or [`Self::record_shader_read_layout`].
+    pub(crate) fn acquire(
+        &self,
+        command_buffer: CommandBuffer,
+        src_queue_family_index: u32,
+        dst_queue_family_index: u32,
+    ) {
+        // Within one family the barrier only has to wait for the copy
+        let src_scope = if src_queue_family_index == dst_queue_family_index {
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE)
+        } else {
+            (PipelineStageFlags::TOP_OF_PIPE, AccessFlags::empty())
+        };
+
+        self.queue_family_barrier(
+            command_buffer,
+            (src_queue_family_index, dst_queue_family_index),
+            src_scope,
+            (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_READ | AccessFlags::TRANSFER_WRITE,
+            ),
+        );
+    }
+
+    fn queue_family_barrier(
+        &self,
+        command_buffer: CommandBuffer,
+        (src_queue_family_index, dst_queue_family_index): (u32, u32),
+        (src_stage_mask, src_access_mask): (PipelineStageFlags, AccessFlags),
+        (dst_stage_mask, dst_access_mask): (PipelineStageFlags, AccessFlags),
+    ) {
+        let subresource_range = ImageSubresourceRange::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(self.mip_levels)
+            .base_array_layer(0)
+            .layer_count(self.layer_count);
+
+        let image_memory_barrier = ImageMemoryBarrier::builder()
+            .old_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(src_queue_family_index)
+            .dst_queue_family_index(dst_queue_family_index)
+            .image(self.vk_image)
+            .subresource_range(subresource_range)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask);
 
         unsafe {
-            let mapped_memory = vkDevice::from(self.device.clone())
-                .map_memory(
-                    staging_buffer.memory,
-                    0,
-                    self.size(),
-                    MemoryMapFlags::empty(),
-                )
-                .unwrap();
-            copy_memory(pixels.as_ptr(), mapped_memory.cast(), pixels.len());
-            vkDevice::from(self.device.clone()).unmap_memory(staging_buffer.memory);
+            vkDevice::from(self.device.clone()).cmd_pipeline_barrier(
+                command_buffer.into(),
+                src_stage_mask,
+                dst_stage_mask,
+                DependencyFlags::empty(),
+                &[] as &[MemoryBarrier],
+                &[] as &[BufferMemoryBarrier],
+                &[image_memory_barrier],
+            );
         }
+    }
+
+    /// Records the upload of every mip level of every array layer. `texels` holds the
+    /// levels one after another, each level holding its layers one after another.
+    pub(crate) fn fill_layers(&self, texels: &[u8], upload_batcher: &mut UploadBatcher) {
+        self.fill_levels(texels, upload_batcher);
+
+        upload_batcher.record(|command_buffer| self.record_shader_read_layout(command_buffer));
+    }
+
+    /// Like [`Self::fill_layers`], but every level is left as a transfer destination.
+    pub(crate) fn fill_levels(&self, texels: &[u8], upload_batcher: &mut UploadBatcher) {
+        upload_batcher.upload(texels, |command_buffer, staging_buffer, offset| {
+            self.transition_image_layout(
+                command_buffer.clone(),
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
 
-        self.optimize(command_pool, graphics_queue, staging_buffer.clone());
+            self.copy_buffer_to_layers(command_buffer, staging_buffer, offset);
+        });
+    }
 
-        staging_buffer.destroy();
+    /// Makes an image whose levels have all been filled readable by shaders.
+    pub(crate) fn record_shader_read_layout(&self, command_buffer: CommandBuffer) {
+        self.transition_image_layout(
+            command_buffer,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+
+    /// Makes an image that has never been written readable by shaders, for images that a
+    /// shader fills later on.
+    pub(crate) fn record_empty_shader_read_layout(&self, command_buffer: CommandBuffer) {
+        self.transition_image_layout(
+            command_buffer,
+            ImageLayout::UNDEFINED,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
     }
 
     #[allow(clippy::too_many_arguments)]
     fn create_image(
         extent: Extent3D,
         mip_levels: u32,
+        array_layers: u32,
+        flags: ImageCreateFlags,
         format: Format,
         tiling: ImageTiling,
         usage_flags: ImageUsageFlags,
@@ -188,10 +375,15 @@ impl Image {
         device: Device,
     ) -> vk::Image {
         let image_create_info = ImageCreateInfo::builder()
-            .image_type(ImageType::_2D)
+            .image_type(if extent.depth > 1 {
+                ImageType::_3D
+            } else {
+                ImageType::_2D
+            })
             .extent(extent)
             .mip_levels(mip_levels)
-            .array_layers(1)
+            .array_layers(array_layers)
+            .flags(flags)
             .format(format)
             .tiling(tiling)
             .initial_layout(ImageLayout::UNDEFINED)
@@ -210,44 +402,9 @@ impl Image {
         (extent.width.max(extent.height) as f32).log2().floor() as u32 + 1
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn optimize(
-        &self,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
-        staging_buffer: Buffer<u8>,
-    ) {
-        self.transition_image_layout(
-            command_pool.clone(),
-            graphics_queue.clone(),
-            ImageLayout::UNDEFINED,
-            ImageLayout::TRANSFER_DST_OPTIMAL,
-        );
-
-        self.copy_buffer_to_image(
-            command_pool.clone(),
-            graphics_queue.clone(),
-            staging_buffer,
-            self.clone(),
-        );
-
-        Self::create_mipmaps(
-            self.device.clone(),
-            self.instance.clone(),
-            self.physical_device.clone(),
-            command_pool,
-            graphics_queue,
-            self.vk_image,
-            self.mip_levels,
-            self.extent,
-            Format::R8G8B8A8_SRGB,
-        );
-    }
-
     fn transition_image_layout(
         &self,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
+        command_buffer: CommandBuffer,
         old_layout: ImageLayout,
         new_layout: ImageLayout,
     ) {
@@ -265,6 +422,12 @@ impl Image {
                     PipelineStageFlags::TRANSFER,
                     PipelineStageFlags::FRAGMENT_SHADER,
                 ),
+                (ImageLayout::UNDEFINED, ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+                    AccessFlags::empty(),
+                    AccessFlags::SHADER_READ,
+                    PipelineStageFlags::TOP_OF_PIPE,
+                    PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::COMPUTE_SHADER,
+                ),
                 _ => {
                     error!("Matches of image layout not founded");
                     (
@@ -276,41 +439,34 @@ impl Image {
                 }
             };
 
-        CommandExecutor::execute(
-            command_pool,
-            self.device.clone(),
-            |command_buffer| {
-                let subresource_range = ImageSubresourceRange::builder()
-                    .aspect_mask(ImageAspectFlags::COLOR)
-                    .base_mip_level(0)
-                    .level_count(self.mip_levels)
-                    .base_array_layer(0)
-                    .layer_count(1);
-
-                let image_memory_barrier = ImageMemoryBarrier::builder()
-                    .old_layout(old_layout)
-                    .new_layout(new_layout)
-                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
-                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
-                    .image(self.vk_image)
-                    .subresource_range(subresource_range)
-                    .src_access_mask(src_access_mask)
-                    .dst_access_mask(dst_access_mask);
-
-                unsafe {
-                    vkDevice::from(self.device.clone()).cmd_pipeline_barrier(
-                        command_buffer.into(),
-                        src_stage_mask,
-                        dst_stage_mask,
-                        DependencyFlags::empty(),
-                        &[] as &[MemoryBarrier],
-                        &[] as &[BufferMemoryBarrier],
-                        &[image_memory_barrier],
-                    );
-                };
-            },
-            graphics_queue,
-        );
+        let subresource_range = ImageSubresourceRange::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(self.mip_levels)
+            .base_array_layer(0)
+            .layer_count(self.layer_count);
+
+        let image_memory_barrier = ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+            .image(self.vk_image)
+            .subresource_range(subresource_range)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask);
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_pipeline_barrier(
+                command_buffer.into(),
+                src_stage_mask,
+                dst_stage_mask,
+                DependencyFlags::empty(),
+                &[] as &[MemoryBarrier],
+                &[] as &[BufferMemoryBarrier],
+                &[image_memory_barrier],
+            );
+        };
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -318,8 +474,7 @@ impl Image {
         device: Device,
         instance: Instance,
         physical_device: PhysicalDevice,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
+        command_buffer: CommandBuffer,
         vk_image: vk::Image,
         mip_levels: u32,
         extent: Extent3D,
@@ -338,204 +493,229 @@ impl Image {
             );
         }
 
-        CommandExecutor::execute(
-            command_pool,
-            device.clone(),
-            |command_buffer| {
-                let image_subresource_range = ImageSubresourceRange::builder()
-                    .aspect_mask(ImageAspectFlags::COLOR)
-                    .base_array_layer(0)
-                    .layer_count(1)
-                    .level_count(1);
-
-                let mut image_memory_barrier = ImageMemoryBarrier::builder()
-                    .image(vk_image)
-                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
-                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
-                    .subresource_range(image_subresource_range);
-
-                let mut mipmap_width = extent.width;
-                let mut mipmap_height = extent.height;
-
-                (1..mip_levels).for_each(|mip_level| {
-                    image_memory_barrier.subresource_range.base_mip_level = mip_level - 1;
-                    image_memory_barrier.old_layout = ImageLayout::TRANSFER_DST_OPTIMAL;
-                    image_memory_barrier.new_layout = ImageLayout::TRANSFER_SRC_OPTIMAL;
-                    image_memory_barrier.src_access_mask = AccessFlags::TRANSFER_WRITE;
-                    image_memory_barrier.dst_access_mask = AccessFlags::TRANSFER_READ;
-
-                    unsafe {
-                        vkDevice::from(device.clone()).cmd_pipeline_barrier(
-                            command_buffer.clone().into(),
-                            PipelineStageFlags::TRANSFER,
-                            PipelineStageFlags::TRANSFER,
-                            DependencyFlags::empty(),
-                            &[] as &[MemoryBarrier],
-                            &[] as &[BufferMemoryBarrier],
-                            &[image_memory_barrier],
-                        );
-                    };
-
-                    let src_subresource_layers = ImageSubresourceLayers::builder()
-                        .aspect_mask(ImageAspectFlags::COLOR)
-                        .mip_level(mip_level - 1)
-                        .base_array_layer(0)
-                        .layer_count(1);
-
-                    let dst_subresource_layers = ImageSubresourceLayers::builder()
-                        .aspect_mask(ImageAspectFlags::COLOR)
-                        .mip_level(mip_level)
-                        .base_array_layer(0)
-                        .layer_count(1);
-
-                    let image_blit = ImageBlit::builder()
-                        .src_offsets([
-                            Offset3D::builder().x(0).y(0).z(0).build(),
-                            Offset3D::builder()
-                                .x(mipmap_width as i32)
-                                .y(mipmap_height as i32)
-                                .z(1)
-                                .build(),
-                        ])
-                        .src_subresource(src_subresource_layers)
-                        .dst_offsets([
-                            Offset3D::builder().x(0).y(0).z(0).build(),
-                            Offset3D::builder()
-                                .x(if mipmap_width > 1 {
-                                    mipmap_width / 2
-                                } else {
-                                    1
-                                } as i32)
-                                .y(if mipmap_height > 1 {
-                                    mipmap_height / 2
-                                } else {
-                                    1
-                                } as i32)
-                                .z(1)
-                                .build(),
-                        ])
-                        .dst_subresource(dst_subresource_layers);
-
-                    unsafe {
-                        vkDevice::from(device.clone()).cmd_blit_image(
-                            command_buffer.clone().into(),
-                            vk_image,
-                            ImageLayout::TRANSFER_SRC_OPTIMAL,
-                            vk_image,
-                            ImageLayout::TRANSFER_DST_OPTIMAL,
-                            &[image_blit],
-                            Filter::LINEAR,
-                        );
-                    };
-
-                    image_memory_barrier.old_layout = ImageLayout::TRANSFER_SRC_OPTIMAL;
-                    image_memory_barrier.new_layout = ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-                    image_memory_barrier.src_access_mask = AccessFlags::TRANSFER_READ;
-                    image_memory_barrier.dst_access_mask = AccessFlags::SHADER_READ;
-
-                    unsafe {
-                        vkDevice::from(device.clone()).cmd_pipeline_barrier(
-                            command_buffer.clone().into(),
-                            PipelineStageFlags::TRANSFER,
-                            PipelineStageFlags::FRAGMENT_SHADER,
-                            DependencyFlags::empty(),
-                            &[] as &[MemoryBarrier],
-                            &[] as &[BufferMemoryBarrier],
-                            &[image_memory_barrier],
-                        );
-                    };
-
-                    if mipmap_width > 1 {
-                        mipmap_width /= 2;
-                    }
-                    if mipmap_height > 1 {
-                        mipmap_height /= 2;
-                    }
-                });
-
-                image_memory_barrier.subresource_range.base_mip_level = mip_levels - 1;
-                image_memory_barrier.old_layout = ImageLayout::TRANSFER_DST_OPTIMAL;
-                image_memory_barrier.new_layout = ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-                image_memory_barrier.src_access_mask = AccessFlags::TRANSFER_WRITE;
-                image_memory_barrier.dst_access_mask = AccessFlags::SHADER_READ;
-
-                unsafe {
-                    vkDevice::from(device).cmd_pipeline_barrier(
-                        command_buffer.into(),
-                        PipelineStageFlags::TRANSFER,
-                        PipelineStageFlags::FRAGMENT_SHADER,
-                        DependencyFlags::empty(),
-                        &[] as &[MemoryBarrier],
-                        &[] as &[BufferMemoryBarrier],
-                        &[image_memory_barrier],
-                    );
-                };
-            },
-            graphics_queue,
-        );
+        let image_subresource_range = ImageSubresourceRange::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .base_array_layer(0)
+            .layer_count(1)
+            .level_count(1);
+
+        let mut image_memory_barrier = ImageMemoryBarrier::builder()
+            .image(vk_image)
+            .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+            .subresource_range(image_subresource_range);
+
+        let mut mipmap_width = extent.width;
+        let mut mipmap_height = extent.height;
+
+        (1..mip_levels).for_each(|mip_level| {
+            image_memory_barrier.subresource_range.base_mip_level = mip_level - 1;
+            image_memory_barrier.old_layout = ImageLayout::TRANSFER_DST_OPTIMAL;
+            image_memory_barrier.new_layout = ImageLayout::TRANSFER_SRC_OPTIMAL;
+            image_memory_barrier.src_access_mask = AccessFlags::TRANSFER_WRITE;
+            image_memory_barrier.dst_access_mask = AccessFlags::TRANSFER_READ;
+
+            unsafe {
+                vkDevice::from(device.clone()).cmd_pipeline_barrier(
+                    command_buffer.clone().into(),
+                    PipelineStageFlags::TRANSFER,
+                    PipelineStageFlags::TRANSFER,
+                    DependencyFlags::empty(),
+                    &[] as &[MemoryBarrier],
+                    &[] as &[BufferMemoryBarrier],
+                    &[image_memory_barrier],
+                );
+            };
+
+            let src_subresource_layers = ImageSubresourceLayers::builder()
+                .aspect_mask(ImageAspectFlags::COLOR)
+                .mip_level(mip_level - 1)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            let dst_subresource_layers = ImageSubresourceLayers::builder()
+                .aspect_mask(ImageAspectFlags::COLOR)
+                .mip_level(mip_level)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            let image_blit = ImageBlit::builder()
+                .src_offsets([
+                    Offset3D::builder().x(0).y(0).z(0).build(),
+                    Offset3D::builder()
+                        .x(mipmap_width as i32)
+                        .y(mipmap_height as i32)
+                        .z(1)
+                        .build(),
+                ])
+                .src_subresource(src_subresource_layers)
+                .dst_offsets([
+                    Offset3D::builder().x(0).y(0).z(0).build(),
+                    Offset3D::builder()
+                        .x(if mipmap_width > 1 {
+                            mipmap_width / 2
+                        } else {
+                            1
+                        } as i32)
+                        .y(if mipmap_height > 1 {
+                            mipmap_height / 2
+                        } else {
+                            1
+                        } as i32)
+                        .z(1)
+                        .build(),
+                ])
+                .dst_subresource(dst_subresource_layers);
+
+            unsafe {
+                vkDevice::from(device.clone()).cmd_blit_image(
+                    command_buffer.clone().into(),
+                    vk_image,
+                    ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk_image,
+                    ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[image_blit],
+                    Filter::LINEAR,
+                );
+            };
+
+            image_memory_barrier.old_layout = ImageLayout::TRANSFER_SRC_OPTIMAL;
+            image_memory_barrier.new_layout = ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+            image_memory_barrier.src_access_mask = AccessFlags::TRANSFER_READ;
+            image_memory_barrier.dst_access_mask = AccessFlags::SHADER_READ;
+
+            unsafe {
+                vkDevice::from(device.clone()).cmd_pipeline_barrier(
+                    command_buffer.clone().into(),
+                    PipelineStageFlags::TRANSFER,
+                    PipelineStageFlags::FRAGMENT_SHADER,
+                    DependencyFlags::empty(),
+                    &[] as &[MemoryBarrier],
+                    &[] as &[BufferMemoryBarrier],
+                    &[image_memory_barrier],
+                );
+            };
+
+            if mipmap_width > 1 {
+                mipmap_width /= 2;
+            }
+            if mipmap_height > 1 {
+                mipmap_height /= 2;
+            }
+        });
+
+        image_memory_barrier.subresource_range.base_mip_level = mip_levels - 1;
+        image_memory_barrier.old_layout = ImageLayout::TRANSFER_DST_OPTIMAL;
+        image_memory_barrier.new_layout = ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        image_memory_barrier.src_access_mask = AccessFlags::TRANSFER_WRITE;
+        image_memory_barrier.dst_access_mask = AccessFlags::SHADER_READ;
+
+        unsafe {
+            vkDevice::from(device).cmd_pipeline_barrier(
+                command_buffer.into(),
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::FRAGMENT_SHADER,
+                DependencyFlags::empty(),
+                &[] as &[MemoryBarrier],
+                &[] as &[BufferMemoryBarrier],
+                &[image_memory_barrier],
+            );
+        };
     }
 
     fn copy_buffer_to_image(
         &self,
-        command_pool: CommandPool,
-        graphics_queue: Queue,
-        src_buffer: Buffer<u8>,
-        dst_image: Image,
+        command_buffer: CommandBuffer,
+        src_buffer: vk::Buffer,
+        buffer_offset: DeviceSize,
     ) {
-        CommandExecutor::execute(
-            command_pool,
-            self.device.clone(),
-            |command_buffer| {
-                let subresource_layers = ImageSubresourceLayers::builder()
-                    .aspect_mask(ImageAspectFlags::COLOR)
-                    .mip_level(0)
-                    .base_array_layer(0)
-                    .layer_count(1);
-
-                let buffer_image_copy = BufferImageCopy::builder()
-                    .buffer_offset(0)
-                    .buffer_row_length(0)
-                    .buffer_image_height(0)
-                    .image_subresource(subresource_layers)
-                    .image_offset(Offset3D::builder().x(0).y(0).z(0))
-                    .image_extent(self.extent);
-
-                unsafe {
-                    vkDevice::from(self.device.clone()).cmd_copy_buffer_to_image(
-                        command_buffer.into(),
-                        src_buffer.into(),
-                        dst_image.vk_image,
-                        ImageLayout::TRANSFER_DST_OPTIMAL,
-                        &[buffer_image_copy],
-                    )
-                };
-            },
-            graphics_queue,
-        );
+        let subresource_layers = ImageSubresourceLayers::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let buffer_image_copy = BufferImageCopy::builder()
+            .buffer_offset(buffer_offset)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource_layers)
+            .image_offset(Offset3D::builder().x(0).y(0).z(0))
+            .image_extent(self.extent);
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_copy_buffer_to_image(
+                command_buffer.into(),
+                src_buffer,
+                self.vk_image,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[buffer_image_copy],
+            )
+        };
     }
 
-    fn create_memory(
-        device: Device,
-        image: vk::Image,
-        instance: Instance,
-        physical_device: PhysicalDevice,
-        memory_property_flags: MemoryPropertyFlags,
-    ) -> DeviceMemory {
-        let image_memory_requirements =
-            unsafe { vkDevice::from(device.clone()).get_image_memory_requirements(image) };
-        let image_memory_alloc_info = MemoryAllocateInfo::builder()
-            .allocation_size(image_memory_requirements.size)
-            .memory_type_index(Memory::type_index(
-                instance,
-                physical_device,
-                memory_property_flags,
-                image_memory_requirements,
-            ));
+    fn copy_buffer_to_layers(
+        &self,
+        command_buffer: CommandBuffer,
+        src_buffer: vk::Buffer,
+        mut buffer_offset: DeviceSize,
+    ) {
+        let texel_size = Self::texel_size(self.format);
+
+        let buffer_image_copies = (0..self.mip_levels)
+            .flat_map(|mip_level| {
+                let width = (self.extent.width >> mip_level).max(1);
+                let height = (self.extent.height >> mip_level).max(1);
+                let depth = (self.extent.depth >> mip_level).max(1);
+
+                (0..self.layer_count)
+                    .map(|layer| {
+                        let subresource_layers = ImageSubresourceLayers::builder()
+                            .aspect_mask(ImageAspectFlags::COLOR)
+                            .mip_level(mip_level)
+                            .base_array_layer(layer)
+                            .layer_count(1);
+
+                        let buffer_image_copy = BufferImageCopy::builder()
+                            .buffer_offset(buffer_offset)
+                            .buffer_row_length(0)
+                            .buffer_image_height(0)
+                            .image_subresource(subresource_layers)
+                            .image_offset(Offset3D::builder().x(0).y(0).z(0))
+                            .image_extent(
+                                Extent3D::builder().width(width).height(height).depth(depth),
+                            )
+                            .build();
+
+                        buffer_offset += (width * height * depth) as u64 * texel_size;
+
+                        buffer_image_copy
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
 
         unsafe {
-            vkDevice::from(device)
-                .allocate_memory(&image_memory_alloc_info, None)
-                .unwrap()
+            vkDevice::from(self.device.clone()).cmd_copy_buffer_to_image(
+                command_buffer.into(),
+                src_buffer,
+                self.vk_image,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &buffer_image_copies,
+            )
+        }
+    }
+
+    pub(crate) fn texel_size(format: Format) -> u64 {
+        match format {
+            Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UNORM | Format::B8G8R8A8_SRGB => 4,
+            Format::R16G16_SFLOAT => 4,
+            Format::R16G16B16A16_SFLOAT => 8,
+            Format::R32G32B32A32_SFLOAT => 16,
+            _ => {
+                error!("Texel size of {:?} is not known", format);
+                4
+            }
         }
     }
 
@@ -566,8 +746,54 @@ impl Image {
         }
     }
 
-    pub(crate) fn size(&self) -> u64 {
-        (self.extent.width * self.extent.height * 4) as u64
+    /// View of a single mip level, it has to be destroyed by the caller.
+    pub(crate) fn create_level_view(&self, mip_level: u32) -> vk::ImageView {
+        let image_subresource_range = ImageSubresourceRange::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .base_mip_level(mip_level)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let image_view_create_info = ImageViewCreateInfo::builder()
+            .image(self.vk_image)
+            .view_type(ImageViewType::_2D)
+            .format(self.format)
+            .subresource_range(image_subresource_range);
+
+        unsafe {
+            vkDevice::from(self.device.clone())
+                .create_image_view(&image_view_create_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_typed_view(
+        device: Device,
+        vk_image: vk::Image,
+        format: Format,
+        view_type: ImageViewType,
+        mip_levels: u32,
+        layer_count: u32,
+    ) -> vk::ImageView {
+        let image_subresource_range = ImageSubresourceRange::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(mip_levels)
+            .base_array_layer(0)
+            .layer_count(layer_count);
+
+        let image_view_create_info = ImageViewCreateInfo::builder()
+            .image(vk_image)
+            .view_type(view_type)
+            .format(format)
+            .subresource_range(image_subresource_range);
+
+        unsafe {
+            vkDevice::from(device)
+                .create_image_view(&image_view_create_info, None)
+                .unwrap()
+        }
     }
 
     pub(crate) fn supported_format(
@@ -604,20 +830,13 @@ impl Image {
             .unwrap()
     }
 
-    fn bind_memory(device: Device, image: vk::Image, memory: DeviceMemory) {
-        unsafe {
-            vkDevice::from(device)
-                .bind_image_memory(image, memory, 0)
-                .unwrap()
-        }
-    }
-
     pub(crate) fn destroy(&self) {
         unsafe {
             vkDevice::from(self.device.clone()).destroy_image(self.vk_image, None);
-            vkDevice::from(self.device.clone()).free_memory(self.memory, None);
             vkDevice::from(self.device.clone()).destroy_image_view(self.view, None);
         }
+
+        self.device.free_memory(&self.allocation);
     }
 }
 
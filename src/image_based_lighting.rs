@@ -0,0 +1,126 @@
+use vulkanalia::vk::{
+    Extent3D, Format, HasBuilder, ImageAspectFlags, ImageTiling, ImageUsageFlags,
+    MemoryPropertyFlags, SampleCountFlags,
+};
+
+use crate::{
+    cube_map::CubeMap,
+    device::Device,
+    environment_map::{EnvironmentMap, BRDF_LUT_SIZE},
+    image::Image,
+    instance::Instance,
+    physical_device::PhysicalDevice,
+    sampler::Sampler,
+    upload_batcher::UploadBatcher,
+};
+
+#[derive(Clone, Debug)]
+pub(crate) struct ImageBasedLighting {
+    pub(crate) environment: Image,
+    pub(crate) environment_sampler: Sampler,
+    pub(crate) irradiance: Image,
+    pub(crate) irradiance_sampler: Sampler,
+    pub(crate) prefiltered: Image,
+    pub(crate) prefiltered_sampler: Sampler,
+    pub(crate) brdf_lut: Image,
+    pub(crate) brdf_lut_sampler: Sampler,
+}
+
+impl ImageBasedLighting {
+    pub(crate) fn new(
+        environment_map: &EnvironmentMap,
+        device: Device,
+        instance: Instance,
+        physical_device: PhysicalDevice,
+        upload_batcher: &mut UploadBatcher,
+    ) -> Self {
+        let (environment, environment_sampler) = Self::create_cube(
+            &environment_map.skybox,
+            device.clone(),
+            instance.clone(),
+            physical_device.clone(),
+            upload_batcher,
+        );
+        let (irradiance, irradiance_sampler) = Self::create_cube(
+            &environment_map.irradiance,
+            device.clone(),
+            instance.clone(),
+            physical_device.clone(),
+            upload_batcher,
+        );
+        let (prefiltered, prefiltered_sampler) = Self::create_cube(
+            &environment_map.prefiltered,
+            device.clone(),
+            instance.clone(),
+            physical_device.clone(),
+            upload_batcher,
+        );
+
+        let brdf_lut_extent = Extent3D::builder()
+            .width(BRDF_LUT_SIZE)
+            .height(BRDF_LUT_SIZE)
+            .depth(1)
+            .build();
+
+        let brdf_lut = Image::new(
+            brdf_lut_extent,
+            SampleCountFlags::_1,
+            device.clone(),
+            instance,
+            physical_device,
+            1,
+            Format::R16G16_SFLOAT,
+            ImageTiling::OPTIMAL,
+            ImageUsageFlags::SAMPLED | ImageUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+            ImageAspectFlags::COLOR,
+        );
+        brdf_lut.fill_layers(environment_map.brdf_lut_texels().as_slice(), upload_batcher);
+        let brdf_lut_sampler = Sampler::new_clamped(device, 1);
+
+        Self {
+            environment,
+            environment_sampler,
+            irradiance,
+            irradiance_sampler,
+            prefiltered,
+            prefiltered_sampler,
+            brdf_lut,
+            brdf_lut_sampler,
+        }
+    }
+
+    fn create_cube(
+        cube_map: &CubeMap,
+        device: Device,
+        instance: Instance,
+        physical_device: PhysicalDevice,
+        upload_batcher: &mut UploadBatcher,
+    ) -> (Image, Sampler) {
+        let image = Image::new_cube(
+            cube_map.size,
+            cube_map.mip_levels(),
+            Format::R16G16B16A16_SFLOAT,
+            device.clone(),
+            instance,
+            physical_device,
+        );
+
+        image.fill_layers(cube_map.to_texels().as_slice(), upload_batcher);
+
+        let sampler = Sampler::new_clamped(device, cube_map.mip_levels());
+
+        (image, sampler)
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.environment_sampler.destroy();
+        self.environment.destroy();
+        self.irradiance_sampler.destroy();
+        self.irradiance.destroy();
+        self.prefiltered_sampler.destroy();
+        self.prefiltered.destroy();
+        self.brdf_lut_sampler.destroy();
+        self.brdf_lut.destroy();
+    }
+}
@@ -0,0 +1,71 @@
+use memoffset::offset_of;
+use nalgebra::{Matrix3, Matrix4, Vector3, Vector4};
+use std::mem::size_of;
+use vulkanalia::vk::{
+    Format, HasBuilder, VertexInputAttributeDescription, VertexInputBindingDescription,
+    VertexInputRate,
+};
+
+/// Data of one instance of a mesh, read from the second vertex binding once per instance.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct InstanceData {
+    pub(crate) model: Matrix4<f32>,
+    /// Inverse transpose of `model`, so that normals stay perpendicular to scaled surfaces.
+    pub(crate) normal_matrix: Matrix3<f32>,
+}
+
+impl InstanceData {
+    /// Location of the first column of `model`, the ones of `normal_matrix` follow.
+    const FIRST_LOCATION: u32 = 5;
+
+    pub(crate) fn new(model: Matrix4<f32>) -> Self {
+        let normal_matrix = model
+            .fixed_view::<3, 3>(0, 0)
+            .into_owned()
+            .try_inverse()
+            .unwrap_or_else(Matrix3::identity)
+            .transpose();
+
+        Self {
+            model,
+            normal_matrix,
+        }
+    }
+
+    pub(crate) fn binding_description() -> VertexInputBindingDescription {
+        VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<Self>() as u32)
+            .input_rate(VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    pub(crate) fn attribute_descriptions() -> Vec<VertexInputAttributeDescription> {
+        let model_columns = (0..4).map(|column| {
+            (
+                Format::R32G32B32A32_SFLOAT,
+                offset_of!(Self, model) + column * size_of::<Vector4<f32>>(),
+            )
+        });
+        let normal_matrix_columns = (0..3).map(|column| {
+            (
+                Format::R32G32B32_SFLOAT,
+                offset_of!(Self, normal_matrix) + column * size_of::<Vector3<f32>>(),
+            )
+        });
+
+        model_columns
+            .chain(normal_matrix_columns)
+            .zip(Self::FIRST_LOCATION..)
+            .map(|((format, offset), location)| {
+                VertexInputAttributeDescription::builder()
+                    .binding(1)
+                    .location(location)
+                    .format(format)
+                    .offset(offset as u32)
+                    .build()
+            })
+            .collect()
+    }
+}
@@ -1,7 +1,9 @@
-mod model;
 mod entity;
-
-use crate::entity::Entity;
+// Shared with the engine, which also uses what draws materials
+#[allow(dead_code)]
+mod material;
+mod post_process_settings;
+mod python_module;
 
 use pyo3::prelude::*;
 
@@ -11,10 +13,11 @@ fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
     Ok((a + b).to_string())
 }
 
-/// A Python module implemented in Rust.
+/// A Python module implemented in Rust. The scene and its components need the engine, they
+/// are only available to the scripts it runs.
 #[pymodule]
 fn cpyte_engine(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(sum_as_string, m)?)?;
-    m.add_class::<Entity>()?;
+    python_module::add_classes(m)?;
     Ok(())
 }
@@ -0,0 +1,40 @@
+use nalgebra::Vector3;
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) enum LightKind {
+    /// Lights along the forward axis of its entity from infinitely far away.
+    Directional,
+    /// Lights all around the position of its entity, up to `range`.
+    Point,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct Light {
+    #[pyo3(get, set)]
+    pub(crate) kind: LightKind,
+    /// Linear RGB.
+    #[pyo3(get, set)]
+    pub(crate) color: Vector3<f32>,
+    #[pyo3(get, set)]
+    pub(crate) intensity: f32,
+    /// Distance at which a point light stops lighting, ignored by directional lights.
+    #[pyo3(get, set)]
+    pub(crate) range: f32,
+}
+
+#[pymethods]
+impl Light {
+    #[new]
+    pub(crate) fn new(kind: LightKind, color: Vector3<f32>, intensity: f32) -> Self {
+        Self {
+            kind,
+            color,
+            intensity,
+            range: 10.0,
+        }
+    }
+}
@@ -1,76 +1,157 @@
+mod asset_cache;
+mod asset_server;
+mod asset_streamer;
+mod asset_watcher;
+mod bloom;
+mod bounding_volume;
 mod buffer;
+mod camera;
+mod color_lut;
 mod command_buffer;
-mod command_executor;
 mod command_pool;
+mod component_storage;
+mod compute_pipeline;
+mod cube_map;
 mod debug_messenger;
 mod descriptor_pool;
 mod descriptor_set;
 mod device;
 mod entity;
 mod entry;
+mod environment_map;
+mod exposure;
 mod fence;
 mod framebuffer;
+mod frustum;
+mod fullscreen_pass;
+mod gpu_compute;
+mod gpu_culling;
+mod hi_z;
 mod image;
+mod image_based_lighting;
 mod instance;
+mod instance_data;
+mod light;
+mod material;
 mod memory;
+mod mesh;
+mod mesh_optimizer;
+mod mesh_pool;
+mod mesh_simplifier;
 mod model;
 mod physical_device;
 mod pipeline;
+mod post_process;
+mod post_process_settings;
+mod python_module;
 mod queue;
 mod queue_family_index;
+mod render_graph;
 mod render_pass;
+mod renderable;
 mod renderer;
 mod sampler;
+mod scene;
+mod scene_file;
 mod scene_graph;
+mod script;
 mod semaphore;
 mod shader;
+mod shader_watcher;
+mod skybox;
+mod staging_ring_buffer;
 mod surface;
 mod swapchain;
 mod texture;
+mod transform;
 mod ubo;
+mod upload_batcher;
 mod validation_layers;
 mod vertex;
 mod window;
 
+use log::error;
 use nalgebra::{UnitQuaternion, Vector3};
-use std::{cell::RefCell, rc::Rc, time::Instant};
+use std::{cell::RefCell, env, path::Path, rc::Rc, time::Instant};
 use vulkanalia::vk::SampleCountFlags;
 use winit::{
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
 };
 
-use crate::{entity::Entity, scene_graph::SceneGraph};
+use crate::{
+    post_process_settings::PostProcessSettings,
+    scene::Scene,
+    scene_file::SceneFile,
+    scene_graph::{SceneGraph, System},
+    script::Script,
+};
 use renderer::Renderer;
 
+/// `cpyte-engine [--environment-map <file.hdr>] [script.py]`
+#[derive(Debug, Default)]
+struct Arguments {
+    /// Equirectangular HDR image of the skybox and image-based lighting.
+    environment_map_path: Option<String>,
+    script_path: Option<String>,
+}
+
+impl Arguments {
+    /// Invalid arguments are logged and skipped.
+    fn parse(mut arguments: impl Iterator<Item = String>) -> Self {
+        let mut parsed = Self::default();
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "--environment-map" => match arguments.next() {
+                    Some(path) => parsed.environment_map_path = Some(path),
+                    None => error!("--environment-map needs a path"),
+                },
+                option if option.starts_with("--") => error!("Unknown option {}", option),
+                _ if parsed.script_path.is_none() => parsed.script_path = Some(argument),
+                _ => error!("Skipping {}, a script is already given", argument),
+            }
+        }
+
+        parsed
+    }
+}
+
 fn main() {
     pretty_env_logger::init();
 
+    let arguments = Arguments::parse(env::args().skip(1));
+
     let event_loop = EventLoop::new();
 
-    let entity_1 = Entity::new(
-        Vector3::default(),
-        UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 90.0f32.to_radians()),
-        Vector3::new(1.0, 1.0, 1.0),
-        "/home/arman/Документы/может быть нужное/cpyte-engine (копия)/data/3d-models/viking_room.obj",
-        Some("/home/arman/Документы/может быть нужное/cpyte-engine (копия)/data/textures/viking_room.png"),
-    );
+    let scene_graph = Rc::new(RefCell::new(SceneGraph::new()));
+    let scene_path = Path::new("data/scenes/main.ron");
+    if let Err(error) = SceneFile::load(&mut scene_graph.borrow_mut(), scene_path) {
+        error!("Starting with an empty scene: {}", error);
+    }
+    scene_graph.borrow_mut().add_system(System::Native(spin));
+
+    let post_process_settings = Rc::new(RefCell::new(PostProcessSettings::new()));
 
-    let entity_2 = Entity::new(
-        Vector3::new(1.0, 0.0, 0.0),
-        UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 90.0f32.to_radians()),
-        Vector3::new(1.0, 1.0, 1.0),
-        "/home/arman/Документы/может быть нужное/cpyte-engine (копия)/data/3d-models/bochka.obj",
-        Some(
-            "/home/arman/Документы/может быть нужное/cpyte-engine (копия)/data/textures/bochka.png",
-        ),
+    let mut renderer = Renderer::new(
+        &event_loop,
+        Rc::clone(&scene_graph),
+        Rc::clone(&post_process_settings),
+        SampleCountFlags::_1,
+        arguments.environment_map_path.as_deref(),
     );
 
-    let scene_graph = Rc::new(RefCell::new(SceneGraph::new()));
-    scene_graph.borrow_mut().insert("Entity", entity_1);
-    scene_graph.borrow_mut().insert("Entity 1", entity_2);
+    let scene = Scene::new(
+        scene_graph,
+        Rc::clone(&renderer.gpu_compute),
+        post_process_settings,
+    );
 
-    let mut renderer = Renderer::new(&event_loop, Rc::clone(&scene_graph), SampleCountFlags::_1);
+    // Scripts are optional, the engine runs the scene file without one
+    if let Some(script_path) = &arguments.script_path {
+        if let Err(error) = Script::run(Path::new(script_path), scene.clone()) {
+            error!("{}", error);
+        }
+    }
 
     let start_time = Instant::now();
 
@@ -84,10 +165,27 @@ fn main() {
             Event::MainEventsCleared => {
                 let exec_time = start_time.elapsed().as_secs_f32();
 
-                scene_graph.borrow_mut().on_update(exec_time);
+                scene.run_systems(exec_time);
                 renderer.draw_frame(exec_time);
             }
             _ => {}
         }
     });
 }
+
+/// Turns the entities that are drawn around the z axis.
+fn spin(scene_graph: &mut SceneGraph, _exec_time: f32) {
+    let SceneGraph {
+        transforms,
+        renderables,
+        ..
+    } = scene_graph;
+
+    transforms
+        .iter_mut()
+        .filter(|(entity, _)| renderables.contains(*entity))
+        .for_each(|(_, transform)| {
+            transform.rotation *=
+                UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 1.0f32.to_radians());
+        });
+}
@@ -0,0 +1,135 @@
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+use vulkanalia::vk::{self, BlendFactor, CullModeFlags, PrimitiveTopology};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) enum CullMode {
+    Disabled,
+    Front,
+    Back,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+/// How the color written by a material is combined with the color already in the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) enum Topology {
+    TriangleList,
+    TriangleStrip,
+    LineList,
+    LineStrip,
+    PointList,
+}
+
+/// Shaders from `shaders/src` and fixed-function state an entity is drawn with, entities
+/// with equal materials share a pipeline. The shaders can only use the bindings and push
+/// constants declared by `main.vert` and `main.frag`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(default)]
+#[pyclass]
+pub(crate) struct Material {
+    #[pyo3(get, set)]
+    pub(crate) vertex_shader: String,
+    #[pyo3(get, set)]
+    pub(crate) fragment_shader: String,
+    #[pyo3(get, set)]
+    pub(crate) cull_mode: CullMode,
+    #[pyo3(get, set)]
+    pub(crate) polygon_mode: PolygonMode,
+    #[pyo3(get, set)]
+    pub(crate) blend_mode: BlendMode,
+    #[pyo3(get, set)]
+    pub(crate) depth_test: bool,
+    /// Ignored by transparent blend modes, which never write depth.
+    #[pyo3(get, set)]
+    pub(crate) depth_write: bool,
+    #[pyo3(get, set)]
+    pub(crate) topology: Topology,
+}
+
+#[pymethods]
+impl Material {
+    #[new]
+    pub(crate) fn new(vertex_shader: &str, fragment_shader: &str) -> Self {
+        Self {
+            vertex_shader: vertex_shader.to_string(),
+            fragment_shader: fragment_shader.to_string(),
+            cull_mode: CullMode::Back,
+            polygon_mode: PolygonMode::Fill,
+            blend_mode: BlendMode::Opaque,
+            depth_test: true,
+            depth_write: true,
+            topology: Topology::TriangleList,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new("main.vert", "main.frag")
+    }
+}
+
+impl BlendMode {
+    /// Blended entities are drawn after opaque ones, from back to front.
+    pub(crate) fn is_transparent(self) -> bool {
+        self != Self::Opaque
+    }
+
+    /// Source and destination color factors, `None` when blending is disabled.
+    pub(crate) fn color_blend_factors(self) -> Option<(BlendFactor, BlendFactor)> {
+        match self {
+            Self::Opaque => None,
+            Self::Alpha => Some((BlendFactor::SRC_ALPHA, BlendFactor::ONE_MINUS_SRC_ALPHA)),
+            Self::Additive => Some((BlendFactor::SRC_ALPHA, BlendFactor::ONE)),
+        }
+    }
+}
+
+impl From<CullMode> for CullModeFlags {
+    fn from(value: CullMode) -> Self {
+        match value {
+            CullMode::Disabled => CullModeFlags::NONE,
+            CullMode::Front => CullModeFlags::FRONT,
+            CullMode::Back => CullModeFlags::BACK,
+        }
+    }
+}
+
+impl From<PolygonMode> for vk::PolygonMode {
+    fn from(value: PolygonMode) -> Self {
+        match value {
+            PolygonMode::Fill => vk::PolygonMode::FILL,
+            PolygonMode::Line => vk::PolygonMode::LINE,
+            PolygonMode::Point => vk::PolygonMode::POINT,
+        }
+    }
+}
+
+impl From<Topology> for PrimitiveTopology {
+    fn from(value: Topology) -> Self {
+        match value {
+            Topology::TriangleList => PrimitiveTopology::TRIANGLE_LIST,
+            Topology::TriangleStrip => PrimitiveTopology::TRIANGLE_STRIP,
+            Topology::LineList => PrimitiveTopology::LINE_LIST,
+            Topology::LineStrip => PrimitiveTopology::LINE_STRIP,
+            Topology::PointList => PrimitiveTopology::POINT_LIST,
+        }
+    }
+}
@@ -1,29 +1,315 @@
-use vulkanalia::vk::{InstanceV1_0, MemoryPropertyFlags, MemoryRequirements};
+use std::ptr;
+
+use vulkanalia::{
+    prelude::v1_0::Device as vkDevice,
+    vk::{
+        DeviceMemory, DeviceSize, DeviceV1_0, HasBuilder, InstanceV1_0, MemoryAllocateInfo,
+        MemoryMapFlags, MemoryPropertyFlags, MemoryRequirements, PhysicalDeviceMemoryProperties,
+    },
+};
 
 use crate::{instance::Instance, physical_device::PhysicalDevice};
 
-pub(crate) struct Memory;
+const BLOCK_SIZE: DeviceSize = 64 * 1024 * 1024;
+// Resources at least this big get their own allocation instead of a block
+const DEDICATED_ALLOCATION_SIZE: DeviceSize = BLOCK_SIZE / 2;
 
-impl Memory {
-    pub(crate) fn type_index(
-        instance: Instance,
-        physical_device: PhysicalDevice,
-        memory_property_flags: MemoryPropertyFlags,
-        memory_requirements: MemoryRequirements,
-    ) -> u32 {
+/// Part of a device memory object a resource is bound to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Allocation {
+    pub(crate) memory: DeviceMemory,
+    pub(crate) offset: DeviceSize,
+    pub(crate) size: DeviceSize,
+    /// Start of the allocation in host memory, null when it is not host visible.
+    pub(crate) mapped: *mut u8,
+    pool: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct AllocatorStatistics {
+    pub(crate) block_count: usize,
+    pub(crate) dedicated_allocation_count: usize,
+    pub(crate) allocation_count: usize,
+    /// Bytes of device memory allocated for blocks and dedicated allocations.
+    pub(crate) allocated_bytes: DeviceSize,
+    /// Bytes taken by resources, alignment padding excluded.
+    pub(crate) used_bytes: DeviceSize,
+}
+
+#[derive(Debug)]
+struct Block {
+    memory: DeviceMemory,
+    mapped: *mut u8,
+    /// Offsets and sizes of the free ranges, sorted by offset.
+    free_ranges: Vec<(DeviceSize, DeviceSize)>,
+    allocation_count: usize,
+}
+
+/// Blocks of one memory type holding either only linear resources (buffers and linear
+/// images) or only optimal images, so neighbouring resources never break
+/// `bufferImageGranularity`.
+#[derive(Debug)]
+struct Pool {
+    memory_type_index: u32,
+    linear: bool,
+    blocks: Vec<Block>,
+}
+
+/// Sub-allocates resources from large device memory blocks, since drivers only allow a few
+/// thousand allocations. Host visible memory stays mapped for as long as it is allocated.
+#[derive(Debug)]
+pub(crate) struct Allocator {
+    memory_properties: PhysicalDeviceMemoryProperties,
+    pools: Vec<Pool>,
+    statistics: AllocatorStatistics,
+}
+
+impl Allocator {
+    pub(crate) fn new(instance: &Instance, physical_device: &PhysicalDevice) -> Self {
         let memory_properties = unsafe {
             instance
                 .instance
                 .get_physical_device_memory_properties(physical_device.physical_device)
         };
 
-        (0..memory_properties.memory_type_count)
+        Self {
+            memory_properties,
+            pools: Vec::new(),
+            statistics: AllocatorStatistics::default(),
+        }
+    }
+
+    /// Finds memory for a resource, `linear` is false for images with optimal tiling.
+    pub(crate) fn allocate(
+        &mut self,
+        device: &vkDevice,
+        memory_requirements: MemoryRequirements,
+        memory_property_flags: MemoryPropertyFlags,
+        linear: bool,
+    ) -> Allocation {
+        let memory_type_index = self.memory_type_index(memory_property_flags, memory_requirements);
+
+        self.statistics.allocation_count += 1;
+        self.statistics.used_bytes += memory_requirements.size;
+
+        if memory_requirements.size >= DEDICATED_ALLOCATION_SIZE {
+            let (memory, mapped) =
+                self.allocate_memory(device, memory_type_index, memory_requirements.size);
+
+            self.statistics.dedicated_allocation_count += 1;
+            self.statistics.allocated_bytes += memory_requirements.size;
+
+            return Allocation {
+                memory,
+                offset: 0,
+                size: memory_requirements.size,
+                mapped,
+                pool: None,
+            };
+        }
+
+        let pool_index =
+            match self.pools.iter().position(|pool| {
+                pool.memory_type_index == memory_type_index && pool.linear == linear
+            }) {
+                Some(pool_index) => pool_index,
+                None => {
+                    self.pools.push(Pool {
+                        memory_type_index,
+                        linear,
+                        blocks: Vec::new(),
+                    });
+                    self.pools.len() - 1
+                }
+            };
+
+        let found = self.pools[pool_index]
+            .blocks
+            .iter_mut()
+            .find_map(|block| Self::allocate_in_block(block, memory_requirements));
+        let (memory, offset, mapped) = match found {
+            Some(found) => found,
+            None => {
+                let (memory, mapped) = self.allocate_memory(device, memory_type_index, BLOCK_SIZE);
+                let mut block = Block {
+                    memory,
+                    mapped,
+                    free_ranges: vec![(0, BLOCK_SIZE)],
+                    allocation_count: 0,
+                };
+                let found = Self::allocate_in_block(&mut block, memory_requirements).unwrap();
+
+                self.pools[pool_index].blocks.push(block);
+                self.statistics.block_count += 1;
+                self.statistics.allocated_bytes += BLOCK_SIZE;
+
+                found
+            }
+        };
+
+        Allocation {
+            memory,
+            offset,
+            size: memory_requirements.size,
+            mapped,
+            pool: Some(pool_index),
+        }
+    }
+
+    /// First fit, the padding in front of an aligned range stays free.
+    fn allocate_in_block(
+        block: &mut Block,
+        memory_requirements: MemoryRequirements,
+    ) -> Option<(DeviceMemory, DeviceSize, *mut u8)> {
+        let alignment = memory_requirements.alignment.max(1);
+
+        let (index, offset) = block.free_ranges.iter().enumerate().find_map(
+            |(index, (free_offset, free_size))| {
+                let offset = free_offset.div_ceil(alignment) * alignment;
+                (offset + memory_requirements.size <= free_offset + free_size)
+                    .then_some((index, offset))
+            },
+        )?;
+
+        let (free_offset, free_size) = block.free_ranges.remove(index);
+        let end = offset + memory_requirements.size;
+        if end < free_offset + free_size {
+            block
+                .free_ranges
+                .insert(index, (end, free_offset + free_size - end));
+        }
+        if offset > free_offset {
+            block
+                .free_ranges
+                .insert(index, (free_offset, offset - free_offset));
+        }
+
+        block.allocation_count += 1;
+
+        let mapped = if block.mapped.is_null() {
+            ptr::null_mut()
+        } else {
+            unsafe { block.mapped.add(offset as usize) }
+        };
+
+        Some((block.memory, offset, mapped))
+    }
+
+    /// Returns the range to its block, blocks are released once they are empty.
+    pub(crate) fn free(&mut self, device: &vkDevice, allocation: &Allocation) {
+        self.statistics.allocation_count -= 1;
+        self.statistics.used_bytes -= allocation.size;
+
+        let pool_index = match allocation.pool {
+            Some(pool_index) => pool_index,
+            None => {
+                unsafe {
+                    device.free_memory(allocation.memory, None);
+                }
+
+                self.statistics.dedicated_allocation_count -= 1;
+                self.statistics.allocated_bytes -= allocation.size;
+
+                return;
+            }
+        };
+
+        let blocks = &mut self.pools[pool_index].blocks;
+        let block_index = blocks
+            .iter()
+            .position(|block| block.memory == allocation.memory)
+            .unwrap();
+        let block = &mut blocks[block_index];
+
+        block.allocation_count -= 1;
+        if block.allocation_count == 0 {
+            unsafe {
+                device.free_memory(block.memory, None);
+            }
+
+            blocks.remove(block_index);
+            self.statistics.block_count -= 1;
+            self.statistics.allocated_bytes -= BLOCK_SIZE;
+
+            return;
+        }
+
+        let index = block
+            .free_ranges
+            .iter()
+            .position(|(offset, _)| *offset > allocation.offset)
+            .unwrap_or(block.free_ranges.len());
+        block
+            .free_ranges
+            .insert(index, (allocation.offset, allocation.size));
+
+        // Merges with the following range, then with the previous one
+        if index + 1 < block.free_ranges.len()
+            && allocation.offset + allocation.size == block.free_ranges[index + 1].0
+        {
+            block.free_ranges[index].1 += block.free_ranges.remove(index + 1).1;
+        }
+        if index > 0 {
+            let (previous_offset, previous_size) = block.free_ranges[index - 1];
+            if previous_offset + previous_size == allocation.offset {
+                block.free_ranges[index - 1].1 += block.free_ranges.remove(index).1;
+            }
+        }
+    }
+
+    pub(crate) fn statistics(&self) -> AllocatorStatistics {
+        self.statistics
+    }
+
+    fn allocate_memory(
+        &self,
+        device: &vkDevice,
+        memory_type_index: u32,
+        size: DeviceSize,
+    ) -> (DeviceMemory, *mut u8) {
+        let memory_allocate_info = MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+
+        let memory = unsafe { device.allocate_memory(&memory_allocate_info, None).unwrap() };
+
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(MemoryPropertyFlags::HOST_VISIBLE);
+        let mapped = if host_visible {
+            unsafe {
+                device
+                    .map_memory(memory, 0, size, MemoryMapFlags::empty())
+                    .unwrap()
+                    .cast()
+            }
+        } else {
+            ptr::null_mut()
+        };
+
+        (memory, mapped)
+    }
+
+    fn memory_type_index(
+        &self,
+        memory_property_flags: MemoryPropertyFlags,
+        memory_requirements: MemoryRequirements,
+    ) -> u32 {
+        (0..self.memory_properties.memory_type_count)
             .find(|memory_type_index| {
-                memory_properties.memory_types[*memory_type_index as usize]
+                self.memory_properties.memory_types[*memory_type_index as usize]
                     .property_flags
                     .contains(memory_property_flags)
                     && (memory_requirements.memory_type_bits & (1 << memory_type_index)) != 0
             })
             .unwrap()
     }
+
+    /// Frees the blocks that are left, every resource must have been destroyed.
+    pub(crate) fn destroy(&mut self, device: &vkDevice) {
+        self.pools
+            .drain(..)
+            .flat_map(|pool| pool.blocks)
+            .for_each(|block| unsafe { device.free_memory(block.memory, None) });
+    }
 }
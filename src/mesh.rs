@@ -0,0 +1,489 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use collada::{document::ColladaDocument, PrimitiveElement, Shape};
+use fbxcel_dom::{
+    any::AnyDocument,
+    v7400::{
+        data::mesh::{layer::TypedLayerElementHandle, PolygonVertexIndex, PolygonVertices},
+        object::{geometry::TypedGeometryHandle, TypedObjectHandle},
+    },
+};
+use gltf::mesh::Mode;
+use itertools::Itertools;
+use log::warn;
+use nalgebra::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
+use tobj::{load_obj, GPU_LOAD_OPTIONS};
+
+use crate::{
+    mesh_optimizer::MeshOptimizer,
+    mesh_simplifier::{LodSettings, MeshSimplifier},
+    vertex::Vertex,
+};
+
+/// Indices of one object of a model file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Submesh {
+    pub(crate) index_offset: u32,
+    pub(crate) index_count: u32,
+}
+
+/// Indices of the whole mesh at one level of detail, the first LOD is the full mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Lod {
+    pub(crate) index_offset: u32,
+    pub(crate) index_count: u32,
+    /// Largest distance between the surface of the LOD and the full one.
+    pub(crate) error: f32,
+}
+
+/// Triangles of a model file, before they are stored in the engine format.
+#[derive(Default, Clone, Debug)]
+pub(crate) struct Mesh {
+    pub(crate) vertices: Vec<Vertex>,
+    /// Indices of the full mesh followed by the ones of the other LODs.
+    pub(crate) indices: Vec<u32>,
+    /// Index the full mesh.
+    pub(crate) submeshes: Vec<Submesh>,
+    pub(crate) lods: Vec<Lod>,
+}
+
+impl Mesh {
+    /// Reads an OBJ, glTF, FBX or COLLADA file, it can be done on any thread. Vertices that
+    /// have no normal in the file get a zero one.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "obj" => Self::load_obj(path),
+            "gltf" | "glb" => Self::load_gltf(path),
+            "fbx" => Self::load_fbx(path),
+            "dae" => Self::load_collada(path),
+            _ => Err(format!("{} is not a model file", path.display())),
+        }
+    }
+
+    /// Prepares the mesh for rendering: drops the triangles that cover no area, welds equal
+    /// vertices and generates the normals the file doesn't have, then the tangents, and
+    /// reorders it for the GPU and finally simplifies it into LODs.
+    pub(crate) fn process(&mut self, lod_settings: &LodSettings) {
+        self.remove_degenerate_triangles();
+        MeshOptimizer::weld_vertices(self);
+        self.generate_normals();
+        self.generate_tangents();
+        MeshOptimizer::optimize(self);
+        MeshSimplifier::generate_lods(self, lod_settings);
+    }
+
+    fn load_obj(path: &Path) -> Result<Self, String> {
+        let (models, _) = load_obj(path, &GPU_LOAD_OPTIONS).map_err(|error| error.to_string())?;
+
+        let mut mesh = Self::default();
+        models.iter().for_each(|model| {
+            let positions = &model.mesh.positions;
+            let texture_uvs = &model.mesh.texcoords;
+            let normals = &model.mesh.normals;
+
+            let vertices = (0..positions.len() / 3)
+                .map(|i| {
+                    Self::vertex(
+                        Vector3::new(positions[3 * i], positions[3 * i + 1], positions[3 * i + 2]),
+                        texture_uvs
+                            .get(2 * i..2 * i + 2)
+                            .map_or_else(Vector2::zeros, |uv| Vector2::new(uv[0], 1.0 - uv[1])),
+                        normals
+                            .get(3 * i..3 * i + 3)
+                            .map_or_else(Vector3::zeros, |normal| {
+                                Vector3::new(normal[0], normal[1], normal[2])
+                            }),
+                    )
+                })
+                .collect_vec();
+
+            mesh.append(vertices, model.mesh.indices.iter().copied());
+        });
+
+        Ok(mesh)
+    }
+
+    /// Meshes of the default scene, moved by the transforms of their nodes.
+    fn load_gltf(path: &Path) -> Result<Self, String> {
+        let (document, buffers, _) = gltf::import(path).map_err(|error| error.to_string())?;
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| "the file has no scene".to_string())?;
+
+        let mut mesh = Self::default();
+        let mut nodes = scene
+            .nodes()
+            .map(|node| (node, Matrix4::<f32>::identity()))
+            .collect_vec();
+        while let Some((node, parent_transform)) = nodes.pop() {
+            let transform = parent_transform * Matrix4::from(node.transform().matrix());
+            let normal_transform = transform
+                .fixed_view::<3, 3>(0, 0)
+                .clone_owned()
+                .try_inverse()
+                .map_or_else(Matrix3::identity, |inverse| inverse.transpose());
+
+            for primitive in node
+                .mesh()
+                .iter()
+                .flat_map(|node_mesh| node_mesh.primitives())
+            {
+                if primitive.mode() != Mode::Triangles {
+                    warn!("Skipping {:?} of {}", primitive.mode(), path.display());
+                    continue;
+                }
+
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions = reader
+                    .read_positions()
+                    .ok_or_else(|| "a primitive has no positions".to_string())?
+                    .collect_vec();
+                let normals = reader
+                    .read_normals()
+                    .map(|normals| normals.collect_vec())
+                    .unwrap_or_default();
+                let texture_uvs = reader
+                    .read_tex_coords(0)
+                    .map(|texture_uvs| texture_uvs.into_f32().collect_vec())
+                    .unwrap_or_default();
+
+                let vertices = positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, position)| {
+                        Self::vertex(
+                            (transform * Vector4::new(position[0], position[1], position[2], 1.0))
+                                .xyz(),
+                            texture_uvs
+                                .get(i)
+                                .map_or_else(Vector2::zeros, |uv| (*uv).into()),
+                            normals.get(i).map_or_else(Vector3::zeros, |normal| {
+                                (normal_transform * Vector3::from(*normal))
+                                    .try_normalize(f32::EPSILON)
+                                    .unwrap_or_else(Vector3::zeros)
+                            }),
+                        )
+                    })
+                    .collect_vec();
+                let vertex_count = vertices.len() as u32;
+
+                match reader.read_indices() {
+                    Some(indices) => mesh.append(vertices, indices.into_u32()),
+                    None => mesh.append(vertices, 0..vertex_count),
+                }
+            }
+
+            nodes.extend(node.children().map(|child| (child, transform)));
+        }
+
+        Ok(mesh)
+    }
+
+    fn load_fbx(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let document = match AnyDocument::from_seekable_reader(BufReader::new(file))
+            .map_err(|error| error.to_string())?
+        {
+            AnyDocument::V7400(_, document) => document,
+            _ => return Err("the FBX version is not supported".to_string()),
+        };
+
+        let mut mesh = Self::default();
+        for object in document.objects() {
+            let fbx_mesh = match object.get_typed() {
+                TypedObjectHandle::Geometry(TypedGeometryHandle::Mesh(fbx_mesh)) => fbx_mesh,
+                _ => continue,
+            };
+
+            let triangles = fbx_mesh
+                .polygon_vertices()
+                .and_then(|polygon_vertices| {
+                    polygon_vertices.triangulate_each(Self::triangulate_polygon)
+                })
+                .map_err(|error| error.to_string())?;
+            let layer_elements = fbx_mesh
+                .layers()
+                .flat_map(|layer| layer.layer_element_entries())
+                .filter_map(|entry| entry.typed_layer_element().ok())
+                .collect_vec();
+            let normals = layer_elements
+                .iter()
+                .find_map(|layer_element| match layer_element {
+                    TypedLayerElementHandle::Normal(normals) => Some(normals.normals()),
+                    _ => None,
+                })
+                .transpose()
+                .map_err(|error| error.to_string())?;
+            let texture_uvs = layer_elements
+                .iter()
+                .find_map(|layer_element| match layer_element {
+                    TypedLayerElementHandle::Uv(texture_uvs) => Some(texture_uvs.uv()),
+                    _ => None,
+                })
+                .transpose()
+                .map_err(|error| error.to_string())?;
+
+            let vertices = triangles
+                .triangle_vertex_indices()
+                .map(|triangle_vertex| {
+                    let position = triangles
+                        .control_point(triangle_vertex)
+                        .ok_or_else(|| "a triangle has no control point".to_string())?;
+                    let normal = normals
+                        .as_ref()
+                        .map(|normals| normals.normal(&triangles, triangle_vertex))
+                        .transpose()
+                        .map_err(|error| error.to_string())?;
+                    let texture_uv = texture_uvs
+                        .as_ref()
+                        .map(|texture_uvs| texture_uvs.uv(&triangles, triangle_vertex))
+                        .transpose()
+                        .map_err(|error| error.to_string())?;
+
+                    Ok(Self::vertex(
+                        Vector3::new(position.x as f32, position.y as f32, position.z as f32),
+                        texture_uv.map_or_else(Vector2::zeros, |uv| {
+                            Vector2::new(uv.x as f32, 1.0 - uv.y as f32)
+                        }),
+                        normal.map_or_else(Vector3::zeros, |normal| {
+                            Vector3::new(normal.x as f32, normal.y as f32, normal.z as f32)
+                        }),
+                    ))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            let vertex_count = vertices.len() as u32;
+
+            mesh.append(vertices, 0..vertex_count);
+        }
+
+        Ok(mesh)
+    }
+
+    /// Splits a convex polygon into a fan of triangles.
+    fn triangulate_polygon(
+        _: &PolygonVertices<'_>,
+        polygon: &[PolygonVertexIndex],
+        triangles: &mut Vec<[PolygonVertexIndex; 3]>,
+    ) -> anyhow::Result<()> {
+        triangles.extend(
+            (1..polygon.len().saturating_sub(1)).map(|i| [polygon[0], polygon[i], polygon[i + 1]]),
+        );
+
+        Ok(())
+    }
+
+    fn load_collada(path: &Path) -> Result<Self, String> {
+        let document = ColladaDocument::from_path(path)?;
+        let objects = document
+            .get_obj_set()
+            .ok_or_else(|| "the file has no geometry".to_string())?
+            .objects;
+
+        let mut mesh = Self::default();
+        objects.iter().for_each(|object| {
+            // Positions, texture coordinates and normals are indexed separately
+            let corners = object
+                .geometry
+                .iter()
+                .flat_map(|geometry| &geometry.mesh)
+                .flat_map(|primitive| match primitive {
+                    PrimitiveElement::Triangles(triangles) => triangles
+                        .vertices
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(i, positions)| {
+                            let texture_uvs = triangles.tex_vertices.as_ref().map(|uvs| uvs[i]);
+                            let normals = triangles.normals.as_ref().map(|normals| normals[i]);
+
+                            [
+                                (
+                                    positions.0,
+                                    texture_uvs.map(|uvs| uvs.0),
+                                    normals.map(|normals| normals.0),
+                                ),
+                                (
+                                    positions.1,
+                                    texture_uvs.map(|uvs| uvs.1),
+                                    normals.map(|normals| normals.1),
+                                ),
+                                (
+                                    positions.2,
+                                    texture_uvs.map(|uvs| uvs.2),
+                                    normals.map(|normals| normals.2),
+                                ),
+                            ]
+                        })
+                        .collect_vec(),
+                    PrimitiveElement::Polylist(polylist) => polylist
+                        .shapes
+                        .iter()
+                        .filter_map(|shape| match shape {
+                            Shape::Triangle(a, b, c) => Some([*a, *b, *c]),
+                            _ => None,
+                        })
+                        .flatten()
+                        .collect_vec(),
+                })
+                .collect_vec();
+
+            let vertices = corners
+                .iter()
+                .map(|(position, texture_uv, normal)| {
+                    let position = object.vertices[*position];
+                    Self::vertex(
+                        Vector3::new(position.x as f32, position.y as f32, position.z as f32),
+                        texture_uv.map_or_else(Vector2::zeros, |texture_uv| {
+                            let uv = object.tex_vertices[texture_uv];
+                            Vector2::new(uv.x as f32, 1.0 - uv.y as f32)
+                        }),
+                        normal.map_or_else(Vector3::zeros, |normal| {
+                            let normal = object.normals[normal];
+                            Vector3::new(normal.x as f32, normal.y as f32, normal.z as f32)
+                        }),
+                    )
+                })
+                .collect_vec();
+
+            mesh.append(vertices, 0..corners.len() as u32);
+        });
+
+        Ok(mesh)
+    }
+
+    fn vertex(pos: Vector3<f32>, texture_uv: Vector2<f32>, normal: Vector3<f32>) -> Vertex {
+        Vertex::new(pos, Vector3::new(1.0, 1.0, 1.0), texture_uv, normal)
+    }
+
+    /// Adds an object whose indices start at its first vertex.
+    fn append(&mut self, vertices: Vec<Vertex>, indices: impl Iterator<Item = u32>) {
+        let vertex_offset = self.vertices.len() as u32;
+        let index_offset = self.indices.len() as u32;
+
+        self.vertices.extend(vertices);
+        self.indices
+            .extend(indices.map(|index| index + vertex_offset));
+        self.submeshes.push(Submesh {
+            index_offset,
+            index_count: self.indices.len() as u32 - index_offset,
+        });
+    }
+
+    /// Normal scaled by twice the area of the triangle.
+    pub(crate) fn face_normal(vertices: &[Vertex], triangle: &[u32]) -> Vector3<f32> {
+        let position = |i: usize| vertices[triangle[i] as usize].pos;
+
+        (position(1) - position(0)).cross(&(position(2) - position(0)))
+    }
+
+    fn remove_degenerate_triangles(&mut self) {
+        let mut indices = Vec::with_capacity(self.indices.len());
+
+        self.submeshes = self
+            .submeshes
+            .iter()
+            .map(|submesh| {
+                let index_offset = indices.len() as u32;
+                let start = submesh.index_offset as usize;
+                let end = start + submesh.index_count as usize;
+
+                indices.extend(
+                    self.indices[start..end]
+                        .chunks_exact(3)
+                        .filter(|triangle| {
+                            Self::face_normal(&self.vertices, triangle).norm_squared() > 0.0
+                        })
+                        .flatten(),
+                );
+
+                Submesh {
+                    index_offset,
+                    index_count: indices.len() as u32 - index_offset,
+                }
+            })
+            .collect_vec();
+        self.indices = indices;
+    }
+
+    /// Gives the vertices without a normal the average of the normals of their triangles,
+    /// weighted by area.
+    fn generate_normals(&mut self) {
+        let mut normals = vec![Vector3::zeros(); self.vertices.len()];
+        self.indices.chunks_exact(3).for_each(|triangle| {
+            let face_normal = Self::face_normal(&self.vertices, triangle);
+            triangle
+                .iter()
+                .for_each(|index| normals[*index as usize] += face_normal);
+        });
+
+        self.vertices
+            .iter_mut()
+            .zip(normals)
+            .filter(|(vertex, _)| vertex.normal == Vector3::zeros())
+            .for_each(|(vertex, normal)| {
+                vertex.normal = normal
+                    .try_normalize(f32::EPSILON)
+                    .unwrap_or_else(Vector3::z)
+            });
+    }
+
+    /// Tangents point along increasing u, averaged over the triangles of a vertex and made
+    /// perpendicular to its normal.
+    fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vector3::zeros(); self.vertices.len()];
+        let mut bitangents = vec![Vector3::zeros(); self.vertices.len()];
+
+        self.indices.chunks_exact(3).for_each(|triangle| {
+            let vertex = |i: usize| &self.vertices[triangle[i] as usize];
+            let (edge_1, edge_2) = (vertex(1).pos - vertex(0).pos, vertex(2).pos - vertex(0).pos);
+            let (uv_1, uv_2) = (
+                vertex(1).texture_uv - vertex(0).texture_uv,
+                vertex(2).texture_uv - vertex(0).texture_uv,
+            );
+
+            let determinant = uv_1.x * uv_2.y - uv_2.x * uv_1.y;
+            if determinant.abs() <= f32::EPSILON {
+                return;
+            }
+            let tangent = (edge_1 * uv_2.y - edge_2 * uv_1.y) / determinant;
+            let bitangent = (edge_2 * uv_1.x - edge_1 * uv_2.x) / determinant;
+
+            triangle.iter().for_each(|index| {
+                tangents[*index as usize] += tangent;
+                bitangents[*index as usize] += bitangent;
+            });
+        });
+
+        self.vertices
+            .iter_mut()
+            .zip(tangents.iter().zip(&bitangents))
+            .for_each(|(vertex, (tangent, bitangent))| {
+                let normal = vertex.normal;
+                let tangent = (tangent - normal * normal.dot(tangent))
+                    .try_normalize(f32::EPSILON)
+                    .unwrap_or_else(|| {
+                        // Any direction along the surface, for triangles without texture
+                        // coordinates
+                        let axis = if normal.x.abs() < 0.9 {
+                            Vector3::x()
+                        } else {
+                            Vector3::y()
+                        };
+                        normal.cross(&axis).normalize()
+                    });
+                let handedness = if normal.cross(&tangent).dot(bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+
+                vertex.tangent = Vector4::new(tangent.x, tangent.y, tangent.z, handedness);
+            });
+    }
+}
@@ -0,0 +1,411 @@
+use std::cmp::Ordering;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use nalgebra::Vector3;
+
+use crate::{mesh::Mesh, vertex::Vertex};
+
+const VERTEX_CACHE_SIZE: usize = 16;
+// Fraction of the size of the mesh under which positions are welded
+const POSITION_EPSILON: f32 = 1e-6;
+const ATTRIBUTE_EPSILON: f32 = 1e-5;
+
+/// Welds and reorders meshes so that GPUs shade, overdraw and fetch fewer vertices. Triangles
+/// are ordered as in "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw" by
+/// Sander, Nehab and Barczak.
+pub(crate) struct MeshOptimizer;
+
+impl MeshOptimizer {
+    /// Merges the vertices whose attributes are equal up to a small epsilon and drops the
+    /// unused ones.
+    pub(crate) fn weld_vertices(mesh: &mut Mesh) {
+        let (min, max) = mesh.vertices.iter().fold(
+            (
+                Vector3::repeat(f32::INFINITY),
+                Vector3::repeat(f32::NEG_INFINITY),
+            ),
+            |(min, max), vertex| (min.inf(&vertex.pos), max.sup(&vertex.pos)),
+        );
+        let position_epsilon = ((max - min).max() * POSITION_EPSILON).max(f32::MIN_POSITIVE);
+
+        let quantize = |attribute: f32, epsilon: f32| (attribute / epsilon).round() as i64;
+        let vertices = &mesh.vertices;
+        let mut welded_vertices = Vec::with_capacity(vertices.len());
+        let mut welded_indices = HashMap::with_capacity(vertices.len());
+
+        mesh.indices.iter_mut().for_each(|index| {
+            let vertex = vertices[*index as usize];
+            let attributes = vertex
+                .pos
+                .iter()
+                .map(|attribute| quantize(*attribute, position_epsilon))
+                .chain(
+                    vertex
+                        .color
+                        .iter()
+                        .chain(vertex.texture_uv.iter())
+                        .chain(vertex.normal.iter())
+                        .chain(vertex.tangent.iter())
+                        .map(|attribute| quantize(*attribute, ATTRIBUTE_EPSILON)),
+                )
+                .collect_vec();
+
+            *index = *welded_indices.entry(attributes).or_insert_with(|| {
+                welded_vertices.push(vertex);
+                welded_vertices.len() as u32 - 1
+            });
+        });
+
+        mesh.vertices = welded_vertices;
+    }
+
+    /// Reorders the triangles of every submesh for the post-transform vertex cache, then
+    /// their clusters against overdraw, then the vertices in the order the triangles use them.
+    pub(crate) fn optimize(mesh: &mut Mesh) {
+        let mut indices = Vec::with_capacity(mesh.indices.len());
+
+        mesh.submeshes.iter().for_each(|submesh| {
+            let start = submesh.index_offset as usize;
+            let end = start + submesh.index_count as usize;
+
+            indices.extend(Self::optimize_triangles(
+                &mesh.vertices,
+                &mesh.indices[start..end],
+            ));
+        });
+        mesh.indices = indices;
+
+        Self::optimize_vertex_fetch(mesh);
+    }
+
+    /// Triangles reordered for the post-transform vertex cache, then their clusters against
+    /// overdraw.
+    pub(crate) fn optimize_triangles(vertices: &[Vertex], indices: &[u32]) -> Vec<u32> {
+        let (cache_ordered_indices, cluster_starts) = Self::optimize_vertex_cache(indices);
+
+        Self::optimize_overdraw(vertices, &cache_ordered_indices, &cluster_starts)
+    }
+
+    /// Tipsify: fans around recently used vertices, which are likely to still be in the
+    /// cache. Returns the indices and the first triangle of each cluster, clusters starting
+    /// where the fanning had to jump to a vertex that is out of the cache.
+    fn optimize_vertex_cache(indices: &[u32]) -> (Vec<u32>, Vec<usize>) {
+        // Numbered from 0 so that the arrays below only cover the vertices of the submesh
+        let mut local_vertices = HashMap::new();
+        let corners = indices
+            .iter()
+            .map(|index| {
+                let next_vertex = local_vertices.len();
+                *local_vertices.entry(*index).or_insert(next_vertex)
+            })
+            .collect_vec();
+        let vertex_count = local_vertices.len();
+        let triangle_count = indices.len() / 3;
+
+        // Triangles of each vertex, one after another from their offset
+        let mut triangle_offsets = vec![0; vertex_count + 1];
+        corners
+            .iter()
+            .for_each(|vertex| triangle_offsets[vertex + 1] += 1);
+        (0..vertex_count).for_each(|vertex| {
+            triangle_offsets[vertex + 1] += triangle_offsets[vertex];
+        });
+        let mut adjacent_triangles = vec![0; corners.len()];
+        let mut next_slots = triangle_offsets.clone();
+        corners.iter().enumerate().for_each(|(corner, vertex)| {
+            adjacent_triangles[next_slots[*vertex]] = corner / 3;
+            next_slots[*vertex] += 1;
+        });
+
+        let mut live_triangle_counts = (0..vertex_count)
+            .map(|vertex| triangle_offsets[vertex + 1] - triangle_offsets[vertex])
+            .collect_vec();
+        let mut cache_times = vec![0; vertex_count];
+        let mut time = VERTEX_CACHE_SIZE + 1;
+        let mut emitted = vec![false; triangle_count];
+        let mut dead_ends = Vec::new();
+        let mut next_triangle = 0;
+
+        let mut optimized_indices = Vec::with_capacity(indices.len());
+        let mut cluster_starts = vec![0];
+        let mut fanning_vertex = corners.first().copied();
+        while let Some(vertex) = fanning_vertex {
+            let mut candidates = Vec::new();
+
+            for triangle in
+                &adjacent_triangles[triangle_offsets[vertex]..triangle_offsets[vertex + 1]]
+            {
+                if emitted[*triangle] {
+                    continue;
+                }
+                emitted[*triangle] = true;
+
+                for corner in 3 * triangle..3 * triangle + 3 {
+                    let corner_vertex = corners[corner];
+                    optimized_indices.push(indices[corner]);
+                    dead_ends.push(corner_vertex);
+                    candidates.push(corner_vertex);
+                    live_triangle_counts[corner_vertex] -= 1;
+
+                    if time - cache_times[corner_vertex] > VERTEX_CACHE_SIZE {
+                        cache_times[corner_vertex] = time;
+                        time += 1;
+                    }
+                }
+            }
+
+            // The candidate that stays longest in the cache while its triangles are fanned
+            fanning_vertex = candidates
+                .iter()
+                .filter(|candidate| live_triangle_counts[**candidate] > 0)
+                .map(|candidate| {
+                    let age = time - cache_times[*candidate];
+                    let priority =
+                        if age + 2 * live_triangle_counts[*candidate] <= VERTEX_CACHE_SIZE {
+                            age
+                        } else {
+                            0
+                        };
+
+                    (priority, *candidate)
+                })
+                .fold(None, |best, (priority, candidate)| match best {
+                    Some((best_priority, _)) if best_priority >= priority => best,
+                    _ => Some((priority, candidate)),
+                })
+                .map(|(_, candidate)| candidate);
+
+            if fanning_vertex.is_none() {
+                fanning_vertex =
+                    Self::pop_dead_end(&mut dead_ends, &live_triangle_counts).or_else(|| {
+                        while next_triangle < triangle_count && emitted[next_triangle] {
+                            next_triangle += 1;
+                        }
+                        corners.get(3 * next_triangle).copied()
+                    });
+
+                if fanning_vertex.is_some() {
+                    cluster_starts.push(optimized_indices.len() / 3);
+                }
+            }
+        }
+
+        (optimized_indices, cluster_starts)
+    }
+
+    /// Draws the clusters that face away from the center of the submesh first, since they are
+    /// the ones most likely to hide the others.
+    fn optimize_overdraw(
+        vertices: &[Vertex],
+        indices: &[u32],
+        cluster_starts: &[usize],
+    ) -> Vec<u32> {
+        let triangle_count = indices.len() / 3;
+        let centroid = |triangle: &[u32]| {
+            triangle
+                .iter()
+                .map(|index| vertices[*index as usize].pos)
+                .sum::<Vector3<f32>>()
+                / 3.0
+        };
+        // Sums of the triangle centroids weighted by area, and of the area weighted normals
+        let weighted_sums = |triangles: &[u32]| {
+            triangles.chunks_exact(3).fold(
+                (Vector3::zeros(), 0.0, Vector3::zeros()),
+                |(centroid_sum, area_sum, normal_sum), triangle| {
+                    let face_normal = Mesh::face_normal(vertices, triangle);
+                    let area = face_normal.norm();
+
+                    (
+                        centroid_sum + centroid(triangle) * area,
+                        area_sum + area,
+                        normal_sum + face_normal,
+                    )
+                },
+            )
+        };
+
+        let (centroid_sum, area_sum, _) = weighted_sums(indices);
+        let submesh_centroid = centroid_sum / area_sum.max(f32::MIN_POSITIVE);
+
+        cluster_starts
+            .iter()
+            .copied()
+            .chain([triangle_count])
+            .tuple_windows()
+            .map(|(start, end)| {
+                let cluster = &indices[3 * start..3 * end];
+                let (centroid_sum, area_sum, normal_sum) = weighted_sums(cluster);
+                let cluster_centroid = centroid_sum / area_sum.max(f32::MIN_POSITIVE);
+                let occlusion_potential = (cluster_centroid - submesh_centroid).dot(
+                    &normal_sum
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or_else(Vector3::zeros),
+                );
+
+                (occlusion_potential, cluster)
+            })
+            .sorted_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal))
+            .flat_map(|(_, cluster)| cluster.iter().copied())
+            .collect_vec()
+    }
+
+    /// Renumbers the vertices in the order in which the indices first use them, so that they
+    /// are fetched sequentially.
+    fn optimize_vertex_fetch(mesh: &mut Mesh) {
+        let mut new_indices = vec![u32::MAX; mesh.vertices.len()];
+        let mut vertices = Vec::with_capacity(mesh.vertices.len());
+
+        for index in mesh.indices.iter_mut() {
+            let new_index = &mut new_indices[*index as usize];
+            if *new_index == u32::MAX {
+                *new_index = vertices.len() as u32;
+                vertices.push(mesh.vertices[*index as usize]);
+            }
+
+            *index = *new_index;
+        }
+
+        mesh.vertices = vertices;
+    }
+
+    /// Most recently used vertex that still has triangles to emit.
+    fn pop_dead_end(dead_ends: &mut Vec<usize>, live_triangle_counts: &[usize]) -> Option<usize> {
+        while let Some(vertex) = dead_ends.pop() {
+            if live_triangle_counts[vertex] > 0 {
+                return Some(vertex);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use nalgebra::{Vector2, Vector3};
+
+    use super::MeshOptimizer;
+    use crate::{
+        mesh::{Mesh, Submesh},
+        vertex::Vertex,
+    };
+
+    fn vertex(x: f32, y: f32) -> Vertex {
+        Vertex::new(
+            Vector3::new(x, y, 0.0),
+            Vector3::repeat(1.0),
+            Vector2::new(x, y),
+            Vector3::z(),
+        )
+    }
+
+    /// Square of `size` by `size` quads, each with its own copy of its corners.
+    fn grid(size: usize) -> Mesh {
+        let vertices = (0..size)
+            .cartesian_product(0..size)
+            .flat_map(|(x, y)| {
+                let (x, y) = (x as f32, y as f32);
+                [
+                    vertex(x, y),
+                    vertex(x + 1.0, y),
+                    vertex(x + 1.0, y + 1.0),
+                    vertex(x, y),
+                    vertex(x + 1.0, y + 1.0),
+                    vertex(x, y + 1.0),
+                ]
+            })
+            .collect_vec();
+        let indices = (0..vertices.len() as u32).collect_vec();
+
+        Mesh {
+            submeshes: vec![Submesh {
+                index_offset: 0,
+                index_count: indices.len() as u32,
+            }],
+            vertices,
+            indices,
+            lods: Vec::new(),
+        }
+    }
+
+    /// Corner positions of every triangle, in a canonical order.
+    fn triangles(mesh: &Mesh) -> Vec<Vec<[i64; 3]>> {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                triangle
+                    .iter()
+                    .map(|index| {
+                        let pos = mesh.vertices[*index as usize].pos;
+                        [pos.x as i64, pos.y as i64, pos.z as i64]
+                    })
+                    .collect_vec()
+            })
+            .sorted()
+            .collect_vec()
+    }
+
+    #[test]
+    fn weld_vertices_merges_copies() {
+        let mut mesh = grid(3);
+        let expected_triangles = triangles(&mesh);
+
+        MeshOptimizer::weld_vertices(&mut mesh);
+
+        assert_eq!(mesh.vertices.len(), 16);
+        assert_eq!(triangles(&mesh), expected_triangles);
+    }
+
+    #[test]
+    fn weld_vertices_keeps_different_attributes() {
+        let mut mesh = grid(1);
+        mesh.vertices[3].texture_uv = Vector2::new(0.5, 0.5);
+
+        MeshOptimizer::weld_vertices(&mut mesh);
+
+        assert_eq!(mesh.vertices.len(), 5);
+    }
+
+    #[test]
+    fn optimize_keeps_triangles() {
+        let mut mesh = grid(8);
+        MeshOptimizer::weld_vertices(&mut mesh);
+        let expected_triangles = triangles(&mesh);
+
+        MeshOptimizer::optimize(&mut mesh);
+
+        assert_eq!(triangles(&mesh), expected_triangles);
+    }
+
+    #[test]
+    fn optimize_orders_vertices_by_first_use() {
+        let mut mesh = grid(8);
+        MeshOptimizer::weld_vertices(&mut mesh);
+
+        MeshOptimizer::optimize(&mut mesh);
+
+        let first_uses = mesh.indices.iter().copied().unique().collect_vec();
+        assert_eq!(first_uses, (0..mesh.vertices.len() as u32).collect_vec());
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let mut mesh = Mesh {
+            submeshes: vec![Submesh {
+                index_offset: 0,
+                index_count: 0,
+            }],
+            ..Mesh::default()
+        };
+
+        MeshOptimizer::weld_vertices(&mut mesh);
+        MeshOptimizer::optimize(&mut mesh);
+
+        assert!(mesh.vertices.is_empty());
+        assert!(mesh.indices.is_empty());
+        assert!(MeshOptimizer::optimize_triangles(&[], &[]).is_empty());
+    }
+}
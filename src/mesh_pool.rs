@@ -0,0 +1,397 @@
+use std::mem::size_of;
+
+use log::debug;
+use vulkanalia::{
+    vk::{
+        self, AccessFlags, BufferCopy, BufferUsageFlags, DeviceSize, DeviceV1_0, HasBuilder,
+        IndexType, MemoryPropertyFlags, PipelineStageFlags,
+    },
+    Device as vkDevice,
+};
+
+use crate::{
+    asset_server::GpuMesh, buffer::Buffer, command_buffer::CommandBuffer, device::Device,
+    renderer::MAX_FLIGHT_FRAMES_COUNT, upload_batcher::UploadBatcher, vertex::Vertex,
+};
+
+const INITIAL_VERTEX_CAPACITY: DeviceSize = 1 << 18;
+const INITIAL_INDEX_CAPACITY: DeviceSize = 1 << 22;
+// Index ranges start on a multiple of both index sizes, so that their first index can be
+// given in either of them
+const INDEX_ALIGNMENT: DeviceSize = 4;
+
+/// Where a mesh is in the buffers of the [`MeshPool`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PooledMesh {
+    /// Added to the indices of the mesh.
+    pub(crate) vertex_offset: i32,
+    /// First index of the mesh in the index buffer, counted in its index type.
+    pub(crate) first_index: u32,
+    vertices: (DeviceSize, DeviceSize),
+    index_bytes: (DeviceSize, DeviceSize),
+}
+
+/// First fit allocator of ranges in a buffer, free ranges are kept sorted and merged.
+#[derive(Clone, Debug)]
+struct RangeAllocator {
+    capacity: DeviceSize,
+    /// Starts and sizes.
+    free_ranges: Vec<(DeviceSize, DeviceSize)>,
+}
+
+impl RangeAllocator {
+    fn new(capacity: DeviceSize) -> Self {
+        Self {
+            capacity,
+            free_ranges: vec![(0, capacity)],
+        }
+    }
+
+    fn allocate(&mut self, size: DeviceSize, alignment: DeviceSize) -> Option<DeviceSize> {
+        let (index, start, aligned_start) =
+            self.free_ranges
+                .iter()
+                .enumerate()
+                .find_map(|(index, (start, free_size))| {
+                    let aligned_start = start.next_multiple_of(alignment);
+
+                    (aligned_start + size <= start + free_size).then_some((
+                        index,
+                        *start,
+                        aligned_start,
+                    ))
+                })?;
+
+        let (_, free_size) = self.free_ranges.remove(index);
+        let end = start + free_size;
+        if aligned_start + size < end {
+            self.free_ranges
+                .insert(index, (aligned_start + size, end - aligned_start - size));
+        }
+        if start < aligned_start {
+            self.free_ranges
+                .insert(index, (start, aligned_start - start));
+        }
+
+        Some(aligned_start)
+    }
+
+    fn free(&mut self, start: DeviceSize, size: DeviceSize) {
+        if size == 0 {
+            return;
+        }
+
+        let index = self
+            .free_ranges
+            .partition_point(|(free_start, _)| *free_start < start);
+        self.free_ranges.insert(index, (start, size));
+
+        // With the next range, then with the previous one
+        if index + 1 < self.free_ranges.len() {
+            let (next_start, next_size) = self.free_ranges[index + 1];
+            if start + size == next_start {
+                self.free_ranges[index].1 += next_size;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+        if index > 0 {
+            let (previous_start, previous_size) = self.free_ranges[index - 1];
+            if previous_start + previous_size == start {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+    }
+
+    fn grow(&mut self, capacity: DeviceSize) {
+        let previous_capacity = self.capacity;
+        self.capacity = capacity;
+
+        self.free(previous_capacity, capacity - previous_capacity);
+    }
+}
+
+/// One vertex buffer and one index buffer holding every loaded mesh, so that the meshes can
+/// be drawn by indirect draws without binding buffers in between. Meshes are copied in
+/// from their own buffers, 16 and 32-bit indices share the index buffer.
+#[derive(Debug)]
+pub(crate) struct MeshPool {
+    pub(crate) vertex_buffer: Buffer<Vertex>,
+    pub(crate) index_buffer: Buffer<u8>,
+    vertex_allocator: RangeAllocator,
+    index_allocator: RangeAllocator,
+    /// Buffers replaced by bigger ones and the frame they were replaced in.
+    retired_vertex_buffers: Vec<(u64, Buffer<Vertex>)>,
+    retired_index_buffers: Vec<(u64, Buffer<u8>)>,
+    device: Device,
+}
+
+impl MeshPool {
+    pub(crate) fn new(device: Device) -> Self {
+        Self {
+            vertex_buffer: Self::create_vertex_buffer(INITIAL_VERTEX_CAPACITY, device.clone()),
+            index_buffer: Self::create_index_buffer(INITIAL_INDEX_CAPACITY, device.clone()),
+            vertex_allocator: RangeAllocator::new(INITIAL_VERTEX_CAPACITY),
+            index_allocator: RangeAllocator::new(INITIAL_INDEX_CAPACITY),
+            retired_vertex_buffers: Vec::new(),
+            retired_index_buffers: Vec::new(),
+            device,
+        }
+    }
+
+    /// Records the copy of a mesh into the pool, the buffers of the mesh must be readable
+    /// by transfers in `upload_batcher`. The pool grows when the mesh doesn't fit.
+    pub(crate) fn insert(
+        &mut self,
+        mesh: &GpuMesh,
+        frame_count: u64,
+        upload_batcher: &mut UploadBatcher,
+    ) -> PooledMesh {
+        let vertex_count = mesh.vertex_count as DeviceSize;
+        let index_width = Self::index_width(mesh.index_type);
+        let index_size = mesh.index_count as DeviceSize * index_width;
+
+        let vertex_start = match self.vertex_allocator.allocate(vertex_count, 1) {
+            Some(vertex_start) => vertex_start,
+            None => {
+                self.grow_vertices(vertex_count, frame_count, upload_batcher);
+                self.vertex_allocator.allocate(vertex_count, 1).unwrap()
+            }
+        };
+        let index_start = match self.index_allocator.allocate(index_size, INDEX_ALIGNMENT) {
+            Some(index_start) => index_start,
+            None => {
+                self.grow_indices(index_size, frame_count, upload_batcher);
+                self.index_allocator
+                    .allocate(index_size, INDEX_ALIGNMENT)
+                    .unwrap()
+            }
+        };
+
+        let vertex_size = size_of::<Vertex>() as DeviceSize;
+        let copies = [
+            (
+                vk::Buffer::from(&mesh.vertex_buffer),
+                vk::Buffer::from(&self.vertex_buffer),
+                vertex_start * vertex_size,
+                vertex_count * vertex_size,
+            ),
+            (
+                vk::Buffer::from(&mesh.index_buffer),
+                vk::Buffer::from(&self.index_buffer),
+                index_start,
+                index_size,
+            ),
+        ];
+        upload_batcher.record(|command_buffer| {
+            // The mesh may have been filled in the same batch
+            command_buffer.record_memory_barrier(
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_READ),
+            );
+            copies.iter().filter(|(.., size)| *size > 0).for_each(
+                |(src_buffer, dst_buffer, dst_offset, size)| {
+                    self.record_copy(
+                        &command_buffer,
+                        *src_buffer,
+                        *dst_buffer,
+                        *dst_offset,
+                        *size,
+                    )
+                },
+            );
+
+            Self::record_draw_barrier(&command_buffer);
+        });
+
+        PooledMesh {
+            vertex_offset: vertex_start as i32,
+            first_index: (index_start / index_width) as u32,
+            vertices: (vertex_start, vertex_count),
+            index_bytes: (index_start, index_size),
+        }
+    }
+
+    /// Gives the ranges of a mesh back, no frame in flight may draw it anymore.
+    pub(crate) fn free(&mut self, pooled_mesh: &PooledMesh) {
+        let (vertex_start, vertex_count) = pooled_mesh.vertices;
+        let (index_start, index_size) = pooled_mesh.index_bytes;
+
+        self.vertex_allocator.free(vertex_start, vertex_count);
+        self.index_allocator.free(index_start, index_size);
+    }
+
+    /// Destroys the buffers that were replaced long enough ago for no frame to use them.
+    pub(crate) fn destroy_retired(&mut self, frame_count: u64) {
+        Self::take_destroyable(&mut self.retired_vertex_buffers, frame_count)
+            .iter()
+            .for_each(Buffer::destroy);
+        Self::take_destroyable(&mut self.retired_index_buffers, frame_count)
+            .iter()
+            .for_each(Buffer::destroy);
+    }
+
+    fn take_destroyable<T: Clone>(
+        retired: &mut Vec<(u64, Buffer<T>)>,
+        frame_count: u64,
+    ) -> Vec<Buffer<T>> {
+        let (destroyable, kept) = retired
+            .drain(..)
+            .partition::<Vec<_>, _>(|(retired_frame, _)| {
+                retired_frame + MAX_FLIGHT_FRAMES_COUNT as u64 <= frame_count
+            });
+        *retired = kept;
+
+        destroyable.into_iter().map(|(_, buffer)| buffer).collect()
+    }
+
+    fn grow_vertices(
+        &mut self,
+        vertex_count: DeviceSize,
+        frame_count: u64,
+        upload_batcher: &mut UploadBatcher,
+    ) {
+        let capacity = Self::grown_capacity(self.vertex_allocator.capacity, vertex_count);
+        debug!("growing mesh pool to {} vertices", capacity);
+
+        let vertex_buffer = Self::create_vertex_buffer(capacity, self.device.clone());
+        let vertex_size = size_of::<Vertex>() as DeviceSize;
+        self.record_grow_copy(
+            (&self.vertex_buffer).into(),
+            (&vertex_buffer).into(),
+            self.vertex_allocator.capacity * vertex_size,
+            upload_batcher,
+        );
+
+        let previous_buffer = std::mem::replace(&mut self.vertex_buffer, vertex_buffer);
+        self.retired_vertex_buffers
+            .push((frame_count, previous_buffer));
+        self.vertex_allocator.grow(capacity);
+    }
+
+    fn grow_indices(
+        &mut self,
+        index_size: DeviceSize,
+        frame_count: u64,
+        upload_batcher: &mut UploadBatcher,
+    ) {
+        let capacity =
+            Self::grown_capacity(self.index_allocator.capacity, index_size + INDEX_ALIGNMENT);
+        debug!("growing mesh pool to {} index bytes", capacity);
+
+        let index_buffer = Self::create_index_buffer(capacity, self.device.clone());
+        self.record_grow_copy(
+            (&self.index_buffer).into(),
+            (&index_buffer).into(),
+            self.index_allocator.capacity,
+            upload_batcher,
+        );
+
+        let previous_buffer = std::mem::replace(&mut self.index_buffer, index_buffer);
+        self.retired_index_buffers
+            .push((frame_count, previous_buffer));
+        self.index_allocator.grow(capacity);
+    }
+
+    // Doubled until the range fits at the end, whatever is free there already
+    fn grown_capacity(capacity: DeviceSize, size: DeviceSize) -> DeviceSize {
+        let mut grown_capacity = capacity.max(1) * 2;
+        while grown_capacity < capacity + size {
+            grown_capacity *= 2;
+        }
+
+        grown_capacity
+    }
+
+    fn record_grow_copy(
+        &self,
+        src_buffer: vk::Buffer,
+        dst_buffer: vk::Buffer,
+        size: DeviceSize,
+        upload_batcher: &mut UploadBatcher,
+    ) {
+        upload_batcher.record(|command_buffer| {
+            // Copies into the previous buffer may have been recorded in the same batch
+            command_buffer.record_memory_barrier(
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_READ),
+            );
+            self.record_copy(&command_buffer, src_buffer, dst_buffer, 0, size);
+            Self::record_draw_barrier(&command_buffer);
+        });
+    }
+
+    fn record_copy(
+        &self,
+        command_buffer: &CommandBuffer,
+        src_buffer: vk::Buffer,
+        dst_buffer: vk::Buffer,
+        dst_offset: DeviceSize,
+        size: DeviceSize,
+    ) {
+        let buffer_copy = BufferCopy::builder()
+            .src_offset(0)
+            .dst_offset(dst_offset)
+            .size(size);
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_copy_buffer(
+                command_buffer.into(),
+                src_buffer,
+                dst_buffer,
+                &[buffer_copy],
+            );
+        }
+    }
+
+    fn record_draw_barrier(command_buffer: &CommandBuffer) {
+        command_buffer.record_memory_barrier(
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+            (
+                PipelineStageFlags::VERTEX_INPUT,
+                AccessFlags::VERTEX_ATTRIBUTE_READ | AccessFlags::INDEX_READ,
+            ),
+        );
+    }
+
+    fn index_width(index_type: IndexType) -> DeviceSize {
+        if index_type == IndexType::UINT16 {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn create_vertex_buffer(capacity: DeviceSize, device: Device) -> Buffer<Vertex> {
+        Buffer::new(
+            capacity * size_of::<Vertex>() as DeviceSize,
+            BufferUsageFlags::VERTEX_BUFFER
+                | BufferUsageFlags::TRANSFER_SRC
+                | BufferUsageFlags::TRANSFER_DST,
+            device,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+    }
+
+    fn create_index_buffer(capacity: DeviceSize, device: Device) -> Buffer<u8> {
+        Buffer::new(
+            capacity,
+            BufferUsageFlags::INDEX_BUFFER
+                | BufferUsageFlags::TRANSFER_SRC
+                | BufferUsageFlags::TRANSFER_DST,
+            device,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+    }
+
+    pub(crate) fn destroy(&mut self) {
+        self.vertex_buffer.destroy();
+        self.index_buffer.destroy();
+        self.retired_vertex_buffers
+            .drain(..)
+            .for_each(|(_, buffer)| buffer.destroy());
+        self.retired_index_buffers
+            .drain(..)
+            .for_each(|(_, buffer)| buffer.destroy());
+    }
+}
@@ -0,0 +1,582 @@
+use std::{cmp::Ordering, collections::BinaryHeap, mem, ops::Add};
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::{
+    bounding_volume::BoundingSphere,
+    mesh::{Lod, Mesh},
+    mesh_optimizer::MeshOptimizer,
+    vertex::Vertex,
+};
+
+// LODs that keep more than this fraction of the triangles of the previous one are dropped
+const MAX_KEPT_FRACTION: f32 = 0.9;
+
+/// How many LODs are made for a mesh and how far they are simplified.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct LodSettings {
+    /// LODs after the full mesh.
+    pub(crate) level_count: u32,
+    /// Fraction of the triangles of the previous LOD that each LOD keeps.
+    pub(crate) reduction: f32,
+    /// Largest error of a LOD, as a fraction of the radius of the mesh.
+    pub(crate) max_error: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            level_count: 3,
+            reduction: 0.5,
+            max_error: 0.1,
+        }
+    }
+}
+
+/// Sum of the squared distances to planes, weighted by the area of the triangles they come
+/// from.
+#[derive(Clone, Copy, Debug)]
+struct Quadric {
+    matrix: Matrix4<f64>,
+    weight: f64,
+}
+
+impl Quadric {
+    fn new(vertices: &[Vertex], triangle: &[u32; 3]) -> Self {
+        let normal = Mesh::face_normal(vertices, triangle).cast::<f64>();
+        let area = normal.norm() / 2.0;
+        if area == 0.0 {
+            return Self::default();
+        }
+
+        let normal = normal.normalize();
+        let point = vertices[triangle[0] as usize].pos.cast::<f64>();
+        let plane = Vector4::new(normal.x, normal.y, normal.z, -normal.dot(&point));
+
+        Self {
+            matrix: plane * plane.transpose() * area,
+            weight: area,
+        }
+    }
+
+    /// Mean squared distance from the position to the planes.
+    fn error(&self, position: &Vector3<f32>) -> f64 {
+        let point = position.cast::<f64>().push(1.0);
+
+        (point.transpose() * self.matrix * point)[0].max(0.0) / self.weight.max(f64::MIN_POSITIVE)
+    }
+}
+
+impl Default for Quadric {
+    fn default() -> Self {
+        Self {
+            matrix: Matrix4::zeros(),
+            weight: 0.0,
+        }
+    }
+}
+
+impl Add for Quadric {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            matrix: self.matrix + other.matrix,
+            weight: self.weight + other.weight,
+        }
+    }
+}
+
+/// Moving a vertex onto a neighbour, valid while neither has changed since.
+#[derive(Clone, Copy, Debug)]
+struct Collapse {
+    /// Squared distance the surface moves by.
+    error: f64,
+    from: usize,
+    to: usize,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed so that the heap pops the smallest error first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .error
+            .partial_cmp(&self.error)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Makes LODs by collapsing the edges that change the surface the least, measured with
+/// quadric error metrics as in "Surface Simplification Using Quadric Error Metrics" by
+/// Garland and Heckbert. Vertices only move onto their neighbours, so every LOD indexes the
+/// vertices of the full mesh.
+pub(crate) struct MeshSimplifier;
+
+impl MeshSimplifier {
+    /// Appends the indices of every LOD the settings ask for after the ones of the full mesh.
+    /// The chain ends early once a LOD would be over the error or barely simpler.
+    pub(crate) fn generate_lods(mesh: &mut Mesh, settings: &LodSettings) {
+        let full_index_count = mesh.indices.len();
+        mesh.lods = vec![Lod {
+            index_offset: 0,
+            index_count: full_index_count as u32,
+            error: 0.0,
+        }];
+
+        let max_error = settings.max_error * BoundingSphere::new(&mesh.vertices).radius;
+        let mut previous_triangle_count = full_index_count / 3;
+        let mut previous_error = 0.0;
+        for level in 1..=settings.level_count as i32 {
+            let target_triangle_count =
+                (full_index_count as f32 / 3.0 * settings.reduction.powi(level)) as usize;
+            let (indices, error) = Self::simplify(
+                &mesh.vertices,
+                &mesh.indices[..full_index_count],
+                target_triangle_count,
+                max_error,
+            );
+
+            let triangle_count = indices.len() / 3;
+            if triangle_count == 0
+                || triangle_count as f32 > previous_triangle_count as f32 * MAX_KEPT_FRACTION
+            {
+                break;
+            }
+
+            let indices = MeshOptimizer::optimize_triangles(&mesh.vertices, &indices);
+            // Coarser LODs are chosen further away, which needs their errors to grow
+            let error = f32::max(error, previous_error);
+            mesh.lods.push(Lod {
+                index_offset: mesh.indices.len() as u32,
+                index_count: indices.len() as u32,
+                error,
+            });
+            mesh.indices.extend(indices);
+
+            previous_triangle_count = triangle_count;
+            previous_error = error;
+        }
+    }
+
+    /// Collapses edges until the triangle count is reached or the next collapse would move
+    /// the surface by more than the error. Returns the indices and the largest distance the
+    /// surface moved by.
+    fn simplify(
+        vertices: &[Vertex],
+        indices: &[u32],
+        target_triangle_count: usize,
+        max_error: f32,
+    ) -> (Vec<u32>, f32) {
+        let mut surface = Surface::new(vertices, indices);
+        let mut collapses = BinaryHeap::new();
+        surface
+            .triangles
+            .iter()
+            .flat_map(|triangle| {
+                (0..3).map(move |corner| (triangle[corner], triangle[(corner + 1) % 3]))
+            })
+            .map(|(a, b)| {
+                let (a, b) = (
+                    surface.vertex_positions[a as usize],
+                    surface.vertex_positions[b as usize],
+                );
+                (a.min(b), a.max(b))
+            })
+            .unique()
+            .for_each(|(a, b)| collapses.extend(surface.cheapest_collapse(a, b)));
+
+        let max_squared_error = (max_error as f64).powi(2);
+        let mut squared_error = 0.0;
+        while surface.live_triangle_count > target_triangle_count {
+            let collapse = match collapses.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (from, to) = (collapse.from, collapse.to);
+            if collapse.versions != (surface.versions[from], surface.versions[to]) {
+                continue;
+            }
+            if collapse.error > max_squared_error {
+                break;
+            }
+
+            let partners = match surface.partners(from, to) {
+                Some(partners) => partners,
+                None => continue,
+            };
+            if surface.flips_triangles(from, to) {
+                continue;
+            }
+
+            surface.collapse(from, to, &partners);
+            squared_error = f64::max(squared_error, collapse.error);
+
+            for neighbour in surface.neighbours(to) {
+                collapses.extend(surface.cheapest_collapse(neighbour, to));
+            }
+        }
+
+        (surface.indices(), squared_error.sqrt() as f32)
+    }
+}
+
+/// Triangles being simplified. Collapses move positions rather than vertices, so that the
+/// vertices a seam splits move together.
+struct Surface<'a> {
+    vertices: &'a [Vertex],
+    triangles: Vec<[u32; 3]>,
+    live: Vec<bool>,
+    live_triangle_count: usize,
+    vertex_positions: Vec<usize>,
+    position_vertices: Vec<Vec<u32>>,
+    /// Triangles around each position, the dead ones are dropped when the position changes.
+    position_triangles: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    /// Positions on borders or where more than two triangles share an edge, which don't move
+    /// so that no holes open.
+    locked: Vec<bool>,
+    /// Number of times each position has changed.
+    versions: Vec<u32>,
+}
+
+impl<'a> Surface<'a> {
+    fn new(vertices: &'a [Vertex], indices: &[u32]) -> Self {
+        let mut positions_by_bits = HashMap::new();
+        let mut position_vertices = Vec::new();
+        let vertex_positions = vertices
+            .iter()
+            .enumerate()
+            .map(|(vertex, Vertex { pos, .. })| {
+                let position = *positions_by_bits
+                    .entry(pos.map(f32::to_bits))
+                    .or_insert_with(|| {
+                        position_vertices.push(Vec::new());
+                        position_vertices.len() - 1
+                    });
+                position_vertices[position].push(vertex as u32);
+
+                position
+            })
+            .collect_vec();
+        let position_count = position_vertices.len();
+
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect_vec();
+        let mut position_triangles = vec![Vec::new(); position_count];
+        let mut quadrics = vec![Quadric::default(); position_count];
+        let mut edge_triangle_counts = HashMap::new();
+        triangles.iter().enumerate().for_each(|(i, triangle)| {
+            let quadric = Quadric::new(vertices, triangle);
+            (0..3).for_each(|corner| {
+                let position = vertex_positions[triangle[corner] as usize];
+                let next_position = vertex_positions[triangle[(corner + 1) % 3] as usize];
+
+                position_triangles[position].push(i);
+                quadrics[position] = quadrics[position] + quadric;
+                *edge_triangle_counts
+                    .entry((position.min(next_position), position.max(next_position)))
+                    .or_insert(0) += 1;
+            });
+        });
+
+        let mut locked = vec![false; position_count];
+        edge_triangle_counts
+            .iter()
+            .filter(|(_, count)| **count != 2)
+            .for_each(|((a, b), _)| {
+                locked[*a] = true;
+                locked[*b] = true;
+            });
+
+        Self {
+            vertices,
+            live: vec![true; triangles.len()],
+            live_triangle_count: triangles.len(),
+            triangles,
+            vertex_positions,
+            position_vertices,
+            position_triangles,
+            quadrics,
+            locked,
+            versions: vec![0; position_count],
+        }
+    }
+
+    fn position(&self, position: usize) -> Vector3<f32> {
+        self.vertices[self.position_vertices[position][0] as usize].pos
+    }
+
+    /// Collapse of the edge in whichever direction moves the surface less.
+    fn cheapest_collapse(&self, a: usize, b: usize) -> Option<Collapse> {
+        let quadric = self.quadrics[a] + self.quadrics[b];
+        let collapse = |from: usize, to: usize| Collapse {
+            error: quadric.error(&self.position(to)),
+            from,
+            to,
+            versions: (self.versions[from], self.versions[to]),
+        };
+
+        match (self.locked[a], self.locked[b]) {
+            (true, true) => None,
+            (false, true) => Some(collapse(a, b)),
+            (true, false) => Some(collapse(b, a)),
+            // The one with the smaller error, as collapses are ordered for the heap
+            (false, false) => Some(collapse(a, b).max(collapse(b, a))),
+        }
+    }
+
+    /// Vertex at the target position that each vertex at the moved one merges into, the one
+    /// it shares an edge with. `None` if one of them has no such vertex, as its attributes
+    /// would be lost.
+    fn partners(&self, from: usize, to: usize) -> Option<Vec<(u32, u32)>> {
+        self.position_vertices[from]
+            .iter()
+            .map(|vertex| {
+                self.position_triangles[from]
+                    .iter()
+                    .filter(|triangle| self.live[**triangle])
+                    .map(|triangle| &self.triangles[*triangle])
+                    .filter(|triangle| triangle.contains(vertex))
+                    .flat_map(|triangle| triangle.iter())
+                    .find(|other| self.vertex_positions[**other as usize] == to)
+                    .map(|partner| (*vertex, *partner))
+            })
+            .collect()
+    }
+
+    /// Whether moving the position would turn one of the triangles around it over.
+    fn flips_triangles(&self, from: usize, to: usize) -> bool {
+        let moved_position = self.position(to);
+
+        self.position_triangles[from]
+            .iter()
+            .filter(|triangle| self.live[**triangle])
+            .map(|triangle| &self.triangles[*triangle])
+            .filter(|triangle| {
+                !triangle
+                    .iter()
+                    .any(|vertex| self.vertex_positions[*vertex as usize] == to)
+            })
+            .any(|triangle| {
+                let position = |vertex: u32| {
+                    if self.vertex_positions[vertex as usize] == from {
+                        moved_position
+                    } else {
+                        self.vertices[vertex as usize].pos
+                    }
+                };
+                let moved_normal = (position(triangle[1]) - position(triangle[0]))
+                    .cross(&(position(triangle[2]) - position(triangle[0])));
+
+                moved_normal.dot(&Mesh::face_normal(self.vertices, triangle)) <= 0.0
+            })
+    }
+
+    fn collapse(&mut self, from: usize, to: usize, partners: &[(u32, u32)]) {
+        for triangle in mem::take(&mut self.position_triangles[from]) {
+            if !self.live[triangle] {
+                continue;
+            }
+
+            let corners = &mut self.triangles[triangle];
+            for corner in corners.iter_mut() {
+                if let Some((_, partner)) = partners.iter().find(|(vertex, _)| vertex == corner) {
+                    *corner = *partner;
+                }
+            }
+
+            let positions = corners.map(|vertex| self.vertex_positions[vertex as usize]);
+            if positions[0] == positions[1]
+                || positions[1] == positions[2]
+                || positions[2] == positions[0]
+            {
+                self.live[triangle] = false;
+                self.live_triangle_count -= 1;
+            } else {
+                self.position_triangles[to].push(triangle);
+            }
+        }
+        let live = &self.live;
+        self.position_triangles[to].retain(|triangle| live[*triangle]);
+
+        self.quadrics[to] = self.quadrics[to] + self.quadrics[from];
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+    }
+
+    fn neighbours(&self, position: usize) -> Vec<usize> {
+        self.position_triangles[position]
+            .iter()
+            .flat_map(|triangle| self.triangles[*triangle])
+            .map(|vertex| self.vertex_positions[vertex as usize])
+            .filter(|neighbour| *neighbour != position)
+            .unique()
+            .collect_vec()
+    }
+
+    fn indices(&self) -> Vec<u32> {
+        self.triangles
+            .iter()
+            .zip(&self.live)
+            .filter(|(_, live)| **live)
+            .flat_map(|(triangle, _)| *triangle)
+            .collect_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use nalgebra::{Vector2, Vector3};
+
+    use super::{LodSettings, MeshSimplifier};
+    use crate::{
+        bounding_volume::BoundingSphere,
+        mesh::{Mesh, Submesh},
+        vertex::Vertex,
+    };
+
+    /// Welded grid of `size` by `size` quads, with heights from `height`.
+    fn grid(size: u32, height: impl Fn(f32, f32) -> f32) -> Mesh {
+        let vertices = (0..=size)
+            .cartesian_product(0..=size)
+            .map(|(x, y)| {
+                let (x, y) = (x as f32, y as f32);
+                Vertex::new(
+                    Vector3::new(x, y, height(x, y)),
+                    Vector3::repeat(1.0),
+                    Vector2::new(x, y) / size as f32,
+                    Vector3::z(),
+                )
+            })
+            .collect_vec();
+        let index = |x: u32, y: u32| x * (size + 1) + y;
+        let indices = (0..size)
+            .cartesian_product(0..size)
+            .flat_map(|(x, y)| {
+                [
+                    index(x, y),
+                    index(x + 1, y),
+                    index(x + 1, y + 1),
+                    index(x, y),
+                    index(x + 1, y + 1),
+                    index(x, y + 1),
+                ]
+            })
+            .collect_vec();
+
+        Mesh {
+            submeshes: vec![Submesh {
+                index_offset: 0,
+                index_count: indices.len() as u32,
+            }],
+            vertices,
+            indices,
+            lods: Vec::new(),
+        }
+    }
+
+    fn bumps(x: f32, y: f32) -> f32 {
+        (x * 0.7).sin() * (y * 0.9).cos() * 2.0
+    }
+
+    #[test]
+    fn lods_get_simpler_with_growing_errors() {
+        let mut mesh = grid(16, bumps);
+        let settings = LodSettings {
+            level_count: 4,
+            reduction: 0.5,
+            max_error: 1.0,
+        };
+
+        MeshSimplifier::generate_lods(&mut mesh, &settings);
+
+        assert!(mesh.lods.len() > 1);
+        assert_eq!(mesh.lods[0].error, 0.0);
+        let max_error = settings.max_error * BoundingSphere::new(&mesh.vertices).radius;
+        for (coarser, finer) in mesh.lods.iter().skip(1).zip(&mesh.lods) {
+            assert!(coarser.index_count < finer.index_count);
+            assert!(coarser.error >= finer.error);
+            assert!(coarser.error <= max_error);
+            assert_eq!(coarser.index_offset, finer.index_offset + finer.index_count);
+        }
+        let last_lod = mesh.lods.last().unwrap();
+        assert_eq!(
+            mesh.indices.len(),
+            (last_lod.index_offset + last_lod.index_count) as usize
+        );
+        assert!(mesh
+            .indices
+            .iter()
+            .all(|index| (*index as usize) < mesh.vertices.len()));
+    }
+
+    #[test]
+    fn flat_mesh_simplifies_without_error() {
+        let mut mesh = grid(8, |_, _| 0.0);
+
+        MeshSimplifier::generate_lods(&mut mesh, &LodSettings::default());
+
+        assert!(mesh.lods.len() > 1);
+        assert!(mesh.lods.iter().all(|lod| lod.error < 1e-4));
+    }
+
+    #[test]
+    fn no_error_allowed_keeps_only_full_mesh() {
+        let mut mesh = grid(8, bumps);
+        let settings = LodSettings {
+            max_error: 0.0,
+            ..LodSettings::default()
+        };
+
+        MeshSimplifier::generate_lods(&mut mesh, &settings);
+
+        assert_eq!(mesh.lods.len(), 1);
+    }
+
+    #[test]
+    fn no_levels_keeps_only_full_mesh() {
+        let mut mesh = grid(8, bumps);
+        let index_count = mesh.indices.len();
+        let settings = LodSettings {
+            level_count: 0,
+            ..LodSettings::default()
+        };
+
+        MeshSimplifier::generate_lods(&mut mesh, &settings);
+
+        assert_eq!(mesh.lods.len(), 1);
+        assert_eq!(mesh.lods[0].index_count as usize, index_count);
+        assert_eq!(mesh.indices.len(), index_count);
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let mut mesh = Mesh::default();
+
+        MeshSimplifier::generate_lods(&mut mesh, &LodSettings::default());
+
+        assert_eq!(mesh.lods.len(), 1);
+        assert_eq!(mesh.lods[0].index_count, 0);
+        assert!(mesh.indices.is_empty());
+    }
+}
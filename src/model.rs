@@ -1,7 +1,6 @@
 use hashbrown::HashMap;
 use itertools::Itertools;
 use nalgebra::{Vector2, Vector3};
-use tobj::{load_obj, GPU_LOAD_OPTIONS};
 
 use crate::{texture::Texture, vertex::Vertex};
 
@@ -13,43 +12,38 @@ pub(crate) struct Model {
 }
 
 impl Model {
-    pub(crate) fn new(model_path: &str, texture_path: Option<&str>) -> Self {
-        let texture = Texture::new(texture_path.unwrap_or(""));
-
-        let (models, _) = load_obj(model_path, &GPU_LOAD_OPTIONS).unwrap();
-
-        let vertices = models
-            .iter()
-            .flat_map(|model| {
-                let mesh = &model.mesh;
-                let positions = &mesh.positions;
-                let texture_uvs = &mesh.texcoords;
-
-                (0..(positions.len()) / 3)
-                    .map(|i| {
+    /// Unit cube drawn in place of models that are still loading.
+    pub(crate) fn placeholder() -> Self {
+        let axes = [Vector3::x(), Vector3::y(), Vector3::z()];
+
+        let vertices = (0..6)
+            .flat_map(|face| {
+                let normal = axes[face / 2] * if face % 2 == 0 { 1.0 } else { -1.0 };
+                let u = axes[(face / 2 + 1) % 3];
+                let v = normal.cross(&u);
+
+                [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                    .iter()
+                    .map(|(x, y)| {
                         Vertex::new(
-                            Vector3::new(
-                                positions[3 * i],
-                                positions[3 * i + 1],
-                                positions[3 * i + 2],
-                            ),
+                            (normal + u * *x + v * *y) * 0.5,
                             Vector3::new(1.0, 1.0, 1.0),
-                            Vector2::new(texture_uvs[2 * i], 1.0 - texture_uvs[2 * i + 1]),
+                            Vector2::new((x + 1.0) / 2.0, (y + 1.0) / 2.0),
+                            normal,
                         )
                     })
                     .collect_vec()
             })
             .collect_vec();
 
-        let indices = models
-            .iter()
-            .flat_map(|model| model.mesh.indices.clone())
+        let indices = (0..6u32)
+            .flat_map(|face| [0, 1, 2, 2, 3, 0].map(|index| face * 4 + index))
             .collect_vec();
 
         Self {
             vertices,
             indices,
-            texture,
+            texture: Texture::placeholder(),
         }
     }
 }
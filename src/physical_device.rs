@@ -39,7 +39,8 @@ impl PhysicalDevice {
                         == PhysicalDeviceType::INTEGRATED_GPU
                         || physical_device_properties.device_type
                             == PhysicalDeviceType::DISCRETE_GPU)
-                        && physical_device_features.sampler_anisotropy == TRUE;
+                        && physical_device_features.sampler_anisotropy == TRUE
+                        && physical_device_features.fill_mode_non_solid == TRUE;
 
                     let extensions = extensions.iter().copied().collect::<HashSet<_>>();
 
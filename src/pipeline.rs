@@ -1,56 +1,390 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use log::{error, info};
 use vulkanalia::{
     vk::{
         self, BlendFactor, BlendOp, ColorComponentFlags, CompareOp, CullModeFlags,
-        DescriptorSetLayout, DeviceV1_0, DynamicState, FrontFace, GraphicsPipelineCreateInfo,
-        Handle, HasBuilder, LogicOp, PipelineCache, PipelineColorBlendAttachmentState,
-        PipelineColorBlendStateCreateInfo, PipelineDepthStencilStateCreateInfo,
-        PipelineDynamicStateCreateInfo, PipelineInputAssemblyStateCreateInfo, PipelineLayout,
-        PipelineLayoutCreateInfo, PipelineMultisampleStateCreateInfo,
-        PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo,
-        PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PolygonMode,
-        PrimitiveTopology, PushConstantRange, SampleCountFlags, ShaderStageFlags,
+        DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DeviceV1_0,
+        DynamicState, FrontFace, GraphicsPipelineCreateInfo, Handle, HasBuilder, LogicOp,
+        PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
+        PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateInfo,
+        PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineLayoutCreateInfo,
+        PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo,
+        PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo,
+        PipelineViewportStateCreateInfo, PolygonMode, PrimitiveTopology, PushConstantRange,
+        SampleCountFlags, ShaderStageFlags, VertexInputAttributeDescription,
+        VertexInputBindingDescription,
     },
     Device as vkDevice,
 };
 
-use crate::{device::Device, render_pass::RenderPass, shader::Shader, vertex::Vertex};
+use crate::{
+    device::Device,
+    instance_data::InstanceData,
+    material::Material,
+    render_pass::RenderPass,
+    shader::{CompiledShader, Shader, ShaderReflection},
+    vertex::Vertex,
+};
 
 #[derive(Clone, Debug)]
 pub(crate) struct Pipeline {
     pub(crate) pipeline: vk::Pipeline,
     pub(crate) layout: PipelineLayout,
+    /// Layout of set 0, generated from the bindings declared by the shaders.
+    pub(crate) descriptor_set_layout: DescriptorSetLayout,
+    pub(crate) reflection: ShaderReflection,
+    render_pass: RenderPass,
+    state: PipelineState,
+    dependencies: Vec<PathBuf>,
     device: Device,
 }
 
+#[derive(Clone, Debug)]
+struct PipelineState {
+    vertex_shader: String,
+    fragment_shader: String,
+    defines: Vec<(String, String)>,
+    vertex_input: bool,
+    /// [`InstanceData`] is read from a second binding, once per instance.
+    instance_input: bool,
+    /// Bytes of push constants recorded for every draw.
+    push_constant_size: usize,
+    /// Bindings and push constants of another pipeline the shaders have to fit in, so that
+    /// the same descriptor sets can be bound to both.
+    interface: Option<ShaderReflection>,
+    topology: PrimitiveTopology,
+    polygon_mode: PolygonMode,
+    cull_mode: CullModeFlags,
+    depth_test_enable: bool,
+    depth_write_enable: bool,
+    depth_compare_op: CompareOp,
+    color_blend_factors: Option<(BlendFactor, BlendFactor)>,
+    msaa_sample_count: SampleCountFlags,
+}
+
 impl Pipeline {
     pub(crate) fn new(
         device: Device,
-        descriptor_set_layout: DescriptorSetLayout,
         render_pass: RenderPass,
         msaa_sample_count: SampleCountFlags,
     ) -> Self {
-        let layout = Self::create_layout(device.clone(), descriptor_set_layout);
-        let pipeline =
-            Self::create_pipeline(device.clone(), layout, render_pass, msaa_sample_count);
+        Self::new_material(
+            device,
+            render_pass,
+            msaa_sample_count,
+            &Material::default(),
+            None,
+        )
+        .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Pipeline drawing entities with `material`, its shaders have to fit in `interface`
+    /// when it is given. The error lists what doesn't compile or doesn't match.
+    pub(crate) fn new_material(
+        device: Device,
+        render_pass: RenderPass,
+        msaa_sample_count: SampleCountFlags,
+        material: &Material,
+        interface: Option<&ShaderReflection>,
+    ) -> Result<Self, String> {
+        let state = PipelineState {
+            vertex_shader: material.vertex_shader.clone(),
+            fragment_shader: material.fragment_shader.clone(),
+            defines: Vec::new(),
+            vertex_input: true,
+            instance_input: true,
+            push_constant_size: 0,
+            interface: interface.cloned(),
+            topology: material.topology.into(),
+            polygon_mode: material.polygon_mode.into(),
+            cull_mode: material.cull_mode.into(),
+            depth_test_enable: material.depth_test,
+            // Sorted transparent entities must not hide the ones drawn after them
+            depth_write_enable: material.depth_write && !material.blend_mode.is_transparent(),
+            depth_compare_op: CompareOp::LESS,
+            color_blend_factors: material.blend_mode.color_blend_factors(),
+            msaa_sample_count,
+        };
+
+        Self::with_state(device, render_pass, state)
+    }
+
+    /// Draws the inside of a unit cube at the far plane, so it has to be recorded after
+    /// opaque geometry and never writes depth.
+    pub(crate) fn new_skybox(
+        device: Device,
+        render_pass: RenderPass,
+        msaa_sample_count: SampleCountFlags,
+    ) -> Self {
+        let state = PipelineState {
+            vertex_shader: "skybox.vert".to_string(),
+            fragment_shader: "skybox.frag".to_string(),
+            defines: Vec::new(),
+            vertex_input: true,
+            instance_input: false,
+            push_constant_size: 0,
+            interface: None,
+            topology: PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: PolygonMode::FILL,
+            cull_mode: CullModeFlags::NONE,
+            depth_test_enable: true,
+            depth_write_enable: false,
+            depth_compare_op: CompareOp::LESS_OR_EQUAL,
+            color_blend_factors: None,
+            msaa_sample_count,
+        };
+
+        Self::with_state(device, render_pass, state).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Draws a single triangle covering the whole framebuffer, the vertices are generated
+    /// from `gl_VertexIndex` so nothing has to be bound besides the descriptor set.
+    pub(crate) fn new_fullscreen(
+        device: Device,
+        render_pass: RenderPass,
+        fragment_shader: &str,
+        push_constant_size: usize,
+    ) -> Self {
+        let state = PipelineState {
+            vertex_shader: "fullscreen.vert".to_string(),
+            fragment_shader: fragment_shader.to_string(),
+            defines: Vec::new(),
+            vertex_input: false,
+            instance_input: false,
+            push_constant_size,
+            interface: None,
+            topology: PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: PolygonMode::FILL,
+            cull_mode: CullModeFlags::NONE,
+            depth_test_enable: false,
+            depth_write_enable: false,
+            depth_compare_op: CompareOp::ALWAYS,
+            color_blend_factors: None,
+            msaa_sample_count: SampleCountFlags::_1,
+        };
+
+        Self::with_state(device, render_pass, state).unwrap_or_else(|error| panic!("{}", error))
+    }
 
-        Self {
+    fn with_state(
+        device: Device,
+        render_pass: RenderPass,
+        state: PipelineState,
+    ) -> Result<Self, String> {
+        let (vertex_shader, fragment_shader, reflection) = Self::compile_shaders(&state)?;
+
+        let descriptor_set_layout = Self::create_descriptor_set_layout(device.clone(), &reflection);
+        let layout = Self::create_layout(device.clone(), descriptor_set_layout, &reflection);
+        let pipeline = Self::create_pipeline(
+            device.clone(),
+            layout,
+            render_pass.clone(),
+            &state,
+            &vertex_shader.code,
+            &fragment_shader.code,
+        );
+
+        Ok(Self {
             pipeline,
             layout,
+            descriptor_set_layout,
+            reflection,
+            render_pass,
+            state,
+            dependencies: Self::dependencies(vertex_shader, fragment_shader),
             device,
+        })
+    }
+
+    /// Rebuilds the pipeline when one of the files its shaders were compiled from has
+    /// changed, the current pipeline is kept when they fail to compile or when their
+    /// descriptor bindings or push constants changed. The pipeline must not be in use.
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        if !self
+            .dependencies
+            .iter()
+            .any(|path| changed_paths.contains(path))
+        {
+            return;
+        }
+
+        let compiled_shaders =
+            Self::compile_shaders(&self.state).and_then(|(vertex, fragment, reflection)| {
+                if reflection.layout_matches(&self.reflection) {
+                    Ok((vertex, fragment, reflection))
+                } else {
+                    Err(
+                        "Descriptor bindings or push constants changed, restart to apply them"
+                            .to_string(),
+                    )
+                }
+            });
+
+        match compiled_shaders {
+            Ok((vertex_shader, fragment_shader, reflection)) => {
+                let pipeline = Self::create_pipeline(
+                    self.device.clone(),
+                    self.layout,
+                    self.render_pass.clone(),
+                    &self.state,
+                    &vertex_shader.code,
+                    &fragment_shader.code,
+                );
+
+                unsafe {
+                    vkDevice::from(self.device.clone()).destroy_pipeline(self.pipeline, None);
+                }
+
+                self.pipeline = pipeline;
+                self.reflection = reflection;
+                self.dependencies = Self::dependencies(vertex_shader, fragment_shader);
+
+                info!(
+                    "Reloaded {} and {}",
+                    self.state.vertex_shader, self.state.fragment_shader
+                );
+            }
+            Err(error) => error!("Keeping the previous pipeline: {}", error),
+        }
+    }
+
+    /// Compiles both stages and checks their interface against what the renderer binds, the
+    /// reflection has the bindings and push constants of the interface when there is one.
+    fn compile_shaders(
+        state: &PipelineState,
+    ) -> Result<(CompiledShader, CompiledShader, ShaderReflection), String> {
+        let vertex_shader = Shader::compile(&state.vertex_shader, &state.defines)?;
+        let fragment_shader = Shader::compile(&state.fragment_shader, &state.defines)?;
+
+        let reflection = vertex_shader
+            .reflection
+            .merge(&fragment_shader.reflection)
+            .map_err(|error| {
+                format!(
+                    "{} and {}: {}",
+                    state.vertex_shader, state.fragment_shader, error
+                )
+            })?;
+
+        let mismatches = Self::interface_mismatches(state, &reflection);
+        if !mismatches.is_empty() {
+            return Err(format!(
+                "{} and {} don't match the renderer:\n{}",
+                state.vertex_shader,
+                state.fragment_shader,
+                mismatches.join("\n")
+            ));
+        }
+
+        let reflection = match &state.interface {
+            Some(interface) => ShaderReflection {
+                vertex_inputs: reflection.vertex_inputs,
+                ..interface.clone()
+            },
+            None => reflection,
+        };
+
+        Ok((vertex_shader, fragment_shader, reflection))
+    }
+
+    fn interface_mismatches(state: &PipelineState, reflection: &ShaderReflection) -> Vec<String> {
+        let (_, attribute_descriptions) = Self::vertex_input_descriptions(state);
+
+        let binding_mismatches = reflection
+            .descriptor_bindings
+            .iter()
+            .filter(|binding| binding.set != 0)
+            .map(|binding| {
+                format!(
+                    "set {} binding {} is not in set 0",
+                    binding.set, binding.binding
+                )
+            });
+        let vertex_input_mismatches =
+            reflection
+                .vertex_inputs
+                .iter()
+                .filter_map(move |(location, format)| {
+                    match attribute_descriptions
+                        .iter()
+                        .find(|attribute| attribute.location == *location)
+                    {
+                        Some(attribute) if attribute.format == *format => None,
+                        Some(attribute) => Some(format!(
+                            "vertex input {} is {:?} but the renderer provides {:?}",
+                            location, format, attribute.format
+                        )),
+                        None => Some(format!(
+                            "vertex input {} is not provided by the renderer",
+                            location
+                        )),
+                    }
+                });
+        let push_constant_mismatch = (reflection.push_constant_size > state.push_constant_size)
+            .then(|| {
+                format!(
+                    "push constants take {} bytes but the renderer pushes {}",
+                    reflection.push_constant_size, state.push_constant_size
+                )
+            });
+
+        let interface_mismatches = state
+            .interface
+            .iter()
+            .flat_map(|interface| reflection.layout_mismatches(interface));
+
+        binding_mismatches
+            .chain(vertex_input_mismatches)
+            .chain(push_constant_mismatch)
+            .chain(interface_mismatches)
+            .collect()
+    }
+
+    fn vertex_input_descriptions(
+        state: &PipelineState,
+    ) -> (
+        Vec<VertexInputBindingDescription>,
+        Vec<VertexInputAttributeDescription>,
+    ) {
+        let mut binding_descriptions = Vec::new();
+        let mut attribute_descriptions = Vec::new();
+
+        if state.vertex_input {
+            binding_descriptions.push(Vertex::binding_description());
+            attribute_descriptions.extend(Vertex::attribute_descriptions());
         }
+        if state.instance_input {
+            binding_descriptions.push(InstanceData::binding_description());
+            attribute_descriptions.extend(InstanceData::attribute_descriptions());
+        }
+
+        (binding_descriptions, attribute_descriptions)
+    }
+
+    fn dependencies(
+        vertex_shader: CompiledShader,
+        fragment_shader: CompiledShader,
+    ) -> Vec<PathBuf> {
+        vertex_shader
+            .dependencies
+            .into_iter()
+            .chain(fragment_shader.dependencies)
+            .collect()
     }
 
     fn create_pipeline(
         device: Device,
         pipeline_layout: PipelineLayout,
         render_pass: RenderPass,
-        msaa_sample_count: SampleCountFlags,
+        state: &PipelineState,
+        vertex_shader_code: &[u32],
+        fragment_shader_code: &[u32],
     ) -> vk::Pipeline {
-        let vertex_shader_bytes = include_bytes!("../shaders/build/main.vert.spv");
-        let fragment_shader_bytes = include_bytes!("../shaders/build/main.frag.spv");
-
-        let vertex_shader = Shader::new(device.clone(), vertex_shader_bytes);
-        let fragment_shader = Shader::new(device.clone(), fragment_shader_bytes);
+        let vertex_shader = Shader::new(device.clone(), vertex_shader_code);
+        let fragment_shader = Shader::new(device.clone(), fragment_shader_code);
 
         let vertex_shader_stage_create_info = PipelineShaderStageCreateInfo::builder()
             .stage(ShaderStageFlags::VERTEX)
@@ -61,14 +395,13 @@ impl Pipeline {
             .module(fragment_shader.module)
             .name(b"main\0");
 
-        let binding_descriptions = &[Vertex::binding_description()];
-        let attribute_descriptions = Vertex::attribute_descriptions();
+        let (binding_descriptions, attribute_descriptions) = Self::vertex_input_descriptions(state);
         let vertex_input_create_info = PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(binding_descriptions)
+            .vertex_binding_descriptions(&binding_descriptions)
             .vertex_attribute_descriptions(&attribute_descriptions);
 
         let input_assembly_create_info = PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(PrimitiveTopology::TRIANGLE_LIST)
+            .topology(state.topology)
             .primitive_restart_enable(false);
 
         let viewport_create_info = PipelineViewportStateCreateInfo::builder()
@@ -78,21 +411,21 @@ impl Pipeline {
         let rasterization_create_info = PipelineRasterizationStateCreateInfo::builder()
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
-            .polygon_mode(PolygonMode::FILL)
+            .polygon_mode(state.polygon_mode)
             .line_width(1.0)
-            .cull_mode(CullModeFlags::BACK)
+            .cull_mode(state.cull_mode)
             .front_face(FrontFace::COUNTER_CLOCKWISE)
             .depth_bias_enable(false);
 
         let multisample_create_info = PipelineMultisampleStateCreateInfo::builder()
-            .sample_shading_enable(msaa_sample_count != SampleCountFlags::_1)
+            .sample_shading_enable(state.msaa_sample_count != SampleCountFlags::_1)
             .min_sample_shading(0.2)
-            .rasterization_samples(msaa_sample_count);
+            .rasterization_samples(state.msaa_sample_count);
 
         let depth_stencil_create_info = PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(CompareOp::LESS)
+            .depth_test_enable(state.depth_test_enable)
+            .depth_write_enable(state.depth_write_enable)
+            .depth_compare_op(state.depth_compare_op)
             .depth_bounds_test_enable(false)
             .min_depth_bounds(0.0)
             .max_depth_bounds(1.0)
@@ -100,16 +433,18 @@ impl Pipeline {
         // .front(StencilOpState::builder())
         // .back(StencilOpState::builder())
 
+        let (src_color_blend_factor, dst_color_blend_factor) = state
+            .color_blend_factors
+            .unwrap_or((BlendFactor::ONE, BlendFactor::ZERO));
         let color_blend_attachment_create_info = PipelineColorBlendAttachmentState::builder()
             .color_write_mask(ColorComponentFlags::all())
-            .blend_enable(true)
-            .src_color_blend_factor(BlendFactor::SRC_ALPHA)
-            .dst_color_blend_factor(BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .blend_enable(state.color_blend_factors.is_some())
+            .src_color_blend_factor(src_color_blend_factor)
+            .dst_color_blend_factor(dst_color_blend_factor)
             .color_blend_op(BlendOp::ADD)
             .src_alpha_blend_factor(BlendFactor::ONE)
             .dst_alpha_blend_factor(BlendFactor::ZERO)
             .alpha_blend_op(BlendOp::ADD);
-
         let color_blend_attachment_create_infos = &[color_blend_attachment_create_info];
         let color_blend_create_info = PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
@@ -144,9 +479,9 @@ impl Pipeline {
             .base_pipeline_index(-1);
 
         let graphics_pipeline = unsafe {
-            vkDevice::from(device)
+            vkDevice::from(device.clone())
                 .create_graphics_pipelines(
-                    PipelineCache::null(),
+                    device.pipeline_cache,
                     &[graphics_pipeline_create_info],
                     None,
                 )
@@ -160,17 +495,52 @@ impl Pipeline {
         graphics_pipeline
     }
 
-    fn create_layout(device: Device, descriptor_set_layout: DescriptorSetLayout) -> PipelineLayout {
-        let vert_push_constant_range = PushConstantRange::builder()
-            .stage_flags(ShaderStageFlags::VERTEX)
-            .offset(0)
-            .size(64);
+    /// Layout of set 0 holding every binding of `reflection`.
+    pub(crate) fn create_descriptor_set_layout(
+        device: Device,
+        reflection: &ShaderReflection,
+    ) -> DescriptorSetLayout {
+        let descriptor_set_layout_bindings = reflection
+            .descriptor_bindings
+            .iter()
+            .map(|binding| {
+                DescriptorSetLayoutBinding::builder()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stage_flags)
+                    .build()
+            })
+            .collect_vec();
+        let descriptor_set_layout_create_info = DescriptorSetLayoutCreateInfo::builder()
+            .bindings(descriptor_set_layout_bindings.as_slice());
+
+        unsafe {
+            vkDevice::from(device)
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .unwrap()
+        }
+    }
 
-        let push_constant_ranges = &[vert_push_constant_range];
+    pub(crate) fn create_layout(
+        device: Device,
+        descriptor_set_layout: DescriptorSetLayout,
+        reflection: &ShaderReflection,
+    ) -> PipelineLayout {
+        let push_constant_ranges = (reflection.push_constant_size > 0)
+            .then(|| {
+                PushConstantRange::builder()
+                    .stage_flags(reflection.push_constant_stage_flags)
+                    .offset(0)
+                    .size(reflection.push_constant_size as u32)
+                    .build()
+            })
+            .into_iter()
+            .collect_vec();
         let descriptor_set_layouts = &[descriptor_set_layout];
         let layout_create_info = PipelineLayoutCreateInfo::builder()
             .set_layouts(descriptor_set_layouts)
-            .push_constant_ranges(push_constant_ranges);
+            .push_constant_ranges(push_constant_ranges.as_slice());
 
         unsafe {
             vkDevice::from(device)
@@ -183,6 +553,8 @@ impl Pipeline {
         unsafe {
             vkDevice::from(self.device.clone()).destroy_pipeline(self.pipeline, None);
             vkDevice::from(self.device.clone()).destroy_pipeline_layout(self.layout, None);
+            vkDevice::from(self.device.clone())
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
         }
     }
 }
@@ -0,0 +1,329 @@
+use std::path::PathBuf;
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use nalgebra::Vector3;
+use vulkanalia::{
+    vk::{DeviceV1_0, Format, ImageView},
+    Device as vkDevice,
+};
+
+use crate::{
+    bloom::{Bloom, BloomImages, BloomPass},
+    color_lut::ColorLut,
+    command_buffer::CommandBuffer,
+    device::Device,
+    fullscreen_pass::FullscreenPass,
+    image::Image,
+    instance::Instance,
+    physical_device::PhysicalDevice,
+    post_process_settings::PostProcessSettings,
+    render_graph::{Access, ImageDescription, RenderGraph, ResourceHandle},
+    sampler::Sampler,
+    swapchain::Swapchain,
+    upload_batcher::UploadBatcher,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PostProcessPass {
+    Bloom(BloomPass),
+    Tonemap,
+    Fxaa,
+}
+
+impl From<BloomPass> for PostProcessPass {
+    fn from(pass: BloomPass) -> Self {
+        Self::Bloom(pass)
+    }
+}
+
+/// Images of the chain, declared in the render graph before it is compiled.
+#[derive(Clone, Debug)]
+pub(crate) struct PostProcessImages {
+    bloom: BloomImages,
+    ldr: ResourceHandle,
+}
+
+/// Chain of fullscreen passes between the HDR target and the swapchain: bloom, then
+/// tonemapping combined with chromatic aberration, color grading and vignette into an
+/// offscreen LDR image, then FXAA into the swapchain image.
+#[derive(Clone, Debug)]
+pub(crate) struct PostProcess {
+    hdr_image_view: ImageView,
+    bloom_image_view: ImageView,
+    sampler: Sampler,
+    bloom: Bloom,
+    color_lut: Image,
+    color_lut_domain: (Vector3<f32>, Vector3<f32>),
+    color_lut_path: Option<String>,
+    tonemap: FullscreenPass,
+    fxaa: FullscreenPass,
+    device: Device,
+    instance: Instance,
+    physical_device: PhysicalDevice,
+}
+
+impl PostProcess {
+    pub(crate) fn declare<P: Copy + From<PostProcessPass> + From<BloomPass>>(
+        render_graph: &mut RenderGraph<P>,
+        hdr_image: ResourceHandle,
+        swapchain: &Swapchain,
+        swapchain_format: Format,
+    ) -> PostProcessImages {
+        let bloom = Bloom::declare(render_graph, hdr_image);
+
+        let ldr = render_graph.create_image(ImageDescription {
+            format: swapchain_format,
+            ..render_graph.description(hdr_image)
+        });
+        let swapchain_images =
+            render_graph.import_images(swapchain.images.clone(), Some(Access::Present));
+
+        render_graph.add_pass(
+            PostProcessPass::Tonemap.into(),
+            &[
+                (hdr_image, Access::Sampled),
+                (bloom.output(), Access::Sampled),
+            ],
+            &[(ldr, Access::ColorAttachment)],
+        );
+        render_graph.add_pass(
+            PostProcessPass::Fxaa.into(),
+            &[(ldr, Access::Sampled)],
+            &[(swapchain_images, Access::ColorAttachment)],
+        );
+
+        PostProcessImages { bloom, ldr }
+    }
+
+    /// Creates the passes over `images`, the graph has to be compiled.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new<P: Copy>(
+        render_graph: &RenderGraph<P>,
+        hdr_image: ResourceHandle,
+        images: &PostProcessImages,
+        swapchain: &Swapchain,
+        swapchain_format: Format,
+        device: Device,
+        instance: Instance,
+        physical_device: PhysicalDevice,
+        upload_batcher: &mut UploadBatcher,
+    ) -> Self {
+        let sampler = Sampler::new_clamped(device.clone(), 1);
+
+        let bloom = Bloom::new(
+            render_graph,
+            hdr_image,
+            &images.bloom,
+            &sampler,
+            device.clone(),
+        );
+
+        let hdr_image_view = render_graph.image(hdr_image).view;
+        let bloom_image_view = render_graph.image(images.bloom.output()).view;
+        let ldr_image_view = render_graph.image(images.ldr).view;
+
+        let color_lut_source = ColorLut::identity();
+        let color_lut = Self::create_color_lut(
+            &color_lut_source,
+            device.clone(),
+            instance.clone(),
+            physical_device.clone(),
+            upload_batcher,
+        );
+
+        let tonemap = FullscreenPass::new(
+            device.clone(),
+            swapchain_format,
+            "tonemap.frag",
+            64,
+            &[(ldr_image_view, swapchain.extent)],
+            &[vec![
+                (hdr_image_view, sampler.clone()),
+                (bloom_image_view, sampler.clone()),
+                (color_lut.view, sampler.clone()),
+            ]],
+        );
+
+        let fxaa_targets = swapchain
+            .image_views
+            .iter()
+            .map(|image_view| (*image_view, swapchain.extent))
+            .collect_vec();
+        let fxaa = FullscreenPass::new(
+            device.clone(),
+            swapchain_format,
+            "fxaa.frag",
+            4,
+            fxaa_targets.as_slice(),
+            &[vec![(ldr_image_view, sampler.clone())]],
+        );
+
+        Self {
+            hdr_image_view,
+            bloom_image_view,
+            sampler,
+            bloom,
+            color_lut,
+            color_lut_domain: (color_lut_source.domain_min, color_lut_source.domain_max),
+            color_lut_path: None,
+            tonemap,
+            fxaa,
+            device,
+            instance,
+            physical_device,
+        }
+    }
+
+    fn create_color_lut(
+        color_lut: &ColorLut,
+        device: Device,
+        instance: Instance,
+        physical_device: PhysicalDevice,
+        upload_batcher: &mut UploadBatcher,
+    ) -> Image {
+        let image = Image::new_3d(
+            color_lut.size,
+            Format::R16G16B16A16_SFLOAT,
+            device,
+            instance,
+            physical_device,
+        );
+
+        image.fill_layers(color_lut.to_texels().as_slice(), upload_batcher);
+
+        image
+    }
+
+    /// Applies settings that need resources to be recreated, it waits for the device when
+    /// something has changed.
+    pub(crate) fn update(
+        &mut self,
+        settings: &PostProcessSettings,
+        upload_batcher: &mut UploadBatcher,
+    ) {
+        if settings.color_grading_lut_path == self.color_lut_path {
+            return;
+        }
+
+        let color_lut_source = settings
+            .color_grading_lut_path
+            .as_deref()
+            .map_or_else(ColorLut::identity, ColorLut::new);
+        let color_lut = Self::create_color_lut(
+            &color_lut_source,
+            self.device.clone(),
+            self.instance.clone(),
+            self.physical_device.clone(),
+            upload_batcher,
+        );
+        upload_batcher.wait();
+
+        unsafe {
+            vkDevice::from(self.device.clone())
+                .device_wait_idle()
+                .unwrap();
+        }
+
+        self.tonemap.update_input(
+            0,
+            &[
+                (self.hdr_image_view, self.sampler.clone()),
+                (self.bloom_image_view, self.sampler.clone()),
+                (color_lut.view, self.sampler.clone()),
+            ],
+        );
+
+        self.color_lut.destroy();
+        self.color_lut = color_lut;
+        self.color_lut_domain = (color_lut_source.domain_min, color_lut_source.domain_max);
+        self.color_lut_path = settings.color_grading_lut_path.clone();
+    }
+
+    pub(crate) fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        self.bloom.reload_shaders(changed_paths);
+        self.tonemap.reload_shaders(changed_paths);
+        self.fxaa.reload_shaders(changed_paths);
+    }
+
+    /// Records `pass` of the chain, the bloom passes record nothing when bloom is disabled.
+    pub(crate) fn record(
+        &self,
+        pass: PostProcessPass,
+        command_buffer: &CommandBuffer,
+        image_index: usize,
+        exposure: f32,
+        settings: &PostProcessSettings,
+    ) {
+        match pass {
+            PostProcessPass::Bloom(pass) => {
+                if settings.bloom_enabled {
+                    self.bloom.record(
+                        pass,
+                        command_buffer,
+                        settings.bloom_threshold,
+                        settings.bloom_radius,
+                    );
+                }
+            }
+            PostProcessPass::Tonemap => self.record_tonemap(command_buffer, exposure, settings),
+            PostProcessPass::Fxaa => self.fxaa.record(
+                command_buffer,
+                image_index,
+                0,
+                &(settings.fxaa_enabled as u32).to_ne_bytes(),
+            ),
+        }
+    }
+
+    fn record_tonemap(
+        &self,
+        command_buffer: &CommandBuffer,
+        exposure: f32,
+        settings: &PostProcessSettings,
+    ) {
+        let enabled_or_zero = |enabled: bool, value: f32| if enabled { value } else { 0.0 };
+        let (domain_min, domain_max) = self.color_lut_domain;
+
+        let tonemap_push_constants = [
+            exposure.to_bits(),
+            settings.tonemapper as u32,
+            enabled_or_zero(settings.bloom_enabled, settings.bloom_intensity).to_bits(),
+            enabled_or_zero(settings.vignette_enabled, settings.vignette_intensity).to_bits(),
+            settings.vignette_smoothness.to_bits(),
+            enabled_or_zero(
+                settings.chromatic_aberration_enabled,
+                settings.chromatic_aberration_intensity,
+            )
+            .to_bits(),
+            enabled_or_zero(
+                settings.color_grading_enabled,
+                settings.color_grading_contribution,
+            )
+            .to_bits(),
+            0,
+            domain_min.x.to_bits(),
+            domain_min.y.to_bits(),
+            domain_min.z.to_bits(),
+            0,
+            domain_max.x.to_bits(),
+            domain_max.y.to_bits(),
+            domain_max.z.to_bits(),
+            0,
+        ]
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect_vec();
+
+        self.tonemap
+            .record(command_buffer, 0, 0, tonemap_push_constants.as_slice());
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.fxaa.destroy();
+        self.tonemap.destroy();
+        self.color_lut.destroy();
+        self.bloom.destroy();
+        self.sampler.destroy();
+    }
+}
@@ -0,0 +1,85 @@
+use pyo3::prelude::*;
+
+/// Curve mapping exposed HDR color into the displayable range, the discriminant is the
+/// value checked by `tonemap.frag`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[pyclass]
+pub(crate) enum Tonemapper {
+    Reinhard,
+    Aces,
+    Agx,
+}
+
+/// Parameters of the post-processing chain and of the exposure, read by the renderer every
+/// frame. Scripts change the ones of the renderer through `Scene.post_process`.
+#[derive(Clone, Debug)]
+#[pyclass]
+pub(crate) struct PostProcessSettings {
+    #[pyo3(get, set)]
+    pub(crate) auto_exposure: bool,
+    #[pyo3(get, set)]
+    pub(crate) exposure: f32,
+    #[pyo3(get, set)]
+    pub(crate) exposure_compensation: f32,
+    #[pyo3(get, set)]
+    pub(crate) tonemapper: Tonemapper,
+    #[pyo3(get, set)]
+    pub(crate) bloom_enabled: bool,
+    #[pyo3(get, set)]
+    pub(crate) bloom_threshold: f32,
+    #[pyo3(get, set)]
+    pub(crate) bloom_intensity: f32,
+    #[pyo3(get, set)]
+    pub(crate) bloom_radius: f32,
+    #[pyo3(get, set)]
+    pub(crate) fxaa_enabled: bool,
+    #[pyo3(get, set)]
+    pub(crate) vignette_enabled: bool,
+    #[pyo3(get, set)]
+    pub(crate) vignette_intensity: f32,
+    #[pyo3(get, set)]
+    pub(crate) vignette_smoothness: f32,
+    #[pyo3(get, set)]
+    pub(crate) chromatic_aberration_enabled: bool,
+    #[pyo3(get, set)]
+    pub(crate) chromatic_aberration_intensity: f32,
+    #[pyo3(get, set)]
+    pub(crate) color_grading_enabled: bool,
+    /// `.cube` file with a 3D lookup table, the identity is used when it is `None`.
+    #[pyo3(get, set)]
+    pub(crate) color_grading_lut_path: Option<String>,
+    #[pyo3(get, set)]
+    pub(crate) color_grading_contribution: f32,
+}
+
+#[pymethods]
+impl PostProcessSettings {
+    #[new]
+    pub(crate) fn new() -> Self {
+        Self {
+            auto_exposure: true,
+            exposure: 1.0,
+            exposure_compensation: 0.0,
+            tonemapper: Tonemapper::Aces,
+            bloom_enabled: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.05,
+            bloom_radius: 1.0,
+            fxaa_enabled: true,
+            vignette_enabled: false,
+            vignette_intensity: 0.3,
+            vignette_smoothness: 0.5,
+            chromatic_aberration_enabled: false,
+            chromatic_aberration_intensity: 0.005,
+            color_grading_enabled: false,
+            color_grading_lut_path: None,
+            color_grading_contribution: 1.0,
+        }
+    }
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
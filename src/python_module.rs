@@ -0,0 +1,22 @@
+use pyo3::prelude::*;
+
+use crate::{
+    entity::Entity,
+    material::{BlendMode, CullMode, Material, PolygonMode, Topology},
+    post_process_settings::{PostProcessSettings, Tonemapper},
+};
+
+/// Adds the classes that don't need the engine to `module`, for the extension module and
+/// for the scripts the engine runs.
+pub(crate) fn add_classes(module: &PyModule) -> PyResult<()> {
+    module.add_class::<Entity>()?;
+    module.add_class::<Material>()?;
+    module.add_class::<CullMode>()?;
+    module.add_class::<PolygonMode>()?;
+    module.add_class::<BlendMode>()?;
+    module.add_class::<Topology>()?;
+    module.add_class::<PostProcessSettings>()?;
+    module.add_class::<Tonemapper>()?;
+
+    Ok(())
+}
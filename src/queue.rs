@@ -34,14 +34,6 @@ impl Queue {
                 .unwrap();
         }
     }
-
-    pub(crate) fn wait_idle(&self) {
-        unsafe {
-            vkDevice::from(self.device.clone())
-                .queue_wait_idle(self.queue)
-                .unwrap();
-        }
-    }
 }
 
 impl From<Queue> for vk::Queue {
@@ -22,6 +22,63 @@ impl QueueFamilyIndex {
             .unwrap() as u32
     }
 
+    /// Family meant for transfers only when the device has one, so that uploads run next
+    /// to rendering. Falls back to the graphics family.
+    pub(crate) fn transfer(instance: Instance, physical_device: PhysicalDevice) -> u32 {
+        let physical_device_properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_queue_family_properties(physical_device.physical_device)
+        };
+
+        [
+            QueueFlags::GRAPHICS | QueueFlags::COMPUTE,
+            QueueFlags::GRAPHICS,
+        ]
+        .iter()
+        .find_map(|excluded_flags| {
+            physical_device_properties
+                .iter()
+                .position(|queue_family_properties| {
+                    queue_family_properties
+                        .queue_flags
+                        .contains(QueueFlags::TRANSFER)
+                        && !queue_family_properties
+                            .queue_flags
+                            .intersects(*excluded_flags)
+                })
+        })
+        .map_or_else(
+            || Self::graphics(instance.clone(), physical_device.clone()),
+            |index| index as u32,
+        )
+    }
+
+    /// Family meant for compute without graphics when the device has one, so that compute
+    /// work runs next to rendering. Falls back to the graphics family.
+    pub(crate) fn compute(instance: Instance, physical_device: PhysicalDevice) -> u32 {
+        let physical_device_properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_queue_family_properties(physical_device.physical_device)
+        };
+
+        physical_device_properties
+            .iter()
+            .position(|queue_family_properties| {
+                queue_family_properties
+                    .queue_flags
+                    .contains(QueueFlags::COMPUTE)
+                    && !queue_family_properties
+                        .queue_flags
+                        .contains(QueueFlags::GRAPHICS)
+            })
+            .map_or_else(
+                || Self::graphics(instance.clone(), physical_device.clone()),
+                |index| index as u32,
+            )
+    }
+
     pub(crate) fn present(
         instance: Instance,
         physical_device: PhysicalDevice,
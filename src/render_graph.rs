@@ -0,0 +1,750 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use itertools::Itertools;
+use log::error;
+use vulkanalia::{
+    vk::{
+        self, AccessFlags, BufferMemoryBarrier, DependencyFlags, DeviceV1_0, Extent3D, Format,
+        HasBuilder, ImageAspectFlags, ImageLayout, ImageMemoryBarrier, ImageSubresourceRange,
+        ImageTiling, ImageUsageFlags, MemoryBarrier, MemoryPropertyFlags, PipelineStageFlags,
+        SampleCountFlags, QUEUE_FAMILY_IGNORED, REMAINING_ARRAY_LAYERS, REMAINING_MIP_LEVELS,
+        WHOLE_SIZE,
+    },
+    Device as vkDevice,
+};
+
+use crate::{
+    command_buffer::CommandBuffer, device::Device, image::Image, instance::Instance,
+    physical_device::PhysicalDevice,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ResourceHandle(usize);
+
+/// How a pass uses a resource, it decides the layout of images and the stages and accesses
+/// the barriers around the pass synchronize with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    ColorAttachment,
+    DepthAttachment,
+    Sampled,
+    /// Sampled by a compute shader.
+    ComputeSampled,
+    /// Storage image or buffer read and written by a compute shader.
+    Storage,
+    /// Draw parameters or draw count of indirect draws.
+    Indirect,
+    TransferSrc,
+    TransferDst,
+    HostRead,
+    Present,
+}
+
+impl Access {
+    fn layout(self) -> ImageLayout {
+        match self {
+            Self::ColorAttachment => ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Self::DepthAttachment => ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Self::Sampled | Self::ComputeSampled => ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Self::Storage | Self::Indirect => ImageLayout::GENERAL,
+            Self::TransferSrc => ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Self::TransferDst => ImageLayout::TRANSFER_DST_OPTIMAL,
+            Self::HostRead => ImageLayout::GENERAL,
+            Self::Present => ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    fn stage_mask(self) -> PipelineStageFlags {
+        match self {
+            Self::ColorAttachment => PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Self::DepthAttachment => {
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            Self::Sampled => PipelineStageFlags::FRAGMENT_SHADER,
+            Self::ComputeSampled | Self::Storage => PipelineStageFlags::COMPUTE_SHADER,
+            Self::Indirect => PipelineStageFlags::DRAW_INDIRECT,
+            Self::TransferSrc | Self::TransferDst => PipelineStageFlags::TRANSFER,
+            Self::HostRead => PipelineStageFlags::HOST,
+            Self::Present => PipelineStageFlags::BOTTOM_OF_PIPE,
+        }
+    }
+
+    fn access_mask(self) -> AccessFlags {
+        match self {
+            Self::ColorAttachment => {
+                AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            Self::DepthAttachment => {
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            Self::Sampled | Self::ComputeSampled => AccessFlags::SHADER_READ,
+            Self::Storage => AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE,
+            Self::Indirect => AccessFlags::INDIRECT_COMMAND_READ,
+            Self::TransferSrc => AccessFlags::TRANSFER_READ,
+            Self::TransferDst => AccessFlags::TRANSFER_WRITE,
+            Self::HostRead => AccessFlags::HOST_READ,
+            Self::Present => AccessFlags::empty(),
+        }
+    }
+
+    fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::ColorAttachment | Self::DepthAttachment | Self::Storage | Self::TransferDst
+        )
+    }
+
+    fn image_usage(self) -> ImageUsageFlags {
+        match self {
+            Self::ColorAttachment => ImageUsageFlags::COLOR_ATTACHMENT,
+            Self::DepthAttachment => ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            Self::Sampled | Self::ComputeSampled => ImageUsageFlags::SAMPLED,
+            Self::Storage => ImageUsageFlags::STORAGE,
+            Self::TransferSrc => ImageUsageFlags::TRANSFER_SRC,
+            Self::TransferDst => ImageUsageFlags::TRANSFER_DST,
+            Self::Indirect | Self::HostRead | Self::Present => ImageUsageFlags::empty(),
+        }
+    }
+}
+
+/// Image allocated by the graph when a pass that is not culled uses it, its usage flags are
+/// gathered from the accesses of those passes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ImageDescription {
+    pub(crate) extent: Extent3D,
+    pub(crate) format: Format,
+    pub(crate) msaa_sample_count: SampleCountFlags,
+}
+
+#[derive(Clone, Debug)]
+enum Resource {
+    Image {
+        description: ImageDescription,
+        image: Option<Box<Image>>,
+    },
+    /// One color image per swapchain image, picked with the image index.
+    ImportedImages {
+        images: Vec<vk::Image>,
+        final_access: Option<Access>,
+    },
+    /// Color image owned outside of the graph whose contents are kept from one frame to the
+    /// next, it starts every frame as the previous one left it.
+    PersistentImage {
+        image: vk::Image,
+        final_access: Access,
+    },
+    /// One buffer per frame in flight, picked with the frame.
+    ImportedBuffers {
+        buffers: Vec<vk::Buffer>,
+        final_access: Option<Access>,
+    },
+}
+
+impl Resource {
+    // Resources that are used after the graph has run, the passes writing them are never
+    // culled
+    fn final_access(&self) -> Option<Access> {
+        match self {
+            Self::Image { .. } => None,
+            Self::ImportedImages { final_access, .. }
+            | Self::ImportedBuffers { final_access, .. } => *final_access,
+            Self::PersistentImage { final_access, .. } => Some(*final_access),
+        }
+    }
+
+    fn is_image(&self) -> bool {
+        !matches!(self, Self::ImportedBuffers { .. })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Pass<P> {
+    label: P,
+    reads: Vec<(ResourceHandle, Access)>,
+    writes: Vec<(ResourceHandle, Access)>,
+}
+
+impl<P> Pass<P> {
+    fn accesses(&self) -> impl Iterator<Item = &(ResourceHandle, Access)> {
+        self.writes.iter().chain(self.reads.iter())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct State {
+    layout: ImageLayout,
+    stage_mask: PipelineStageFlags,
+    access_mask: AccessFlags,
+    write: bool,
+    /// Stages and accesses of the last write, reads of another kind than the ones it was
+    /// made visible to still have to wait for it.
+    last_write: Option<(PipelineStageFlags, AccessFlags)>,
+}
+
+impl State {
+    fn unused() -> Self {
+        Self {
+            layout: ImageLayout::UNDEFINED,
+            stage_mask: PipelineStageFlags::empty(),
+            access_mask: AccessFlags::empty(),
+            write: false,
+            last_write: None,
+        }
+    }
+}
+
+impl From<Access> for State {
+    fn from(access: Access) -> Self {
+        Self {
+            layout: access.layout(),
+            stage_mask: access.stage_mask(),
+            access_mask: access.access_mask(),
+            write: access.is_write(),
+            last_write: access
+                .is_write()
+                .then(|| (access.stage_mask(), access.access_mask())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Barrier {
+    resource: ResourceHandle,
+    old: State,
+    new: State,
+}
+
+impl Barrier {
+    // Nothing has to be waited for when the resource has not been used yet, the stage of the
+    // first use keeps the barrier valid
+    fn src_stage_mask(&self) -> PipelineStageFlags {
+        if self.old.stage_mask.is_empty() {
+            self.new.stage_mask
+        } else {
+            self.old.stage_mask
+        }
+    }
+
+    fn src_access_mask(&self) -> AccessFlags {
+        if self.old.write {
+            self.old.access_mask
+        } else {
+            AccessFlags::empty()
+        }
+    }
+}
+
+/// Passes of a frame declared with the resources they read and write. Compiling the graph
+/// culls the passes whose results are never used, orders the others after the passes
+/// writing what they read, allocates the images they need and works out the layout
+/// transitions and barriers recorded before each of them.
+///
+/// A resource is read after every pass writing it, and passes writing the same resource run
+/// in the order they were added.
+#[derive(Clone, Debug)]
+pub(crate) struct RenderGraph<P> {
+    resources: Vec<Resource>,
+    passes: Vec<Pass<P>>,
+    order: Vec<usize>,
+    barriers: Vec<Vec<Barrier>>,
+    final_barriers: Vec<Barrier>,
+    device: Device,
+    instance: Instance,
+    physical_device: PhysicalDevice,
+}
+
+impl<P: Copy> RenderGraph<P> {
+    pub(crate) fn new(device: Device, instance: Instance, physical_device: PhysicalDevice) -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+            order: Vec::new(),
+            barriers: Vec::new(),
+            final_barriers: Vec::new(),
+            device,
+            instance,
+            physical_device,
+        }
+    }
+
+    pub(crate) fn create_image(&mut self, description: ImageDescription) -> ResourceHandle {
+        self.add_resource(Resource::Image {
+            description,
+            image: None,
+        })
+    }
+
+    /// Color images owned outside of the graph, such as the swapchain images. They are
+    /// left with `final_access` at the end of the frame.
+    pub(crate) fn import_images(
+        &mut self,
+        images: Vec<vk::Image>,
+        final_access: Option<Access>,
+    ) -> ResourceHandle {
+        self.add_resource(Resource::ImportedImages {
+            images,
+            final_access,
+        })
+    }
+
+    /// Image owned outside of the graph that passes of one frame write and passes of the
+    /// next frame read, such as a depth pyramid. It is left with `final_access`, which is
+    /// also the state the next frame finds it in.
+    pub(crate) fn import_persistent_image(
+        &mut self,
+        image: vk::Image,
+        final_access: Access,
+    ) -> ResourceHandle {
+        self.add_resource(Resource::PersistentImage {
+            image,
+            final_access,
+        })
+    }
+
+    pub(crate) fn import_buffers(
+        &mut self,
+        buffers: Vec<vk::Buffer>,
+        final_access: Option<Access>,
+    ) -> ResourceHandle {
+        self.add_resource(Resource::ImportedBuffers {
+            buffers,
+            final_access,
+        })
+    }
+
+    fn add_resource(&mut self, resource: Resource) -> ResourceHandle {
+        self.resources.push(resource);
+
+        ResourceHandle(self.resources.len() - 1)
+    }
+
+    pub(crate) fn add_pass(
+        &mut self,
+        label: P,
+        reads: &[(ResourceHandle, Access)],
+        writes: &[(ResourceHandle, Access)],
+    ) {
+        self.passes.push(Pass {
+            label,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        });
+    }
+
+    pub(crate) fn description(&self, handle: ResourceHandle) -> ImageDescription {
+        match &self.resources[handle.0] {
+            Resource::Image { description, .. } => *description,
+            _ => panic!("{:?} is not created by the render graph", handle),
+        }
+    }
+
+    /// Image allocated for `handle`, only available after `compile`.
+    pub(crate) fn image(&self, handle: ResourceHandle) -> &Image {
+        match &self.resources[handle.0] {
+            Resource::Image {
+                image: Some(image), ..
+            } => image,
+            _ => panic!("{:?} is not an allocated image", handle),
+        }
+    }
+
+    pub(crate) fn compile(&mut self) {
+        let writers = self.writers();
+        let dependencies = self.dependencies(&writers);
+        let alive = self.alive_passes(&writers, &dependencies);
+
+        self.order = Self::sort(&dependencies, &alive);
+        self.allocate();
+
+        // Images start the frame undefined, after the accesses of the previous frame, apart
+        // from the persistent ones
+        let (_, _, end_states) = self.simulate(vec![State::unused(); self.resources.len()]);
+        let initial_states = self
+            .resources
+            .iter()
+            .zip(end_states)
+            .map(|(resource, end_state)| match resource {
+                Resource::Image { .. } => State {
+                    layout: ImageLayout::UNDEFINED,
+                    ..end_state
+                },
+                Resource::PersistentImage { .. } => end_state,
+                _ => State::unused(),
+            })
+            .collect_vec();
+
+        let (barriers, final_barriers, _) = self.simulate(initial_states);
+        self.barriers = barriers;
+        self.final_barriers = final_barriers;
+    }
+
+    fn writers(&self) -> Vec<Vec<usize>> {
+        (0..self.resources.len())
+            .map(|resource| {
+                self.passes
+                    .iter()
+                    .positions(|pass| pass.writes.iter().any(|(handle, _)| handle.0 == resource))
+                    .collect_vec()
+            })
+            .collect_vec()
+    }
+
+    fn dependencies(&self, writers: &[Vec<usize>]) -> Vec<Vec<usize>> {
+        self.passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| {
+                let read_dependencies = pass
+                    .reads
+                    .iter()
+                    .flat_map(|(handle, _)| writers[handle.0].iter().copied());
+                let write_dependencies = pass.writes.iter().flat_map(|(handle, _)| {
+                    writers[handle.0]
+                        .iter()
+                        .copied()
+                        .take_while(move |writer| *writer != index)
+                });
+
+                read_dependencies
+                    .chain(write_dependencies)
+                    .filter(|dependency| *dependency != index)
+                    .unique()
+                    .collect_vec()
+            })
+            .collect_vec()
+    }
+
+    fn alive_passes(&self, writers: &[Vec<usize>], dependencies: &[Vec<usize>]) -> Vec<bool> {
+        let mut alive = vec![false; self.passes.len()];
+        let mut stack = self
+            .resources
+            .iter()
+            .positions(|resource| resource.final_access().is_some())
+            .flat_map(|resource| writers[resource].iter().copied())
+            .collect_vec();
+
+        while let Some(pass) = stack.pop() {
+            if !alive[pass] {
+                alive[pass] = true;
+                stack.extend(dependencies[pass].iter().copied());
+            }
+        }
+
+        alive
+    }
+
+    // Topological sort that keeps the order the passes were added in when it is free to
+    fn sort(dependencies: &[Vec<usize>], alive: &[bool]) -> Vec<usize> {
+        let mut remaining = dependencies.iter().map(Vec::len).collect_vec();
+        let mut ready = (0..dependencies.len())
+            .filter(|pass| alive[*pass] && remaining[*pass] == 0)
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>();
+        let mut order = Vec::new();
+
+        while let Some(Reverse(pass)) = ready.pop() {
+            order.push(pass);
+
+            (0..dependencies.len())
+                .filter(|other| alive[*other] && dependencies[*other].contains(&pass))
+                .for_each(|other| {
+                    remaining[other] -= 1;
+                    if remaining[other] == 0 {
+                        ready.push(Reverse(other));
+                    }
+                });
+        }
+
+        if order.len() != alive.iter().filter(|alive| **alive).count() {
+            error!("Render graph has a cycle, the passes in it are skipped");
+        }
+
+        order
+    }
+
+    fn allocate(&mut self) {
+        let mut usages = vec![ImageUsageFlags::empty(); self.resources.len()];
+        self.order.iter().for_each(|pass| {
+            self.passes[*pass]
+                .accesses()
+                .for_each(|(handle, access)| usages[handle.0] |= access.image_usage());
+        });
+
+        let (device, instance, physical_device) = (
+            self.device.clone(),
+            self.instance.clone(),
+            self.physical_device.clone(),
+        );
+
+        self.resources
+            .iter_mut()
+            .zip(usages)
+            .filter(|(_, usage)| !usage.is_empty())
+            .for_each(|(resource, usage)| {
+                if let Resource::Image { description, image } = resource {
+                    *image = Some(Box::new(Image::new(
+                        description.extent,
+                        description.msaa_sample_count,
+                        device.clone(),
+                        instance.clone(),
+                        physical_device.clone(),
+                        1,
+                        description.format,
+                        ImageTiling::OPTIMAL,
+                        usage,
+                        MemoryPropertyFlags::DEVICE_LOCAL,
+                        Self::view_aspect_flags(description.format),
+                    )));
+                }
+            });
+    }
+
+    // Barriers before every pass in order, barriers at the end of the frame and the states the
+    // resources are left in
+    #[allow(clippy::type_complexity)]
+    fn simulate(&self, mut states: Vec<State>) -> (Vec<Vec<Barrier>>, Vec<Barrier>, Vec<State>) {
+        let barriers = self
+            .order
+            .iter()
+            .map(|pass| {
+                self.passes[*pass]
+                    .accesses()
+                    .filter_map(|(handle, access)| {
+                        let (barrier, state) =
+                            self.transition(*handle, states[handle.0], (*access).into());
+                        states[handle.0] = state;
+
+                        barrier
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+
+        let final_barriers = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, resource)| {
+                let access = resource.final_access()?;
+                let (barrier, state) =
+                    self.transition(ResourceHandle(index), states[index], access.into());
+                states[index] = state;
+
+                barrier
+            })
+            .collect_vec();
+
+        (barriers, final_barriers, states)
+    }
+
+    // Reads following reads in the same layout need no barrier, their stages are merged so
+    // that the next write waits for all of them
+    fn transition(
+        &self,
+        handle: ResourceHandle,
+        old: State,
+        new: State,
+    ) -> (Option<Barrier>, State) {
+        let layout_changed = self.resources[handle.0].is_image() && old.layout != new.layout;
+        let hazard = !old.stage_mask.is_empty() && (old.write || new.write);
+
+        if layout_changed || hazard {
+            let barrier = Barrier {
+                resource: handle,
+                old,
+                new,
+            };
+            let state = State {
+                last_write: new.last_write.or(old.last_write),
+                ..new
+            };
+
+            return (Some(barrier), state);
+        }
+
+        let state = State {
+            layout: new.layout,
+            stage_mask: old.stage_mask | new.stage_mask,
+            access_mask: old.access_mask | new.access_mask,
+            write: new.write,
+            last_write: old.last_write,
+        };
+
+        // Like a host read of a buffer that was only made visible to indirect draws
+        let unsynchronized_write = old
+            .last_write
+            .filter(|_| !old.access_mask.contains(new.access_mask));
+        let barrier = unsynchronized_write.map(|(stage_mask, access_mask)| Barrier {
+            resource: handle,
+            old: State {
+                stage_mask,
+                access_mask,
+                write: true,
+                ..old
+            },
+            new,
+        });
+
+        (barrier, state)
+    }
+
+    /// Records the passes in order with the barriers they need, `record` is called with the
+    /// label of every pass that has not been culled.
+    pub(crate) fn execute(
+        &self,
+        command_buffer: &CommandBuffer,
+        frame: usize,
+        image_index: usize,
+        mut record: impl FnMut(P, &CommandBuffer),
+    ) {
+        self.order
+            .iter()
+            .zip(self.barriers.iter())
+            .for_each(|(pass, barriers)| {
+                self.record_barriers(command_buffer, barriers, frame, image_index);
+                record(self.passes[*pass].label, command_buffer);
+            });
+
+        self.record_barriers(command_buffer, &self.final_barriers, frame, image_index);
+    }
+
+    fn record_barriers(
+        &self,
+        command_buffer: &CommandBuffer,
+        barriers: &[Barrier],
+        frame: usize,
+        image_index: usize,
+    ) {
+        if barriers.is_empty() {
+            return;
+        }
+
+        let image_memory_barriers = barriers
+            .iter()
+            .filter_map(|barrier| {
+                let (image, aspect_flags) = self.vk_image(barrier.resource, image_index)?;
+
+                let subresource_range = ImageSubresourceRange::builder()
+                    .aspect_mask(aspect_flags)
+                    .base_mip_level(0)
+                    .level_count(REMAINING_MIP_LEVELS)
+                    .base_array_layer(0)
+                    .layer_count(REMAINING_ARRAY_LAYERS);
+
+                let image_memory_barrier = ImageMemoryBarrier::builder()
+                    .old_layout(barrier.old.layout)
+                    .new_layout(barrier.new.layout)
+                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(subresource_range)
+                    .src_access_mask(barrier.src_access_mask())
+                    .dst_access_mask(barrier.new.access_mask)
+                    .build();
+
+                Some(image_memory_barrier)
+            })
+            .collect_vec();
+
+        let buffer_memory_barriers = barriers
+            .iter()
+            .filter_map(|barrier| {
+                let buffer = self.vk_buffer(barrier.resource, frame)?;
+
+                let buffer_memory_barrier = BufferMemoryBarrier::builder()
+                    .src_access_mask(barrier.src_access_mask())
+                    .dst_access_mask(barrier.new.access_mask)
+                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(WHOLE_SIZE as u64)
+                    .build();
+
+                Some(buffer_memory_barrier)
+            })
+            .collect_vec();
+
+        let src_stage_mask = barriers
+            .iter()
+            .fold(PipelineStageFlags::empty(), |stage_mask, barrier| {
+                stage_mask | barrier.src_stage_mask()
+            });
+        let dst_stage_mask = barriers
+            .iter()
+            .fold(PipelineStageFlags::empty(), |stage_mask, barrier| {
+                stage_mask | barrier.new.stage_mask
+            });
+
+        unsafe {
+            vkDevice::from(self.device.clone()).cmd_pipeline_barrier(
+                command_buffer.into(),
+                src_stage_mask,
+                dst_stage_mask,
+                DependencyFlags::empty(),
+                &[] as &[MemoryBarrier],
+                buffer_memory_barriers.as_slice(),
+                image_memory_barriers.as_slice(),
+            );
+        }
+    }
+
+    fn vk_image(
+        &self,
+        handle: ResourceHandle,
+        image_index: usize,
+    ) -> Option<(vk::Image, ImageAspectFlags)> {
+        match &self.resources[handle.0] {
+            Resource::Image { description, image } => image
+                .as_ref()
+                .map(|image| (image.vk_image, Self::aspect_flags(description.format))),
+            Resource::ImportedImages { images, .. } => {
+                Some((images[image_index], ImageAspectFlags::COLOR))
+            }
+            Resource::PersistentImage { image, .. } => Some((*image, ImageAspectFlags::COLOR)),
+            Resource::ImportedBuffers { .. } => None,
+        }
+    }
+
+    fn vk_buffer(&self, handle: ResourceHandle, frame: usize) -> Option<vk::Buffer> {
+        match &self.resources[handle.0] {
+            Resource::ImportedBuffers { buffers, .. } => Some(buffers[frame]),
+            _ => None,
+        }
+    }
+
+    // Barriers on depth formats with a stencil have to cover both aspects
+    fn aspect_flags(format: Format) -> ImageAspectFlags {
+        match format {
+            Format::D16_UNORM | Format::D32_SFLOAT | Format::X8_D24_UNORM_PACK32 => {
+                ImageAspectFlags::DEPTH
+            }
+            Format::D16_UNORM_S8_UINT | Format::D24_UNORM_S8_UINT | Format::D32_SFLOAT_S8_UINT => {
+                ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+            }
+            _ => ImageAspectFlags::COLOR,
+        }
+    }
+
+    fn view_aspect_flags(format: Format) -> ImageAspectFlags {
+        let aspect_flags = Self::aspect_flags(format);
+
+        if aspect_flags.contains(ImageAspectFlags::DEPTH) {
+            ImageAspectFlags::DEPTH
+        } else {
+            aspect_flags
+        }
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.resources.iter().for_each(|resource| {
+            if let Resource::Image {
+                image: Some(image), ..
+            } = resource
+            {
+                image.destroy();
+            }
+        });
+    }
+}
@@ -21,8 +21,9 @@ impl RenderPass {
         device: Device,
         color_attachment_format: Format,
         depth_attachment_format: Format,
-        swapchain_format: Format,
+        resolve_attachment_format: Format,
         msaa_sample_count: SampleCountFlags,
+        final_layout: ImageLayout,
     ) -> Self {
         let color_attachment_description = AttachmentDescription::builder()
             .format(color_attachment_format)
@@ -35,13 +36,14 @@ impl RenderPass {
             .final_layout(if msaa_sample_count != SampleCountFlags::_1 {
                 ImageLayout::COLOR_ATTACHMENT_OPTIMAL
             } else {
-                ImageLayout::PRESENT_SRC_KHR
+                final_layout
             });
         let depth_attachment_description = AttachmentDescription::builder()
             .format(depth_attachment_format)
             .samples(msaa_sample_count)
             .load_op(AttachmentLoadOp::CLEAR)
-            .store_op(AttachmentStoreOp::DONT_CARE)
+            // Kept for the hierarchical depth buffer of occlusion culling
+            .store_op(AttachmentStoreOp::STORE)
             .stencil_load_op(AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(AttachmentStoreOp::DONT_CARE)
             .initial_layout(ImageLayout::UNDEFINED)
@@ -49,14 +51,14 @@ impl RenderPass {
 
         let attachment_descriptions = if msaa_sample_count != SampleCountFlags::_1 {
             let color_resolve_attachment_description = AttachmentDescription::builder()
-                .format(swapchain_format)
+                .format(resolve_attachment_format)
                 .samples(SampleCountFlags::_1)
                 .load_op(AttachmentLoadOp::DONT_CARE)
                 .store_op(AttachmentStoreOp::STORE)
                 .stencil_load_op(AttachmentLoadOp::DONT_CARE)
                 .stencil_store_op(AttachmentStoreOp::DONT_CARE)
                 .initial_layout(ImageLayout::UNDEFINED)
-                .final_layout(ImageLayout::PRESENT_SRC_KHR);
+                .final_layout(final_layout);
 
             vec![
                 color_attachment_description,
@@ -91,7 +93,9 @@ impl RenderPass {
             .dst_subpass(0)
             .src_stage_mask(
                 PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-                    | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    | PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | PipelineStageFlags::FRAGMENT_SHADER
+                    | PipelineStageFlags::TRANSFER,
             )
             .src_access_mask(AccessFlags::empty())
             .dst_stage_mask(
@@ -100,9 +104,10 @@ impl RenderPass {
             )
             .dst_access_mask(
                 AccessFlags::COLOR_ATTACHMENT_WRITE | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-            );
+            )
+            .build();
 
-        let subpass_dependencies = &[subpass_dependency];
+        let subpass_dependencies = &[subpass_dependency, Self::outgoing_dependency()];
 
         let mut subpass_description = SubpassDescription::builder()
             .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
@@ -132,6 +137,77 @@ impl RenderPass {
         }
     }
 
+    /// Single color attachment pass for fullscreen effects, its contents are always fully
+    /// overwritten so nothing is loaded.
+    pub(crate) fn new_fullscreen(
+        device: Device,
+        color_attachment_format: Format,
+        final_layout: ImageLayout,
+    ) -> Self {
+        let color_attachment_description = AttachmentDescription::builder()
+            .format(color_attachment_format)
+            .samples(SampleCountFlags::_1)
+            .load_op(AttachmentLoadOp::DONT_CARE)
+            .store_op(AttachmentStoreOp::STORE)
+            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .final_layout(final_layout);
+
+        let color_attachment_ref = AttachmentReference::builder()
+            .attachment(0)
+            .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let color_attachment_refs = &[color_attachment_ref];
+
+        let subpass_dependency = SubpassDependency::builder()
+            .src_subpass(SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .src_access_mask(AccessFlags::empty())
+            .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .build();
+
+        let subpass_dependencies = &[subpass_dependency, Self::outgoing_dependency()];
+
+        let subpass_description = SubpassDescription::builder()
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachment_refs);
+
+        let attachment_descriptions = &[color_attachment_description];
+        let subpasses = &[subpass_description];
+
+        let render_pass_create_info = RenderPassCreateInfo::builder()
+            .attachments(attachment_descriptions)
+            .subpasses(subpasses)
+            .dependencies(subpass_dependencies);
+
+        let render_pass = unsafe {
+            vkDevice::from(device.clone())
+                .create_render_pass(&render_pass_create_info, None)
+                .unwrap()
+        };
+
+        Self {
+            render_pass,
+            device,
+        }
+    }
+
+    // Makes color writes visible to the passes and copies that read the attachment afterwards
+    fn outgoing_dependency() -> SubpassDependency {
+        SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(SUBPASS_EXTERNAL)
+            .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::TRANSFER)
+            .dst_access_mask(AccessFlags::SHADER_READ | AccessFlags::TRANSFER_READ)
+            .build()
+    }
+
     pub(crate) fn destroy(&self) {
         unsafe {
             vkDevice::from(self.device.clone()).destroy_render_pass(self.render_pass, None);
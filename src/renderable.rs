@@ -0,0 +1,50 @@
+use pyo3::prelude::*;
+
+use crate::asset_server::{GpuMesh, GpuTexture, Handle};
+
+/// Model and texture an entity is drawn with, by its material or the default one.
+#[derive(Clone, Debug)]
+#[pyclass(unsendable)]
+pub(crate) struct Renderable {
+    #[pyo3(get)]
+    pub(crate) model_path: String,
+    #[pyo3(get)]
+    pub(crate) texture_path: Option<String>,
+    /// Shared with the entities using the same files, set when the renderer first sees it.
+    pub(crate) mesh: Option<Handle<GpuMesh>>,
+    pub(crate) texture: Option<Handle<GpuTexture>>,
+    /// Whether the model and texture are on the GPU, a placeholder is drawn until then.
+    #[pyo3(get)]
+    pub(crate) loaded: bool,
+    /// Whether the model or texture couldn't be loaded, the placeholder stays drawn until
+    /// the file is fixed.
+    #[pyo3(get)]
+    pub(crate) failed: bool,
+    /// LOD of the mesh drawn, 0 is the full mesh. The renderer picks it every frame.
+    #[pyo3(get)]
+    pub(crate) lod: usize,
+    /// Whether the entity was in view in the last frame, entities out of view aren't drawn.
+    #[pyo3(get)]
+    pub(crate) visible: bool,
+    /// Called without arguments once the entity is loaded.
+    #[pyo3(get, set)]
+    pub(crate) on_loaded: Option<PyObject>,
+}
+
+#[pymethods]
+impl Renderable {
+    #[new]
+    pub(crate) fn new(model_path: &str, texture_path: Option<&str>) -> Self {
+        Self {
+            model_path: model_path.to_string(),
+            texture_path: texture_path.map(str::to_string),
+            mesh: None,
+            texture: None,
+            loaded: false,
+            failed: false,
+            lod: 0,
+            visible: true,
+            on_loaded: None,
+        }
+    }
+}
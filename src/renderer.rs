@@ -1,27 +1,92 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, cmp::Reverse, iter, mem::size_of, ops::Range, path::PathBuf, rc::Rc};
 
-use hashbrown::HashMap;
-use itertools::Itertools;
+use hashbrown::{HashMap, HashSet};
+use itertools::{Either, Itertools};
+use log::{debug, error, info, warn};
+use nalgebra::Matrix4;
+use pyo3::Python;
 use vulkanalia::{
     vk::{
-        self, DeviceV1_0, Extent3D, HasBuilder, InstanceV1_0, PipelineStageFlags, PresentInfoKHR,
-        SampleCountFlags, SubmitInfo, SwapchainKHR, KHR_SWAPCHAIN_EXTENSION,
+        self, DescriptorSet, DeviceSize, DeviceV1_0, Extent3D, Format, HasBuilder, ImageLayout,
+        IndexType, InstanceV1_0, PipelineStageFlags, PresentInfoKHR, SampleCountFlags, SubmitInfo,
+        SwapchainKHR, KHR_SWAPCHAIN_EXTENSION,
     },
     Device as vkDevice,
 };
 use winit::event_loop::EventLoop;
 
 use crate::{
-    buffer::Buffer, command_buffer::CommandBuffer, command_pool::CommandPool,
-    debug_messenger::DebugMessenger, descriptor_pool::DescriptorPool,
-    descriptor_set::DescriptorSet, device::Device, entry::Entry, fence::Fence,
-    framebuffer::Framebuffer, image::Image, instance::Instance, physical_device::PhysicalDevice,
-    pipeline::Pipeline, queue::Queue, queue_family_index::QueueFamilyIndex,
-    render_pass::RenderPass, sampler::Sampler, scene_graph::SceneGraph, semaphore::Semaphore,
-    surface::Surface, swapchain::Swapchain, texture::Texture, ubo::Ubo,
-    validation_layers::ValidationLayers, vertex::Vertex, window::Window,
+    asset_server::{AssetServer, GpuMesh},
+    bloom::BloomPass,
+    buffer::{Buffer, InstanceBuffer},
+    command_buffer::CommandBuffer,
+    command_pool::CommandPool,
+    debug_messenger::DebugMessenger,
+    device::Device,
+    entry::Entry,
+    environment_map::EnvironmentMap,
+    exposure::{Exposure, ExposurePass},
+    fence::Fence,
+    framebuffer::Framebuffer,
+    frustum::Frustum,
+    gpu_compute::GpuCompute,
+    gpu_culling::{CulledObject, GpuCulling, MAX_BATCH_COUNT, MAX_OBJECT_COUNT},
+    hi_z::HiZ,
+    image::Image,
+    image_based_lighting::ImageBasedLighting,
+    instance::Instance,
+    instance_data::InstanceData,
+    material::Material,
+    mesh::Lod,
+    physical_device::PhysicalDevice,
+    pipeline::Pipeline,
+    post_process::{PostProcess, PostProcessPass},
+    post_process_settings::PostProcessSettings,
+    queue::Queue,
+    queue_family_index::QueueFamilyIndex,
+    render_graph::{Access, ImageDescription, RenderGraph},
+    render_pass::RenderPass,
+    renderable::Renderable,
+    scene_graph::SceneGraph,
+    semaphore::Semaphore,
+    shader_watcher::ShaderWatcher,
+    skybox::Skybox,
+    surface::Surface,
+    swapchain::Swapchain,
+    ubo::Ubo,
+    upload_batcher::UploadBatcher,
+    validation_layers::ValidationLayers,
+    vertex::Vertex,
+    window::Window,
 };
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FramePass {
+    Cull,
+    Scene,
+    HiZ,
+    Exposure(ExposurePass),
+    PostProcess(PostProcessPass),
+}
+
+impl From<ExposurePass> for FramePass {
+    fn from(pass: ExposurePass) -> Self {
+        Self::Exposure(pass)
+    }
+}
+
+impl From<PostProcessPass> for FramePass {
+    fn from(pass: PostProcessPass) -> Self {
+        Self::PostProcess(pass)
+    }
+}
+
+impl From<BloomPass> for FramePass {
+    fn from(pass: BloomPass) -> Self {
+        Self::PostProcess(pass.into())
+    }
+}
+
 pub(crate) struct Renderer {
     entry: Entry,
     instance: Instance,
@@ -35,10 +100,15 @@ pub(crate) struct Renderer {
     old_swapchain: SwapchainKHR,
     swapchain: Swapchain,
     render_pass: RenderPass,
-    pipeline: Pipeline,
-    framebuffers: Vec<Framebuffer>,
+    msaa_sample_count: SampleCountFlags,
+    /// Pipelines of the materials used by entities, the default material is always there.
+    pipelines: HashMap<Material, Pipeline>,
+    /// Materials whose shaders failed to compile or don't fit the default pipeline, they
+    /// are drawn with the default material until their shaders change.
+    invalid_materials: HashSet<Material>,
+    framebuffer: Framebuffer,
     command_pool: CommandPool,
-    color_image: Option<Image>,
+    upload_batcher: UploadBatcher,
     command_buffers: Vec<CommandBuffer>,
     wait_semaphores: Vec<Semaphore>,
     signal_semaphores: Vec<Semaphore>,
@@ -46,23 +116,121 @@ pub(crate) struct Renderer {
     signaled_fences: Vec<Fence>,
     scene_graph: Rc<RefCell<SceneGraph>>,
     uniform_buffers: Vec<Buffer<Ubo>>,
-    texture_images: HashMap<usize, Image>,
-    texture_samplers: HashMap<usize, Sampler>,
-    descriptor_pool: DescriptorPool,
-    vertex_buffers: HashMap<usize, Buffer<Vertex>>,
-    index_buffers: HashMap<usize, Buffer<u32>>,
-    descriptor_sets: HashMap<usize, DescriptorSet>,
-    depth_image: Image,
+    /// Per instance data of the visible entities, grown when they don't fit.
+    instance_buffers: Vec<InstanceBuffer>,
+    instance_capacities: Vec<usize>,
+    instance_batches: Vec<InstanceBatch>,
+    /// Drawn from back to front after the opaque batches and the skybox.
+    transparent_batches: Vec<InstanceBatch>,
+    /// Culls on the GPU and draws the mesh pool with indirect draws, when supported.
+    gpu_culling: Option<GpuCulling>,
+    /// Built when culling on the GPU without multisampling.
+    hi_z: Option<HiZ>,
+    indirect_batches: Vec<IndirectBatch>,
+    asset_server: AssetServer,
+    image_based_lighting: ImageBasedLighting,
+    skybox: Skybox,
+    render_graph: RenderGraph<FramePass>,
+    exposure: Exposure,
+    post_process: PostProcess,
+    post_process_settings: Rc<RefCell<PostProcessSettings>>,
+    shader_watcher: ShaderWatcher,
+    pub(crate) frame_statistics: FrameStatistics,
+    /// Compute shaders for scripts, on the compute queue.
+    pub(crate) gpu_compute: Rc<RefCell<GpuCompute>>,
+    previous_time: f32,
     frame: usize,
 }
 
-const MAX_FLIGHT_FRAMES_COUNT: usize = 2;
+/// Counts of the last frame drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FrameStatistics {
+    pub(crate) visible_entity_count: usize,
+    /// Entities out of the view frustum, which weren't drawn.
+    pub(crate) culled_entity_count: usize,
+}
+
+/// Visible entities sharing a pipeline, a mesh LOD and a texture, recorded as a single
+/// instanced draw.
+struct InstanceBatch {
+    pipeline: Pipeline,
+    vertex_buffer: Buffer<Vertex>,
+    index_buffer: Buffer<u8>,
+    index_type: IndexType,
+    descriptor_set: DescriptorSet,
+    lod: Lod,
+    /// Range of the instance buffer of the frame.
+    instances: Range<u32>,
+}
+
+/// Entities sharing a pipeline, an index type and a texture, drawn from the mesh pool by a
+/// single indirect draw of the commands written by the culling.
+struct IndirectBatch {
+    pipeline: Pipeline,
+    index_type: IndexType,
+    descriptor_set: DescriptorSet,
+    /// Range of the draw buffer of the frame, the visible commands are packed at its start
+    /// when they are counted.
+    commands: Range<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum RenderQueue {
+    Opaque,
+    Transparent,
+}
+
+/// Order draws are recorded in: by queue, then back to front for transparent entities, then
+/// by the state they bind, then front to back. It only depends on the scene, not on the
+/// handles Vulkan returned, so frames are recorded the same way on every run.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey<'a> {
+    queue: RenderQueue,
+    /// Depth of transparent entities, zero for opaque ones.
+    far_depth: Reverse<u32>,
+    material: &'a Material,
+    /// Handle ids, `None` for placeholders.
+    mesh: Option<usize>,
+    lod: usize,
+    texture: Option<usize>,
+    /// Depth of opaque entities, zero for transparent ones.
+    near_depth: u32,
+}
+
+impl SortKey<'_> {
+    /// Entries with the same state can be drawn as instances of one draw.
+    fn state(&self) -> (RenderQueue, &Material, Option<usize>, usize, Option<usize>) {
+        (self.queue, self.material, self.mesh, self.lod, self.texture)
+    }
+}
+
+/// Key, pipeline, mesh, LOD, texture set and model matrix of a draw, see
+/// [`Renderer::draw_entry`].
+type DrawEntry<'a> = (
+    SortKey<'a>,
+    &'a Pipeline,
+    &'a GpuMesh,
+    Lod,
+    DescriptorSet,
+    Matrix4<f32>,
+);
+
+pub(crate) const MAX_FLIGHT_FRAMES_COUNT: usize = 2;
+const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+const INITIAL_INSTANCE_CAPACITY: usize = 1024;
+// Size in pixels that the error of the drawn LOD may reach on the screen
+const MAX_LOD_SCREEN_ERROR: f32 = 1.0;
+// A coarser LOD is only switched to under this fraction of the largest error, so that
+// entities around a switching distance don't keep popping between two LODs
+const LOD_HYSTERESIS: f32 = 0.75;
 
 impl Renderer {
     pub(crate) fn new(
         event_loop: &EventLoop<()>,
         scene_graph: Rc<RefCell<SceneGraph>>,
+        post_process_settings: Rc<RefCell<PostProcessSettings>>,
         msaa_sample_count: SampleCountFlags,
+        environment_map_path: Option<&str>,
     ) -> Self {
         let entry = Entry::new();
 
@@ -106,6 +274,15 @@ impl Renderer {
             QueueFamilyIndex::present(instance.clone(), physical_device.clone(), surface.clone());
         let present_queue = Queue::new(device.clone(), present_queue_family_index);
 
+        let compute_queue_family_index =
+            QueueFamilyIndex::compute(instance.clone(), physical_device.clone());
+        let compute_queue = Queue::new(device.clone(), compute_queue_family_index);
+        let gpu_compute = Rc::new(RefCell::new(GpuCompute::new(
+            device.clone(),
+            compute_queue_family_index,
+            compute_queue,
+        )));
+
         let old_swapchain = Swapchain::old_swapchain();
         let swapchain = Swapchain::new(
             instance.clone(),
@@ -117,12 +294,16 @@ impl Renderer {
             &window,
         );
 
+        let swapchain_format =
+            Swapchain::format(instance.clone(), physical_device.clone(), surface.clone()).format;
+
         let render_pass = RenderPass::new(
             device.clone(),
-            Swapchain::format(instance.clone(), physical_device.clone(), surface.clone()).format,
+            HDR_FORMAT,
             Image::depth_format(instance.clone(), physical_device.clone()),
-            Swapchain::format(instance.clone(), physical_device.clone(), surface.clone()).format,
+            HDR_FORMAT,
             msaa_sample_count,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
         );
 
         let command_pool = CommandPool::new(
@@ -130,136 +311,51 @@ impl Renderer {
             QueueFamilyIndex::graphics(instance.clone(), physical_device.clone()),
         );
 
+        let mut upload_batcher =
+            UploadBatcher::new(device.clone(), command_pool.clone(), graphics_queue.clone());
+
         let uniform_buffers = swapchain
+            .images
+            .iter()
+            .map(|_| Buffer::<Ubo>::from_uniform_data(device.clone()))
+            .collect_vec();
+        let instance_buffers = swapchain
             .images
             .iter()
             .map(|_| {
-                Buffer::<Ubo>::from_uniform_data(
-                    device.clone(),
-                    instance.clone(),
-                    physical_device.clone(),
-                )
+                Buffer::<InstanceData>::from_instances(INITIAL_INSTANCE_CAPACITY, device.clone())
             })
             .collect_vec();
+        let instance_capacities = vec![INITIAL_INSTANCE_CAPACITY; instance_buffers.len()];
 
-        let descriptor_pool = DescriptorPool::new(
+        let pipeline = Pipeline::new(device.clone(), render_pass.clone(), msaa_sample_count);
+
+        let environment_map = EnvironmentMap::new(environment_map_path);
+        let image_based_lighting = ImageBasedLighting::new(
+            &environment_map,
             device.clone(),
-            scene_graph
-                .borrow()
-                .entities_with_names
-                .values()
-                .collect_vec()
-                .as_slice(),
+            instance.clone(),
+            physical_device.clone(),
+            &mut upload_batcher,
         );
 
-        let texture_images = scene_graph
-            .borrow()
-            .entities_with_names
-            .values()
-            .map(|entity| {
-                let id = entity.id;
-
-                let extent = Extent3D::builder()
-                    .width(entity.model.texture.image.width())
-                    .height(entity.model.texture.image.height())
-                    .depth(1)
-                    .build();
-
-                let image = Texture::create_image(
-                    extent,
-                    msaa_sample_count,
-                    device.clone(),
-                    instance.clone(),
-                    physical_device.clone(),
-                );
-
-                image.fill(
-                    Rc::clone(&entity.model.texture.image),
-                    command_pool.clone(),
-                    graphics_queue.clone(),
-                );
-
-                (id, image)
-            })
-            .collect::<HashMap<usize, Image>>();
-
-        let texture_samplers = scene_graph
-            .borrow()
-            .entities_with_names
-            .values()
-            .zip(texture_images.values())
-            .map(|(entity, image)| {
-                let id = entity.id;
-
-                let sampler = Texture::create_sampler(device.clone(), image.mip_levels);
-
-                (id, sampler)
-            })
-            .collect::<HashMap<usize, Sampler>>();
-
-        let vertex_buffers = scene_graph
-            .borrow()
-            .entities_with_names
-            .values()
-            .map(|entity| {
-                let id = entity.id;
-
-                let vertex_buffer = Buffer::<Vertex>::from_vertices(
-                    entity.model.vertices.as_slice(),
-                    device.clone(),
-                    instance.clone(),
-                    physical_device.clone(),
-                    command_pool.clone(),
-                    graphics_queue.clone(),
-                );
-
-                (id, vertex_buffer)
-            })
-            .collect::<HashMap<usize, Buffer<Vertex>>>();
-
-        let index_buffers = scene_graph
-            .borrow()
-            .entities_with_names
-            .values()
-            .map(|entity| {
-                let id = entity.id;
-
-                let index_buffer = Buffer::<u32>::from_indices(
-                    entity.model.indices.as_slice(),
-                    device.clone(),
-                    instance.clone(),
-                    physical_device.clone(),
-                    command_pool.clone(),
-                    graphics_queue.clone(),
-                );
-
-                (id, index_buffer)
-            })
-            .collect::<HashMap<usize, Buffer<u32>>>();
-
-        let descriptor_sets = uniform_buffers
-            .iter()
-            .zip(scene_graph.borrow().entities_with_names.values())
-            .map(|(uniform_buffer, entity)| {
-                let id = entity.id;
-
-                let descriptor_set = DescriptorSet::new(
-                    device.clone(),
-                    descriptor_pool.clone().into(),
-                    uniform_buffer.into(),
-                    texture_images[&id].view,
-                    texture_samplers[&id].clone(),
-                );
-
-                (id, descriptor_set)
-            })
-            .collect::<HashMap<usize, DescriptorSet>>();
-
-        let pipeline = Pipeline::new(
+        let asset_server = AssetServer::new(
+            &pipeline,
+            uniform_buffers.as_slice(),
+            &image_based_lighting,
             device.clone(),
-            descriptor_sets.values().next().unwrap().layout,
+            instance.clone(),
+            physical_device.clone(),
+            &mut upload_batcher,
+        );
+
+        let skybox = Skybox::new(
+            &image_based_lighting,
+            uniform_buffers.as_slice(),
             render_pass.clone(),
             msaa_sample_count,
+            device.clone(),
+            &mut upload_batcher,
         );
 
         let extent = Extent3D::builder()
@@ -268,56 +364,130 @@ impl Renderer {
             .depth(1)
             .build();
 
+        let mut render_graph =
+            RenderGraph::new(device.clone(), instance.clone(), physical_device.clone());
+
+        let hdr_image = render_graph.create_image(ImageDescription {
+            extent,
+            format: HDR_FORMAT,
+            msaa_sample_count: SampleCountFlags::_1,
+        });
+        let depth_image = render_graph.create_image(ImageDescription {
+            extent,
+            format: Image::depth_format(instance.clone(), physical_device.clone()),
+            msaa_sample_count,
+        });
         let color_image = if msaa_sample_count > SampleCountFlags::_1 {
-            Some(Image::new_unresolved(
+            Some(render_graph.create_image(ImageDescription {
                 extent,
+                format: HDR_FORMAT,
                 msaa_sample_count,
+            }))
+        } else {
+            None
+        };
+
+        let gpu_driven = device.indirect_drawing;
+        info!(
+            "Culling on the {}",
+            if gpu_driven {
+                "GPU with indirect draws"
+            } else {
+                "CPU"
+            }
+        );
+
+        let mut hi_z = gpu_driven.then(|| {
+            HiZ::new(
+                extent,
                 device.clone(),
                 instance.clone(),
                 physical_device.clone(),
-                Swapchain::format(instance.clone(), physical_device.clone(), surface.clone())
-                    .format,
-            ))
-        } else {
-            None
+                &mut upload_batcher,
+            )
+        });
+        let (gpu_culling, scene_reads) = match &hi_z {
+            Some(hi_z) => {
+                let (gpu_culling, [draws, counts]) = GpuCulling::new(
+                    &mut render_graph,
+                    FramePass::Cull,
+                    hi_z,
+                    MAX_FLIGHT_FRAMES_COUNT,
+                    device.clone(),
+                );
+
+                (
+                    Some(gpu_culling),
+                    vec![(draws, Access::Indirect), (counts, Access::Indirect)],
+                )
+            }
+            None => (None, Vec::new()),
         };
 
-        let depth_image = Image::new_depth(
-            extent,
+        let scene_writes = [
+            (hdr_image, Access::ColorAttachment),
+            (depth_image, Access::DepthAttachment),
+        ]
+        .iter()
+        .copied()
+        .chain(color_image.map(|image| (image, Access::ColorAttachment)))
+        .collect_vec();
+        render_graph.add_pass(
+            FramePass::Scene,
+            scene_reads.as_slice(),
+            scene_writes.as_slice(),
+        );
+        // The pyramid is built from single sampled depth only
+        if let Some(hi_z) = hi_z
+            .as_mut()
+            .filter(|_| msaa_sample_count == SampleCountFlags::_1)
+        {
+            hi_z.declare(&mut render_graph, FramePass::HiZ, depth_image);
+        }
+
+        let exposure = Exposure::new(
+            &mut render_graph,
+            hdr_image,
+            MAX_FLIGHT_FRAMES_COUNT,
+            device.clone(),
+        );
+        let post_process_images =
+            PostProcess::declare(&mut render_graph, hdr_image, &swapchain, swapchain_format);
+
+        render_graph.compile();
+        if let Some(hi_z) = &mut hi_z {
+            hi_z.create_descriptor_sets(&render_graph);
+        }
+
+        let framebuffer = Framebuffer::new(
+            device.clone(),
+            &render_graph.image(hdr_image).view,
+            render_pass.clone(),
+            swapchain.extent,
+            render_graph.image(depth_image).view,
+            color_image.map(|image| render_graph.image(image).view),
+        );
+
+        let post_process = PostProcess::new(
+            &render_graph,
+            hdr_image,
+            &post_process_images,
+            &swapchain,
+            swapchain_format,
+            device.clone(),
             instance.clone(),
             physical_device,
-            msaa_sample_count,
-            device.clone(),
+            &mut upload_batcher,
         );
 
-        let framebuffers = swapchain
-            .image_views
-            .iter()
-            .map(|image_view| {
-                Framebuffer::new(
-                    device.clone(),
-                    image_view,
-                    render_pass.clone(),
-                    swapchain.extent,
-                    depth_image.view,
-                    color_image.as_ref().map(|image| image.view),
-                )
-            })
-            .collect_vec();
+        upload_batcher.wait();
 
-        let command_buffers = framebuffers
+        let command_buffers = swapchain
+            .images
             .iter()
             .map(|_| CommandBuffer::new(device.clone(), command_pool.clone()))
             .collect_vec();
 
-        scene_graph
-            .borrow_mut()
-            .entities_with_names
-            .iter_mut()
-            .for_each(|(_, entity)| {
-                entity.model.texture = Texture::new(&entity.model.texture.path);
-            });
-
         let wait_semaphores = (0..MAX_FLIGHT_FRAMES_COUNT)
             .map(|_| Semaphore::new(device.clone()))
             .collect_vec();
@@ -332,6 +502,21 @@ impl Renderer {
             .map(|_| Fence::new(device.clone(), false))
             .collect_vec();
 
+        let memory_statistics = device.memory_statistics();
+        info!(
+            "{} allocations in {} blocks and {} dedicated allocations, {} of {} bytes used",
+            memory_statistics.allocation_count,
+            memory_statistics.block_count,
+            memory_statistics.dedicated_allocation_count,
+            memory_statistics.used_bytes,
+            memory_statistics.allocated_bytes
+        );
+
+        let shader_watcher = ShaderWatcher::new();
+
+        let pipelines = iter::once((Material::default(), pipeline)).collect();
+
+        let previous_time = 0.0;
         let frame = 0;
 
         Self {
@@ -347,10 +532,12 @@ impl Renderer {
             old_swapchain,
             swapchain,
             render_pass,
-            pipeline,
-            framebuffers,
+            msaa_sample_count,
+            pipelines,
+            invalid_materials: HashSet::new(),
+            framebuffer,
             command_pool,
-            color_image,
+            upload_batcher,
             command_buffers,
             wait_semaphores,
             signal_semaphores,
@@ -358,13 +545,24 @@ impl Renderer {
             signaled_fences,
             scene_graph,
             uniform_buffers,
-            texture_images,
-            texture_samplers,
-            descriptor_pool,
-            vertex_buffers,
-            index_buffers,
-            descriptor_sets,
-            depth_image,
+            instance_buffers,
+            instance_capacities,
+            instance_batches: Vec::new(),
+            transparent_batches: Vec::new(),
+            gpu_culling,
+            hi_z,
+            indirect_batches: Vec::new(),
+            asset_server,
+            image_based_lighting,
+            skybox,
+            render_graph,
+            exposure,
+            post_process,
+            post_process_settings,
+            shader_watcher,
+            frame_statistics: FrameStatistics::default(),
+            gpu_compute,
+            previous_time,
             frame,
         }
     }
@@ -407,6 +605,31 @@ impl Renderer {
     pub(crate) fn draw_frame(&mut self, exec_time: f32) {
         self.signaled_fences[self.frame].wait();
 
+        let changed_paths = self.shader_watcher.changed_paths();
+        if !changed_paths.is_empty() {
+            self.reload_shaders(&changed_paths);
+        }
+
+        self.create_material_pipelines();
+        self.stream_entities();
+
+        // After the callbacks of loaded entities, which may move them
+        self.scene_graph.borrow_mut().update_world_matrices();
+        let camera = self.camera();
+        self.cull_entities(&camera);
+        self.select_lods(&camera);
+
+        let post_process_settings = self.post_process_settings.borrow().clone();
+        self.post_process
+            .update(&post_process_settings, &mut self.upload_batcher);
+
+        self.exposure.automatic = post_process_settings.auto_exposure;
+        self.exposure.manual = post_process_settings.exposure;
+        self.exposure.compensation = post_process_settings.exposure_compensation;
+        self.exposure
+            .update(self.frame, exec_time - self.previous_time);
+        self.previous_time = exec_time;
+
         let image_index = self
             .swapchain
             .next_image_index(self.wait_semaphores[self.frame].clone());
@@ -417,31 +640,50 @@ impl Renderer {
 
         self.command_buffers[image_index].reset();
 
-        self.uniform_buffers[image_index].update(self.swapchain.extent);
-
-        self.command_buffers[image_index].start_recording(
-            self.swapchain.extent,
-            self.render_pass.clone(),
-            self.framebuffers[image_index].clone(),
-            self.pipeline.clone(),
-        );
+        self.uniform_buffers[image_index].update(&camera);
+        let mut instances = Vec::new();
+        self.batch_objects(&camera, image_index, &mut instances);
+        self.batch_instances(&camera, image_index, &mut instances);
+        self.write_instances(image_index, &instances);
 
-        self.scene_graph
-            .borrow()
-            .entities_with_names
-            .values()
-            .for_each(|entity| {
-                self.command_buffers[image_index].record_drawing(
-                    self.vertex_buffers[&entity.id].clone(),
-                    self.index_buffers[&entity.id].clone(),
-                    self.pipeline.layout,
-                    self.descriptor_sets[&entity.id].clone().into(),
-                    entity.transform_matrix().as_slice(),
-                    entity.model.indices.as_slice(),
-                );
-            });
+        let frame = self.frame;
+        let command_buffer = &self.command_buffers[image_index];
 
-        self.command_buffers[image_index].finish_recording();
+        command_buffer.begin();
+        self.render_graph.execute(
+            command_buffer,
+            frame,
+            image_index,
+            |pass, command_buffer| match pass {
+                FramePass::Cull => {
+                    if let Some(gpu_culling) = &self.gpu_culling {
+                        gpu_culling.record(command_buffer, frame);
+                    }
+                }
+                FramePass::Scene => self.record_scene(command_buffer, image_index),
+                FramePass::HiZ => {
+                    if let Some(hi_z) = &self.hi_z {
+                        hi_z.record(command_buffer);
+                    }
+                }
+                FramePass::Exposure(pass) => {
+                    self.exposure
+                        .record(&self.render_graph, pass, command_buffer, frame)
+                }
+                FramePass::PostProcess(pass) => self.post_process.record(
+                    pass,
+                    command_buffer,
+                    image_index,
+                    self.exposure.value(),
+                    &post_process_settings,
+                ),
+            },
+        );
+        command_buffer.end();
+        // Occlusion is tested from the next frame on, once a pyramid has been built
+        if let Some(hi_z) = &mut self.hi_z {
+            hi_z.built = hi_z.declared();
+        }
 
         let wait_semaphores = &[self.wait_semaphores[self.frame].semaphore];
         let wait_stages = &[PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
@@ -472,6 +714,619 @@ impl Renderer {
 
         self.frame = (self.frame + 1) % MAX_FLIGHT_FRAMES_COUNT;
     }
+
+    fn reload_shaders(&mut self, changed_paths: &HashSet<PathBuf>) {
+        unsafe {
+            vkDevice::from(self.device.clone())
+                .device_wait_idle()
+                .unwrap();
+        }
+
+        self.pipelines
+            .values_mut()
+            .for_each(|pipeline| pipeline.reload_shaders(changed_paths));
+        self.skybox.pipeline.reload_shaders(changed_paths);
+        self.post_process.reload_shaders(changed_paths);
+        if let Some(hi_z) = &mut self.hi_z {
+            hi_z.reload_shaders(changed_paths);
+        }
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.reload_shaders(changed_paths);
+        }
+
+        self.invalid_materials.clear();
+    }
+
+    /// Creates the pipelines of materials that were assigned since the last frame.
+    fn create_material_pipelines(&mut self) {
+        let default_material = Material::default();
+        let new_materials = self
+            .scene_graph
+            .borrow()
+            .materials
+            .iter()
+            .map(|(_, material)| material)
+            .filter(|material| {
+                !self.pipelines.contains_key(*material)
+                    && !self.invalid_materials.contains(*material)
+            })
+            .unique()
+            .cloned()
+            .collect_vec();
+
+        for material in new_materials {
+            match Pipeline::new_material(
+                self.device.clone(),
+                self.render_pass.clone(),
+                self.msaa_sample_count,
+                &material,
+                Some(&self.pipelines[&default_material].reflection),
+            ) {
+                Ok(pipeline) => {
+                    self.pipelines.insert(material, pipeline);
+                }
+                Err(error) => {
+                    error!("Drawing with the default material instead: {}", error);
+                    self.invalid_materials.insert(material);
+                }
+            }
+        }
+    }
+
+    /// Loads the assets of new entities and swaps the placeholder for the ones that have
+    /// been loaded.
+    fn stream_entities(&mut self) {
+        let scene_graph = Rc::clone(&self.scene_graph);
+
+        scene_graph
+            .borrow_mut()
+            .renderables
+            .iter_mut()
+            .filter(|(_, renderable)| renderable.mesh.is_none())
+            .for_each(|(_, renderable)| {
+                renderable.mesh = Some(self.asset_server.load_mesh(&renderable.model_path));
+                renderable.texture = renderable
+                    .texture_path
+                    .as_deref()
+                    .map(|texture_path| self.asset_server.load_texture(texture_path));
+            });
+
+        self.asset_server.update(
+            &self.pipelines[&Material::default()],
+            self.uniform_buffers.as_slice(),
+            &self.image_based_lighting,
+            &mut self.upload_batcher,
+        );
+        self.upload_batcher.submit();
+        self.upload_batcher.retire_finished();
+
+        let asset_server = &self.asset_server;
+        let on_loaded_callbacks = scene_graph
+            .borrow_mut()
+            .renderables
+            .iter_mut()
+            .filter(|(_, renderable)| !renderable.loaded)
+            .filter_map(|(_, renderable)| {
+                renderable.failed = renderable
+                    .mesh
+                    .as_ref()
+                    .is_some_and(|mesh| asset_server.mesh_failed(mesh))
+                    || renderable
+                        .texture
+                        .as_ref()
+                        .is_some_and(|texture| asset_server.texture_failed(texture));
+
+                let mesh_loaded = renderable
+                    .mesh
+                    .as_ref()
+                    .is_some_and(|mesh| asset_server.mesh(mesh).is_some());
+                let texture_loaded = renderable
+                    .texture
+                    .as_ref()
+                    .is_none_or(|texture| asset_server.texture(texture).is_some());
+                if !mesh_loaded || !texture_loaded {
+                    return None;
+                }
+
+                renderable.loaded = true;
+
+                renderable.on_loaded.clone()
+            })
+            .collect_vec();
+
+        // Called once the scene graph is released, so that callbacks can change it
+        on_loaded_callbacks.into_iter().for_each(|on_loaded| {
+            Python::with_gil(|py| {
+                if let Err(error) = on_loaded.call0(py) {
+                    error!("on_loaded callback failed: {}", error);
+                }
+            })
+        });
+    }
+
+    /// Matrices of the first active camera of the scene, or of a default camera without
+    /// one.
+    fn camera(&self) -> Ubo {
+        let scene_graph = self.scene_graph.borrow();
+        let camera = scene_graph.cameras.iter().find(|(_, camera)| camera.active);
+
+        match camera {
+            Some((entity, camera)) => Ubo::from_camera(
+                camera,
+                &scene_graph.world_matrix(entity),
+                self.swapchain.extent,
+            ),
+            None => Ubo::camera(self.swapchain.extent),
+        }
+    }
+
+    /// Marks the entities whose bounding volumes are out of the view frustum, so that they
+    /// aren't drawn. Opaque entities are left to the culling on the GPU when there is one,
+    /// they are counted from its last run for the frame.
+    fn cull_entities(&mut self, camera: &Ubo) {
+        let frustum = Frustum::new(&(camera.projection * camera.view));
+
+        let asset_server = &self.asset_server;
+        let gpu_culling = self.gpu_culling.as_ref();
+        let mut frame_statistics =
+            gpu_culling.map_or_else(FrameStatistics::default, |gpu_culling| {
+                let (visible_entity_count, culled_entity_count) = gpu_culling.counts(self.frame);
+
+                FrameStatistics {
+                    visible_entity_count,
+                    culled_entity_count,
+                }
+            });
+        self.scene_graph
+            .borrow_mut()
+            .drawables_mut()
+            .filter(|(.., material)| gpu_culling.is_none() || material.blend_mode.is_transparent())
+            .for_each(|(renderable, model_matrix, _)| {
+                let mesh = asset_server.drawn_mesh(renderable);
+
+                // The sphere is tested first as it is cheaper
+                renderable.visible = frustum
+                    .intersects_sphere(&mesh.bounding_sphere, &model_matrix)
+                    && frustum.intersects_aabb(&mesh.aabb, &model_matrix);
+                if renderable.visible {
+                    frame_statistics.visible_entity_count += 1;
+                } else {
+                    frame_statistics.culled_entity_count += 1;
+                }
+            });
+
+        if frame_statistics != self.frame_statistics {
+            debug!(
+                "{} entities visible, {} culled",
+                frame_statistics.visible_entity_count, frame_statistics.culled_entity_count
+            );
+        }
+        self.frame_statistics = frame_statistics;
+    }
+
+    /// Picks the coarsest LOD of every entity whose error is small enough once projected on
+    /// the screen.
+    fn select_lods(&mut self, camera: &Ubo) {
+        // At a distance of 1
+        let pixels_per_unit =
+            camera.projection[(1, 1)].abs() * self.swapchain.extent.height as f32 / 2.0;
+
+        let asset_server = &self.asset_server;
+        self.scene_graph
+            .borrow_mut()
+            .drawables_mut()
+            .for_each(|(renderable, model_matrix, _)| {
+                let mesh = renderable
+                    .mesh
+                    .as_ref()
+                    .filter(|_| renderable.loaded)
+                    .and_then(|mesh| asset_server.mesh(mesh));
+                let mesh = match mesh {
+                    Some(mesh) => mesh,
+                    None => {
+                        renderable.lod = 0;
+                        return;
+                    }
+                };
+
+                let scale = Frustum::max_scale(&model_matrix);
+                let (center, radius) =
+                    Frustum::transform_sphere(&mesh.bounding_sphere, &model_matrix);
+                let distance = camera.view.transform_point(&center.into()).coords.norm() - radius;
+                if distance <= 0.0 {
+                    renderable.lod = 0;
+                    return;
+                }
+
+                let screen_error = |error: f32| error * scale / distance * pixels_per_unit;
+                let finest_lod = mesh
+                    .lods
+                    .iter()
+                    .rposition(|lod| screen_error(lod.error) <= MAX_LOD_SCREEN_ERROR)
+                    .unwrap_or(0);
+                let coarsest_lod = mesh
+                    .lods
+                    .iter()
+                    .rposition(|lod| {
+                        screen_error(lod.error) <= MAX_LOD_SCREEN_ERROR * LOD_HYSTERESIS
+                    })
+                    .unwrap_or(0);
+
+                renderable.lod = renderable.lod.min(finest_lod).max(coarsest_lod);
+            });
+    }
+
+    fn material_pipeline(&self, material: &Material) -> &Pipeline {
+        self.drawn_material(material).1
+    }
+
+    /// Material whose pipeline draws `material`, the default one when it has no pipeline.
+    fn drawn_material(&self, material: &Material) -> (&Material, &Pipeline) {
+        self.pipelines
+            .get_key_value(material)
+            .unwrap_or_else(|| self.pipelines.get_key_value(&Material::default()).unwrap())
+    }
+
+    /// Sort key, pipeline, mesh LOD and texture `renderable` is drawn with, for a camera
+    /// with the `view` matrix and the swapchain image `image_index`.
+    fn draw_entry<'a>(
+        &'a self,
+        renderable: &Renderable,
+        model_matrix: Matrix4<f32>,
+        material: &Material,
+        view: &Matrix4<f32>,
+        image_index: usize,
+    ) -> DrawEntry<'a> {
+        let transparent = material.blend_mode.is_transparent();
+        let (material, pipeline) = self.drawn_material(material);
+        // Meshes and textures are swapped together once both have been loaded
+        let mesh = self.asset_server.drawn_mesh(renderable);
+        let texture = renderable
+            .texture
+            .as_ref()
+            .filter(|_| renderable.loaded)
+            .and_then(|texture| self.asset_server.texture(texture))
+            .unwrap_or(&self.asset_server.placeholder_texture);
+        // A re-imported mesh may have fewer LODs until the next selection
+        let lod_index = renderable.lod.min(mesh.lods.len() - 1);
+        let descriptor_set = vk::DescriptorSet::from(&texture.descriptor_sets[image_index]);
+
+        // Of the center along the view direction, the bits of positive floats sort like them
+        let depth = -(view * model_matrix)
+            .transform_point(&mesh.bounding_sphere.center.into())
+            .z;
+        let depth = depth.max(0.0).to_bits();
+        let (queue, far_depth, near_depth) = if transparent {
+            (RenderQueue::Transparent, depth, 0)
+        } else {
+            (RenderQueue::Opaque, 0, depth)
+        };
+        let key = SortKey {
+            queue,
+            far_depth: Reverse(far_depth),
+            material,
+            mesh: renderable
+                .mesh
+                .as_ref()
+                .filter(|_| renderable.loaded)
+                .map(|handle| handle.id()),
+            lod: lod_index,
+            texture: renderable
+                .texture
+                .as_ref()
+                .filter(|_| renderable.loaded)
+                .map(|handle| handle.id()),
+            near_depth,
+        };
+
+        (
+            key,
+            pipeline,
+            mesh,
+            mesh.lods[lod_index],
+            descriptor_set,
+            model_matrix,
+        )
+    }
+
+    /// Groups the visible entities into instanced draws and appends their instances. Opaque
+    /// entities are only batched here without culling on the GPU, transparent ones always
+    /// are.
+    fn batch_instances(
+        &mut self,
+        camera: &Ubo,
+        image_index: usize,
+        instances: &mut Vec<InstanceData>,
+    ) {
+        let batches = {
+            let scene_graph = self.scene_graph.borrow();
+
+            let draw_entries = scene_graph
+                .drawables()
+                .filter(|(renderable, ..)| renderable.visible)
+                .filter(|(.., material)| {
+                    self.gpu_culling.is_none() || material.blend_mode.is_transparent()
+                })
+                .map(|(renderable, model_matrix, material)| {
+                    self.draw_entry(
+                        renderable,
+                        model_matrix,
+                        material,
+                        &camera.view,
+                        image_index,
+                    )
+                })
+                .sorted_by(|(key, ..), (other_key, ..)| key.cmp(other_key))
+                .collect_vec();
+
+            Self::group_instances(&draw_entries, instances)
+        };
+
+        let (opaque_batches, transparent_batches) =
+            batches
+                .into_iter()
+                .partition_map(|(queue, batch)| match queue {
+                    RenderQueue::Opaque => Either::Left(batch),
+                    RenderQueue::Transparent => Either::Right(batch),
+                });
+        self.instance_batches = opaque_batches;
+        self.transparent_batches = transparent_batches;
+    }
+
+    /// Draws consecutive entries binding the same state as instances of one batch, so the
+    /// order of the entries is kept.
+    fn group_instances(
+        draw_entries: &[DrawEntry],
+        instances: &mut Vec<InstanceData>,
+    ) -> Vec<(RenderQueue, InstanceBatch)> {
+        draw_entries
+            .iter()
+            .group_by(|(key, ..)| key.state())
+            .into_iter()
+            .map(|((queue, ..), group)| {
+                let mut group = group.peekable();
+                let (_, pipeline, mesh, lod, descriptor_set, _) = group.peek().unwrap();
+
+                let first_instance = instances.len() as u32;
+                instances.extend(group.map(|(.., model_matrix)| InstanceData::new(*model_matrix)));
+
+                let batch = InstanceBatch {
+                    pipeline: (*pipeline).clone(),
+                    vertex_buffer: mesh.vertex_buffer.clone(),
+                    index_buffer: mesh.index_buffer.clone(),
+                    index_type: mesh.index_type,
+                    descriptor_set: *descriptor_set,
+                    lod: *lod,
+                    instances: first_instance..instances.len() as u32,
+                };
+
+                (queue, batch)
+            })
+            .collect_vec()
+    }
+
+    /// Groups the opaque entities into indirect draws of the mesh pool when culling on the
+    /// GPU, and writes their objects to the culling of the frame. Their instances come
+    /// first, the culling draws them by index.
+    fn batch_objects(
+        &mut self,
+        camera: &Ubo,
+        image_index: usize,
+        instances: &mut Vec<InstanceData>,
+    ) {
+        let compact = match &self.gpu_culling {
+            Some(gpu_culling) => gpu_culling.compact,
+            None => return,
+        };
+        let max_draw_count = self.device.max_draw_indirect_count.max(1) as usize;
+
+        let mut objects = Vec::new();
+        let mut batches = Vec::new();
+        {
+            let scene_graph = self.scene_graph.borrow();
+
+            let entities = scene_graph
+                .drawables()
+                .filter(|(.., material)| !material.blend_mode.is_transparent())
+                .filter_map(|(renderable, model_matrix, material)| {
+                    let (key, pipeline, mesh, lod, descriptor_set, _) = self.draw_entry(
+                        renderable,
+                        model_matrix,
+                        material,
+                        &camera.view,
+                        image_index,
+                    );
+                    let pooled = mesh.pooled?;
+                    let key = (key.material, mesh.index_type.as_raw(), key.texture);
+
+                    Some((
+                        key,
+                        pipeline,
+                        mesh,
+                        pooled,
+                        lod,
+                        descriptor_set,
+                        model_matrix,
+                    ))
+                })
+                .sorted_by(|(key, ..), (other_key, ..)| key.cmp(other_key))
+                .collect_vec();
+
+            for (_, group) in &entities.iter().group_by(|(key, ..)| *key) {
+                // A batch can't have more commands than an indirect draw
+                for chunk in &group.chunks(max_draw_count) {
+                    let mut chunk = chunk.peekable();
+                    let (_, pipeline, mesh, _, _, descriptor_set, _) = **chunk.peek().unwrap();
+
+                    let batch = batches.len() as u32;
+                    let first_command = objects.len() as u32;
+                    for (_, _, mesh, pooled, lod, _, model_matrix) in chunk {
+                        let (center, radius) =
+                            Frustum::transform_sphere(&mesh.bounding_sphere, model_matrix);
+                        let command = if compact {
+                            first_command
+                        } else {
+                            objects.len() as u32
+                        };
+
+                        objects.push(CulledObject::new(
+                            center.push(radius),
+                            pooled.first_index + lod.index_offset,
+                            lod.index_count,
+                            pooled.vertex_offset,
+                            batch,
+                            command,
+                        ));
+                        instances.push(InstanceData::new(*model_matrix));
+                    }
+
+                    batches.push(IndirectBatch {
+                        pipeline: pipeline.clone(),
+                        index_type: mesh.index_type,
+                        descriptor_set,
+                        commands: first_command..objects.len() as u32,
+                    });
+                }
+            }
+        }
+
+        let batch_count = batches
+            .iter()
+            .take(MAX_BATCH_COUNT)
+            .take_while(|batch| batch.commands.end as usize <= MAX_OBJECT_COUNT)
+            .count();
+        if batch_count < batches.len() {
+            batches.truncate(batch_count);
+            let object_count = batches
+                .last()
+                .map_or(0, |batch| batch.commands.end as usize);
+            warn!(
+                "Drawing {} of {} entities, the others don't fit the culling buffers",
+                object_count,
+                objects.len()
+            );
+            objects.truncate(object_count);
+            instances.truncate(object_count);
+        }
+
+        let occlusion = self.hi_z.as_ref().is_some_and(|hi_z| hi_z.built);
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.update(self.frame, camera, &objects, batches.len(), occlusion);
+        }
+
+        self.indirect_batches = batches;
+    }
+
+    /// Writes the instances of the frame to the instance buffer of the image, which is
+    /// grown when they don't fit.
+    fn write_instances(&mut self, image_index: usize, instances: &[InstanceData]) {
+        // The previous frame of this image is done, so its buffer can be replaced
+        if instances.len() > self.instance_capacities[image_index] {
+            let capacity = instances.len().next_power_of_two();
+            debug!("growing instance buffer to {} instances", capacity);
+
+            self.instance_buffers[image_index].destroy();
+            self.instance_buffers[image_index] =
+                Buffer::<InstanceData>::from_instances(capacity, self.device.clone());
+            self.instance_capacities[image_index] = capacity;
+        }
+
+        let instance_buffer = &self.instance_buffers[image_index];
+        instance_buffer.copy_memory(instance_buffer.clone(), instances);
+    }
+
+    fn record_scene(&self, command_buffer: &CommandBuffer, image_index: usize) {
+        let default_pipeline = self.material_pipeline(&Material::default());
+        command_buffer.begin_render_pass(
+            self.swapchain.extent,
+            self.render_pass.clone(),
+            self.framebuffer.clone(),
+            default_pipeline.clone(),
+        );
+        command_buffer.bind_instance_buffer(&self.instance_buffers[image_index]);
+
+        let mut bound_pipeline = default_pipeline.pipeline;
+        Self::record_instance_batches(command_buffer, &self.instance_batches, &mut bound_pipeline);
+
+        if let (Some(gpu_culling), Some(mesh_pool)) =
+            (&self.gpu_culling, &self.asset_server.mesh_pool)
+        {
+            let frame = self.frame;
+            self.indirect_batches
+                .iter()
+                .enumerate()
+                .for_each(|(batch_index, batch)| {
+                    if batch.pipeline.pipeline != bound_pipeline {
+                        command_buffer.bind_pipeline(batch.pipeline.clone());
+                        bound_pipeline = batch.pipeline.pipeline;
+                    }
+
+                    let count_buffer = gpu_culling.compact.then(|| {
+                        (
+                            vk::Buffer::from(&gpu_culling.count_buffers[frame]),
+                            (batch_index * size_of::<u32>()) as DeviceSize,
+                        )
+                    });
+                    command_buffer.record_indirect_drawing(
+                        &mesh_pool.vertex_buffer,
+                        &mesh_pool.index_buffer,
+                        batch.index_type,
+                        &batch.pipeline,
+                        batch.descriptor_set,
+                        (&gpu_culling.draw_buffers[frame]).into(),
+                        batch.commands.start,
+                        batch.commands.len() as u32,
+                        count_buffer,
+                    );
+                });
+        }
+
+        command_buffer.bind_pipeline(self.skybox.pipeline.clone());
+        command_buffer.record_drawing(
+            self.skybox.vertex_buffer.clone(),
+            self.skybox.index_buffer.clone(),
+            IndexType::UINT32,
+            &self.skybox.pipeline,
+            self.skybox.descriptor_sets[image_index].clone().into(),
+            0,
+            self.skybox.indices.len() as u32,
+            0..1,
+        );
+
+        // Blended over everything else, sky included
+        bound_pipeline = self.skybox.pipeline.pipeline;
+        Self::record_instance_batches(
+            command_buffer,
+            &self.transparent_batches,
+            &mut bound_pipeline,
+        );
+
+        command_buffer.end_render_pass();
+    }
+
+    fn record_instance_batches(
+        command_buffer: &CommandBuffer,
+        batches: &[InstanceBatch],
+        bound_pipeline: &mut vk::Pipeline,
+    ) {
+        batches.iter().for_each(|batch| {
+            if batch.pipeline.pipeline != *bound_pipeline {
+                command_buffer.bind_pipeline(batch.pipeline.clone());
+                *bound_pipeline = batch.pipeline.pipeline;
+            }
+
+            command_buffer.record_drawing(
+                batch.vertex_buffer.clone(),
+                batch.index_buffer.clone(),
+                batch.index_type,
+                &batch.pipeline,
+                batch.descriptor_set,
+                batch.lod.index_offset,
+                batch.lod.index_count,
+                batch.instances.clone(),
+            );
+        });
+    }
 }
 
 impl Drop for Renderer {
@@ -481,20 +1336,25 @@ impl Drop for Renderer {
                 .device_wait_idle()
                 .unwrap();
 
-            self.texture_samplers.values().for_each(Sampler::destroy);
-            self.texture_images.values().for_each(Image::destroy);
+            self.gpu_compute.borrow_mut().destroy();
 
-            self.depth_image.destroy();
+            self.post_process.destroy();
+            self.exposure.destroy();
+            if let Some(gpu_culling) = &self.gpu_culling {
+                gpu_culling.destroy();
+            }
+            if let Some(hi_z) = &self.hi_z {
+                hi_z.destroy();
+            }
+            self.render_graph.destroy();
 
-            self.descriptor_sets
-                .values()
-                .for_each(DescriptorSet::destroy);
+            self.skybox.destroy();
+            self.image_based_lighting.destroy();
 
-            self.index_buffers.values().for_each(Buffer::destroy);
-            self.vertex_buffers.values().for_each(Buffer::destroy);
+            self.asset_server.destroy();
 
-            self.descriptor_pool.destroy();
             self.uniform_buffers.iter().for_each(Buffer::destroy);
+            self.instance_buffers.iter().for_each(Buffer::destroy);
 
             self.unsignaled_fences.iter().for_each(Fence::destroy);
             self.signaled_fences.iter().for_each(Fence::destroy);
@@ -502,14 +1362,11 @@ impl Drop for Renderer {
             self.signal_semaphores.iter().for_each(Semaphore::destroy);
             self.wait_semaphores.iter().for_each(Semaphore::destroy);
 
-            if let Some(color_image) = self.color_image.clone() {
-                color_image.destroy();
-            }
-
+            self.upload_batcher.destroy();
             self.command_pool.destroy();
-            self.framebuffers.iter().for_each(Framebuffer::destroy);
+            self.framebuffer.destroy();
 
-            self.pipeline.destroy();
+            self.pipelines.values().for_each(Pipeline::destroy);
             self.render_pass.destroy();
             self.swapchain.destroy();
             self.device.destroy();
@@ -16,12 +16,24 @@ pub(crate) struct Sampler {
 
 impl Sampler {
     pub(crate) fn new(device: Device, mip_levels: u32) -> Self {
+        Self::with_address_mode(device, mip_levels, SamplerAddressMode::REPEAT)
+    }
+
+    pub(crate) fn new_clamped(device: Device, mip_levels: u32) -> Self {
+        Self::with_address_mode(device, mip_levels, SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+
+    fn with_address_mode(
+        device: Device,
+        mip_levels: u32,
+        address_mode: SamplerAddressMode,
+    ) -> Self {
         let sampler_create_info = SamplerCreateInfo::builder()
             .mag_filter(Filter::LINEAR)
             .min_filter(Filter::LINEAR)
-            .address_mode_u(SamplerAddressMode::REPEAT)
-            .address_mode_v(SamplerAddressMode::REPEAT)
-            .address_mode_w(SamplerAddressMode::REPEAT)
+            .address_mode_u(address_mode)
+            .address_mode_v(address_mode)
+            .address_mode_w(address_mode)
             .anisotropy_enable(true)
             .max_anisotropy(16.0)
             .border_color(BorderColor::INT_OPAQUE_BLACK)
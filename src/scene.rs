@@ -0,0 +1,243 @@
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use log::error;
+use numpy::{PyArrayDyn, PyReadonlyArrayDyn};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    camera::Camera,
+    component_storage::ComponentStorage,
+    entity::Entity,
+    gpu_compute::GpuCompute,
+    light::Light,
+    material::Material,
+    post_process_settings::PostProcessSettings,
+    renderable::Renderable,
+    scene_file::SceneFile,
+    scene_graph::{SceneGraph, System},
+    transform::Transform,
+};
+
+/// Access to the scene graph and the renderer for scripts. Components and settings are
+/// returned as copies, they are changed by setting them back, and components are removed
+/// by setting `None`.
+#[derive(Clone)]
+#[pyclass(unsendable)]
+pub(crate) struct Scene {
+    scene_graph: Rc<RefCell<SceneGraph>>,
+    gpu_compute: Rc<RefCell<GpuCompute>>,
+    /// The ones the renderer reads every frame.
+    post_process_settings: Rc<RefCell<PostProcessSettings>>,
+}
+
+impl Scene {
+    pub(crate) fn new(
+        scene_graph: Rc<RefCell<SceneGraph>>,
+        gpu_compute: Rc<RefCell<GpuCompute>>,
+        post_process_settings: Rc<RefCell<PostProcessSettings>>,
+    ) -> Self {
+        Self {
+            scene_graph,
+            gpu_compute,
+            post_process_settings,
+        }
+    }
+
+    /// Runs every system, the scene graph is only borrowed by native ones so that Python
+    /// ones can change it through the scene. Systems added meanwhile run from the next
+    /// frame.
+    pub(crate) fn run_systems(&self, exec_time: f32) {
+        let systems = self.scene_graph.borrow().systems().to_vec();
+
+        systems.into_iter().for_each(|system| match system {
+            System::Native(system) => system(&mut self.scene_graph.borrow_mut(), exec_time),
+            System::Python(system) => Python::with_gil(|py| {
+                if let Err(error) = system.call1(py, (self.clone(), exec_time)) {
+                    error!("System failed: {}", error);
+                }
+            }),
+        });
+    }
+
+    fn set<T>(
+        &self,
+        entity: Entity,
+        component: Option<T>,
+        storage: impl FnOnce(&mut SceneGraph) -> &mut ComponentStorage<T>,
+    ) -> PyResult<()> {
+        let mut scene_graph = self.scene_graph.borrow_mut();
+        if !scene_graph.is_alive(entity) {
+            return Err(PyValueError::new_err(format!("{:?} was despawned", entity)));
+        }
+
+        let storage = storage(&mut scene_graph);
+        match component {
+            Some(component) => {
+                storage.insert(entity, component);
+            }
+            None => {
+                storage.remove(entity);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl Scene {
+    fn spawn(&self, name: &str) -> Entity {
+        self.scene_graph.borrow_mut().spawn(name)
+    }
+
+    fn despawn(&self, entity: Entity) -> bool {
+        self.scene_graph.borrow_mut().despawn(entity)
+    }
+
+    fn find(&self, name: &str) -> Option<Entity> {
+        self.scene_graph.borrow().find(name)
+    }
+
+    fn name(&self, entity: Entity) -> Option<String> {
+        self.scene_graph.borrow().name(entity).map(str::to_string)
+    }
+
+    fn entities(&self) -> Vec<Entity> {
+        self.scene_graph.borrow().entities().collect()
+    }
+
+    /// Entities having every component of `components`, such as `["transform", "light"]`.
+    fn query(&self, components: Vec<&str>) -> PyResult<Vec<Entity>> {
+        let scene_graph = self.scene_graph.borrow();
+
+        let mut entities = Vec::new();
+        for entity in scene_graph.entities() {
+            let mut matches = true;
+            for component in &components {
+                matches &= scene_graph
+                    .has(entity, component)
+                    .map_err(PyValueError::new_err)?;
+            }
+            if matches {
+                entities.push(entity);
+            }
+        }
+
+        Ok(entities)
+    }
+
+    /// Writes the entities to the RON file at `path`, asset paths are made relative to its
+    /// directory.
+    fn save(&self, path: &str) -> PyResult<()> {
+        SceneFile::save(&self.scene_graph.borrow(), Path::new(path))
+            .map_err(PyRuntimeError::new_err)
+    }
+
+    /// Spawns the entities of the RON file at `path`, in place of the ones with the same
+    /// names.
+    fn load(&self, path: &str) -> PyResult<Vec<Entity>> {
+        SceneFile::load(&mut self.scene_graph.borrow_mut(), Path::new(path))
+            .map_err(PyRuntimeError::new_err)
+    }
+
+    #[getter]
+    fn post_process(&self) -> PostProcessSettings {
+        self.post_process_settings.borrow().clone()
+    }
+
+    #[setter]
+    fn set_post_process(&self, post_process_settings: PostProcessSettings) {
+        *self.post_process_settings.borrow_mut() = post_process_settings;
+    }
+
+    /// Runs `group_counts` work groups of the compute shader `shader` over `arrays` of
+    /// 32-bit floats, and returns them as the shader left them.
+    #[pyo3(signature = (shader, arrays, group_counts, push_constants = None))]
+    fn run_compute(
+        &self,
+        py: Python,
+        shader: &str,
+        arrays: Vec<PyReadonlyArrayDyn<f32>>,
+        group_counts: (u32, u32, u32),
+        push_constants: Option<&[u8]>,
+    ) -> PyResult<Vec<Py<PyArrayDyn<f32>>>> {
+        self.gpu_compute
+            .borrow_mut()
+            .run(py, shader, arrays, group_counts, push_constants)
+    }
+
+    /// Runs `system` every frame with the scene and the time, from the next frame.
+    fn add_system(&self, system: PyObject) {
+        self.scene_graph
+            .borrow_mut()
+            .add_system(System::Python(system));
+    }
+
+    fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.scene_graph.borrow().parent(entity)
+    }
+
+    fn set_parent(&self, entity: Entity, parent: Option<Entity>) -> PyResult<()> {
+        self.scene_graph
+            .borrow_mut()
+            .set_parent(entity, parent)
+            .map_err(PyValueError::new_err)
+    }
+
+    fn children(&self, entity: Entity) -> Vec<Entity> {
+        self.scene_graph.borrow().children(entity)
+    }
+
+    fn transform(&self, entity: Entity) -> Option<Transform> {
+        self.scene_graph.borrow().transforms.get(entity).cloned()
+    }
+
+    fn set_transform(&self, entity: Entity, transform: Option<Transform>) -> PyResult<()> {
+        self.set(entity, transform, |scene_graph| &mut scene_graph.transforms)
+    }
+
+    fn renderable(&self, entity: Entity) -> Option<Renderable> {
+        self.scene_graph.borrow().renderables.get(entity).cloned()
+    }
+
+    fn set_renderable(&self, entity: Entity, renderable: Option<Renderable>) -> PyResult<()> {
+        self.set(entity, renderable, |scene_graph| {
+            &mut scene_graph.renderables
+        })
+    }
+
+    fn material(&self, entity: Entity) -> Option<Material> {
+        self.scene_graph.borrow().materials.get(entity).cloned()
+    }
+
+    fn set_material(&self, entity: Entity, material: Option<Material>) -> PyResult<()> {
+        self.set(entity, material, |scene_graph| &mut scene_graph.materials)
+    }
+
+    fn light(&self, entity: Entity) -> Option<Light> {
+        self.scene_graph.borrow().lights.get(entity).cloned()
+    }
+
+    fn set_light(&self, entity: Entity, light: Option<Light>) -> PyResult<()> {
+        self.set(entity, light, |scene_graph| &mut scene_graph.lights)
+    }
+
+    fn camera(&self, entity: Entity) -> Option<Camera> {
+        self.scene_graph.borrow().cameras.get(entity).cloned()
+    }
+
+    fn set_camera(&self, entity: Entity, camera: Option<Camera>) -> PyResult<()> {
+        self.set(entity, camera, |scene_graph| &mut scene_graph.cameras)
+    }
+
+    fn user_data(&self, entity: Entity) -> Option<PyObject> {
+        self.scene_graph.borrow().user_data.get(entity).cloned()
+    }
+
+    fn set_user_data(&self, entity: Entity, user_data: Option<PyObject>) -> PyResult<()> {
+        self.set(entity, user_data, |scene_graph| &mut scene_graph.user_data)
+    }
+}
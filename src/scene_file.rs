@@ -0,0 +1,439 @@
+use std::{
+    fs, iter,
+    path::{self, Component, Path, PathBuf},
+    process,
+};
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use ron::{extensions::Extensions, ser::PrettyConfig, Options};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Camera, entity::Entity, light::Light, material::Material, renderable::Renderable,
+    scene_graph::SceneGraph, transform::Transform,
+};
+
+/// Version written to scene files, files of other versions are refused.
+const SCENE_VERSION: u32 = 1;
+
+/// Scene as stored in a RON file, for level designers to write and keep under version
+/// control. Parents are referenced by name, and asset paths are relative to the directory
+/// of the file. User data is not saved.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SceneFile {
+    version: u32,
+    entities: Vec<EntityFile>,
+}
+
+/// Entity with the components it has, the others are left out of the file.
+#[derive(Debug, Serialize, Deserialize)]
+struct EntityFile {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transform: Option<Transform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    renderable: Option<RenderableFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<Material>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    light: Option<Light>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    camera: Option<Camera>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RenderableFile {
+    model: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture: Option<PathBuf>,
+}
+
+impl SceneFile {
+    /// Writes every entity of `scene_graph` to `path`, in slot order. The file is replaced
+    /// through a rename, so an interrupted save keeps the previous scene.
+    pub(crate) fn save(scene_graph: &SceneGraph, path: &Path) -> Result<(), String> {
+        let directory = Self::directory(path)?;
+
+        let entities = scene_graph
+            .entities()
+            .map(|entity| EntityFile {
+                name: scene_graph.name(entity).unwrap().to_string(),
+                parent: scene_graph
+                    .parent(entity)
+                    .and_then(|parent| scene_graph.name(parent))
+                    .map(str::to_string),
+                transform: scene_graph.transforms.get(entity).cloned(),
+                renderable: scene_graph
+                    .renderables
+                    .get(entity)
+                    .map(|renderable| RenderableFile {
+                        model: Self::relative_path(&renderable.model_path, &directory),
+                        texture: renderable
+                            .texture_path
+                            .as_ref()
+                            .map(|texture_path| Self::relative_path(texture_path, &directory)),
+                    }),
+                material: scene_graph.materials.get(entity).cloned(),
+                light: scene_graph.lights.get(entity).cloned(),
+                camera: scene_graph.cameras.get(entity).cloned(),
+            })
+            .collect_vec();
+        let scene_file = Self {
+            version: SCENE_VERSION,
+            entities,
+        };
+
+        let text = Self::options()
+            .to_string_pretty(&scene_file, PrettyConfig::default())
+            .map_err(|error| format!("Failed to serialize the scene: {}", error))?;
+        let temporary_path = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&temporary_path, text)
+            .and_then(|_| fs::rename(&temporary_path, path))
+            .map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+    }
+
+    /// Spawns the entities of the scene at `path` into `scene_graph`, in place of the ones
+    /// with the same names, and returns them in file order. Nothing is spawned when the
+    /// file is invalid.
+    pub(crate) fn load(scene_graph: &mut SceneGraph, path: &Path) -> Result<Vec<Entity>, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+        let scene_file: Self = Self::options()
+            .from_str(&text)
+            .map_err(|error| format!("Failed to parse {}: {}", path.display(), error))?;
+        if scene_file.version != SCENE_VERSION {
+            return Err(format!(
+                "{} has version {} but version {} is expected",
+                path.display(),
+                scene_file.version,
+                SCENE_VERSION
+            ));
+        }
+        scene_file.validate(scene_graph)?;
+
+        let directory = Self::directory(path)?;
+        let asset_path = |relative_path: &Path| {
+            let path = directory.join(relative_path);
+
+            path.to_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("{} is not valid UTF-8", path.display()))
+        };
+
+        let renderables = scene_file
+            .entities
+            .iter()
+            .map(|entity_file| {
+                entity_file
+                    .renderable
+                    .as_ref()
+                    .map(|renderable| {
+                        let model_path = asset_path(&renderable.model)?;
+                        let texture_path =
+                            renderable.texture.as_deref().map(asset_path).transpose()?;
+
+                        Ok(Renderable::new(&model_path, texture_path.as_deref()))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut entities = Vec::new();
+        for (entity_file, renderable) in scene_file.entities.iter().zip(renderables) {
+            let entity = scene_graph.spawn(&entity_file.name);
+
+            if let Some(transform) = &entity_file.transform {
+                scene_graph.transforms.insert(entity, transform.clone());
+            }
+            if let Some(renderable) = renderable {
+                scene_graph.renderables.insert(entity, renderable);
+            }
+            if let Some(material) = &entity_file.material {
+                scene_graph.materials.insert(entity, material.clone());
+            }
+            if let Some(light) = &entity_file.light {
+                scene_graph.lights.insert(entity, light.clone());
+            }
+            if let Some(camera) = &entity_file.camera {
+                scene_graph.cameras.insert(entity, camera.clone());
+            }
+
+            entities.push(entity);
+        }
+
+        // Once every entity is spawned, as parents may come after their children
+        for (entity_file, entity) in scene_file.entities.iter().zip(&entities) {
+            if let Some(parent) = &entity_file.parent {
+                let parent = scene_graph.find(parent);
+                scene_graph.set_parent(*entity, parent)?;
+            }
+        }
+
+        Ok(entities)
+    }
+
+    /// Checks that names are unique and that parents exist without cycles, parents can be
+    /// entities of the file or of `scene_graph`. Parents of `scene_graph` must not be
+    /// despawned along with an entity the file replaces.
+    fn validate(&self, scene_graph: &SceneGraph) -> Result<(), String> {
+        let mut parents = HashMap::new();
+        for entity_file in &self.entities {
+            if parents
+                .insert(entity_file.name.as_str(), entity_file.parent.as_deref())
+                .is_some()
+            {
+                return Err(format!(
+                    "There are several entities named {}",
+                    entity_file.name
+                ));
+            }
+        }
+
+        for entity_file in &self.entities {
+            // Entities of the scene graph that aren't replaced are already acyclic
+            let mut ancestor = entity_file.parent.as_deref();
+            let mut depth = 0;
+            while let Some(name) = ancestor {
+                ancestor = match parents.get(name) {
+                    Some(parent) => *parent,
+                    None => match scene_graph.find(name) {
+                        Some(parent) => {
+                            Self::check_kept(scene_graph, parent, &parents)?;
+                            None
+                        }
+                        None => {
+                            return Err(format!(
+                                "{} has no parent named {}",
+                                entity_file.name, name
+                            ))
+                        }
+                    },
+                };
+
+                depth += 1;
+                if depth > self.entities.len() {
+                    return Err(format!("{} is its own ancestor", entity_file.name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails when `entity` or one of its ancestors is replaced by an entity of the file,
+    /// spawning it would despawn `entity`.
+    fn check_kept(
+        scene_graph: &SceneGraph,
+        entity: Entity,
+        parents: &HashMap<&str, Option<&str>>,
+    ) -> Result<(), String> {
+        let mut ancestor = Some(entity);
+        while let Some(current) = ancestor {
+            if let Some(name) = scene_graph
+                .name(current)
+                .filter(|name| parents.contains_key(name))
+            {
+                return Err(format!(
+                    "{} is despawned when {} is replaced",
+                    scene_graph.name(entity).unwrap(),
+                    name
+                ));
+            }
+
+            ancestor = scene_graph.parent(current);
+        }
+
+        Ok(())
+    }
+
+    // Optional components are written without `Some`
+    fn options() -> Options {
+        Options::default().with_default_extension(Extensions::IMPLICIT_SOME)
+    }
+
+    fn directory(path: &Path) -> Result<PathBuf, String> {
+        let directory = path
+            .parent()
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+
+        path::absolute(directory)
+            .map_err(|error| format!("Failed to resolve {}: {}", directory.display(), error))
+    }
+
+    /// `path` relative to `directory`, it is kept as is when it can't be made absolute.
+    fn relative_path(path: &str, directory: &Path) -> PathBuf {
+        let path = match path::absolute(path) {
+            Ok(path) => path,
+            Err(_) => return PathBuf::from(path),
+        };
+        let common_count = path
+            .components()
+            .zip(directory.components())
+            .take_while(|(component, directory_component)| component == directory_component)
+            .count();
+
+        iter::repeat_n(
+            Component::ParentDir,
+            directory.components().count() - common_count,
+        )
+        .chain(path.components().skip(common_count))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        process,
+    };
+
+    use nalgebra::Vector3;
+
+    use super::{SceneFile, SCENE_VERSION};
+    use crate::{renderable::Renderable, scene_graph::SceneGraph, transform::Transform};
+
+    /// Empty directory of its own for each test.
+    fn directory(test: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("cpyte-scene-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn load(text: &str, scene_graph: &mut SceneGraph, path: &Path) -> Result<usize, String> {
+        fs::write(path, text).unwrap();
+
+        SceneFile::load(scene_graph, path).map(|entities| entities.len())
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = directory("round_trip");
+        let path = directory.join("level").join("scene.ron");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::create_dir_all(directory.join("models")).unwrap();
+        let model_path = directory.join("models").join("cube.obj");
+        fs::write(&model_path, "").unwrap();
+
+        let mut scene_graph = SceneGraph::new();
+        let root = scene_graph.spawn("root");
+        let child = scene_graph.spawn("child");
+        let transform = Transform {
+            position: Vector3::new(1.0, 2.0, 3.0),
+            ..Transform::default()
+        };
+        scene_graph.transforms.insert(child, transform.clone());
+        scene_graph
+            .renderables
+            .insert(child, Renderable::new(model_path.to_str().unwrap(), None));
+        scene_graph.set_parent(child, Some(root)).unwrap();
+
+        SceneFile::save(&scene_graph, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("\"../models/cube.obj\""));
+
+        let mut loaded = SceneGraph::new();
+        SceneFile::load(&mut loaded, &path).unwrap();
+        let (root, child) = (loaded.find("root").unwrap(), loaded.find("child").unwrap());
+        assert_eq!(loaded.parent(child), Some(root));
+        assert_eq!(loaded.transforms.get(child), Some(&transform));
+        let loaded_model_path = &loaded.renderables.get(child).unwrap().model_path;
+        assert_eq!(
+            fs::canonicalize(loaded_model_path).unwrap(),
+            fs::canonicalize(model_path).unwrap()
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_the_file() {
+        let directory = directory("save_replaces_the_file");
+        let path = directory.join("scene.ron");
+        fs::write(&path, "previous scene").unwrap();
+
+        let mut scene_graph = SceneGraph::new();
+        scene_graph.spawn("entity");
+        SceneFile::save(&scene_graph, &path).unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().contains("\"entity\""));
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let directory = directory("other_versions_are_refused");
+        let mut scene_graph = SceneGraph::new();
+
+        let text = format!(
+            "(version: {}, entities: [(name: \"entity\")])",
+            SCENE_VERSION + 1
+        );
+        assert!(load(&text, &mut scene_graph, &directory.join("scene.ron")).is_err());
+        assert!(scene_graph.find("entity").is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn invalid_parents_are_refused() {
+        let directory = directory("invalid_parents_are_refused");
+        let path = directory.join("scene.ron");
+        let mut scene_graph = SceneGraph::new();
+
+        for entities in [
+            "(name: \"a\"), (name: \"a\")",
+            "(name: \"a\", parent: \"missing\")",
+            "(name: \"a\", parent: \"b\"), (name: \"b\", parent: \"a\")",
+            "(name: \"a\", parent: \"a\")",
+        ] {
+            let text = format!("(version: {}, entities: [{}])", SCENE_VERSION, entities);
+            assert!(
+                load(&text, &mut scene_graph, &path).is_err(),
+                "{}",
+                entities
+            );
+            assert_eq!(scene_graph.entities().count(), 0);
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn parents_outside_the_file() {
+        let directory = directory("parents_outside_the_file");
+        let path = directory.join("scene.ron");
+        let mut scene_graph = SceneGraph::new();
+        let group = scene_graph.spawn("group");
+        let anchor = scene_graph.spawn("anchor");
+        scene_graph.set_parent(anchor, Some(group)).unwrap();
+
+        let text = format!(
+            "(version: {}, entities: [(name: \"a\", parent: \"anchor\")])",
+            SCENE_VERSION
+        );
+        assert_eq!(load(&text, &mut scene_graph, &path), Ok(1));
+        let a = scene_graph.find("a").unwrap();
+        assert_eq!(scene_graph.parent(a), Some(anchor));
+
+        // Replacing the group would despawn the anchor
+        let text = format!(
+            "(version: {}, entities: [(name: \"group\"), (name: \"b\", parent: \"anchor\")])",
+            SCENE_VERSION
+        );
+        assert!(load(&text, &mut scene_graph, &path).is_err());
+        assert_eq!(scene_graph.find("group"), Some(group));
+        assert!(scene_graph.find("b").is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,26 +1,304 @@
 use hashbrown::HashMap;
+use itertools::Itertools;
+use nalgebra::Matrix4;
+use pyo3::prelude::*;
 
-use crate::entity::Entity;
+use crate::{
+    camera::Camera, component_storage::ComponentStorage, entity::Entity, light::Light,
+    material::Material, renderable::Renderable, transform::Transform,
+};
 
+/// Updates the scene once a frame, systems run in the order they were added.
 #[derive(Clone, Debug)]
+pub(crate) enum System {
+    Native(fn(&mut SceneGraph, f32)),
+    /// Called with the [`Scene`] and the time.
+    ///
+    /// [`Scene`]: crate::scene::Scene
+    Python(PyObject),
+}
+
+#[derive(Clone, Debug)]
+struct Slot {
+    generation: u32,
+    /// `None` from the despawn of the entity until the slot is reused.
+    name: Option<String>,
+}
+
+/// Entities with unique names and their components, each type of component in its own
+/// storage. Entities are allocated in the slots of an arena and iterated in slot order, so
+/// updates, asset loads and draws happen in the same order on every run.
+#[derive(Debug)]
 pub(crate) struct SceneGraph {
-    pub(crate) entities_with_names: HashMap<String, Entity>,
+    pub(crate) transforms: ComponentStorage<Transform>,
+    pub(crate) renderables: ComponentStorage<Renderable>,
+    /// Entities with a renderable but no material are drawn with the default one.
+    pub(crate) materials: ComponentStorage<Material>,
+    pub(crate) lights: ComponentStorage<Light>,
+    pub(crate) cameras: ComponentStorage<Camera>,
+    /// Any Python object scripts keep on an entity.
+    pub(crate) user_data: ComponentStorage<PyObject>,
+    /// Transforms combined with the ones of the parents, see
+    /// [`Self::update_world_matrices`].
+    pub(crate) world_matrices: ComponentStorage<Matrix4<f32>>,
+    /// Kept acyclic by [`Self::set_parent`].
+    parents: ComponentStorage<Entity>,
+    default_material: Material,
+    slots: Vec<Slot>,
+    free_indices: Vec<u32>,
+    entities_by_name: HashMap<String, Entity>,
+    systems: Vec<System>,
 }
 
 impl SceneGraph {
     pub(crate) fn new() -> Self {
         Self {
-            entities_with_names: HashMap::new(),
+            transforms: ComponentStorage::new(),
+            renderables: ComponentStorage::new(),
+            materials: ComponentStorage::new(),
+            lights: ComponentStorage::new(),
+            cameras: ComponentStorage::new(),
+            user_data: ComponentStorage::new(),
+            world_matrices: ComponentStorage::new(),
+            parents: ComponentStorage::new(),
+            default_material: Material::default(),
+            slots: Vec::new(),
+            free_indices: Vec::new(),
+            entities_by_name: HashMap::new(),
+            systems: Vec::new(),
+        }
+    }
+
+    /// Adds an entity without components under `name`, in place of the entity that had the
+    /// name.
+    pub(crate) fn spawn(&mut self, name: &str) -> Entity {
+        if let Some(entity) = self.find(name) {
+            self.despawn(entity);
+        }
+
+        let index = self.free_indices.pop().unwrap_or_else(|| {
+            self.slots.push(Slot {
+                generation: 0,
+                name: None,
+            });
+
+            self.slots.len() as u32 - 1
+        });
+        let slot = &mut self.slots[index as usize];
+        slot.name = Some(name.to_string());
+
+        let entity = Entity {
+            index,
+            generation: slot.generation,
+        };
+        self.entities_by_name.insert(name.to_string(), entity);
+
+        entity
+    }
+
+    /// Removes the entity with its components and children, returns whether it was alive.
+    pub(crate) fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        self.children(entity).into_iter().for_each(|child| {
+            self.despawn(child);
+        });
+
+        self.transforms.remove(entity);
+        self.renderables.remove(entity);
+        self.materials.remove(entity);
+        self.lights.remove(entity);
+        self.cameras.remove(entity);
+        self.user_data.remove(entity);
+        self.world_matrices.remove(entity);
+        self.parents.remove(entity);
+
+        let slot = &mut self.slots[entity.index as usize];
+        if let Some(name) = slot.name.take() {
+            self.entities_by_name.remove(&name);
+        }
+        slot.generation += 1;
+        self.free_indices.push(entity.index);
+
+        true
+    }
+
+    pub(crate) fn is_alive(&self, entity: Entity) -> bool {
+        self.name(entity).is_some()
+    }
+
+    pub(crate) fn find(&self, name: &str) -> Option<Entity> {
+        self.entities_by_name.get(name).copied()
+    }
+
+    pub(crate) fn name(&self, entity: Entity) -> Option<&str> {
+        self.slots
+            .get(entity.index as usize)
+            .filter(|slot| slot.generation == entity.generation)?
+            .name
+            .as_deref()
+    }
+
+    pub(crate) fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.name.as_ref().map(|_| Entity {
+                index: index as u32,
+                generation: slot.generation,
+            })
+        })
+    }
+
+    pub(crate) fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.parents.get(entity).copied()
+    }
+
+    /// Children by slot order.
+    pub(crate) fn children(&self, entity: Entity) -> Vec<Entity> {
+        self.parents
+            .iter()
+            .filter(|(_, parent)| **parent == entity)
+            .map(|(child, _)| child)
+            .sorted()
+            .collect_vec()
+    }
+
+    /// Moves `entity` under `parent`, or to the root without one. Its transform is kept, so
+    /// it becomes relative to the new parent.
+    pub(crate) fn set_parent(
+        &mut self,
+        entity: Entity,
+        parent: Option<Entity>,
+    ) -> Result<(), String> {
+        if !self.is_alive(entity) {
+            return Err(format!("{:?} was despawned", entity));
         }
+
+        match parent {
+            Some(parent) => {
+                if !self.is_alive(parent) {
+                    return Err(format!("{:?} was despawned", parent));
+                }
+                let mut ancestor = Some(parent);
+                while let Some(current) = ancestor {
+                    if current == entity {
+                        return Err(format!(
+                            "{:?} can't be a child of its descendant {:?}",
+                            entity, parent
+                        ));
+                    }
+                    ancestor = self.parent(current);
+                }
+
+                self.parents.insert(entity, parent);
+            }
+            None => {
+                self.parents.remove(entity);
+            }
+        }
+
+        Ok(())
     }
 
-    pub(crate) fn insert(&mut self, key: &str, value: Entity) {
-        self.entities_with_names.insert(key.to_string(), value);
+    /// Identity for entities spawned since the last update.
+    pub(crate) fn world_matrix(&self, entity: Entity) -> Matrix4<f32> {
+        self.world_matrices
+            .get(entity)
+            .copied()
+            .unwrap_or_else(Matrix4::identity)
     }
 
-    pub(crate) fn on_update(&mut self, exec_time: f32) {
-        self.entities_with_names.iter_mut().for_each(|(_, entity)| {
-            entity.on_update(exec_time);
+    /// Combines the transforms down the hierarchies, entities without a transform are
+    /// placed like their parent.
+    pub(crate) fn update_world_matrices(&mut self) {
+        let mut world_matrices = ComponentStorage::new();
+        self.entities().for_each(|entity| {
+            self.combine_world_matrix(entity, &mut world_matrices);
         });
+
+        self.world_matrices = world_matrices;
+    }
+
+    fn combine_world_matrix(
+        &self,
+        entity: Entity,
+        world_matrices: &mut ComponentStorage<Matrix4<f32>>,
+    ) -> Matrix4<f32> {
+        if let Some(world_matrix) = world_matrices.get(entity) {
+            return *world_matrix;
+        }
+
+        let matrix = self
+            .transforms
+            .get(entity)
+            .map_or_else(Matrix4::identity, Transform::matrix);
+        let world_matrix = match self.parent(entity) {
+            Some(parent) => self.combine_world_matrix(parent, world_matrices) * matrix,
+            None => matrix,
+        };
+        world_matrices.insert(entity, world_matrix);
+
+        world_matrix
+    }
+
+    /// Renderables with the world matrix and the material of their entity.
+    pub(crate) fn drawables(&self) -> impl Iterator<Item = (&Renderable, Matrix4<f32>, &Material)> {
+        self.renderables.iter().map(move |(entity, renderable)| {
+            let material = self.materials.get(entity).unwrap_or(&self.default_material);
+
+            (renderable, self.world_matrix(entity), material)
+        })
+    }
+
+    pub(crate) fn drawables_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (&mut Renderable, Matrix4<f32>, &Material)> {
+        let Self {
+            renderables,
+            materials,
+            world_matrices,
+            default_material,
+            ..
+        } = self;
+        let (materials, world_matrices, default_material) =
+            (&*materials, &*world_matrices, &*default_material);
+
+        renderables.iter_mut().map(move |(entity, renderable)| {
+            let world_matrix = world_matrices
+                .get(entity)
+                .copied()
+                .unwrap_or_else(Matrix4::identity);
+            let material = materials.get(entity).unwrap_or(default_material);
+
+            (renderable, world_matrix, material)
+        })
+    }
+
+    /// Whether `entity` has `component`, named like its accessors in the [`Scene`].
+    ///
+    /// [`Scene`]: crate::scene::Scene
+    pub(crate) fn has(&self, entity: Entity, component: &str) -> Result<bool, String> {
+        Ok(match component {
+            "transform" => self.transforms.contains(entity),
+            "renderable" => self.renderables.contains(entity),
+            "material" => self.materials.contains(entity),
+            "light" => self.lights.contains(entity),
+            "camera" => self.cameras.contains(entity),
+            "user_data" => self.user_data.contains(entity),
+            "parent" => self.parents.contains(entity),
+            _ => return Err(format!("There is no {} component", component)),
+        })
+    }
+
+    pub(crate) fn add_system(&mut self, system: System) {
+        self.systems.push(system);
+    }
+
+    /// In the order they run, see [`Scene::run_systems`].
+    ///
+    /// [`Scene::run_systems`]: crate::scene::Scene::run_systems
+    pub(crate) fn systems(&self) -> &[System] {
+        &self.systems
     }
 }
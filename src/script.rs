@@ -0,0 +1,54 @@
+use std::{fs, path::Path};
+
+use pyo3::{prelude::*, types::PyModule};
+
+use crate::{
+    camera::Camera,
+    light::{Light, LightKind},
+    python_module,
+    renderable::Renderable,
+    scene::Scene,
+    transform::Transform,
+};
+
+/// Classes of the extension module and the scene ones, importable as `cpyte_engine`.
+#[pymodule]
+fn cpyte_engine(_py: Python, module: &PyModule) -> PyResult<()> {
+    python_module::add_classes(module)?;
+    module.add_class::<Scene>()?;
+    module.add_class::<Transform>()?;
+    module.add_class::<Renderable>()?;
+    module.add_class::<Light>()?;
+    module.add_class::<LightKind>()?;
+    module.add_class::<Camera>()?;
+
+    Ok(())
+}
+
+/// Python script the engine starts with. Its `setup` function is called once with the
+/// [`Scene`], to spawn entities and add systems.
+pub(crate) struct Script;
+
+impl Script {
+    /// Initializes Python and runs the script at `path`, Python can't be used before.
+    /// Must be called at most once.
+    pub(crate) fn run(path: &Path, scene: Scene) -> Result<(), String> {
+        let source = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+
+        pyo3::append_to_inittab!(cpyte_engine);
+        pyo3::prepare_freethreaded_python();
+
+        Python::with_gil(|py| {
+            let module_name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("script");
+            let module = PyModule::from_code(py, &source, &path.to_string_lossy(), module_name)?;
+            module.getattr("setup")?.call1((scene,))?;
+
+            Ok(())
+        })
+        .map_err(|error: PyErr| format!("{} failed: {}", path.display(), error))
+    }
+}
@@ -1,12 +1,298 @@
+use std::{
+    cell::RefCell,
+    convert::TryInto,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use itertools::Itertools;
+use shaderc::{CompileOptions, Compiler, IncludeType, ResolvedInclude, ShaderKind};
+use spirq::{
+    ty::{self, MatrixType, ScalarType, Type, VectorType},
+    var::Variable,
+    ReflectConfig,
+};
 use vulkanalia::{
-    vk::{self, DeviceV1_0, HasBuilder, ShaderModuleCreateInfo},
+    vk::{
+        self, DescriptorType, DeviceV1_0, Format, HasBuilder, ShaderModuleCreateInfo,
+        ShaderStageFlags,
+    },
     Device as vkDevice,
 };
 
-use log::error;
+use log::warn;
 
 use crate::device::Device;
 
+// Relative to the working directory, like the assets, unless set by this variable
+const SOURCE_DIRECTORY_VARIABLE: &str = "CPYTE_SHADER_DIRECTORY";
+const DEFAULT_SOURCE_DIRECTORY: &str = "shaders/src";
+
+/// SPIR-V compiled from a GLSL file, along with every file that was read to compile it.
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledShader {
+    pub(crate) code: Vec<u32>,
+    pub(crate) dependencies: Vec<PathBuf>,
+    pub(crate) reflection: ShaderReflection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DescriptorBinding {
+    pub(crate) set: u32,
+    pub(crate) binding: u32,
+    pub(crate) descriptor_type: DescriptorType,
+    pub(crate) count: u32,
+    /// Size of the block for buffers, 0 for everything else.
+    pub(crate) size: usize,
+    pub(crate) stage_flags: ShaderStageFlags,
+}
+
+/// Interface of one or several stages, read from their SPIR-V. Resources that are declared
+/// but never used are included so layouts don't change while a shader is being edited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ShaderReflection {
+    /// Sorted by set and binding.
+    pub(crate) descriptor_bindings: Vec<DescriptorBinding>,
+    pub(crate) push_constant_size: usize,
+    pub(crate) push_constant_stage_flags: ShaderStageFlags,
+    /// Locations and formats of the vertex stage inputs.
+    pub(crate) vertex_inputs: Vec<(u32, Format)>,
+}
+
+impl ShaderReflection {
+    pub(crate) fn new(code: &[u32], stage_flags: ShaderStageFlags) -> Result<Self, String> {
+        let entry_points = ReflectConfig::new()
+            .spv(code)
+            .combine_img_samplers(true)
+            .ref_all_rscs(true)
+            .reflect()
+            .map_err(|error| error.to_string())?;
+        let entry_point = entry_points
+            .into_iter()
+            .find(|entry_point| entry_point.name == "main")
+            .ok_or("No main entry point")?;
+
+        let mut reflection = Self {
+            descriptor_bindings: Vec::new(),
+            push_constant_size: 0,
+            push_constant_stage_flags: ShaderStageFlags::empty(),
+            vertex_inputs: Vec::new(),
+        };
+
+        for variable in entry_point.vars {
+            match variable {
+                Variable::Descriptor {
+                    desc_bind,
+                    desc_ty,
+                    ty,
+                    nbind,
+                    ..
+                } => reflection.descriptor_bindings.push(DescriptorBinding {
+                    set: desc_bind.set(),
+                    binding: desc_bind.bind(),
+                    descriptor_type: Self::descriptor_type(&desc_ty),
+                    count: nbind,
+                    size: ty.nbyte().unwrap_or(0),
+                    stage_flags,
+                }),
+                Variable::PushConstant { ty, .. } => {
+                    reflection.push_constant_size = ty.nbyte().unwrap_or(0);
+                    reflection.push_constant_stage_flags = stage_flags;
+                }
+                Variable::Input { name, location, ty }
+                    if stage_flags == ShaderStageFlags::VERTEX =>
+                {
+                    // Matrices take a location per column
+                    let (column_ty, column_count) = match &ty {
+                        Type::Matrix(MatrixType {
+                            vector_ty, nvector, ..
+                        }) => (Type::Vector(vector_ty.clone()), *nvector),
+                        _ => (ty.clone(), 1),
+                    };
+                    let format = Self::format(&column_ty).ok_or_else(|| {
+                        format!(
+                            "Unsupported type {} of vertex input {}",
+                            ty,
+                            name.unwrap_or_default()
+                        )
+                    })?;
+                    reflection
+                        .vertex_inputs
+                        .extend((0..column_count).map(|column| (location.loc() + column, format)));
+                }
+                _ => {}
+            }
+        }
+
+        reflection
+            .descriptor_bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+        reflection
+            .vertex_inputs
+            .sort_by_key(|(location, _)| *location);
+
+        Ok(reflection)
+    }
+
+    /// Interface of a pipeline made of both stages, bindings used by both have to agree on
+    /// their type and count.
+    pub(crate) fn merge(&self, other: &Self) -> Result<Self, String> {
+        let mut descriptor_bindings = self.descriptor_bindings.clone();
+
+        for binding in &other.descriptor_bindings {
+            match descriptor_bindings
+                .iter_mut()
+                .find(|merged| (merged.set, merged.binding) == (binding.set, binding.binding))
+            {
+                Some(merged) => {
+                    if (merged.descriptor_type, merged.count)
+                        != (binding.descriptor_type, binding.count)
+                    {
+                        return Err(format!(
+                            "Set {} binding {} is declared as {} {:?} and {} {:?}",
+                            binding.set,
+                            binding.binding,
+                            merged.count,
+                            merged.descriptor_type,
+                            binding.count,
+                            binding.descriptor_type
+                        ));
+                    }
+
+                    merged.size = merged.size.max(binding.size);
+                    merged.stage_flags |= binding.stage_flags;
+                }
+                None => descriptor_bindings.push(*binding),
+            }
+        }
+
+        descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(Self {
+            descriptor_bindings,
+            push_constant_size: self.push_constant_size.max(other.push_constant_size),
+            push_constant_stage_flags: self.push_constant_stage_flags
+                | other.push_constant_stage_flags,
+            vertex_inputs: self
+                .vertex_inputs
+                .iter()
+                .chain(other.vertex_inputs.iter())
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Whether both need the same descriptor set and pipeline layouts.
+    pub(crate) fn layout_matches(&self, other: &Self) -> bool {
+        self.descriptor_bindings == other.descriptor_bindings
+            && self.push_constant_size == other.push_constant_size
+            && self.push_constant_stage_flags == other.push_constant_stage_flags
+    }
+
+    /// What keeps these shaders from being used with the bindings and push constants of
+    /// `interface`.
+    pub(crate) fn layout_mismatches(&self, interface: &Self) -> Vec<String> {
+        let binding_mismatches = self.descriptor_bindings.iter().filter_map(|binding| {
+            match interface
+                .descriptor_bindings
+                .iter()
+                .find(|other| (other.set, other.binding) == (binding.set, binding.binding))
+            {
+                Some(other)
+                    if (other.descriptor_type, other.count)
+                        != (binding.descriptor_type, binding.count) =>
+                {
+                    Some(format!(
+                        "set {} binding {} is {} {:?} instead of {} {:?}",
+                        binding.set,
+                        binding.binding,
+                        binding.count,
+                        binding.descriptor_type,
+                        other.count,
+                        other.descriptor_type
+                    ))
+                }
+                Some(other) if !other.stage_flags.contains(binding.stage_flags) => Some(format!(
+                    "set {} binding {} is only available to {:?}",
+                    binding.set, binding.binding, other.stage_flags
+                )),
+                Some(_) => None,
+                None => Some(format!(
+                    "set {} binding {} is not bound",
+                    binding.set, binding.binding
+                )),
+            }
+        });
+        let push_constant_mismatch = (self.push_constant_size > 0
+            && (self.push_constant_size > interface.push_constant_size
+                || !interface
+                    .push_constant_stage_flags
+                    .contains(self.push_constant_stage_flags)))
+        .then(|| {
+            format!(
+                "push constants are limited to {} bytes in {:?}",
+                interface.push_constant_size, interface.push_constant_stage_flags
+            )
+        });
+
+        binding_mismatches.chain(push_constant_mismatch).collect()
+    }
+
+    fn descriptor_type(descriptor_type: &ty::DescriptorType) -> DescriptorType {
+        match descriptor_type {
+            ty::DescriptorType::Sampler() => DescriptorType::SAMPLER,
+            ty::DescriptorType::CombinedImageSampler() => DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ty::DescriptorType::SampledImage() => DescriptorType::SAMPLED_IMAGE,
+            ty::DescriptorType::StorageImage(_) => DescriptorType::STORAGE_IMAGE,
+            ty::DescriptorType::UniformTexelBuffer() => DescriptorType::UNIFORM_TEXEL_BUFFER,
+            ty::DescriptorType::StorageTexelBuffer(_) => DescriptorType::STORAGE_TEXEL_BUFFER,
+            ty::DescriptorType::UniformBuffer() => DescriptorType::UNIFORM_BUFFER,
+            ty::DescriptorType::StorageBuffer(_) => DescriptorType::STORAGE_BUFFER,
+            ty::DescriptorType::InputAttachment(_) => DescriptorType::INPUT_ATTACHMENT,
+            ty::DescriptorType::AccelStruct() => DescriptorType::ACCELERATION_STRUCTURE_KHR,
+        }
+    }
+
+    fn format(ty: &Type) -> Option<Format> {
+        let (scalar_type, component_count) = match ty {
+            Type::Scalar(scalar_type) => (scalar_type, 1),
+            Type::Vector(VectorType { scalar_ty, nscalar }) => (scalar_ty, *nscalar),
+            _ => return None,
+        };
+
+        let formats = match scalar_type {
+            ScalarType::Float { bits: 32 } => [
+                Format::R32_SFLOAT,
+                Format::R32G32_SFLOAT,
+                Format::R32G32B32_SFLOAT,
+                Format::R32G32B32A32_SFLOAT,
+            ],
+            ScalarType::Integer {
+                bits: 32,
+                is_signed: true,
+            } => [
+                Format::R32_SINT,
+                Format::R32G32_SINT,
+                Format::R32G32B32_SINT,
+                Format::R32G32B32A32_SINT,
+            ],
+            ScalarType::Integer {
+                bits: 32,
+                is_signed: false,
+            } => [
+                Format::R32_UINT,
+                Format::R32G32_UINT,
+                Format::R32G32B32_UINT,
+                Format::R32G32B32A32_UINT,
+            ],
+            _ => return None,
+        };
+
+        formats.get(component_count as usize - 1).copied()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Shader {
     pub(crate) module: vk::ShaderModule,
@@ -14,22 +300,179 @@ pub(crate) struct Shader {
 }
 
 impl Shader {
-    pub(crate) fn new(device: Device, bytes: &[u8]) -> Self {
-        let module = Self::create_module(device.clone(), bytes);
+    pub(crate) fn new(device: Device, code: &[u32]) -> Self {
+        let module = Self::create_module(device.clone(), code);
 
         Self { module, device }
     }
 
-    fn create_module(device: Device, bytes: &[u8]) -> vk::ShaderModule {
-        let byte_vector = Vec::<u8>::from(bytes);
-        let (prefix, code, suffix) = unsafe { byte_vector.align_to::<u32>() };
+    /// `shaders/src` in the working directory, or the directory set by
+    /// `CPYTE_SHADER_DIRECTORY`.
+    pub(crate) fn source_directory() -> PathBuf {
+        env::var_os(SOURCE_DIRECTORY_VARIABLE)
+            .map_or_else(|| PathBuf::from(DEFAULT_SOURCE_DIRECTORY), PathBuf::from)
+    }
+
+    /// Compiles `name` from the source directory, the stage is taken from its extension.
+    /// Includes in quotes are relative to the including file and includes in angle brackets
+    /// to the source directory, the error is the compiler output.
+    ///
+    /// The SPIR-V is kept in the `build` directory next to the source directory, and used
+    /// when the source is missing, without dependencies as there is nothing to reload.
+    pub(crate) fn compile(
+        name: &str,
+        defines: &[(String, String)],
+    ) -> Result<CompiledShader, String> {
+        let source_directory = Self::source_directory();
+        let spirv_path = Self::spirv_path(&source_directory, name, defines);
+
+        let path = match fs::canonicalize(source_directory.join(name)) {
+            Ok(path) => path,
+            Err(error) => {
+                return Self::load_spirv(name, &spirv_path).map_err(|spirv_error| {
+                    format!("{}: {}, and its SPIR-V: {}", name, error, spirv_error)
+                })
+            }
+        };
+
+        let compiled_shader = Self::compile_source(name, &path, &source_directory, defines)?;
+        if let Err(error) = Self::save_spirv(&spirv_path, &compiled_shader.code) {
+            warn!("Failed to save {}: {}", spirv_path.display(), error);
+        }
+
+        Ok(compiled_shader)
+    }
+
+    fn compile_source(
+        name: &str,
+        path: &Path,
+        source_directory: &Path,
+        defines: &[(String, String)],
+    ) -> Result<CompiledShader, String> {
+        let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", name, error))?;
+        let (shader_kind, stage_flags) = Self::stage(name)?;
+
+        let dependencies = RefCell::new(vec![path.to_path_buf()]);
+
+        let compiler = Compiler::new().ok_or("Failed to create the shader compiler")?;
+        let mut options =
+            CompileOptions::new().ok_or("Failed to create the shader compiler options")?;
+        options.set_generate_debug_info();
+        defines
+            .iter()
+            .for_each(|(name, value)| options.add_macro_definition(name, Some(value)));
+        options.set_include_callback(|requested, include_type, requesting, _| {
+            let directory = match include_type {
+                IncludeType::Relative => Path::new(requesting)
+                    .parent()
+                    .map_or_else(PathBuf::new, Path::to_path_buf),
+                IncludeType::Standard => source_directory.to_path_buf(),
+            };
+            let include_path = fs::canonicalize(directory.join(requested))
+                .map_err(|error| format!("{}: {}", requested, error))?;
+            let content = fs::read_to_string(&include_path)
+                .map_err(|error| format!("{}: {}", requested, error))?;
+
+            dependencies.borrow_mut().push(include_path.clone());
+
+            Ok(ResolvedInclude {
+                resolved_name: include_path.to_string_lossy().into_owned(),
+                content,
+            })
+        });
+
+        let artifact = compiler
+            .compile_into_spirv(
+                &source,
+                shader_kind,
+                &path.to_string_lossy(),
+                "main",
+                Some(&options),
+            )
+            .map_err(|error| error.to_string())?;
+
+        if artifact.get_num_warnings() > 0 {
+            warn!("{}", artifact.get_warning_messages());
+        }
+
+        let code = artifact.as_binary().to_vec();
+        // The include callback borrows the dependencies
+        drop(options);
+
+        let reflection = ShaderReflection::new(&code, stage_flags)
+            .map_err(|error| format!("{}: {}", name, error))?;
+
+        Ok(CompiledShader {
+            code,
+            dependencies: dependencies.into_inner(),
+            reflection,
+        })
+    }
+
+    fn stage(name: &str) -> Result<(ShaderKind, ShaderStageFlags), String> {
+        match Path::new(name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("vert") => Ok((ShaderKind::Vertex, ShaderStageFlags::VERTEX)),
+            Some("frag") => Ok((ShaderKind::Fragment, ShaderStageFlags::FRAGMENT)),
+            Some("comp") => Ok((ShaderKind::Compute, ShaderStageFlags::COMPUTE)),
+            _ => Err(format!("{}: unknown shader stage", name)),
+        }
+    }
+
+    /// Shaders compiled with other defines get their own file.
+    fn spirv_path(source_directory: &Path, name: &str, defines: &[(String, String)]) -> PathBuf {
+        let defines_hash = defines
+            .iter()
+            .flat_map(|(name, value)| [name.as_bytes(), b"=", value.as_bytes(), b"\n"])
+            .flatten()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            });
 
-        if !prefix.is_empty() || !suffix.is_empty() {
-            error!("Shader bytecode is not properly aligned");
+        source_directory
+            .with_file_name("build")
+            .join(format!("{}.{:016x}.spv", name, defines_hash))
+    }
+
+    fn load_spirv(name: &str, spirv_path: &Path) -> Result<CompiledShader, String> {
+        let bytes = fs::read(spirv_path).map_err(|error| error.to_string())?;
+        if bytes.len() % 4 != 0 {
+            return Err("the file is truncated".to_string());
         }
+        let code = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect_vec();
+
+        let (_, stage_flags) = Self::stage(name)?;
+        let reflection = ShaderReflection::new(&code, stage_flags)?;
+
+        Ok(CompiledShader {
+            code,
+            dependencies: Vec::new(),
+            reflection,
+        })
+    }
+
+    fn save_spirv(spirv_path: &Path, code: &[u32]) -> io::Result<()> {
+        let bytes = code
+            .iter()
+            .flat_map(|word| word.to_ne_bytes())
+            .collect_vec();
+        let temporary_path = spirv_path.with_extension(format!("{}.tmp", process::id()));
+
+        spirv_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temporary_path, bytes))
+            .and_then(|_| fs::rename(&temporary_path, spirv_path))
+    }
 
+    fn create_module(device: Device, code: &[u32]) -> vk::ShaderModule {
         let shader_module_create_info = ShaderModuleCreateInfo::builder()
-            .code_size(byte_vector.len())
+            .code_size(code.len() * 4)
             .code(code);
 
         unsafe {
@@ -0,0 +1,55 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver},
+};
+
+use hashbrown::HashSet;
+use log::error;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::shader::Shader;
+
+/// Watches the shader source directory so that pipelines can be rebuilt when their sources
+/// change.
+pub(crate) struct ShaderWatcher {
+    /// `None` when watching failed, shaders are then not reloaded.
+    _watcher: Option<RecommendedWatcher>,
+    receiver: Receiver<notify::Result<Event>>,
+}
+
+impl ShaderWatcher {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = channel();
+        let source_directory = Shader::source_directory();
+
+        let watcher = notify::recommended_watcher(sender)
+            .and_then(|mut watcher| {
+                watcher.watch(&source_directory, RecursiveMode::Recursive)?;
+
+                Ok(watcher)
+            })
+            .map_err(|error| error!("Failed to watch {}: {}", source_directory.display(), error))
+            .ok();
+
+        Self {
+            _watcher: watcher,
+            receiver,
+        }
+    }
+
+    /// Canonical paths of the files created or modified since the last call.
+    pub(crate) fn changed_paths(&self) -> HashSet<PathBuf> {
+        self.receiver
+            .try_iter()
+            .filter_map(|event| {
+                event
+                    .map_err(|error| error!("Shader watcher failed: {}", error))
+                    .ok()
+            })
+            .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)))
+            .flat_map(|event| event.paths)
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect()
+    }
+}
@@ -0,0 +1,91 @@
+use itertools::Itertools;
+use nalgebra::{Vector2, Vector3};
+use vulkanalia::vk::SampleCountFlags;
+
+use crate::{
+    buffer::Buffer, descriptor_pool::DescriptorPool, descriptor_set::DescriptorSet, device::Device,
+    image_based_lighting::ImageBasedLighting, pipeline::Pipeline, render_pass::RenderPass,
+    ubo::Ubo, upload_batcher::UploadBatcher, vertex::Vertex,
+};
+
+const CUBE_INDICES: [u32; 36] = [
+    0, 1, 2, 2, 1, 3, 4, 6, 5, 5, 6, 7, 0, 4, 1, 1, 4, 5, 2, 3, 6, 6, 3, 7, 0, 2, 4, 4, 2, 6, 1, 5,
+    3, 3, 5, 7,
+];
+
+#[derive(Clone, Debug)]
+pub(crate) struct Skybox {
+    pub(crate) pipeline: Pipeline,
+    pub(crate) vertex_buffer: Buffer<Vertex>,
+    pub(crate) index_buffer: Buffer<u32>,
+    pub(crate) indices: Vec<u32>,
+    pub(crate) descriptor_sets: Vec<DescriptorSet>,
+    descriptor_pool: DescriptorPool,
+}
+
+impl Skybox {
+    pub(crate) fn new(
+        image_based_lighting: &ImageBasedLighting,
+        uniform_buffers: &[Buffer<Ubo>],
+        render_pass: RenderPass,
+        msaa_sample_count: SampleCountFlags,
+        device: Device,
+        upload_batcher: &mut UploadBatcher,
+    ) -> Self {
+        let vertices = (0..8)
+            .map(|corner| {
+                Vertex::new(
+                    Vector3::new(
+                        if corner & 1 == 0 { -1.0 } else { 1.0 },
+                        if corner & 2 == 0 { -1.0 } else { 1.0 },
+                        if corner & 4 == 0 { -1.0 } else { 1.0 },
+                    ),
+                    Vector3::new(1.0, 1.0, 1.0),
+                    Vector2::zeros(),
+                    Vector3::zeros(),
+                )
+            })
+            .collect_vec();
+        let indices = CUBE_INDICES.to_vec();
+
+        let vertex_buffer =
+            Buffer::<Vertex>::from_vertices(vertices.as_slice(), device.clone(), upload_batcher);
+        let index_buffer =
+            Buffer::<u32>::from_indices(indices.as_slice(), device.clone(), upload_batcher);
+
+        let pipeline = Pipeline::new_skybox(device.clone(), render_pass, msaa_sample_count);
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            &[(&pipeline.reflection, uniform_buffers.len())],
+        );
+        let descriptor_sets = uniform_buffers
+            .iter()
+            .map(|uniform_buffer| {
+                DescriptorSet::new_skybox(
+                    device.clone(),
+                    descriptor_pool.clone().into(),
+                    &pipeline,
+                    uniform_buffer.into(),
+                    image_based_lighting,
+                )
+            })
+            .collect_vec();
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            indices,
+            descriptor_sets,
+            descriptor_pool,
+        }
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.pipeline.destroy();
+        self.descriptor_pool.destroy();
+        self.index_buffer.destroy();
+        self.vertex_buffer.destroy();
+    }
+}
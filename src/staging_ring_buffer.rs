@@ -0,0 +1,100 @@
+use std::{mem::size_of_val, ptr::copy_nonoverlapping as copy_memory};
+
+use vulkanalia::vk::{BufferUsageFlags, DeviceSize, MemoryPropertyFlags};
+
+use crate::{buffer::Buffer, device::Device};
+
+/// Persistently mapped staging buffer handed out front to back. Space is given back in the
+/// order it was taken, once the GPU has finished reading it.
+#[derive(Clone, Debug)]
+pub(crate) struct StagingRingBuffer {
+    pub(crate) buffer: Buffer<u8>,
+    pub(crate) size: DeviceSize,
+    head: DeviceSize,
+    tail: DeviceSize,
+    // The head has wrapped around and is behind the tail
+    wrapped: bool,
+}
+
+impl StagingRingBuffer {
+    pub(crate) fn new(size: DeviceSize, device: Device) -> Self {
+        let buffer = Buffer::new(
+            size,
+            BufferUsageFlags::TRANSFER_SRC,
+            device,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        Self {
+            buffer,
+            size,
+            head: 0,
+            tail: 0,
+            wrapped: false,
+        }
+    }
+
+    /// Offset of `size` free bytes, `None` when the ring is too full.
+    pub(crate) fn allocate(
+        &mut self,
+        size: DeviceSize,
+        alignment: DeviceSize,
+    ) -> Option<DeviceSize> {
+        let offset = self.head.div_ceil(alignment) * alignment;
+
+        // The head never catches up with the tail, equal offsets mean the ring is empty
+        if self.wrapped {
+            if offset + size >= self.tail {
+                return None;
+            }
+        } else if offset + size > self.size {
+            if size >= self.tail {
+                return None;
+            }
+
+            self.head = size;
+            self.wrapped = true;
+
+            return Some(0);
+        }
+
+        self.head = offset + size;
+
+        Some(offset)
+    }
+
+    pub(crate) fn write<T>(&self, offset: DeviceSize, values: &[T]) {
+        unsafe {
+            copy_memory(
+                values.as_ptr().cast::<u8>(),
+                self.buffer.allocation.mapped.add(offset as usize),
+                size_of_val(values),
+            );
+        }
+    }
+
+    /// End of the space taken so far.
+    pub(crate) fn head(&self) -> DeviceSize {
+        self.head
+    }
+
+    /// Gives back everything taken before `head` was returned by [`Self::head`].
+    pub(crate) fn release(&mut self, head: DeviceSize) {
+        if head < self.tail {
+            self.wrapped = false;
+        }
+
+        self.tail = head;
+    }
+
+    /// Gives back everything, nothing may be in use by the GPU.
+    pub(crate) fn reset(&mut self) {
+        self.head = 0;
+        self.tail = 0;
+        self.wrapped = false;
+    }
+
+    pub(crate) fn destroy(&self) {
+        self.buffer.destroy();
+    }
+}
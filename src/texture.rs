@@ -1,7 +1,6 @@
 use std::rc::Rc;
 
-use image::{io::Reader as ImageReader, RgbaImage};
-use rand::{thread_rng, Rng};
+use image::{Rgba, RgbaImage};
 use vulkanalia::vk::{
     Extent3D, Format, ImageAspectFlags, ImageTiling, ImageUsageFlags, MemoryPropertyFlags,
     SampleCountFlags,
@@ -14,28 +13,21 @@ use crate::{
 
 #[derive(Default, Clone, Debug)]
 pub(crate) struct Texture {
-    pub(crate) id: usize,
-    pub(crate) path: String,
     pub(crate) image: Rc<RgbaImage>,
 }
 
 impl Texture {
-    pub(crate) fn new(image_path: &str) -> Self {
-        let image = Rc::new(
-            ImageReader::open(image_path)
-                .unwrap()
-                .decode()
-                .unwrap()
-                .into_rgba8(),
-        );
-
+    pub(crate) fn from_image(image: RgbaImage) -> Self {
         Self {
-            id: thread_rng().gen::<usize>(),
-            path: image_path.to_string(),
-            image,
+            image: Rc::new(image),
         }
     }
 
+    /// Plain white texture for entities whose texture is not loaded yet.
+    pub(crate) fn placeholder() -> Self {
+        Self::from_image(RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 255])))
+    }
+
     pub(crate) fn create_image(
         extent: Extent3D,
         msaa_sample_count: SampleCountFlags,
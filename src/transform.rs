@@ -0,0 +1,50 @@
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Placement of an entity relative to its parent, or to the world without one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[pyclass]
+pub(crate) struct Transform {
+    #[pyo3(get, set)]
+    pub(crate) position: Vector3<f32>,
+    #[pyo3(get, set)]
+    pub(crate) rotation: UnitQuaternion<f32>,
+    #[pyo3(get, set)]
+    pub(crate) scale: Vector3<f32>,
+}
+
+#[pymethods]
+impl Transform {
+    #[new]
+    pub(crate) fn new(
+        position: Vector3<f32>,
+        rotation: UnitQuaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Self {
+        Self {
+            position,
+            rotation,
+            scale,
+        }
+    }
+}
+
+impl Transform {
+    pub(crate) fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_nonuniform_scaling(&self.scale)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_translation(&self.position)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::new(
+            Vector3::zeros(),
+            UnitQuaternion::identity(),
+            Vector3::new(1.0, 1.0, 1.0),
+        )
+    }
+}
@@ -1,16 +1,59 @@
 use nalgebra::{Matrix4, Vector3};
+use vulkanalia::vk::Extent2D;
+
+use crate::camera::Camera;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub(crate) struct Ubo {
-    view: Matrix4<f32>,
-    projection: Matrix4<f32>,
+    pub(crate) view: Matrix4<f32>,
+    pub(crate) projection: Matrix4<f32>,
 }
 
 impl Ubo {
     pub(crate) fn new(view: Matrix4<f32>, projection: Matrix4<f32>) -> Self {
         Self { view, projection }
     }
+
+    /// Matrices of the camera for a swapchain of the given extent.
+    pub(crate) fn camera(extent: Extent2D) -> Self {
+        let view_matrix = Matrix4::look_at_rh(
+            &Vector3::new(2.0, 2.0, 2.0).into(),
+            &Vector3::new(0.0, 0.0, 0.0).into(),
+            &Vector3::new(0.0, 0.0, 0.1),
+        );
+
+        let mut perspective_matrix = Matrix4::new_perspective(
+            extent.width as f32 / extent.height as f32,
+            45.0f32.to_radians(),
+            0.1,
+            10.0,
+        );
+
+        perspective_matrix[(1, 1)] *= -1.0;
+
+        Self::new(view_matrix, perspective_matrix)
+    }
+
+    /// Matrices of `camera`, placed by the `world_matrix` of its entity.
+    pub(crate) fn from_camera(
+        camera: &Camera,
+        world_matrix: &Matrix4<f32>,
+        extent: Extent2D,
+    ) -> Self {
+        let view_matrix = world_matrix.try_inverse().unwrap_or_else(Matrix4::identity);
+
+        let mut perspective_matrix = Matrix4::new_perspective(
+            extent.width as f32 / extent.height as f32,
+            camera.fov.to_radians(),
+            camera.near,
+            camera.far,
+        );
+
+        perspective_matrix[(1, 1)] *= -1.0;
+
+        Self::new(view_matrix, perspective_matrix)
+    }
 }
 
 impl Default for Ubo {
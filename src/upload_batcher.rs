@@ -0,0 +1,199 @@
+use std::{collections::VecDeque, mem::size_of_val, slice};
+
+use vulkanalia::vk::{self, DeviceSize, HasBuilder, SubmitInfo};
+
+use crate::{
+    buffer::Buffer, command_buffer::CommandBuffer, command_pool::CommandPool, device::Device,
+    fence::Fence, queue::Queue, staging_ring_buffer::StagingRingBuffer,
+};
+
+const STAGING_RING_BUFFER_SIZE: DeviceSize = 64 * 1024 * 1024;
+// Multiple of every texel size, so images can be copied from any staged offset
+const STAGING_ALIGNMENT: DeviceSize = 16;
+
+#[derive(Debug)]
+struct Batch {
+    number: u64,
+    command_buffer: CommandBuffer,
+    fence: Fence,
+    /// Buffers for uploads too big for the ring.
+    staging_buffers: Vec<Buffer<u8>>,
+    staging_head: DeviceSize,
+}
+
+/// Records uploads into one command buffer that is submitted with a fence, instead of
+/// waiting for the queue after every copy. Data is staged in a ring buffer whose space is
+/// reused once the batches reading it have finished.
+#[derive(Debug)]
+pub(crate) struct UploadBatcher {
+    staging_ring_buffer: StagingRingBuffer,
+    recording: Option<Batch>,
+    in_flight: VecDeque<Batch>,
+    batch_count: u64,
+    device: Device,
+    command_pool: CommandPool,
+    queue: Queue,
+}
+
+impl UploadBatcher {
+    pub(crate) fn new(device: Device, command_pool: CommandPool, queue: Queue) -> Self {
+        let staging_ring_buffer = StagingRingBuffer::new(STAGING_RING_BUFFER_SIZE, device.clone());
+
+        Self {
+            staging_ring_buffer,
+            recording: None,
+            in_flight: VecDeque::new(),
+            batch_count: 0,
+            device,
+            command_pool,
+            queue,
+        }
+    }
+
+    /// Stages `values` and lets `record` copy them from the staging buffer at the given
+    /// offset. The copy happens on the next [`Self::submit`].
+    pub(crate) fn upload<T, F: FnOnce(CommandBuffer, vk::Buffer, DeviceSize)>(
+        &mut self,
+        values: &[T],
+        record: F,
+    ) {
+        let size = size_of_val(values) as DeviceSize;
+
+        let (staging_buffer, offset) = if size > self.staging_ring_buffer.size {
+            let bytes =
+                unsafe { slice::from_raw_parts(values.as_ptr().cast::<u8>(), size as usize) };
+            let staging_buffer = Buffer::from_staging_data(bytes, self.device.clone());
+            staging_buffer.copy_memory(staging_buffer.clone(), bytes);
+
+            self.batch().staging_buffers.push(staging_buffer.clone());
+
+            (vk::Buffer::from(staging_buffer), 0)
+        } else {
+            let offset = self.allocate_staging(size);
+            self.staging_ring_buffer.write(offset, values);
+
+            ((&self.staging_ring_buffer.buffer).into(), offset)
+        };
+
+        record(self.batch().command_buffer.clone(), staging_buffer, offset);
+    }
+
+    /// Records commands that don't need staged data, like queue ownership transfers.
+    pub(crate) fn record<F: FnOnce(CommandBuffer)>(&mut self, record: F) {
+        record(self.batch().command_buffer.clone());
+    }
+
+    /// Submits the recorded uploads without waiting for them. Returns the number of the
+    /// batch that holds everything recorded so far, see [`Self::is_finished`].
+    pub(crate) fn submit(&mut self) -> u64 {
+        let mut batch = match self.recording.take() {
+            Some(batch) => batch,
+            None => return self.batch_count,
+        };
+
+        batch.command_buffer.end();
+        batch.staging_head = self.staging_ring_buffer.head();
+
+        let command_buffers = &[batch.command_buffer.clone().into()];
+        let submit_info = SubmitInfo::builder()
+            .command_buffers(command_buffers)
+            .build();
+
+        self.queue.submit(submit_info, batch.fence.clone());
+        self.in_flight.push_back(batch);
+
+        self.batch_count
+    }
+
+    /// Releases the batches the GPU is done with without blocking.
+    pub(crate) fn retire_finished(&mut self) {
+        while self
+            .in_flight
+            .front()
+            .is_some_and(|batch| batch.fence.is_signaled())
+        {
+            self.retire_oldest();
+        }
+    }
+
+    pub(crate) fn is_finished(&self, batch_number: u64) -> bool {
+        self.in_flight
+            .front()
+            .is_none_or(|batch| batch.number > batch_number)
+    }
+
+    /// Submits the recorded uploads and waits until every upload has finished.
+    pub(crate) fn wait(&mut self) {
+        self.submit();
+
+        while self.retire_oldest() {}
+
+        self.staging_ring_buffer.reset();
+    }
+
+    fn batch(&mut self) -> &mut Batch {
+        let device = &self.device;
+        let command_pool = &self.command_pool;
+        let batch_count = &mut self.batch_count;
+
+        self.recording.get_or_insert_with(|| {
+            *batch_count += 1;
+
+            let command_buffer = CommandBuffer::new(device.clone(), command_pool.clone());
+            command_buffer.begin();
+
+            // Fences are only created signaled
+            let fence = Fence::new(device.clone(), true);
+            fence.reset();
+
+            Batch {
+                number: *batch_count,
+                command_buffer,
+                fence,
+                staging_buffers: Vec::new(),
+                staging_head: 0,
+            }
+        })
+    }
+
+    /// Takes ring space, submitting the recorded uploads and waiting for older ones when
+    /// the ring is full.
+    fn allocate_staging(&mut self, size: DeviceSize) -> DeviceSize {
+        loop {
+            if let Some(offset) = self.staging_ring_buffer.allocate(size, STAGING_ALIGNMENT) {
+                return offset;
+            }
+
+            self.submit();
+
+            if !self.retire_oldest() {
+                self.staging_ring_buffer.reset();
+            }
+        }
+    }
+
+    fn retire_oldest(&mut self) -> bool {
+        let batch = match self.in_flight.pop_front() {
+            Some(batch) => batch,
+            None => return false,
+        };
+
+        batch.fence.wait();
+        self.staging_ring_buffer.release(batch.staging_head);
+
+        batch.fence.destroy();
+        batch.command_buffer.destroy();
+        batch
+            .staging_buffers
+            .iter()
+            .for_each(|staging_buffer| staging_buffer.destroy());
+
+        true
+    }
+
+    pub(crate) fn destroy(&mut self) {
+        self.wait();
+
+        self.staging_ring_buffer.destroy();
+    }
+}
@@ -1,5 +1,5 @@
 use memoffset::offset_of;
-use nalgebra::{Vector2, Vector3};
+use nalgebra::{Vector2, Vector3, Vector4};
 use std::mem::size_of;
 use vulkanalia::vk::{
     Format, HasBuilder, VertexInputAttributeDescription, VertexInputBindingDescription,
@@ -9,17 +9,27 @@ use vulkanalia::vk::{
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default)]
 pub(crate) struct Vertex {
-    pos: Vector3<f32>,
-    color: Vector3<f32>,
-    texture_uv: Vector2<f32>,
+    pub(crate) pos: Vector3<f32>,
+    pub(crate) color: Vector3<f32>,
+    pub(crate) texture_uv: Vector2<f32>,
+    pub(crate) normal: Vector3<f32>,
+    /// Direction of increasing u, with the handedness of the bitangent in w.
+    pub(crate) tangent: Vector4<f32>,
 }
 
 impl Vertex {
-    pub(crate) fn new(pos: Vector3<f32>, color: Vector3<f32>, texture_uv: Vector2<f32>) -> Self {
+    pub(crate) fn new(
+        pos: Vector3<f32>,
+        color: Vector3<f32>,
+        texture_uv: Vector2<f32>,
+        normal: Vector3<f32>,
+    ) -> Self {
         Self {
             pos,
             color,
             texture_uv,
+            normal,
+            tangent: Vector4::zeros(),
         }
     }
 
@@ -31,7 +41,7 @@ impl Vertex {
             .build()
     }
 
-    pub(crate) fn attribute_descriptions() -> [VertexInputAttributeDescription; 3] {
+    pub(crate) fn attribute_descriptions() -> [VertexInputAttributeDescription; 5] {
         [
             VertexInputAttributeDescription::builder()
                 .binding(0)
@@ -51,6 +61,18 @@ impl Vertex {
                 .format(Format::R32G32_SFLOAT)
                 .offset(offset_of!(Self, texture_uv) as u32)
                 .build(),
+            VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(3)
+                .format(Format::R32G32B32_SFLOAT)
+                .offset(offset_of!(Self, normal) as u32)
+                .build(),
+            VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(4)
+                .format(Format::R32G32B32A32_SFLOAT)
+                .offset(offset_of!(Self, tangent) as u32)
+                .build(),
         ]
     }
 }